@@ -22,6 +22,8 @@ struct TaskArgs {
     #[darling(default)]
     send: bool,
     #[darling(default)]
+    priority: Option<u8>,
+    #[darling(default)]
     embassy_prefix: ModulePrefix,
 }
 
@@ -104,10 +106,33 @@ pub fn task(args: TokenStream, item: TokenStream) -> TokenStream {
 
     let visibility = &task_fn.vis;
     task_fn.sig.ident = format_ident!("task");
+    // Name the output type, so it's usable through a JoinHandle. `!` can't be named on
+    // stable, but there's no output to get from such tasks anyway.
+    let future = match &task_fn.sig.output {
+        ReturnType::Default => quote!(::core::future::Future<Output = ()>),
+        ReturnType::Type(_, ty) => match **ty {
+            Type::Never(_) => quote!(::core::future::Future),
+            _ => quote!(::core::future::Future<Output = #ty>),
+        },
+    };
     let impl_ty = if macro_args.send {
-        quote!(impl ::core::future::Future + Send + 'static)
+        quote!(impl #future + Send + 'static)
     } else {
-        quote!(impl ::core::future::Future + 'static)
+        quote!(impl #future + 'static)
+    };
+
+    let new_task = match macro_args.priority {
+        Some(priority) => {
+            let priority = priority as usize;
+            quote! {
+                // Fails to compile if the priority is out of range.
+                const _: usize = #embassy_path::executor::raw::PRIORITY_LEVELS - 1 - #priority;
+                const NEW_TASK: Task<F> = Task::new_with_priority(#priority as u8);
+            }
+        }
+        None => quote! {
+            const NEW_TASK: Task<F> = Task::new();
+        },
     };
 
     let result = quote! {
@@ -115,7 +140,7 @@ pub fn task(args: TokenStream, item: TokenStream) -> TokenStream {
             use #embassy_path::executor::raw::Task;
             #task_fn
             type F = #impl_ty;
-            const NEW_TASK: Task<F> = Task::new();
+            #new_task
             static POOL: [Task<F>; #pool_size] = [NEW_TASK; #pool_size];
             unsafe { Task::spawn_pool(&POOL, move || task(#arg_names)) }
         }
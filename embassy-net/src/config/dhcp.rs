@@ -1,59 +1,969 @@
-use heapless::Vec;
-use smoltcp::socket::{Dhcpv4Event, Dhcpv4Socket, SocketHandle};
-use smoltcp::time::Instant;
+use core::mem;
+use heapless::{String, Vec};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer, SocketHandle};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr};
 
 use super::*;
 use crate::device::LinkState;
 use crate::fmt::*;
+use crate::stack::rand;
 use crate::{Interface, SocketSet};
 
+/// Maximum length of the hostname and vendor class sent to the server.
+pub const DHCP_MAX_OPTION_LEN: usize = 32;
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+const IP_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+/// Every DHCP implementation must accept messages of this size, including the IP and UDP
+/// headers, so servers don't send larger ones unless asked to.
+const MAX_PACKET_LEN: usize = 576;
+const MAX_PAYLOAD_LEN: usize = MAX_PACKET_LEN - IP_HEADER_LEN - UDP_HEADER_LEN;
+/// Size of the fixed BOOTP fields, including the magic cookie.
+const BOOTP_LEN: usize = 240;
+/// Some servers ignore messages shorter than a BOOTP message without options.
+const MIN_PAYLOAD_LEN: usize = 300;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const MSG_DISCOVER: u8 = 1;
+const MSG_OFFER: u8 = 2;
+const MSG_REQUEST: u8 = 3;
+const MSG_ACK: u8 = 5;
+const MSG_NAK: u8 = 6;
+const MSG_RELEASE: u8 = 7;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVERS: u8 = 6;
+const OPT_HOSTNAME: u8 = 12;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_VENDOR_CLASS: u8 = 60;
+const OPT_CLIENT_ID: u8 = 61;
+const OPT_END: u8 = 255;
+
+const DISCOVER_TIMEOUT_SECS: u64 = 10;
+const REQUEST_TIMEOUT_SECS: u64 = 2;
+const REQUEST_ATTEMPTS: u32 = 5;
+const RENEW_TIMEOUT_SECS: u64 = 60;
+/// Used if the server doesn't say how long the lease is.
+const DEFAULT_LEASE_SECS: u32 = 120;
+
+enum State {
+    /// Looking for a server.
+    Discovering { retry_at: Instant },
+    /// Requesting the address offered by `server`.
+    Requesting {
+        retry_at: Instant,
+        attempts: u32,
+        server: Ipv4Address,
+        address: Ipv4Address,
+    },
+    /// Holding a lease, and renewing it when it's time to.
+    Bound {
+        config: Config,
+        renew_at: Instant,
+        rebind_at: Instant,
+        expires_at: Instant,
+    },
+    /// A DHCPRELEASE was just sent.
+    Releasing,
+    /// Idle until `renew` is called.
+    Released,
+}
+
+/// Buffers for the DHCP raw socket, part of [StackResources](crate::StackResources).
+pub(crate) struct DhcpResources {
+    rx_meta: [RawPacketMetadata; 2],
+    rx_buffer: [u8; 2 * MAX_PACKET_LEN],
+    tx_meta: [RawPacketMetadata; 1],
+    tx_buffer: [u8; MAX_PACKET_LEN],
+}
+
+impl DhcpResources {
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [RawPacketMetadata::EMPTY; 2],
+            rx_buffer: [0; 2 * MAX_PACKET_LEN],
+            tx_meta: [RawPacketMetadata::EMPTY; 1],
+            tx_buffer: [0; MAX_PACKET_LEN],
+        }
+    }
+}
+
+/// Configures the stack with DHCPv4.
+///
+/// Lease details are available in [Config::dhcp]. Leases are renewed automatically before
+/// they expire, [Stack::renew_config](crate::Stack::renew_config) and
+/// [Stack::release_config](crate::Stack::release_config) allow doing it on demand.
 pub struct DhcpConfigurator {
     handle: Option<SocketHandle>,
+    state: State,
+    /// Transaction ID of the last message sent.
+    xid: u32,
+    /// Whether the last event returned was `Configured`.
+    configured: bool,
+    renew_requested: bool,
+    release_requested: bool,
+    hostname: Option<String<DHCP_MAX_OPTION_LEN>>,
+    vendor_class: Option<String<DHCP_MAX_OPTION_LEN>>,
 }
 
 impl DhcpConfigurator {
     pub fn new() -> Self {
-        Self { handle: None }
+        Self {
+            handle: None,
+            state: State::Discovering {
+                retry_at: Instant::from_millis(0),
+            },
+            xid: 0,
+            configured: false,
+            renew_requested: false,
+            release_requested: false,
+            hostname: None,
+            vendor_class: None,
+        }
+    }
+
+    /// Send `hostname` to the server (option 12), for example so it can register it in DNS.
+    ///
+    /// Fails with `Error::Exhausted` if it's longer than [DHCP_MAX_OPTION_LEN] bytes.
+    pub fn set_hostname(&mut self, hostname: &str) -> crate::Result<()> {
+        self.hostname = Some(option_string(hostname)?);
+        Ok(())
+    }
+
+    /// Send `vendor_class` to the server (option 60), to identify the kind of device.
+    ///
+    /// Fails with `Error::Exhausted` if it's longer than [DHCP_MAX_OPTION_LEN] bytes.
+    pub fn set_vendor_class(&mut self, vendor_class: &str) -> crate::Result<()> {
+        self.vendor_class = Some(option_string(vendor_class)?);
+        Ok(())
+    }
+
+    fn new_xid(&mut self) -> u32 {
+        let mut xid = [0; 4];
+        rand(&mut xid);
+        self.xid = u32::from_le_bytes(xid);
+        self.xid
+    }
+
+    /// Handle a reply from a server, returning true if the configuration changed.
+    fn process(&mut self, reply: Reply, timestamp: Instant) -> bool {
+        if reply.xid != self.xid {
+            return false;
+        }
+
+        match (&self.state, reply.message_type) {
+            (State::Discovering { .. }, MSG_OFFER) => {
+                let server = match reply.server_id {
+                    Some(server) => server,
+                    None => return false,
+                };
+                debug!("DHCP: offered {} by {}", reply.your_ip, server);
+                self.state = State::Requesting {
+                    retry_at: timestamp,
+                    attempts: 0,
+                    server,
+                    address: reply.your_ip,
+                };
+                false
+            }
+            (State::Requesting { server, .. }, MSG_ACK) if reply.server_id == Some(*server) => {
+                self.bind(reply, timestamp)
+            }
+            (State::Bound { .. }, MSG_ACK) => self.bind(reply, timestamp),
+            (State::Requesting { .. }, MSG_NAK) | (State::Bound { .. }, MSG_NAK) => {
+                debug!("DHCP: lease refused by server");
+                self.state = State::Discovering {
+                    retry_at: timestamp,
+                };
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn bind(&mut self, reply: Reply, timestamp: Instant) -> bool {
+        let (server, mask) = match (reply.server_id, reply.subnet_mask) {
+            (Some(server), Some(mask)) => (server, mask),
+            _ => {
+                debug!("DHCP: ignoring ACK without server ID or subnet mask");
+                return false;
+            }
+        };
+        let mask = u32::from_be_bytes([mask.0[0], mask.0[1], mask.0[2], mask.0[3]]);
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            debug!("DHCP: ignoring ACK with invalid subnet mask");
+            return false;
+        }
+
+        let lease_secs = reply.lease_time.unwrap_or(DEFAULT_LEASE_SECS);
+        let renew_secs = reply.renewal_time.unwrap_or(lease_secs / 2);
+        let rebind_secs = reply
+            .rebinding_time
+            .unwrap_or((lease_secs as u64 * 7 / 8) as u32);
+        let duration = Duration::from_secs(lease_secs as u64);
+
+        let mut config = Config::new(Ipv4Cidr::new(reply.your_ip, mask.leading_ones() as u8));
+        config.gateway = reply.router;
+        config.dns_servers = reply.dns_servers;
+        let mut lease = DhcpLease {
+            server,
+            duration,
+            acquired_at: timestamp,
+        };
+        let old = match &self.state {
+            State::Bound { config, .. } => config.dhcp,
+            _ => None,
+        };
+        // A renewal that changes nothing isn't a new configuration.
+        if let Some(old) = old {
+            if old.server == lease.server && old.duration == lease.duration {
+                lease.acquired_at = old.acquired_at;
+            }
+        }
+        config.dhcp = Some(lease);
+
+        let changed = !matches!(&self.state, State::Bound { config: old, .. } if *old == config);
+        self.state = State::Bound {
+            config,
+            renew_at: timestamp + Duration::from_secs(renew_secs as u64),
+            rebind_at: timestamp + Duration::from_secs(rebind_secs as u64),
+            expires_at: timestamp + duration,
+        };
+        changed
+    }
+
+    /// Return the message to send now, if any, and update the retry times.
+    fn next_request(&mut self, timestamp: Instant) -> Option<Request> {
+        let request = match self.state {
+            State::Discovering { ref mut retry_at } if *retry_at <= timestamp => {
+                *retry_at = timestamp + Duration::from_secs(DISCOVER_TIMEOUT_SECS);
+                self.new_xid();
+                Request {
+                    message_type: MSG_DISCOVER,
+                    client_ip: Ipv4Address::UNSPECIFIED,
+                    requested_ip: None,
+                    server_id: None,
+                    dst: Ipv4Address::BROADCAST,
+                }
+            }
+            State::Requesting {
+                ref mut retry_at,
+                ref mut attempts,
+                server,
+                address,
+            } if *retry_at <= timestamp => {
+                if *attempts >= REQUEST_ATTEMPTS {
+                    debug!("DHCP: no answer to request, restarting discovery");
+                    self.state = State::Discovering {
+                        retry_at: timestamp,
+                    };
+                    return self.next_request(timestamp);
+                }
+                *retry_at = timestamp + Duration::from_secs(REQUEST_TIMEOUT_SECS);
+                *attempts += 1;
+                // Keep the offer's transaction ID.
+                Request {
+                    message_type: MSG_REQUEST,
+                    client_ip: Ipv4Address::UNSPECIFIED,
+                    requested_ip: Some(address),
+                    server_id: Some(server),
+                    dst: Ipv4Address::BROADCAST,
+                }
+            }
+            State::Bound { expires_at, .. } if expires_at <= timestamp => {
+                debug!("DHCP: lease expired");
+                self.state = State::Discovering {
+                    retry_at: timestamp,
+                };
+                return self.next_request(timestamp);
+            }
+            State::Bound {
+                ref config,
+                ref mut renew_at,
+                rebind_at,
+                ..
+            } if *renew_at <= timestamp => {
+                *renew_at = timestamp + Duration::from_secs(RENEW_TIMEOUT_SECS);
+                // Once it's time to rebind, any server may extend the lease.
+                let dst = match &config.dhcp {
+                    Some(lease) if timestamp < rebind_at => lease.server,
+                    _ => Ipv4Address::BROADCAST,
+                };
+                let client_ip = config.address.address();
+                self.new_xid();
+                Request {
+                    message_type: MSG_REQUEST,
+                    client_ip,
+                    requested_ip: None,
+                    server_id: None,
+                    dst,
+                }
+            }
+            _ => return None,
+        };
+        Some(request)
+    }
+
+    fn send(&self, socket: &mut RawSocket, mac: [u8; 6], request: &Request) -> crate::Result<()> {
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        let payload_len = self.write_payload(&mut payload, mac, request);
+        let udp_len = UDP_HEADER_LEN + payload_len;
+        let ip_repr = Ipv4Repr {
+            src_addr: request.client_ip,
+            dst_addr: request.dst,
+            protocol: IpProtocol::Udp,
+            payload_len: udp_len,
+            hop_limit: 64,
+        };
+
+        let buf = socket.send(IP_HEADER_LEN + udp_len)?;
+        let mut ip_packet = Ipv4Packet::new_unchecked(buf);
+        ip_repr.emit(&mut ip_packet, &ChecksumCapabilities::default());
+        let udp = ip_packet.payload_mut();
+        udp[0..2].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+        udp[2..4].copy_from_slice(&SERVER_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        // A zero checksum means "none" over IPv4.
+        udp[6..8].fill(0);
+        udp[8..].copy_from_slice(&payload[..payload_len]);
+        Ok(())
+    }
+
+    /// Write the DHCP message for `request` into `payload`, returning its length.
+    fn write_payload(&self, payload: &mut [u8], mac: [u8; 6], request: &Request) -> usize {
+        payload[0] = OP_BOOTREQUEST;
+        payload[1] = HTYPE_ETHERNET;
+        payload[2] = mac.len() as u8;
+        payload[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if request.client_ip.is_unspecified() {
+            // We can't receive unicast replies without an address.
+            payload[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        }
+        payload[12..16].copy_from_slice(request.client_ip.as_bytes());
+        payload[28..34].copy_from_slice(&mac);
+        payload[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut pos = BOOTP_LEN;
+        let mut client_id = [0; 7];
+        client_id[0] = HTYPE_ETHERNET;
+        client_id[1..].copy_from_slice(&mac);
+        put_option(payload, &mut pos, OPT_MESSAGE_TYPE, &[request.message_type]);
+        put_option(payload, &mut pos, OPT_CLIENT_ID, &client_id);
+        if let Some(addr) = request.requested_ip {
+            put_option(payload, &mut pos, OPT_REQUESTED_IP, addr.as_bytes());
+        }
+        if let Some(addr) = request.server_id {
+            put_option(payload, &mut pos, OPT_SERVER_ID, addr.as_bytes());
+        }
+        if request.message_type != MSG_RELEASE {
+            if let Some(hostname) = &self.hostname {
+                put_option(payload, &mut pos, OPT_HOSTNAME, hostname.as_bytes());
+            }
+            if let Some(vendor_class) = &self.vendor_class {
+                put_option(payload, &mut pos, OPT_VENDOR_CLASS, vendor_class.as_bytes());
+            }
+            put_option(
+                payload,
+                &mut pos,
+                OPT_PARAMETER_REQUEST_LIST,
+                &[
+                    OPT_SUBNET_MASK,
+                    OPT_ROUTER,
+                    OPT_DNS_SERVERS,
+                    OPT_LEASE_TIME,
+                    OPT_RENEWAL_TIME,
+                    OPT_REBINDING_TIME,
+                ],
+            );
+        }
+        payload[pos] = OPT_END;
+        (pos + 1).max(MIN_PAYLOAD_LEN)
     }
 }
 
 impl Configurator for DhcpConfigurator {
+    fn init(&mut self, resources: &mut ConfiguratorResources) -> crate::Result<()> {
+        // The buffers are in the StackResources, there's only one set per stack.
+        let buffers = resources.dhcp.take().ok_or(crate::Error::Exhausted)?;
+        let socket = RawSocket::new(
+            IpVersion::Ipv4,
+            IpProtocol::Udp,
+            RawSocketBuffer::new(&mut buffers.rx_meta[..], &mut buffers.rx_buffer[..]),
+            RawSocketBuffer::new(&mut buffers.tx_meta[..], &mut buffers.tx_buffer[..]),
+        );
+        self.handle = Some(resources.add_socket(socket)?);
+        Ok(())
+    }
+
     fn poll(
         &mut self,
         iface: &mut Interface,
         sockets: &mut SocketSet,
-        _timestamp: Instant,
+        timestamp: Instant,
     ) -> Event {
-        if self.handle.is_none() {
-            let handle = sockets.add(Dhcpv4Socket::new());
-            self.handle = Some(handle)
-        }
+        let mut socket = sockets.get::<RawSocket>(unwrap!(self.handle));
+        let mac = iface.device_mut().device().ethernet_address();
+        let mut changed = false;
 
-        let mut socket = sockets.get::<Dhcpv4Socket>(self.handle.unwrap());
-
-        let link_up = iface.device_mut().device.link_state() == LinkState::Up;
+        let link_up = iface.device_mut().device().link_state() == LinkState::Up;
         if !link_up {
-            socket.reset();
-            return Event::Deconfigured;
-        }
-
-        match socket.poll() {
-            None => Event::NoChange,
-            Some(Dhcpv4Event::Deconfigured) => Event::Deconfigured,
-            Some(Dhcpv4Event::Configured(config)) => {
-                let mut dns_servers = Vec::new();
-                for s in &config.dns_servers {
-                    if let Some(addr) = s {
-                        dns_servers.push(addr.clone()).unwrap();
+            match self.state {
+                State::Released | State::Releasing => self.state = State::Released,
+                _ => {
+                    self.state = State::Discovering {
+                        retry_at: timestamp,
                     }
                 }
+            }
+        } else {
+            // The DHCPRELEASE was sent by the interface poll that just happened.
+            if let State::Releasing = self.state {
+                self.state = State::Released;
+            }
+
+            if mem::take(&mut self.release_requested) {
+                let state = mem::replace(&mut self.state, State::Released);
+                if let State::Bound { config, .. } = state {
+                    let server = config.dhcp.map(|lease| lease.server);
+                    let request = Request {
+                        message_type: MSG_RELEASE,
+                        client_ip: config.address.address(),
+                        requested_ip: None,
+                        server_id: server,
+                        dst: unwrap!(server),
+                    };
+                    self.new_xid();
+                    match self.send(&mut socket, mac, &request) {
+                        Ok(()) => self.state = State::Releasing,
+                        Err(e) => debug!("DHCP: failed to send release: {:?}", e),
+                    }
+                }
+            }
+
+            if mem::take(&mut self.renew_requested) {
+                match &mut self.state {
+                    State::Bound { renew_at, .. } => *renew_at = timestamp,
+                    _ => {
+                        self.state = State::Discovering {
+                            retry_at: timestamp,
+                        }
+                    }
+                }
+            }
+
+            while let Ok(pkt) = socket.recv() {
+                if let Some(reply) = parse_reply(pkt, &mac) {
+                    changed |= self.process(reply, timestamp);
+                }
+            }
+
+            if let Some(request) = self.next_request(timestamp) {
+                if let Err(e) = self.send(&mut socket, mac, &request) {
+                    // tx buffer full, will be retried after the timeout.
+                    debug!("DHCP: failed to send message: {:?}", e);
+                }
+            }
+        }
+
+        match &self.state {
+            State::Bound { config, .. } if changed || !self.configured => {
+                self.configured = true;
+                Event::Configured(config.clone())
+            }
+            State::Bound { .. } => Event::NoChange,
+            // Keep the configuration until the release has been sent.
+            State::Releasing => Event::NoChange,
+            _ if self.configured => {
+                self.configured = false;
+                Event::Deconfigured
+            }
+            _ => Event::NoChange,
+        }
+    }
 
-                Event::Configured(Config {
-                    address: config.address,
-                    gateway: config.router,
-                    dns_servers,
-                })
+    fn renew(&mut self) {
+        self.renew_requested = true;
+    }
+
+    fn release(&mut self) {
+        self.release_requested = true;
+    }
+
+    fn poll_at(&self) -> Option<Instant> {
+        if self.renew_requested || self.release_requested {
+            return Some(Instant::from_millis(0));
+        }
+        match &self.state {
+            State::Discovering { retry_at } | State::Requesting { retry_at, .. } => Some(*retry_at),
+            State::Bound {
+                renew_at,
+                expires_at,
+                ..
+            } => Some((*renew_at).min(*expires_at)),
+            // Finish releasing right after the DHCPRELEASE is sent.
+            State::Releasing => Some(Instant::from_millis(0)),
+            State::Released => None,
+        }
+    }
+}
+
+struct Request {
+    message_type: u8,
+    /// Our current address, when renewing or releasing a lease.
+    client_ip: Ipv4Address,
+    requested_ip: Option<Ipv4Address>,
+    server_id: Option<Ipv4Address>,
+    dst: Ipv4Address,
+}
+
+struct Reply {
+    message_type: u8,
+    xid: u32,
+    your_ip: Ipv4Address,
+    server_id: Option<Ipv4Address>,
+    subnet_mask: Option<Ipv4Address>,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<Ipv4Address, 3>,
+    lease_time: Option<u32>,
+    renewal_time: Option<u32>,
+    rebinding_time: Option<u32>,
+}
+
+fn option_string(s: &str) -> crate::Result<String<DHCP_MAX_OPTION_LEN>> {
+    let mut string = String::new();
+    string.push_str(s).map_err(|_| crate::Error::Exhausted)?;
+    Ok(string)
+}
+
+fn put_option(buf: &mut [u8], pos: &mut usize, code: u8, data: &[u8]) {
+    buf[*pos] = code;
+    buf[*pos + 1] = data.len() as u8;
+    buf[*pos + 2..*pos + 2 + data.len()].copy_from_slice(data);
+    *pos += 2 + data.len();
+}
+
+/// Parse a DHCP reply addressed to `mac` from a raw IPv4 packet.
+///
+/// Returns `None` if the packet is not a well-formed DHCP reply for us.
+fn parse_reply(pkt: &[u8], mac: &[u8; 6]) -> Option<Reply> {
+    let ip_packet = Ipv4Packet::new_checked(pkt).ok()?;
+    Ipv4Repr::parse(&ip_packet, &ChecksumCapabilities::default()).ok()?;
+
+    let udp = ip_packet.payload();
+    let read_u16 = |b: &[u8], pos: usize| -> Option<u16> {
+        let b = b.get(pos..pos + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    };
+    if read_u16(udp, 0)? != SERVER_PORT || read_u16(udp, 2)? != CLIENT_PORT {
+        return None;
+    }
+    let udp_len = read_u16(udp, 4)? as usize;
+    let payload = udp.get(UDP_HEADER_LEN..udp_len)?;
+
+    if payload.len() < BOOTP_LEN
+        || payload[0] != OP_BOOTREPLY
+        || &payload[28..34] != mac
+        || payload[236..240] != MAGIC_COOKIE
+    {
+        return None;
+    }
+
+    let mut reply = Reply {
+        message_type: 0,
+        xid: u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+        your_ip: Ipv4Address::from_bytes(&payload[16..20]),
+        server_id: None,
+        subnet_mask: None,
+        router: None,
+        dns_servers: Vec::new(),
+        lease_time: None,
+        renewal_time: None,
+        rebinding_time: None,
+    };
+
+    let addr = |data: &[u8]| match data.len() {
+        n if n >= 4 => Some(Ipv4Address::from_bytes(&data[..4])),
+        _ => None,
+    };
+    let secs = |data: &[u8]| match data.len() {
+        n if n >= 4 => Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+        _ => None,
+    };
+
+    let mut pos = BOOTP_LEN;
+    loop {
+        let code = *payload.get(pos)?;
+        match code {
+            OPT_END => break,
+            OPT_PAD => {
+                pos += 1;
+                continue;
             }
+            _ => {}
+        }
+        let len = *payload.get(pos + 1)? as usize;
+        let data = payload.get(pos + 2..pos + 2 + len)?;
+        pos += 2 + len;
+
+        match code {
+            OPT_MESSAGE_TYPE => reply.message_type = *data.first()?,
+            OPT_SERVER_ID => reply.server_id = addr(data),
+            OPT_SUBNET_MASK => reply.subnet_mask = addr(data),
+            OPT_ROUTER => reply.router = addr(data),
+            OPT_DNS_SERVERS => {
+                for chunk in data.chunks_exact(4) {
+                    if reply
+                        .dns_servers
+                        .push(Ipv4Address::from_bytes(chunk))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+            OPT_LEASE_TIME => reply.lease_time = secs(data),
+            OPT_RENEWAL_TIME => reply.renewal_time = secs(data),
+            OPT_REBINDING_TIME => reply.rebinding_time = secs(data),
+            _ => {}
+        }
+    }
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const SERVER: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+    const ADDRESS: Ipv4Address = Ipv4Address([192, 168, 1, 100]);
+
+    fn secs(s: i64) -> Instant {
+        Instant::from_secs(s)
+    }
+
+    /// Options of a reply from `SERVER`, granting `ADDRESS` for an hour.
+    fn options(message_type: u8) -> heapless::Vec<u8, 64> {
+        let mut options = heapless::Vec::new();
+        for option in [
+            &[OPT_MESSAGE_TYPE, 1, message_type][..],
+            &[OPT_SERVER_ID, 4, 192, 168, 1, 1],
+            &[OPT_SUBNET_MASK, 4, 255, 255, 255, 0],
+            &[OPT_ROUTER, 4, 192, 168, 1, 1],
+            &[OPT_DNS_SERVERS, 8, 8, 8, 8, 8, 8, 8, 4, 4],
+            &[OPT_LEASE_TIME, 4, 0, 0, 0x0E, 0x10],
+            &[OPT_END],
+        ]
+        .iter()
+        {
+            options.extend_from_slice(option).unwrap();
         }
+        options
+    }
+
+    /// A reply to `MAC`, as received by the raw socket.
+    fn reply(xid: u32, options: &[u8]) -> heapless::Vec<u8, MAX_PACKET_LEN> {
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        payload[0] = OP_BOOTREPLY;
+        payload[1] = HTYPE_ETHERNET;
+        payload[2] = 6;
+        payload[4..8].copy_from_slice(&xid.to_be_bytes());
+        payload[16..20].copy_from_slice(ADDRESS.as_bytes());
+        payload[28..34].copy_from_slice(&MAC);
+        payload[236..240].copy_from_slice(&MAGIC_COOKIE);
+        payload[BOOTP_LEN..BOOTP_LEN + options.len()].copy_from_slice(options);
+        let payload = &payload[..BOOTP_LEN + options.len()];
+
+        let udp_len = UDP_HEADER_LEN + payload.len();
+        let mut pkt = heapless::Vec::new();
+        pkt.resize(IP_HEADER_LEN + udp_len, 0).unwrap();
+        let ip_repr = Ipv4Repr {
+            src_addr: SERVER,
+            dst_addr: Ipv4Address::BROADCAST,
+            protocol: IpProtocol::Udp,
+            payload_len: udp_len,
+            hop_limit: 64,
+        };
+        let mut ip_packet = Ipv4Packet::new_unchecked(&mut pkt[..]);
+        ip_repr.emit(&mut ip_packet, &ChecksumCapabilities::default());
+        let udp = ip_packet.payload_mut();
+        udp[0..2].copy_from_slice(&SERVER_PORT.to_be_bytes());
+        udp[2..4].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[8..].copy_from_slice(payload);
+        pkt
+    }
+
+    fn parse(xid: u32, options: &[u8]) -> Reply {
+        parse_reply(&reply(xid, options), &MAC).unwrap()
+    }
+
+    /// A configurator that was offered `ADDRESS` by `SERVER` at time 0, and requested it.
+    fn requesting() -> DhcpConfigurator {
+        let mut dhcp = DhcpConfigurator::new();
+        let discover = dhcp.next_request(secs(0)).unwrap();
+        assert_eq!(discover.message_type, MSG_DISCOVER);
+        assert!(!dhcp.process(parse(dhcp.xid, &options(MSG_OFFER)), secs(0)));
+        let request = dhcp.next_request(secs(0)).unwrap();
+        assert_eq!(request.message_type, MSG_REQUEST);
+        dhcp
+    }
+
+    /// A configurator that got `ADDRESS` for an hour at time 0.
+    fn bound() -> DhcpConfigurator {
+        let mut dhcp = requesting();
+        assert!(dhcp.process(parse(dhcp.xid, &options(MSG_ACK)), secs(0)));
+        dhcp
+    }
+
+    fn config(dhcp: &DhcpConfigurator) -> &Config {
+        match &dhcp.state {
+            State::Bound { config, .. } => config,
+            _ => panic!("not bound"),
+        }
+    }
+
+    #[test]
+    fn parse_options() {
+        let mut opts = heapless::Vec::<u8, 128>::new();
+        // Pads are skipped, and only 3 DNS servers are kept.
+        for option in [
+            &[OPT_PAD, OPT_PAD][..],
+            &[OPT_DNS_SERVERS, 16],
+            &[1, 1, 1, 1, 2, 2, 2, 2],
+            &[3, 3, 3, 3, 4, 4, 4, 4],
+            &[OPT_RENEWAL_TIME, 4, 0, 0, 0, 60],
+            &[OPT_REBINDING_TIME, 4, 0, 0, 0, 90],
+            &options(MSG_ACK)[..],
+        ]
+        .iter()
+        {
+            opts.extend_from_slice(option).unwrap();
+        }
+
+        let reply = parse(0x1234_5678, &opts);
+        assert_eq!(reply.message_type, MSG_ACK);
+        assert_eq!(reply.xid, 0x1234_5678);
+        assert_eq!(reply.your_ip, ADDRESS);
+        assert_eq!(reply.server_id, Some(SERVER));
+        assert_eq!(reply.subnet_mask, Some(Ipv4Address::new(255, 255, 255, 0)));
+        assert_eq!(reply.router, Some(SERVER));
+        assert_eq!(
+            &reply.dns_servers[..],
+            &[
+                Ipv4Address::new(1, 1, 1, 1),
+                Ipv4Address::new(2, 2, 2, 2),
+                Ipv4Address::new(3, 3, 3, 3),
+            ]
+        );
+        assert_eq!(reply.lease_time, Some(3600));
+        assert_eq!(reply.renewal_time, Some(60));
+        assert_eq!(reply.rebinding_time, Some(90));
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        // Option running past the end of the packet.
+        assert!(parse_reply(&reply(0, &[OPT_MESSAGE_TYPE, 4, MSG_ACK]), &MAC).is_none());
+        // No end option.
+        assert!(parse_reply(&reply(0, &[OPT_MESSAGE_TYPE, 1, MSG_ACK]), &MAC).is_none());
+        // For another client.
+        let other_mac = [0x02, 0, 0, 0, 0, 0x02];
+        assert!(parse_reply(&reply(0, &options(MSG_ACK)), &other_mac).is_none());
+
+        let mut pkt = reply(0, &options(MSG_ACK));
+        pkt[IP_HEADER_LEN + UDP_HEADER_LEN + 236] = 0;
+        assert!(parse_reply(&pkt, &MAC).is_none(), "bad magic cookie");
+
+        let mut pkt = reply(0, &options(MSG_ACK));
+        pkt[IP_HEADER_LEN + UDP_HEADER_LEN] = OP_BOOTREQUEST;
+        assert!(parse_reply(&pkt, &MAC).is_none(), "request, not a reply");
+
+        let mut pkt = reply(0, &options(MSG_ACK));
+        pkt[IP_HEADER_LEN..IP_HEADER_LEN + 2].copy_from_slice(&1234u16.to_be_bytes());
+        assert!(
+            parse_reply(&pkt, &MAC).is_none(),
+            "not from the server port"
+        );
+    }
+
+    #[test]
+    fn request_options() {
+        let mut dhcp = DhcpConfigurator::new();
+        dhcp.set_hostname("embassy").unwrap();
+        dhcp.set_vendor_class("test").unwrap();
+        let discover = dhcp.next_request(secs(0)).unwrap();
+
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        let len = dhcp.write_payload(&mut payload, MAC, &discover);
+        assert_eq!(len, MIN_PAYLOAD_LEN);
+        assert_eq!(payload[0], OP_BOOTREQUEST);
+        assert_eq!(&payload[4..8], &dhcp.xid.to_be_bytes());
+        assert_eq!(&payload[10..12], &FLAG_BROADCAST.to_be_bytes());
+        assert_eq!(&payload[28..34], &MAC);
+        assert_eq!(&payload[236..240], &MAGIC_COOKIE);
+        let options: &[&[u8]] = &[
+            &[OPT_MESSAGE_TYPE, 1, MSG_DISCOVER],
+            &[OPT_CLIENT_ID, 7, HTYPE_ETHERNET, 0x02, 0, 0, 0, 0, 0x01],
+            &[OPT_HOSTNAME, 7],
+            b"embassy",
+            &[OPT_VENDOR_CLASS, 4],
+            b"test",
+            &[OPT_PARAMETER_REQUEST_LIST, 6, 1, 3, 6, 51, 58, 59],
+            &[OPT_END],
+        ];
+        let mut pos = BOOTP_LEN;
+        for option in options {
+            assert_eq!(&payload[pos..pos + option.len()], *option);
+            pos += option.len();
+        }
+    }
+
+    #[test]
+    fn option_too_long() {
+        let mut dhcp = DhcpConfigurator::new();
+        let name = [b'a'; DHCP_MAX_OPTION_LEN + 1];
+        let name = core::str::from_utf8(&name).unwrap();
+        assert_eq!(dhcp.set_hostname(name), Err(crate::Error::Exhausted));
+        assert_eq!(dhcp.set_vendor_class(name), Err(crate::Error::Exhausted));
+        assert!(dhcp.hostname.is_none());
+        assert_eq!(dhcp.set_hostname(&name[1..]), Ok(()));
+    }
+
+    #[test]
+    fn discover_request_bind() {
+        let mut dhcp = DhcpConfigurator::new();
+        let discover = dhcp.next_request(secs(0)).unwrap();
+        assert_eq!(discover.dst, Ipv4Address::BROADCAST);
+        assert!(dhcp.next_request(secs(1)).is_none());
+
+        // Replies to other transactions are ignored.
+        assert!(!dhcp.process(parse(dhcp.xid ^ 1, &options(MSG_OFFER)), secs(1)));
+        assert!(matches!(dhcp.state, State::Discovering { .. }));
+
+        assert!(!dhcp.process(parse(dhcp.xid, &options(MSG_OFFER)), secs(1)));
+        let request = dhcp.next_request(secs(1)).unwrap();
+        assert_eq!(request.message_type, MSG_REQUEST);
+        assert_eq!(request.requested_ip, Some(ADDRESS));
+        assert_eq!(request.server_id, Some(SERVER));
+        assert_eq!(request.dst, Ipv4Address::BROADCAST);
+
+        assert!(dhcp.process(parse(dhcp.xid, &options(MSG_ACK)), secs(2)));
+        let config = config(&dhcp);
+        assert_eq!(config.address, Ipv4Cidr::new(ADDRESS, 24));
+        assert_eq!(config.gateway, Some(SERVER));
+        assert_eq!(
+            &config.dns_servers[..],
+            &[Ipv4Address::new(8, 8, 8, 8), Ipv4Address::new(8, 8, 4, 4)]
+        );
+        assert_eq!(
+            config.dhcp,
+            Some(DhcpLease {
+                server: SERVER,
+                duration: Duration::from_secs(3600),
+                acquired_at: secs(2),
+            })
+        );
+    }
+
+    #[test]
+    fn ack_from_other_server() {
+        let mut dhcp = requesting();
+        let mut options = options(MSG_ACK);
+        options[5..9].copy_from_slice(&[192, 168, 1, 2]);
+        assert!(!dhcp.process(parse(dhcp.xid, &options), secs(0)));
+        assert!(matches!(dhcp.state, State::Requesting { .. }));
+    }
+
+    #[test]
+    fn request_timeout() {
+        let mut dhcp = requesting();
+        for i in 1..REQUEST_ATTEMPTS {
+            let t = secs((i as u64 * REQUEST_TIMEOUT_SECS) as i64);
+            let request = dhcp.next_request(t).unwrap();
+            assert_eq!(request.message_type, MSG_REQUEST);
+        }
+        let t = secs((REQUEST_ATTEMPTS as u64 * REQUEST_TIMEOUT_SECS) as i64);
+        let discover = dhcp.next_request(t).unwrap();
+        assert_eq!(discover.message_type, MSG_DISCOVER);
+    }
+
+    #[test]
+    fn nak() {
+        let mut dhcp = requesting();
+        assert!(!dhcp.process(parse(dhcp.xid, &options(MSG_NAK)), secs(0)));
+        assert!(matches!(dhcp.state, State::Discovering { .. }));
+
+        let mut dhcp = bound();
+        dhcp.next_request(secs(1800)).unwrap();
+        assert!(!dhcp.process(parse(dhcp.xid, &options(MSG_NAK)), secs(1800)));
+        assert!(matches!(dhcp.state, State::Discovering { .. }));
+    }
+
+    #[test]
+    fn renew() {
+        let mut dhcp = bound();
+        assert!(dhcp.next_request(secs(1799)).is_none());
+
+        // Renew with the server that granted the lease, half-way through.
+        let request = dhcp.next_request(secs(1800)).unwrap();
+        assert_eq!(request.message_type, MSG_REQUEST);
+        assert_eq!(request.client_ip, ADDRESS);
+        assert_eq!(request.requested_ip, None);
+        assert_eq!(request.dst, SERVER);
+
+        // Nothing changed, so there's nothing to report.
+        assert!(!dhcp.process(parse(dhcp.xid, &options(MSG_ACK)), secs(1800)));
+        assert_eq!(config(&dhcp).dhcp.unwrap().acquired_at, secs(0));
+        assert!(dhcp.next_request(secs(3599)).is_none());
+
+        // A new gateway is a change.
+        let request = dhcp.next_request(secs(3600)).unwrap();
+        assert_eq!(request.dst, SERVER);
+        let mut options = options(MSG_ACK);
+        options[17..21].copy_from_slice(&[192, 168, 1, 254]);
+        assert!(dhcp.process(parse(dhcp.xid, &options), secs(3600)));
+        assert_eq!(
+            config(&dhcp).gateway,
+            Some(Ipv4Address::new(192, 168, 1, 254))
+        );
+    }
+
+    #[test]
+    fn rebind_and_expire() {
+        let mut dhcp = bound();
+        let request = dhcp.next_request(secs(1800)).unwrap();
+        assert_eq!(request.dst, SERVER);
+
+        // No answer: ask any server once it's time to rebind.
+        let request = dhcp.next_request(secs(3150)).unwrap();
+        assert_eq!(request.message_type, MSG_REQUEST);
+        assert_eq!(request.client_ip, ADDRESS);
+        assert_eq!(request.dst, Ipv4Address::BROADCAST);
+
+        // Still no answer: start over when the lease expires.
+        let discover = dhcp.next_request(secs(3600)).unwrap();
+        assert_eq!(discover.message_type, MSG_DISCOVER);
+        assert_eq!(discover.client_ip, Ipv4Address::UNSPECIFIED);
+        assert!(matches!(dhcp.state, State::Discovering { .. }));
     }
 }
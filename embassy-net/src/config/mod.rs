@@ -1,9 +1,12 @@
 use heapless::Vec;
-use smoltcp::time::Instant;
+use smoltcp::socket::{Socket, SocketHandle};
+use smoltcp::time::{Duration, Instant};
 use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 
 use crate::fmt::*;
-use crate::{Interface, SocketSet};
+use crate::{Interface, Result, SocketSet};
 
 mod statik;
 pub use statik::StaticConfigurator;
@@ -11,7 +14,9 @@ pub use statik::StaticConfigurator;
 #[cfg(feature = "dhcpv4")]
 mod dhcp;
 #[cfg(feature = "dhcpv4")]
-pub use dhcp::DhcpConfigurator;
+pub(crate) use dhcp::DhcpResources;
+#[cfg(feature = "dhcpv4")]
+pub use dhcp::{DhcpConfigurator, DHCP_MAX_OPTION_LEN};
 
 /// Return value for the `Configurator::poll` function
 #[derive(Debug, Clone)]
@@ -24,14 +29,99 @@ pub enum Event {
     Configured(Config),
 }
 
+/// Network configuration of the stack.
+///
+/// Create one with [Config::new], then set the other fields. More fields may be added
+/// in the future, and some depend on Cargo features, so it can't be built with a struct
+/// literal.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Config {
     pub address: Ipv4Cidr,
     pub gateway: Option<Ipv4Address>,
     pub dns_servers: Vec<Ipv4Address, 3>,
+    /// Global IPv6 addresses. These are in addition to the link-local address and the
+    /// SLAAC address, which the stack configures by itself.
+    #[cfg(feature = "proto-ipv6")]
+    pub ipv6_addresses: Vec<Ipv6Cidr, 2>,
+    /// Default IPv6 gateway. If `None`, the router learned through SLAAC is used, if any.
+    #[cfg(feature = "proto-ipv6")]
+    pub ipv6_gateway: Option<Ipv6Address>,
+    /// Lease details, if the configuration was obtained with DHCP.
+    pub dhcp: Option<DhcpLease>,
+}
+
+impl Config {
+    /// A configuration with the given address, and nothing else.
+    pub fn new(address: Ipv4Cidr) -> Self {
+        Self {
+            address,
+            gateway: None,
+            dns_servers: Vec::new(),
+            #[cfg(feature = "proto-ipv6")]
+            ipv6_addresses: Vec::new(),
+            #[cfg(feature = "proto-ipv6")]
+            ipv6_gateway: None,
+            dhcp: None,
+        }
+    }
+}
+
+/// A DHCP lease, as granted by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DhcpLease {
+    /// Address of the server that granted the lease.
+    pub server: Ipv4Address,
+    /// How long the lease lasts, counting from `acquired_at`.
+    pub duration: Duration,
+    /// When the lease was acquired. Renewals that don't change the configuration aren't
+    /// reported and keep this time, so the lease lasts at least until `acquired_at + duration`.
+    pub acquired_at: Instant,
+}
+
+/// What a [Configurator] can use when the stack is created, see [Configurator::init].
+pub struct ConfiguratorResources<'a> {
+    pub(crate) sockets: &'a mut SocketSet,
+    pub(crate) sockets_len: usize,
+    #[cfg(feature = "dhcpv4")]
+    pub(crate) dhcp: Option<&'static mut DhcpResources>,
+}
+
+impl<'a> ConfiguratorResources<'a> {
+    /// Add a socket to the stack, failing with `Error::Exhausted` if the socket set is full.
+    pub fn add_socket<T>(&mut self, socket: T) -> Result<SocketHandle>
+    where
+        T: Into<Socket<'static>>,
+    {
+        crate::stack::add_socket(self.sockets, self.sockets_len, socket)
+    }
 }
 
 pub trait Configurator {
+    /// Called once when the stack is created, before any other socket is created.
+    ///
+    /// Configurators that need sockets should add them here, so they're guaranteed a slot
+    /// in the socket set. An error makes [Stack::new](crate::Stack::new) fail.
+    fn init(&mut self, _resources: &mut ConfiguratorResources) -> Result<()> {
+        Ok(())
+    }
+
     fn poll(&mut self, iface: &mut Interface, sockets: &mut SocketSet, timestamp: Instant)
         -> Event;
+
+    /// When `poll` should be called next, if the configurator has a timeout pending.
+    fn poll_at(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Refresh the configuration as soon as possible, for example renew the DHCP lease.
+    ///
+    /// Does nothing by default.
+    fn renew(&mut self) {}
+
+    /// Give up the configuration, for example release the DHCP lease, and don't acquire a
+    /// new one until `renew` is called.
+    ///
+    /// Does nothing by default.
+    fn release(&mut self) {}
 }
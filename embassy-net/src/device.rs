@@ -1,12 +1,12 @@
+use core::cell::RefCell;
 use core::task::Waker;
+use embassy::time::Instant;
 use smoltcp::phy::Device as SmolDevice;
 use smoltcp::phy::DeviceCapabilities;
 use smoltcp::time::Instant as SmolInstant;
 
 use crate::fmt::*;
-use crate::packet_pool::PacketBoxExt;
-use crate::Result;
-use crate::{Packet, PacketBox, PacketBuf};
+use crate::{Error, Result};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum LinkState {
@@ -14,19 +14,65 @@ pub enum LinkState {
     Up,
 }
 
+/// A network device.
+///
+/// Packets are not copied into buffers owned by the stack. Instead, like smoltcp's
+/// `RxToken`/`TxToken`, the driver lends one of its own buffers (for example a DMA
+/// descriptor's) to a closure, for the duration of a `receive` or `transmit` call. The
+/// closures never call back into the device: a reply to a received packet is copied aside,
+/// and transmitted once `receive` has returned.
+///
+/// When no buffer is available, the driver returns false from `is_receive_ready` or
+/// `is_transmit_ready`, and wakes the waker from `register_waker` once there is one. The
+/// stack then retries, it never panics because buffers ran out.
 pub trait Device {
+    /// Whether a received packet is waiting to be passed to `receive`.
+    fn is_receive_ready(&mut self) -> bool;
+
+    /// Pass the oldest received packet to `f`, then free its buffer.
+    ///
+    /// Only called after `is_receive_ready` returned true. `f` must be called exactly once.
+    fn receive(&mut self, f: &mut dyn FnMut(&mut [u8]));
+
+    /// Whether a buffer is free for `transmit`.
     fn is_transmit_ready(&mut self) -> bool;
-    fn transmit(&mut self, pkt: PacketBuf);
-    fn receive(&mut self) -> Option<PacketBuf>;
+
+    /// Pass a buffer of `len` bytes to `f` to fill in, then transmit it if `f` succeeded.
+    ///
+    /// Only called after `is_transmit_ready` returned true. `f` must be called exactly once,
+    /// and its error returned if it fails. `len` never exceeds the MTU from `capabilities`.
+    fn transmit(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8]) -> Result<()>) -> Result<()>;
 
     fn register_waker(&mut self, waker: &Waker);
+
+    /// When the stack should check `is_receive_ready` again, for devices that have a packet
+    /// waiting for a deadline rather than an interrupt, such as a simulated link delivering
+    /// it after some latency. Returns `None` by default.
+    fn poll_at(&mut self) -> Option<Instant> {
+        None
+    }
+
     fn capabilities(&mut self) -> DeviceCapabilities;
     fn link_state(&mut self) -> LinkState;
     fn ethernet_address(&mut self) -> [u8; 6];
 }
 
+/// Largest reply [DeviceAdapter] can hold: a full Ethernet frame.
+const MAX_REPLY_LEN: usize = 1514;
+
+struct Reply {
+    buf: [u8; MAX_REPLY_LEN],
+    /// Length of the pending reply, 0 if there's none.
+    len: usize,
+}
+
 pub struct DeviceAdapter {
-    pub device: &'static mut dyn Device,
+    // smoltcp hands out an rx and a tx token at the same time, which both need the device.
+    device: RefCell<&'static mut dyn Device>,
+    // smoltcp sends replies (ARP replies, ICMP echo replies, TCP ACKs...) from within the
+    // closure processing the received packet, while the device is still lending us the
+    // packet's buffer. They're copied here, and transmitted once `receive` has returned.
+    reply: RefCell<Reply>,
     caps: DeviceCapabilities,
 }
 
@@ -34,38 +80,71 @@ impl DeviceAdapter {
     pub(crate) fn new(device: &'static mut dyn Device) -> Self {
         Self {
             caps: device.capabilities(),
-            device,
+            device: RefCell::new(device),
+            reply: RefCell::new(Reply {
+                buf: [0; MAX_REPLY_LEN],
+                len: 0,
+            }),
+        }
+    }
+
+    /// The wrapped device.
+    pub fn device(&mut self) -> &mut dyn Device {
+        &mut **self.device.get_mut()
+    }
+
+    /// Transmit the pending reply, if any. Returns false if there is one, but the device
+    /// has no room for it yet.
+    fn flush_reply(&self) -> bool {
+        let mut reply = self.reply.borrow_mut();
+        let reply = &mut *reply;
+        if reply.len == 0 {
+            return true;
         }
+
+        let mut device = self.device.borrow_mut();
+        if !device.is_transmit_ready() {
+            return false;
+        }
+        let data = &reply.buf[..reply.len];
+        reply.len = 0;
+        let res = device.transmit(data.len(), &mut |buf| {
+            buf.copy_from_slice(data);
+            Ok(())
+        });
+        if res.is_err() {
+            warn!("failed to transmit reply");
+        }
+        true
     }
 }
 
 impl<'a> SmolDevice<'a> for DeviceAdapter {
-    type RxToken = RxToken;
+    type RxToken = RxToken<'a>;
     type TxToken = TxToken<'a>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let rx_pkt = self.device.receive()?;
-        let tx_pkt = PacketBox::new(Packet::new()).unwrap(); // TODO: not sure about unwrap
-        let rx_token = RxToken { pkt: rx_pkt };
-        let tx_token = TxToken {
-            device: self.device,
-            pkt: tx_pkt,
-        };
+        // Receive even if the previous reply is still pending: waiting for room to transmit
+        // could deadlock with a peer doing the same. A reply to this packet then fails.
+        self.flush_reply();
+        if !self.device.get_mut().is_receive_ready() {
+            return None;
+        }
 
+        let adapter = &*self;
+        let rx_token = RxToken { adapter };
+        let tx_token = TxToken { adapter };
         Some((rx_token, tx_token))
     }
 
     /// Construct a transmit token.
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        if !self.device.is_transmit_ready() {
+        // The pending reply goes first, to keep packets in order.
+        if !self.flush_reply() || !self.device.get_mut().is_transmit_ready() {
             return None;
         }
 
-        let tx_pkt = PacketBox::new(Packet::new())?;
-        Some(TxToken {
-            device: self.device,
-            pkt: tx_pkt,
-        })
+        Some(TxToken { adapter: self })
     }
 
     /// Get a description of device capabilities.
@@ -74,22 +153,28 @@ impl<'a> SmolDevice<'a> for DeviceAdapter {
     }
 }
 
-pub struct RxToken {
-    pkt: PacketBuf,
+pub struct RxToken<'a> {
+    adapter: &'a DeviceAdapter,
 }
 
-impl smoltcp::phy::RxToken for RxToken {
-    fn consume<R, F>(mut self, _timestamp: SmolInstant, f: F) -> Result<R>
+impl<'a> smoltcp::phy::RxToken for RxToken<'a> {
+    fn consume<R, F>(self, _timestamp: SmolInstant, f: F) -> Result<R>
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        f(&mut self.pkt)
+        let mut f = Some(f);
+        let mut res = None;
+        self.adapter.device.borrow_mut().receive(&mut |buf| {
+            let f = unwrap!(f.take());
+            res = Some(f(buf));
+        });
+        self.adapter.flush_reply();
+        res.unwrap_or(Err(Error::Exhausted))
     }
 }
 
 pub struct TxToken<'a> {
-    device: &'a mut dyn Device,
-    pkt: PacketBox,
+    adapter: &'a DeviceAdapter,
 }
 
 impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
@@ -97,9 +182,137 @@ impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        let mut buf = self.pkt.slice(0..len);
-        let r = f(&mut buf)?;
-        self.device.transmit(buf);
-        Ok(r)
+        let mut device = match self.adapter.device.try_borrow_mut() {
+            Ok(device) => device,
+            // The device is busy receiving the packet we're replying to.
+            Err(_) => {
+                let mut reply = self.adapter.reply.borrow_mut();
+                if reply.len != 0 || len > MAX_REPLY_LEN {
+                    return Err(Error::Exhausted);
+                }
+                let res = f(&mut reply.buf[..len])?;
+                reply.len = len;
+                return Ok(res);
+            }
+        };
+        // Tokens handed out along with a received packet weren't checked for room yet.
+        if !device.is_transmit_ready() {
+            return Err(Error::Exhausted);
+        }
+
+        let mut f = Some(f);
+        let mut res = None;
+        device.transmit(len, &mut |buf| {
+            let f = unwrap!(f.take());
+            res = Some(f(buf)?);
+            Ok(())
+        })?;
+        res.ok_or(Error::Exhausted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use smoltcp::phy::{RxToken as _, TxToken as _};
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct State {
+        rx: Option<u8>,
+        tx: heapless::Vec<u8, 8>,
+        tx_ready: bool,
+    }
+
+    /// Device exchanging one-byte packets.
+    struct TestDevice(&'static RefCell<State>);
+
+    impl Device for TestDevice {
+        fn is_receive_ready(&mut self) -> bool {
+            self.0.borrow().rx.is_some()
+        }
+
+        fn receive(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+            let mut buf = [unwrap!(self.0.borrow_mut().rx.take())];
+            f(&mut buf)
+        }
+
+        fn is_transmit_ready(&mut self) -> bool {
+            self.0.borrow().tx_ready
+        }
+
+        fn transmit(
+            &mut self,
+            len: usize,
+            f: &mut dyn FnMut(&mut [u8]) -> Result<()>,
+        ) -> Result<()> {
+            let mut buf = [0; 1];
+            f(&mut buf[..len])?;
+            unwrap!(self.0.borrow_mut().tx.push(buf[0]));
+            Ok(())
+        }
+
+        fn register_waker(&mut self, _waker: &Waker) {}
+
+        fn capabilities(&mut self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+
+        fn link_state(&mut self) -> LinkState {
+            LinkState::Up
+        }
+
+        fn ethernet_address(&mut self) -> [u8; 6] {
+            [0; 6]
+        }
+    }
+
+    /// Receive `packet`, replying with `packet + 1` from within the rx closure, like
+    /// smoltcp does.
+    fn receive_and_reply(adapter: &mut DeviceAdapter, packet: u8) -> Result<()> {
+        let ts = SmolInstant::from_millis(0);
+        let (rx, tx) = unwrap!(adapter.receive());
+        rx.consume(ts, |buf| {
+            assert_eq!(buf, [packet]);
+            tx.consume(ts, 1, |buf| {
+                buf[0] = packet + 1;
+                Ok(())
+            })
+        })
+    }
+
+    #[test]
+    fn reply_from_rx() {
+        let state: &'static RefCell<State> = Box::leak(Box::new(RefCell::new(State {
+            tx_ready: true,
+            ..Default::default()
+        })));
+        let mut adapter = DeviceAdapter::new(Box::leak(Box::new(TestDevice(state))));
+
+        state.borrow_mut().rx = Some(1);
+        receive_and_reply(&mut adapter, 1).unwrap();
+        assert_eq!(state.borrow().tx, [2]);
+
+        // Without room, the reply waits, and goes before the next packet.
+        state.borrow_mut().rx = Some(5);
+        state.borrow_mut().tx_ready = false;
+        receive_and_reply(&mut adapter, 5).unwrap();
+        assert!(adapter.transmit().is_none());
+        assert_eq!(state.borrow().tx, [2]);
+
+        // Only one reply can wait.
+        state.borrow_mut().rx = Some(8);
+        assert_eq!(receive_and_reply(&mut adapter, 8), Err(Error::Exhausted));
+
+        state.borrow_mut().tx_ready = true;
+        let tx = unwrap!(adapter.transmit());
+        tx.consume(SmolInstant::from_millis(0), 1, |buf| {
+            buf[0] = 7;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(state.borrow().tx, [2, 6, 7]);
     }
 }
@@ -0,0 +1,483 @@
+//! DNS resolver.
+//!
+//! Queries the DNS servers obtained from the current [Config](crate::Config) over UDP.
+//! Only A records are supported. Answers are cached for their TTL in a small fixed-size
+//! cache, one per [Stack].
+
+use embassy::time::{with_timeout, Duration, Instant};
+use heapless::String;
+use smoltcp::socket::UdpPacketMetadata;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use crate::fmt::*;
+use crate::stack::rand;
+use crate::{Stack, UdpSocket};
+
+/// Maximum length of a name that can be resolved, excluding the trailing dot.
+pub const MAX_NAME_LEN: usize = 253;
+
+const CACHE_LEN: usize = 4;
+const DNS_PORT: u16 = 53;
+/// Maximum size of a DNS message over UDP, without EDNS.
+const MAX_PACKET_LEN: usize = 512;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 3;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000F;
+const RCODE_NAME_ERROR: u16 = 3;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The name is not a valid DNS name, or is too long.
+    InvalidName,
+    /// There are no DNS servers in the current configuration.
+    NoServers,
+    /// The name does not exist, or has no A records.
+    NotFound,
+    /// No server answered in time.
+    Timeout,
+    /// A server answered with an error.
+    ServerFailure,
+    /// The UDP socket returned an error.
+    Network(crate::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+struct CacheEntry {
+    name: String<MAX_NAME_LEN>,
+    addr: Ipv4Address,
+    expires_at: Instant,
+}
+
+pub(crate) struct Cache {
+    entries: heapless::Vec<CacheEntry, CACHE_LEN>,
+}
+
+impl Cache {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    fn get(&mut self, name: &str, now: Instant) -> Option<Ipv4Address> {
+        self.entries.retain(|e| e.expires_at > now);
+        self.entries
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+            .map(|e| e.addr)
+    }
+
+    fn insert(&mut self, name: &str, addr: Ipv4Address, expires_at: Instant) {
+        let mut entry_name = String::new();
+        // Can't fail, names are checked against MAX_NAME_LEN before querying.
+        unwrap!(entry_name.push_str(name));
+        let entry = CacheEntry {
+            name: entry_name,
+            addr,
+            expires_at,
+        };
+
+        if let Some(e) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+        {
+            *e = entry;
+            return;
+        }
+
+        if self.entries.is_full() {
+            // Evict the entry that expires first.
+            let (i, _) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, e)| e.expires_at)
+                .unwrap();
+            self.entries.swap_remove(i);
+        }
+
+        // Can't fail, we made room above.
+        let _ = self.entries.push(entry);
+    }
+}
+
+/// Resolve a host name to an IP address, using the DNS servers of the given stack.
+///
+/// If `name` is an IPv4 address literal, it is returned as-is without querying.
+///
+/// Each configured DNS server is tried in turn, with a few attempts each, until one
+/// answers. This needs a free slot in the socket set while the query is in progress, if
+/// there is none it fails with `Error::Network(crate::Error::Exhausted)`.
+pub async fn resolve(stack: &Stack, name: &str) -> Result<IpAddress> {
+    if let Ok(addr) = name.parse::<Ipv4Address>() {
+        return Ok(addr.into());
+    }
+
+    let name = name.strip_suffix('.').unwrap_or(name);
+    check_name(name)?;
+
+    if let Some(addr) = stack.with(|stack| stack.dns_cache.get(name, Instant::now())) {
+        return Ok(addr.into());
+    }
+
+    let servers = stack.with(|stack| match &stack.config {
+        Some(config) => config.dns_servers.clone(),
+        None => heapless::Vec::new(),
+    });
+    if servers.is_empty() {
+        return Err(Error::NoServers);
+    }
+
+    let mut rx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; MAX_PACKET_LEN];
+    let mut tx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; MAX_PACKET_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    )
+    .map_err(Error::Network)?;
+    socket.bind(0).map_err(Error::Network)?;
+
+    let mut buf = [0; MAX_PACKET_LEN];
+    let mut res = Err(Error::Timeout);
+
+    for &server in servers.iter() {
+        let server = IpEndpoint::new(server.into(), DNS_PORT);
+
+        for _ in 0..QUERY_ATTEMPTS {
+            let mut id = [0; 2];
+            rand(&mut id);
+            let id = u16::from_be_bytes(id);
+
+            let len = write_query(&mut buf, id, name);
+            socket
+                .send_to(&buf[..len], server)
+                .await
+                .map_err(Error::Network)?;
+
+            let deadline = Instant::now() + QUERY_TIMEOUT;
+            res = loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break Err(Error::Timeout);
+                }
+
+                let (len, from) =
+                    match with_timeout(deadline.duration_since(now), socket.recv_from(&mut buf))
+                        .await
+                    {
+                        Ok(Ok(x)) => x,
+                        // Too large to be an answer to our query.
+                        Ok(Err(smoltcp::Error::Truncated)) => continue,
+                        Ok(Err(e)) => return Err(Error::Network(e)),
+                        Err(_) => break Err(Error::Timeout),
+                    };
+
+                if from != server {
+                    continue;
+                }
+
+                match parse_response(&buf[..len], id, name) {
+                    // Not an answer to our query, keep waiting.
+                    None => continue,
+                    Some(r) => break r,
+                }
+            };
+
+            match res {
+                Ok((addr, ttl)) => {
+                    debug!("DNS: resolved {} to {}", name, addr);
+                    if ttl != 0 {
+                        let expires_at = Instant::now() + Duration::from_secs(ttl as u64);
+                        stack.with(|stack| stack.dns_cache.insert(name, addr, expires_at));
+                    }
+                    return Ok(addr.into());
+                }
+                // Definitive answer, don't bother the other servers.
+                Err(Error::NotFound) => return Err(Error::NotFound),
+                // Server is misbehaving, move on to the next one.
+                Err(Error::ServerFailure) => break,
+                Err(_) => {}
+            }
+        }
+    }
+
+    res.map(|(addr, _)| addr.into())
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidName);
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::InvalidName);
+        }
+    }
+    Ok(())
+}
+
+/// Write an A query for `name` into `buf`, returning its length.
+///
+/// `name` must have been checked with `check_name`. The longest possible query is well
+/// under `MAX_PACKET_LEN`.
+fn write_query(buf: &mut [u8], id: u16, name: &str) -> usize {
+    buf[0..2].copy_from_slice(&id.to_be_bytes());
+    buf[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    buf[4..6].copy_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    buf[6..12].fill(0); // ANCOUNT, NSCOUNT, ARCOUNT
+
+    let mut pos = 12;
+    for label in name.split('.') {
+        buf[pos] = label.len() as u8;
+        pos += 1;
+        buf[pos..pos + label.len()].copy_from_slice(label.as_bytes());
+        pos += label.len();
+    }
+    buf[pos] = 0;
+    pos += 1;
+
+    buf[pos..pos + 2].copy_from_slice(&TYPE_A.to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&CLASS_IN.to_be_bytes());
+    pos + 4
+}
+
+/// Parse a response to the A query for `name` with the given `id`.
+///
+/// Returns `None` if the packet is not a well-formed response to that query. Otherwise
+/// returns the first A record in the answer section, along with its TTL in seconds.
+fn parse_response(pkt: &[u8], id: u16, name: &str) -> Option<Result<(Ipv4Address, u32)>> {
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let b = pkt.get(pos..pos + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    };
+
+    if read_u16(0)? != id {
+        return None;
+    }
+    let flags = read_u16(2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+
+    // The question is echoed, check it's ours, so that a spoofed answer also has to
+    // guess the name. This includes errors, or a spoofed one could fail the query.
+    if read_u16(4)? != 1 {
+        return None;
+    }
+    let ancount = read_u16(6)?;
+    let mut pos = match_name(pkt, 12, name)?;
+    if read_u16(pos)? != TYPE_A || read_u16(pos + 2)? != CLASS_IN {
+        return None;
+    }
+    pos += 4;
+
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NAME_ERROR => return Some(Err(Error::NotFound)),
+        _ => return Some(Err(Error::ServerFailure)),
+    }
+
+    for _ in 0..ancount {
+        pos = skip_name(pkt, pos)?;
+        let rtype = read_u16(pos)?;
+        let rclass = read_u16(pos + 2)?;
+        let ttl = pkt.get(pos + 4..pos + 8)?;
+        let ttl = u32::from_be_bytes([ttl[0], ttl[1], ttl[2], ttl[3]]);
+        let rdlen = read_u16(pos + 8)? as usize;
+        pos += 10;
+        let rdata = pkt.get(pos..pos + rdlen)?;
+        pos += rdlen;
+
+        // CNAMEs are followed by the records of the name they point to, so just skip them.
+        if rtype == TYPE_A && rclass == CLASS_IN && rdlen == 4 {
+            return Some(Ok((Ipv4Address::from_bytes(rdata), ttl)));
+        }
+    }
+
+    Some(Err(Error::NotFound))
+}
+
+/// Check that the uncompressed name starting at `pos` is `name`, ignoring case, and return
+/// the position after it.
+fn match_name(pkt: &[u8], mut pos: usize, name: &str) -> Option<usize> {
+    for label in name.split('.') {
+        let len = *pkt.get(pos)? as usize;
+        let pkt_label = pkt.get(pos + 1..pos + 1 + len)?;
+        if !pkt_label.eq_ignore_ascii_case(label.as_bytes()) {
+            return None;
+        }
+        pos += 1 + len;
+    }
+    match pkt.get(pos)? {
+        0 => Some(pos + 1),
+        _ => None,
+    }
+}
+
+/// Skip over a possibly compressed name starting at `pos`, returning the position after it.
+fn skip_name(pkt: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *pkt.get(pos)?;
+        if len & 0xC0 == 0xC0 {
+            // Compression pointer, always ends the name.
+            return Some(pos + 2);
+        }
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        pos += 1 + len as usize;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+    const NAME: &str = "www.example.com";
+
+    /// A response to the query for `NAME`, with the given answers appended.
+    fn response(flags: u16, answers: &[&[u8]]) -> heapless::Vec<u8, MAX_PACKET_LEN> {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = write_query(&mut buf, ID, NAME);
+        let mut pkt = heapless::Vec::new();
+        pkt.extend_from_slice(&buf[..len]).unwrap();
+        pkt[2..4].copy_from_slice(&(FLAG_RESPONSE | flags).to_be_bytes());
+        pkt[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for answer in answers {
+            pkt.extend_from_slice(answer).unwrap();
+        }
+        pkt
+    }
+
+    /// A record for the name at offset 12, which is the question's.
+    const ANSWER_A: &[u8] = &[
+        0xC0, 12, // name: pointer to the question
+        0, 1, 0, 1, // A, IN
+        0, 0, 0x0E, 0x10, // TTL: 3600
+        0, 4, 93, 184, 216, 34, // 93.184.216.34
+    ];
+
+    #[test]
+    fn query() {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = write_query(&mut buf, ID, NAME);
+        assert_eq!(
+            &buf[..len],
+            &[
+                0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, // header
+                3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o',
+                b'm', 0, // name
+                0, 1, 0, 1, // A, IN
+            ][..]
+        );
+    }
+
+    #[test]
+    fn a_record() {
+        let pkt = response(0, &[ANSWER_A]);
+        assert_eq!(
+            parse_response(&pkt, ID, NAME),
+            Some(Ok((Ipv4Address::new(93, 184, 216, 34), 3600)))
+        );
+        // Names are case-insensitive.
+        assert!(matches!(
+            parse_response(&pkt, ID, "WWW.Example.COM"),
+            Some(Ok(_))
+        ));
+    }
+
+    #[test]
+    fn cname_is_skipped() {
+        let cname: &[u8] = &[
+            0xC0, 12, // name: pointer to the question
+            0, 5, 0, 1, // CNAME, IN
+            0, 0, 0, 60, // TTL
+            0, 6, 3, b'c', b'd', b'n', 0xC0, 16, // cdn.example.com, compressed
+        ];
+        let a: &[u8] = &[
+            3, b'c', b'd', b'n', 0xC0, 16, // cdn.example.com, compressed
+            0, 1, 0, 1, // A, IN
+            0, 0, 0, 30, // TTL
+            0, 4, 10, 0, 0, 1,
+        ];
+        let pkt = response(0, &[cname, a]);
+        assert_eq!(
+            parse_response(&pkt, ID, NAME),
+            Some(Ok((Ipv4Address::new(10, 0, 0, 1), 30)))
+        );
+    }
+
+    #[test]
+    fn not_our_query() {
+        let pkt = response(0, &[ANSWER_A]);
+        assert_eq!(parse_response(&pkt, ID + 1, NAME), None);
+        assert_eq!(parse_response(&pkt, ID, "www.example.org"), None);
+        assert_eq!(parse_response(&pkt, ID, "example.com"), None);
+        assert_eq!(parse_response(&pkt, ID, "www.example.com.au"), None);
+
+        let mut aaaa = pkt.clone();
+        let qtype = aaaa.len() - ANSWER_A.len() - 4;
+        aaaa[qtype..qtype + 2].copy_from_slice(&28u16.to_be_bytes());
+        assert_eq!(parse_response(&aaaa, ID, NAME), None);
+
+        let mut query = pkt;
+        query[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+        assert_eq!(parse_response(&query, ID, NAME), None);
+    }
+
+    #[test]
+    fn errors() {
+        let pkt = response(RCODE_NAME_ERROR, &[]);
+        assert_eq!(parse_response(&pkt, ID, NAME), Some(Err(Error::NotFound)));
+        let pkt = response(2, &[]);
+        assert_eq!(
+            parse_response(&pkt, ID, NAME),
+            Some(Err(Error::ServerFailure))
+        );
+        // No A record in the answers.
+        let pkt = response(0, &[]);
+        assert_eq!(parse_response(&pkt, ID, NAME), Some(Err(Error::NotFound)));
+
+        // Errors must be about our question too.
+        let pkt = response(RCODE_NAME_ERROR, &[]);
+        assert_eq!(parse_response(&pkt, ID, "www.example.org"), None);
+        let mut no_question = pkt;
+        no_question[4..6].copy_from_slice(&0u16.to_be_bytes());
+        no_question.truncate(12);
+        assert_eq!(parse_response(&no_question, ID, NAME), None);
+    }
+
+    #[test]
+    fn truncated() {
+        let pkt = response(0, &[ANSWER_A]);
+        for len in 0..pkt.len() {
+            assert_eq!(parse_response(&pkt[..len], ID, NAME), None, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn skip_names() {
+        let pkt = [3, b'f', b'o', b'o', 0, 0xC0, 0, 1, b'a', 0xC0, 0];
+        assert_eq!(skip_name(&pkt, 0), Some(5));
+        assert_eq!(skip_name(&pkt, 5), Some(7));
+        // Labels followed by a pointer.
+        assert_eq!(skip_name(&pkt, 7), Some(11));
+        // Ends before the terminating label.
+        assert_eq!(skip_name(&pkt[..4], 0), None);
+        assert_eq!(skip_name(&pkt, 11), None);
+    }
+}
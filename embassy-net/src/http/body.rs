@@ -0,0 +1,276 @@
+use core::cmp::min;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{format_number, Headers, Result};
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// This many bytes are left.
+    Length(usize),
+    /// The body ends when the connection is closed.
+    UntilClose,
+    /// Reading the size of a chunk, with the digits so far, `None` before the first.
+    ChunkSize(Option<usize>),
+    /// Skipping the extensions after the size of a chunk.
+    ChunkExtension(usize),
+    /// This many bytes of the current chunk are left.
+    ChunkData(usize),
+    /// Reading the line break after the data of a chunk.
+    ChunkEnd {
+        cr: bool,
+    },
+    /// Skipping the trailer after the last chunk, until an empty line.
+    Trailer {
+        line_start: bool,
+    },
+    Done,
+}
+
+impl State {
+    /// Process the framing of a chunked body in `data`, until reaching chunk data or the
+    /// end. Returns the number of bytes processed.
+    fn parse_chunked(&mut self, data: &[u8]) -> io::Result<usize> {
+        for (i, &b) in data.iter().enumerate() {
+            *self = match (*self, b) {
+                (State::ChunkSize(size), _) if b.is_ascii_hexdigit() => {
+                    let digit = (b as char).to_digit(16).unwrap() as usize;
+                    let size = size
+                        .unwrap_or(0)
+                        .checked_mul(16)
+                        .and_then(|s| s.checked_add(digit))
+                        .ok_or(io::Error::InvalidData)?;
+                    State::ChunkSize(Some(size))
+                }
+                (State::ChunkSize(Some(size)), b'\n') | (State::ChunkExtension(size), b'\n') => {
+                    match size {
+                        0 => State::Trailer { line_start: true },
+                        size => State::ChunkData(size),
+                    }
+                }
+                (State::ChunkSize(Some(size)), b';')
+                | (State::ChunkSize(Some(size)), b' ')
+                | (State::ChunkSize(Some(size)), b'\t')
+                | (State::ChunkSize(Some(size)), b'\r')
+                | (State::ChunkExtension(size), _) => State::ChunkExtension(size),
+                (State::ChunkEnd { cr: false }, b'\r') => State::ChunkEnd { cr: true },
+                (State::ChunkEnd { .. }, b'\n') => State::ChunkSize(None),
+                (State::Trailer { line_start: true }, b'\n') => State::Done,
+                (State::Trailer { line_start }, b'\r') => State::Trailer { line_start },
+                (State::Trailer { .. }, b'\n') => State::Trailer { line_start: true },
+                (State::Trailer { .. }, _) => State::Trailer { line_start: false },
+                _ => return Err(io::Error::InvalidData),
+            };
+            if let State::ChunkData(_) | State::Done = self {
+                return Ok(i + 1);
+            }
+        }
+        Ok(data.len())
+    }
+}
+
+/// Body of a received request or response.
+///
+/// Reads from the transport until the end of the body, which then looks like the end of
+/// the stream. For the next message to be read on the same connection, the body must have
+/// been read to the end, for example with [discard](Body::discard).
+pub struct Body<'c, C> {
+    conn: &'c mut C,
+    state: State,
+}
+
+impl<'c, C: AsyncBufRead + Unpin> Body<'c, C> {
+    /// A body framed with chunked encoding, or `Content-Length`. If there's neither, the
+    /// body is empty for a request, and ends with the connection for a response.
+    pub(crate) fn new(conn: &'c mut C, headers: &Headers<'_>, is_request: bool) -> Result<Self> {
+        let state = if headers.is_chunked() {
+            State::ChunkSize(None)
+        } else {
+            match headers.content_length()? {
+                Some(len) => State::Length(len),
+                None if is_request => State::Length(0),
+                None => State::UntilClose,
+            }
+        };
+        Ok(Self { conn, state })
+    }
+
+    pub(crate) fn empty(conn: &'c mut C) -> Self {
+        Self {
+            conn,
+            state: State::Done,
+        }
+    }
+
+    /// Read and drop the rest of the body.
+    pub async fn discard(&mut self) -> Result<()> {
+        loop {
+            let n = self.read_buf().await?.len();
+            if n == 0 {
+                return Ok(());
+            }
+            Pin::new(&mut *self).consume(n);
+        }
+    }
+}
+
+impl<'c, C: AsyncBufRead + Unpin> AsyncBufRead for Body<'c, C> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        while let State::ChunkSize(_)
+        | State::ChunkExtension(_)
+        | State::ChunkEnd { .. }
+        | State::Trailer { .. } = this.state
+        {
+            let data = match Pin::new(&mut *this.conn).poll_fill_buf(cx) {
+                Poll::Ready(Ok(data)) => data,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if data.is_empty() {
+                return Poll::Ready(Err(io::Error::UnexpectedEof));
+            }
+            let n = this.state.parse_chunked(data)?;
+            Pin::new(&mut *this.conn).consume(n);
+        }
+
+        let left = match this.state {
+            State::Length(0) | State::Done => return Poll::Ready(Ok(&[])),
+            State::Length(n) | State::ChunkData(n) => Some(n),
+            _ => None,
+        };
+        let data = match Pin::new(&mut *this.conn).poll_fill_buf(cx) {
+            Poll::Ready(Ok(data)) => data,
+            r => return r,
+        };
+        match left {
+            Some(_) if data.is_empty() => Poll::Ready(Err(io::Error::UnexpectedEof)),
+            Some(n) => Poll::Ready(Ok(&data[..min(n, data.len())])),
+            None => Poll::Ready(Ok(data)),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.state = match this.state {
+            State::Length(n) => State::Length(n - amt),
+            State::ChunkData(n) if n == amt => State::ChunkEnd { cr: false },
+            State::ChunkData(n) => State::ChunkData(n - amt),
+            state => state,
+        };
+        Pin::new(&mut *this.conn).consume(amt)
+    }
+}
+
+/// Body sent with chunked encoding, so its length doesn't have to be known in advance.
+///
+/// [finish](ChunkedBody::finish) must be called to end it, otherwise the connection can't
+/// be used for another message.
+pub struct ChunkedBody<'c, C> {
+    conn: &'c mut C,
+}
+
+impl<'c, C: AsyncWrite + Unpin> ChunkedBody<'c, C> {
+    pub(crate) fn new(conn: &'c mut C) -> Self {
+        Self { conn }
+    }
+
+    /// Send `data` as one chunk.
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        // An empty chunk would end the body.
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut buf = [0; 20];
+        self.conn
+            .write_all(format_number(data.len(), 16, &mut buf))
+            .await?;
+        self.conn.write_all(b"\r\n").await?;
+        self.conn.write_all(data).await?;
+        self.conn.write_all(b"\r\n").await?;
+        Ok(())
+    }
+
+    /// Send the last chunk, ending the body.
+    pub async fn finish(self) -> Result<()> {
+        self.conn.write_all(b"0\r\n\r\n").await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use heapless::Vec;
+
+    /// Decode a chunked body fed in pieces of `piece_len` bytes, like reads of the transport.
+    fn decode(mut encoded: &[u8], piece_len: usize) -> io::Result<Vec<u8, 64>> {
+        let mut state = State::ChunkSize(None);
+        let mut body = Vec::new();
+        while !encoded.is_empty() {
+            let piece = &encoded[..min(piece_len, encoded.len())];
+            let n = match state {
+                State::ChunkData(left) => {
+                    let n = min(left, piece.len());
+                    body.extend_from_slice(&piece[..n]).unwrap();
+                    state = match left - n {
+                        0 => State::ChunkEnd { cr: false },
+                        left => State::ChunkData(left),
+                    };
+                    n
+                }
+                State::Done => break,
+                _ => state.parse_chunked(piece)?,
+            };
+            encoded = &encoded[n..];
+        }
+        match state {
+            State::Done if encoded.is_empty() => Ok(body),
+            _ => Err(io::Error::UnexpectedEof),
+        }
+    }
+
+    #[test]
+    fn chunked() {
+        let encoded = b"5\r\nhello\r\n7;name=value\r\n, world\r\nA \r\n0123456789\r\n0\r\n\r\n";
+        for piece_len in 1..=encoded.len() {
+            let body = decode(encoded, piece_len).unwrap();
+            assert_eq!(&body[..], b"hello, world0123456789");
+        }
+    }
+
+    #[test]
+    fn chunked_trailer() {
+        let encoded = b"3\nabc\n0\nChecksum: 1234\r\nOther: x\r\n\r\n";
+        for piece_len in 1..=encoded.len() {
+            assert_eq!(&decode(encoded, piece_len).unwrap()[..], b"abc");
+        }
+    }
+
+    #[test]
+    fn chunked_invalid() {
+        let cases: [&[u8]; 6] = [
+            b"x\r\n",
+            // No size.
+            b"\r\n\r\n",
+            b"3\r\nabcX\r\n0\r\n\r\n",
+            b"3\r\nabc\r\r\n0\r\n\r\n",
+            // Overflows the size.
+            b"10000000000000000\r\n",
+            // Truncated.
+            b"3\r\nabc\r\n0\r\n",
+        ];
+        for &encoded in cases.iter() {
+            assert!(decode(encoded, encoded.len()).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_chunked_stops_at_data() {
+        let mut state = State::ChunkSize(None);
+        assert_eq!(state.parse_chunked(b"1f\r\nrest"), Ok(4));
+        assert!(matches!(state, State::ChunkData(0x1f)));
+    }
+}
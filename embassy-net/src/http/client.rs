@@ -0,0 +1,80 @@
+//! Sending requests.
+
+use embassy::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt};
+
+use super::{format_number, read_head, write_headers, Body, Header, Method, Response, Result};
+use crate::fmt::*;
+
+/// A request to send.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientRequest<'a> {
+    pub method: Method,
+    /// Sent in the `Host` header.
+    pub host: &'a str,
+    /// Path and query of the target.
+    pub path: &'a str,
+    /// Headers other than `Host` and `Content-Length`, which are added.
+    pub headers: &'a [Header<'a>],
+    pub body: &'a [u8],
+}
+
+/// Send a request, and read the head of the response into `buf`.
+///
+/// The body of the response must be read to the end before sending another request on
+/// the same connection, if the server keeps it open.
+pub async fn request<'b, 'c, C>(
+    conn: &'c mut C,
+    req: &ClientRequest<'_>,
+    buf: &'b mut [u8],
+) -> Result<(Response<'b>, Body<'c, C>)>
+where
+    C: AsyncBufRead + AsyncWrite + Unpin,
+{
+    conn.write_all(req.method.as_str().as_bytes()).await?;
+    conn.write_all(b" ").await?;
+    conn.write_all(req.path.as_bytes()).await?;
+    conn.write_all(b" HTTP/1.1\r\nHost: ").await?;
+    conn.write_all(req.host.as_bytes()).await?;
+    conn.write_all(b"\r\n").await?;
+    write_headers(conn, req.headers).await?;
+    let has_body = match req.method {
+        Method::Post | Method::Put | Method::Patch => true,
+        _ => !req.body.is_empty(),
+    };
+    if has_body {
+        let mut len = [0; 20];
+        conn.write_all(b"Content-Length: ").await?;
+        conn.write_all(format_number(req.body.len(), 10, &mut len))
+            .await?;
+        conn.write_all(b"\r\n").await?;
+    }
+    conn.write_all(b"\r\n").await?;
+    conn.write_all(req.body).await?;
+
+    let response = read_response(conn, buf).await?;
+
+    // Responses to HEAD, and these statuses, never have a body whatever their headers say.
+    let body = match response.status {
+        204 | 304 => Body::empty(conn),
+        _ if req.method == Method::Head => Body::empty(conn),
+        _ => Body::new(conn, &response.headers, false)?,
+    };
+    Ok((response, body))
+}
+
+/// Read the head of the final response, skipping the informational ones.
+async fn read_response<'b, C>(conn: &mut C, buf: &'b mut [u8]) -> Result<Response<'b>>
+where
+    C: AsyncBufRead + Unpin,
+{
+    let len = loop {
+        let len = read_head(conn, buf).await?;
+        let status = Response::parse(&buf[..len])?.status;
+        if !(100..200).contains(&status) {
+            break len;
+        }
+        debug!("skipping informational response {}", status);
+    };
+    let buf: &'b [u8] = buf;
+    Response::parse(&buf[..len])
+}
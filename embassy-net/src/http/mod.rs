@@ -0,0 +1,477 @@
+//! HTTP/1.1 client and server.
+//!
+//! This is a codec on top of any `AsyncBufRead + AsyncWrite` transport, typically a
+//! [TcpSocket](crate::TcpSocket) or a [TlsConnection](crate::tls::TlsConnection). Nothing
+//! is allocated: the head of a request or response is read into a buffer supplied by the
+//! caller, and the method, path and headers borrow from it. The body is then streamed from
+//! the transport through [Body], which handles `Content-Length` and chunked bodies.
+//!
+//! A device-side server reads a request with [read_request](server::read_request), looks up
+//! the endpoint with a [Router](server::Router), and answers with
+//! [write_response](server::write_response):
+//!
+//! ```ignore
+//! #[derive(Clone, Copy)]
+//! enum Endpoint { Status, Led }
+//!
+//! static ROUTER: Router<'static, Endpoint> = Router::new(&[
+//!     Route { method: Method::Get, path: "/status", endpoint: Endpoint::Status },
+//!     Route { method: Method::Put, path: "/led", endpoint: Endpoint::Led },
+//! ]);
+//!
+//! loop {
+//!     let (req, mut body) = read_request(&mut socket, &mut buf).await?;
+//!     match ROUTER.route(&req) {
+//!         Ok(Endpoint::Status) => {
+//!             body.discard().await?;
+//!             write_response(&mut socket, 200, &[], b"ok").await?;
+//!         }
+//!         ...
+//!         Err(status) => {
+//!             body.discard().await?;
+//!             write_response(&mut socket, status, &[], b"").await?;
+//!         }
+//!     }
+//!     if !req.keep_alive {
+//!         break;
+//!     }
+//! }
+//! ```
+//!
+//! Only one request is in flight on a connection at a time, pipelined requests are read
+//! once the previous one is answered.
+
+use core::pin::Pin;
+use core::str;
+use embassy::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use heapless::Vec;
+
+mod body;
+pub mod client;
+pub mod server;
+
+pub use self::body::{Body, ChunkedBody};
+
+/// Maximum number of headers in a request or response.
+pub const MAX_HEADERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Reading from or writing to the transport failed.
+    Io(io::Error),
+    /// The transport was closed before a new request or response started.
+    Closed,
+    /// The head of the request or response doesn't fit in the buffer.
+    HeadTooLarge,
+    /// The request or response has more than [MAX_HEADERS] headers.
+    TooManyHeaders,
+    /// The request or response is malformed, or uses an unsupported method or version.
+    Parse,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => return Err(Error::Parse),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// Headers of a received request or response.
+#[derive(Debug, Default)]
+pub struct Headers<'a>(Vec<Header<'a>, MAX_HEADERS>);
+
+impl<'a> Headers<'a> {
+    /// Value of the first header with this name, which is case-insensitive.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, Header<'a>> {
+        self.0.iter()
+    }
+
+    fn parse(lines: str::Split<'a, char>) -> Result<Self> {
+        let mut headers = Vec::new();
+        for line in lines {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let colon = line.find(':').ok_or(Error::Parse)?;
+            let name = &line[..colon];
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return Err(Error::Parse);
+            }
+            let value = line[colon + 1..].trim_matches(|c| c == ' ' || c == '\t');
+            headers
+                .push(Header { name, value })
+                .map_err(|_| Error::TooManyHeaders)?;
+        }
+        Ok(Self(headers))
+    }
+
+    fn is_chunked(&self) -> bool {
+        // Only the last transfer coding tells how the body ends.
+        self.get("transfer-encoding")
+            .and_then(|v| v.rsplit(',').next())
+            .map_or(false, |v| v.trim().eq_ignore_ascii_case("chunked"))
+    }
+
+    fn content_length(&self) -> Result<Option<usize>> {
+        match self.get("content-length") {
+            Some(v) => v.parse().map(Some).map_err(|_| Error::Parse),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the connection stays open after this message, given its version.
+    fn keep_alive(&self, minor_version: u8) -> bool {
+        match self.get("connection") {
+            Some(v) if v.eq_ignore_ascii_case("close") => false,
+            Some(v) if v.eq_ignore_ascii_case("keep-alive") => true,
+            _ => minor_version >= 1,
+        }
+    }
+}
+
+/// Head of a request.
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// Path of the target, without the query.
+    pub path: &'a str,
+    /// Query of the target, without the `?`.
+    pub query: Option<&'a str>,
+    pub headers: Headers<'a>,
+    /// Whether the client wants to send more requests on the connection.
+    pub keep_alive: bool,
+}
+
+impl<'a> Request<'a> {
+    fn parse(head: &'a [u8]) -> Result<Self> {
+        let head = str::from_utf8(head).map_err(|_| Error::Parse)?;
+        let mut lines = head.split('\n');
+        let mut parts = lines
+            .next()
+            .ok_or(Error::Parse)?
+            .trim_end_matches('\r')
+            .split(' ');
+        let method = Method::parse(parts.next().ok_or(Error::Parse)?)?;
+        let target = parts.next().ok_or(Error::Parse)?;
+        let minor_version = parse_version(parts.next().ok_or(Error::Parse)?)?;
+        if parts.next().is_some() || target.is_empty() {
+            return Err(Error::Parse);
+        }
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], Some(&target[i + 1..])),
+            None => (target, None),
+        };
+        let headers = Headers::parse(lines)?;
+
+        Ok(Self {
+            method,
+            path,
+            query,
+            keep_alive: headers.keep_alive(minor_version),
+            headers,
+        })
+    }
+}
+
+/// Head of a response.
+#[derive(Debug)]
+pub struct Response<'a> {
+    pub status: u16,
+    pub reason: &'a str,
+    pub headers: Headers<'a>,
+    /// Whether the server keeps the connection open for more requests.
+    pub keep_alive: bool,
+}
+
+impl<'a> Response<'a> {
+    fn parse(head: &'a [u8]) -> Result<Self> {
+        let head = str::from_utf8(head).map_err(|_| Error::Parse)?;
+        let mut lines = head.split('\n');
+        let mut parts = lines
+            .next()
+            .ok_or(Error::Parse)?
+            .trim_end_matches('\r')
+            .splitn(3, ' ');
+        let minor_version = parse_version(parts.next().ok_or(Error::Parse)?)?;
+        let status = parts.next().ok_or(Error::Parse)?;
+        if status.len() != 3 {
+            return Err(Error::Parse);
+        }
+        let status = status.parse().map_err(|_| Error::Parse)?;
+        let reason = parts.next().unwrap_or("");
+        let headers = Headers::parse(lines)?;
+
+        Ok(Self {
+            status,
+            reason,
+            keep_alive: headers.keep_alive(minor_version),
+            headers,
+        })
+    }
+}
+
+/// Parse `HTTP/1.x`, returning `x`.
+fn parse_version(s: &str) -> Result<u8> {
+    match s.as_bytes() {
+        [b'H', b'T', b'T', b'P', b'/', b'1', b'.', v @ b'0'..=b'9'] => Ok(v - b'0'),
+        _ => Err(Error::Parse),
+    }
+}
+
+/// Read the head of a request or response into `buf`, up to and including the empty line
+/// ending it, and return its length. The body is left unread in `conn`.
+async fn read_head<C>(conn: &mut C, buf: &mut [u8]) -> Result<usize>
+where
+    C: AsyncBufRead + Unpin,
+{
+    let mut len = 0;
+    loop {
+        let data = conn.read_buf().await?;
+        if data.is_empty() {
+            return Err(match len {
+                0 => Error::Closed,
+                _ => Error::Io(io::Error::UnexpectedEof),
+            });
+        }
+
+        let mut n = 0;
+        let mut done = false;
+        for &b in data {
+            n += 1;
+            // Empty lines before the start line are ignored, some clients send one
+            // after the body of a request.
+            if len == 0 && (b == b'\r' || b == b'\n') {
+                continue;
+            }
+            if len == buf.len() {
+                return Err(Error::HeadTooLarge);
+            }
+            buf[len] = b;
+            len += 1;
+            if buf[..len].ends_with(b"\n\r\n") || buf[..len].ends_with(b"\n\n") {
+                done = true;
+                break;
+            }
+        }
+        Pin::new(&mut *conn).consume(n);
+
+        if done {
+            return Ok(len);
+        }
+    }
+}
+
+async fn write_headers<C>(conn: &mut C, headers: &[Header<'_>]) -> Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    for h in headers {
+        conn.write_all(h.name.as_bytes()).await?;
+        conn.write_all(b": ").await?;
+        conn.write_all(h.value.as_bytes()).await?;
+        conn.write_all(b"\r\n").await?;
+    }
+    Ok(())
+}
+
+/// Format `val` in decimal or hexadecimal, into the end of `buf`.
+fn format_number(mut val: usize, radix: usize, buf: &mut [u8; 20]) -> &[u8] {
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b"0123456789abcdef"[val % radix];
+        val /= radix;
+        if val == 0 {
+            return &buf[pos..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request() {
+        let head = b"GET /status?verbose=1 HTTP/1.1\r\nHost: device\r\nX-Empty:\r\nAccept:  text/plain \r\n\r\n";
+        let req = Request::parse(head).unwrap();
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.path, "/status");
+        assert_eq!(req.query, Some("verbose=1"));
+        assert_eq!(req.headers.get("host"), Some("device"));
+        assert_eq!(req.headers.get("ACCEPT"), Some("text/plain"));
+        assert_eq!(req.headers.get("x-empty"), Some(""));
+        assert_eq!(req.headers.get("connection"), None);
+        assert_eq!(req.headers.iter().count(), 3);
+        assert!(req.keep_alive);
+
+        // Bare line feeds are accepted.
+        let req = Request::parse(b"HEAD / HTTP/1.0\nConnection: keep-alive\n\n").unwrap();
+        assert_eq!(req.method, Method::Head);
+        assert_eq!(req.path, "/");
+        assert_eq!(req.query, None);
+        assert!(req.keep_alive);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let cases: [(&[u8], bool); 4] = [
+            (b"GET / HTTP/1.1\r\n\r\n", true),
+            (b"GET / HTTP/1.1\r\nConnection: Close\r\n\r\n", false),
+            (b"GET / HTTP/1.0\r\n\r\n", false),
+            (b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+        ];
+        for &(head, keep_alive) in cases.iter() {
+            assert_eq!(Request::parse(head).unwrap().keep_alive, keep_alive);
+        }
+    }
+
+    #[test]
+    fn bad_request() {
+        let cases: [&[u8]; 9] = [
+            b"FETCH / HTTP/1.1\r\n\r\n",
+            b"GET /\r\n\r\n",
+            b"GET / HTTP/2.0\r\n\r\n",
+            b"GET  HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET / HTTP/1.1\r\nNo colon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nBad name: x\r\n\r\n",
+            b"GET / HTTP/1.1\r\n: x\r\n\r\n",
+            b"GET /\xFF HTTP/1.1\r\n\r\n",
+        ];
+        for &head in cases.iter() {
+            assert_eq!(Request::parse(head).unwrap_err(), Error::Parse);
+        }
+    }
+
+    #[test]
+    fn too_many_headers() {
+        let mut head: Vec<u8, 512> = Vec::new();
+        head.extend_from_slice(b"GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..MAX_HEADERS {
+            head.extend_from_slice(b"A: b\r\n").unwrap();
+        }
+        head.extend_from_slice(b"\r\n").unwrap();
+        assert!(Request::parse(&head).is_ok());
+
+        head.truncate(head.len() - 2);
+        head.extend_from_slice(b"A: b\r\n\r\n").unwrap();
+        assert_eq!(Request::parse(&head).unwrap_err(), Error::TooManyHeaders);
+    }
+
+    #[test]
+    fn response() {
+        let head = b"HTTP/1.1 404 Not Found\r\nContent-Length: 12\r\n\r\n";
+        let resp = Response::parse(head).unwrap();
+        assert_eq!(resp.status, 404);
+        assert_eq!(resp.reason, "Not Found");
+        assert_eq!(resp.headers.content_length(), Ok(Some(12)));
+        assert!(!resp.headers.is_chunked());
+        assert!(resp.keep_alive);
+
+        let resp = Response::parse(b"HTTP/1.0 200\r\n\r\n").unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.reason, "");
+        assert!(!resp.keep_alive);
+
+        assert_eq!(
+            Response::parse(b"HTTP/1.1 20 OK\r\n\r\n").unwrap_err(),
+            Error::Parse
+        );
+        assert_eq!(
+            Response::parse(b"HTTP/1.1 abc OK\r\n\r\n").unwrap_err(),
+            Error::Parse
+        );
+        let resp = Response::parse(b"HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\n").unwrap();
+        assert_eq!(resp.headers.content_length(), Err(Error::Parse));
+    }
+
+    #[test]
+    fn transfer_encoding() {
+        let cases: [(&[u8], bool); 4] = [
+            (
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+                true,
+            ),
+            (
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n",
+                true,
+            ),
+            (
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+                false,
+            ),
+            (b"HTTP/1.1 200 OK\r\n\r\n", false),
+        ];
+        for &(head, chunked) in cases.iter() {
+            assert_eq!(Response::parse(head).unwrap().headers.is_chunked(), chunked);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let mut buf = [0; 20];
+        assert_eq!(format_number(0, 10, &mut buf), b"0");
+        assert_eq!(format_number(1234, 10, &mut buf), b"1234");
+        assert_eq!(format_number(0xbeef, 16, &mut buf), b"beef");
+        assert_eq!(
+            format_number(usize::MAX, 10, &mut buf),
+            b"18446744073709551615"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Serving requests.
+
+use embassy::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt};
+
+use super::{
+    format_number, read_head, write_headers, Body, ChunkedBody, Header, Method, Request, Result,
+};
+
+/// Read the head of the next request on the connection into `buf`.
+///
+/// Returns [Error::Closed](super::Error::Closed) if the client closed the connection
+/// instead of sending another request. The body must be read to the end before reading the
+/// next request.
+pub async fn read_request<'b, 'c, C>(
+    conn: &'c mut C,
+    buf: &'b mut [u8],
+) -> Result<(Request<'b>, Body<'c, C>)>
+where
+    C: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let len = read_head(conn, buf).await?;
+    let buf: &'b [u8] = buf;
+    let request = Request::parse(&buf[..len])?;
+
+    // The client waits for this before sending the body, or for a final response if the
+    // request is rejected right away. Accepting it is simpler than tracking whether the
+    // body was read.
+    if let Some(expect) = request.headers.get("expect") {
+        if expect.eq_ignore_ascii_case("100-continue") {
+            conn.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        }
+    }
+
+    let body = Body::new(conn, &request.headers, true)?;
+    Ok((request, body))
+}
+
+/// Send a response with the whole `body`, adding the `Content-Length` header.
+///
+/// For responses to HEAD requests, the body should be empty.
+pub async fn write_response<C>(
+    conn: &mut C,
+    status: u16,
+    headers: &[Header<'_>],
+    body: &[u8],
+) -> Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    write_status(conn, status).await?;
+    write_headers(conn, headers).await?;
+    let mut len = [0; 20];
+    conn.write_all(b"Content-Length: ").await?;
+    conn.write_all(format_number(body.len(), 10, &mut len))
+        .await?;
+    conn.write_all(b"\r\n\r\n").await?;
+    conn.write_all(body).await?;
+    Ok(())
+}
+
+/// Send the head of a response whose body is then written in chunks, for bodies whose
+/// length isn't known in advance.
+pub async fn write_chunked_response<'c, C>(
+    conn: &'c mut C,
+    status: u16,
+    headers: &[Header<'_>],
+) -> Result<ChunkedBody<'c, C>>
+where
+    C: AsyncWrite + Unpin,
+{
+    write_status(conn, status).await?;
+    write_headers(conn, headers).await?;
+    conn.write_all(b"Transfer-Encoding: chunked\r\n\r\n")
+        .await?;
+    Ok(ChunkedBody::new(conn))
+}
+
+async fn write_status<C>(conn: &mut C, status: u16) -> Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    let mut buf = [0; 20];
+    conn.write_all(b"HTTP/1.1 ").await?;
+    conn.write_all(format_number(status as usize, 10, &mut buf))
+        .await?;
+    conn.write_all(b" ").await?;
+    conn.write_all(reason(status).as_bytes()).await?;
+    conn.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+/// Reason phrase of a status code.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// An endpoint of a [Router].
+#[derive(Debug, Clone, Copy)]
+pub struct Route<'a, T> {
+    pub method: Method,
+    /// Path to match exactly, or prefix to match if it ends with `*`.
+    pub path: &'a str,
+    pub endpoint: T,
+}
+
+/// Finds the endpoint handling a request.
+///
+/// Endpoints are usually an enum, and the code handling them is a `match` on the result of
+/// [route](Router::route), so that each handler can be an inline async block without
+/// boxing.
+pub struct Router<'a, T> {
+    routes: &'a [Route<'a, T>],
+}
+
+impl<'a, T> Router<'a, T> {
+    pub const fn new(routes: &'a [Route<'a, T>]) -> Self {
+        Self { routes }
+    }
+}
+
+impl<'a, T: Copy> Router<'a, T> {
+    /// Endpoint of the first route matching the method and path of the request. Otherwise,
+    /// the status to answer with: 405 if the path matches for other methods, 404 if not.
+    pub fn route(&self, request: &Request<'_>) -> core::result::Result<T, u16> {
+        let mut status = 404;
+        for route in self.routes {
+            let matches = match route.path.strip_suffix('*') {
+                Some(prefix) => request.path.starts_with(prefix),
+                None => request.path == route.path,
+            };
+            if matches {
+                if route.method == request.method {
+                    return Ok(route.endpoint);
+                }
+                status = 405;
+            }
+        }
+        Err(status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Endpoint {
+        Status,
+        SetLed,
+        GetLed,
+        Files,
+    }
+
+    const ROUTER: Router<'static, Endpoint> = Router::new(&[
+        Route {
+            method: Method::Get,
+            path: "/status",
+            endpoint: Endpoint::Status,
+        },
+        Route {
+            method: Method::Put,
+            path: "/led",
+            endpoint: Endpoint::SetLed,
+        },
+        Route {
+            method: Method::Get,
+            path: "/led",
+            endpoint: Endpoint::GetLed,
+        },
+        Route {
+            method: Method::Get,
+            path: "/files/*",
+            endpoint: Endpoint::Files,
+        },
+    ]);
+
+    fn route(head: &[u8]) -> core::result::Result<Endpoint, u16> {
+        ROUTER.route(&Request::parse(head).unwrap())
+    }
+
+    #[test]
+    fn router() {
+        assert_eq!(route(b"GET /status HTTP/1.1\r\n\r\n"), Ok(Endpoint::Status));
+        assert_eq!(
+            route(b"GET /status?x=1 HTTP/1.1\r\n\r\n"),
+            Ok(Endpoint::Status)
+        );
+        assert_eq!(route(b"PUT /led HTTP/1.1\r\n\r\n"), Ok(Endpoint::SetLed));
+        assert_eq!(route(b"GET /led HTTP/1.1\r\n\r\n"), Ok(Endpoint::GetLed));
+        assert_eq!(
+            route(b"GET /files/a/b HTTP/1.1\r\n\r\n"),
+            Ok(Endpoint::Files)
+        );
+        assert_eq!(route(b"GET /files/ HTTP/1.1\r\n\r\n"), Ok(Endpoint::Files));
+    }
+
+    #[test]
+    fn not_found() {
+        assert_eq!(route(b"GET / HTTP/1.1\r\n\r\n"), Err(404));
+        assert_eq!(route(b"GET /status/ HTTP/1.1\r\n\r\n"), Err(404));
+        assert_eq!(route(b"GET /statusx HTTP/1.1\r\n\r\n"), Err(404));
+        assert_eq!(route(b"GET /files HTTP/1.1\r\n\r\n"), Err(404));
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        assert_eq!(route(b"POST /status HTTP/1.1\r\n\r\n"), Err(405));
+        assert_eq!(route(b"DELETE /led HTTP/1.1\r\n\r\n"), Err(405));
+        assert_eq!(route(b"HEAD /files/a HTTP/1.1\r\n\r\n"), Err(405));
+    }
+}
@@ -1,31 +1,62 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+#![feature(generic_associated_types)]
+#![allow(incomplete_features)]
 
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
 mod config;
 mod device;
-mod packet_pool;
+pub mod pcap;
+#[cfg(feature = "std")]
+pub mod pipe;
+#[cfg(feature = "ppp")]
+pub mod ppp;
+#[cfg(feature = "slaac")]
+mod slaac;
 mod stack;
 
+pub use config::{
+    Config, Configurator, ConfiguratorResources, DhcpLease, Event as ConfigEvent,
+    StaticConfigurator,
+};
 #[cfg(feature = "dhcpv4")]
-pub use config::DhcpConfigurator;
-pub use config::{Config, Configurator, Event as ConfigEvent, StaticConfigurator};
+pub use config::{DhcpConfigurator, DHCP_MAX_OPTION_LEN};
 
 pub use device::{Device, LinkState};
-pub use packet_pool::{Packet, PacketBox, PacketBoxExt, PacketBuf};
-pub use stack::{init, is_config_up, is_init, is_link_up, run};
+pub use stack::{Stack, StackResources};
 
 #[cfg(feature = "tcp")]
 mod tcp_socket;
 #[cfg(feature = "tcp")]
 pub use tcp_socket::TcpSocket;
 
+#[cfg(feature = "udp")]
+mod udp_socket;
+#[cfg(feature = "udp")]
+pub use udp_socket::UdpSocket;
+
+#[cfg(feature = "dns")]
+pub mod dns;
+
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+#[cfg(feature = "tls")]
+pub mod tls;
+
 // smoltcp reexports
 pub use smoltcp::phy::{DeviceCapabilities, Medium};
+#[cfg(feature = "udp")]
+pub use smoltcp::socket::UdpPacketMetadata;
 pub use smoltcp::time::Duration as SmolDuration;
 pub use smoltcp::time::Instant as SmolInstant;
-pub use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+pub use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv4Cidr};
+#[cfg(feature = "proto-ipv6")]
+pub use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 pub type Interface = smoltcp::iface::Interface<'static, device::DeviceAdapter>;
 pub type SocketSet = smoltcp::socket::SocketSet<'static>;
 pub use smoltcp::{Error, Result};
@@ -0,0 +1,536 @@
+//! MQTT client.
+//!
+//! [MqttClient] speaks MQTT 3.1.1 or 5 over any `AsyncBufRead + AsyncWrite` transport,
+//! typically a [TcpSocket](crate::TcpSocket) or a [TlsConnection](crate::tls::TlsConnection).
+//! Packets are built and received in buffers supplied by the caller, nothing is allocated.
+//!
+//! It supports publishing and receiving messages with QoS 0 and 1, and subscribing. Incoming
+//! packets, including acknowledgements, are returned by [next_event](MqttClient::next_event),
+//! which also sends the keep-alive pings, so it must be called regularly. It can be
+//! cancelled, for example in a `select` with a timer to publish periodically.
+//!
+//! ```ignore
+//! let mut client = MqttClient::new(socket, &mut rx_buffer, &mut tx_buffer);
+//! client.connect(&MqttConfig { client_id: "sensor-1", ..Default::default() }).await?;
+//! client.subscribe(&[("sensor-1/cmd", QoS::AtLeastOnce)]).await?;
+//! client.publish("sensor-1/status", b"online", QoS::AtMostOnce, true).await?;
+//!
+//! loop {
+//!     match client.next_event().await? {
+//!         Event::Message(msg) => handle(msg.topic, msg.payload),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! Messages published with QoS 1 are not sent again if the connection is lost before they
+//! are acknowledged. Incoming messages larger than the rx buffer are dropped, without
+//! acknowledging them.
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::time::{Duration, Ticker};
+use futures::Stream;
+
+mod packet;
+
+use self::packet::{Reader, Writer, MAX_HEADER_LEN};
+use crate::fmt::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Reading from or writing to the transport failed, or it was closed.
+    Io(io::Error),
+    /// The broker refused the connection, with this return code (MQTT 3.1.1) or reason code
+    /// (MQTT 5).
+    ConnectionRefused(u8),
+    /// The packet to send doesn't fit in the tx buffer.
+    BufferTooSmall,
+    /// The broker sent a packet larger than the rx buffer, it was dropped.
+    PacketTooLarge,
+    /// The broker sent a malformed or unexpected packet.
+    Protocol,
+    /// The broker didn't answer a ping within the keep-alive interval.
+    Timeout,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolVersion {
+    V3_1_1,
+    V5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+/// Parameters of the connection.
+#[derive(Debug, Clone, Copy)]
+pub struct MqttConfig<'a> {
+    pub version: ProtocolVersion,
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    /// Interval of the pings, if there's no answer before the next one, the connection is
+    /// considered dead. Zero disables pings. Rounded down to seconds.
+    pub keep_alive: Duration,
+    /// Start a new session, instead of resuming the subscriptions and undelivered messages
+    /// of the previous one.
+    pub clean_session: bool,
+}
+
+impl<'a> Default for MqttConfig<'a> {
+    fn default() -> Self {
+        Self {
+            version: ProtocolVersion::V3_1_1,
+            client_id: "",
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(60),
+            clean_session: true,
+        }
+    }
+}
+
+/// A message received on a subscribed topic.
+#[derive(Debug)]
+pub struct Message<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A message was received.
+    ///
+    /// With QoS 1, it's acknowledged on the next call to the client, so a message that
+    /// wasn't handled because of a reset will be delivered again.
+    Message(Message<'a>),
+    /// The broker acknowledged the QoS 1 publish with this packet id.
+    Published(u16),
+    /// The broker acknowledged the subscribe with this packet id.
+    Subscribed {
+        packet_id: u16,
+        /// The QoS granted for each topic filter, in order, or a value of 0x80 or more if
+        /// the subscription failed.
+        granted: &'a [u8],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RxState {
+    Header,
+    /// Receiving a body of that many bytes.
+    Body(usize),
+    /// Dropping that many more bytes of a packet too large for the rx buffer.
+    Discard(usize),
+}
+
+/// An MQTT client over `S`, see the [module documentation](self).
+pub struct MqttClient<'a, S> {
+    socket: S,
+    version: ProtocolVersion,
+
+    rx: &'a mut [u8],
+    rx_state: RxState,
+    rx_header: [u8; MAX_HEADER_LEN],
+    rx_header_len: usize,
+    rx_len: usize,
+
+    tx: &'a mut [u8],
+    /// Packet being sent.
+    tx_pos: usize,
+    tx_len: usize,
+
+    next_packet_id: u16,
+    /// Packet id of a received QoS 1 message, to acknowledge.
+    pending_ack: Option<u16>,
+    keep_alive: Option<Duration>,
+    /// Ticks when nothing was sent for the keep-alive interval.
+    ticker: Option<Ticker>,
+    ping_outstanding: bool,
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> MqttClient<'a, S> {
+    /// Wrap a connected transport. Call [connect](Self::connect) before anything else.
+    ///
+    /// `rx` must hold the largest packet the broker sends, which is a message with its
+    /// topic and a few bytes of header. `tx` must hold the largest packet we send.
+    pub fn new(socket: S, rx: &'a mut [u8], tx: &'a mut [u8]) -> Self {
+        Self {
+            socket,
+            version: ProtocolVersion::V3_1_1,
+            rx,
+            rx_state: RxState::Header,
+            rx_header: [0; MAX_HEADER_LEN],
+            rx_header_len: 0,
+            rx_len: 0,
+            tx,
+            tx_pos: 0,
+            tx_len: 0,
+            next_packet_id: 0,
+            pending_ack: None,
+            keep_alive: None,
+            ticker: None,
+            ping_outstanding: false,
+        }
+    }
+
+    /// Unwrap the transport.
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    /// Send CONNECT and wait for the broker to accept it.
+    ///
+    /// Returns whether the broker resumed a previous session.
+    pub async fn connect(&mut self, config: &MqttConfig<'_>) -> Result<bool> {
+        self.version = config.version;
+        self.flush().await?;
+
+        let keep_alive = config.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+        let mut flags = 0;
+        if config.clean_session {
+            flags |= 0x02;
+        }
+        if config.password.is_some() {
+            flags |= 0x40;
+        }
+        if config.username.is_some() {
+            flags |= 0x80;
+        }
+
+        let version = self.version;
+        self.send(packet::CONNECT << 4, |w| {
+            w.prefixed(b"MQTT")?;
+            w.u8(match version {
+                ProtocolVersion::V3_1_1 => 4,
+                ProtocolVersion::V5 => 5,
+            })?;
+            w.u8(flags)?;
+            w.u16(keep_alive)?;
+            if version == ProtocolVersion::V5 {
+                w.varint(0)?; // no properties
+            }
+            w.prefixed(config.client_id.as_bytes())?;
+            if let Some(username) = config.username {
+                w.prefixed(username.as_bytes())?;
+            }
+            if let Some(password) = config.password {
+                w.prefixed(password)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        if self.read_packet().await? >> 4 != packet::CONNACK {
+            return Err(Error::Protocol);
+        }
+        let mut r = Reader::new(&self.rx[..self.rx_len]);
+        let session_present = r.u8()? & 0x01 != 0;
+        let code = r.u8()?;
+        if code != 0 {
+            warn!("MQTT connection refused: {}", code);
+            return Err(Error::ConnectionRefused(code));
+        }
+
+        self.ping_outstanding = false;
+        self.pending_ack = None;
+        self.keep_alive = match keep_alive {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        };
+        self.ticker = self.keep_alive.map(Ticker::every);
+        Ok(session_present)
+    }
+
+    /// Publish a message.
+    ///
+    /// With QoS 1, returns the packet id that [Event::Published] reports once the broker
+    /// acknowledges it.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<Option<u16>> {
+        self.prepare().await?;
+
+        let packet_id = match qos {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => Some(self.new_packet_id()),
+        };
+        let version = self.version;
+        let header = packet::PUBLISH << 4 | packet::publish_flags(qos, retain);
+        self.send(header, |w| {
+            w.prefixed(topic.as_bytes())?;
+            if let Some(id) = packet_id {
+                w.u16(id)?;
+            }
+            if version == ProtocolVersion::V5 {
+                w.varint(0)?;
+            }
+            w.bytes(payload)
+        })
+        .await?;
+        Ok(packet_id)
+    }
+
+    /// Subscribe to topic filters, with the maximum QoS of the messages to receive for each.
+    ///
+    /// Returns the packet id that [Event::Subscribed] reports once the broker acknowledges
+    /// it.
+    pub async fn subscribe(&mut self, filters: &[(&str, QoS)]) -> Result<u16> {
+        self.prepare().await?;
+
+        let packet_id = self.new_packet_id();
+        let version = self.version;
+        self.send(packet::SUBSCRIBE << 4 | 0x02, |w| {
+            w.u16(packet_id)?;
+            if version == ProtocolVersion::V5 {
+                w.varint(0)?;
+            }
+            for &(filter, qos) in filters {
+                w.prefixed(filter.as_bytes())?;
+                w.u8(qos as u8)?;
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(packet_id)
+    }
+
+    /// Send DISCONNECT. The transport should be closed afterwards.
+    pub async fn disconnect(&mut self) -> Result<()> {
+        self.prepare().await?;
+        self.keep_alive = None;
+        self.ticker = None;
+        self.send(packet::DISCONNECT << 4, |_| Ok(())).await
+    }
+
+    /// Wait for the next packet from the broker, sending pings in the meantime.
+    pub async fn next_event(&mut self) -> Result<Event<'_>> {
+        self.prepare().await?;
+
+        let header = loop {
+            let packet = futures::future::poll_fn(|cx| {
+                // Take in everything received before looking at the ticker, so a ping
+                // answered in time isn't reported as timed out.
+                loop {
+                    match self.poll_packet(cx) {
+                        Poll::Ready(Ok(header)) if header >> 4 == packet::PINGRESP => {
+                            self.ping_outstanding = false
+                        }
+                        Poll::Ready(res) => return Poll::Ready(Some(res)),
+                        Poll::Pending => break,
+                    }
+                }
+                if let Some(ticker) = &mut self.ticker {
+                    if Pin::new(ticker).poll_next(cx).is_ready() {
+                        return Poll::Ready(None);
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+
+            match packet {
+                Some(res) => {
+                    let header = res?;
+                    match header >> 4 {
+                        packet::PUBLISH | packet::PUBACK | packet::SUBACK => break header,
+                        _ => return Err(Error::Protocol),
+                    }
+                }
+                None => {
+                    if self.ping_outstanding {
+                        warn!("MQTT ping timed out");
+                        return Err(Error::Timeout);
+                    }
+                    // Set before sending, so the ping still counts if we're cancelled
+                    // while it's being sent. prepare() finishes sending it.
+                    self.ping_outstanding = true;
+                    self.send(packet::PINGREQ << 4, |_| Ok(())).await?;
+                }
+            }
+        };
+
+        self.parse_event(header)
+    }
+
+    fn parse_event(&mut self, header: u8) -> Result<Event<'_>> {
+        let v5 = self.version == ProtocolVersion::V5;
+        let mut r = Reader::new(&self.rx[..self.rx_len]);
+        match header >> 4 {
+            packet::PUBLISH => {
+                let qos = match (header >> 1) & 0x03 {
+                    0 => QoS::AtMostOnce,
+                    1 => QoS::AtLeastOnce,
+                    // We never subscribe with QoS 2.
+                    _ => return Err(Error::Protocol),
+                };
+                let topic = r.str()?;
+                if qos == QoS::AtLeastOnce {
+                    self.pending_ack = Some(r.u16()?);
+                }
+                if v5 {
+                    r.skip_properties()?;
+                }
+                Ok(Event::Message(Message {
+                    topic,
+                    payload: r.rest(),
+                    qos,
+                    retain: header & 0x01 != 0,
+                }))
+            }
+            // With MQTT 5, a reason code and properties may follow, a failed publish is
+            // still reported as acknowledged.
+            packet::PUBACK => Ok(Event::Published(r.u16()?)),
+            _ => {
+                let packet_id = r.u16()?;
+                if v5 {
+                    r.skip_properties()?;
+                }
+                Ok(Event::Subscribed {
+                    packet_id,
+                    granted: r.rest(),
+                })
+            }
+        }
+    }
+
+    fn new_packet_id(&mut self) -> u16 {
+        // 0 is not a valid packet id.
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        self.next_packet_id
+    }
+
+    /// Finish sending what a cancelled call left, and acknowledge the last message received.
+    async fn prepare(&mut self) -> Result<()> {
+        self.flush().await?;
+        if let Some(id) = self.pending_ack {
+            self.send(packet::PUBACK << 4, |w| w.u16(id)).await?;
+            self.pending_ack = None;
+        }
+        Ok(())
+    }
+
+    /// Send a packet, whose body is written by `f`.
+    async fn send(
+        &mut self,
+        header: u8,
+        f: impl FnOnce(&mut Writer<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let mut w = Writer::new(&mut self.tx[MAX_HEADER_LEN..]);
+        f(&mut w)?;
+        let len = w.len();
+        self.tx_pos = packet::finish(self.tx, header, len);
+        self.tx_len = MAX_HEADER_LEN + len;
+        // The broker only needs a ping when nothing else was sent.
+        self.ticker = self.keep_alive.map(Ticker::every);
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        futures::future::poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.tx_pos < self.tx_len {
+            let buf = &self.tx[self.tx_pos..self.tx_len];
+            match Pin::new(&mut self.socket).poll_write(cx, buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Error::Io(io::Error::WriteZero))),
+                Poll::Ready(Ok(n)) => self.tx_pos += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.tx_pos = 0;
+        self.tx_len = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    async fn read_packet(&mut self) -> Result<u8> {
+        futures::future::poll_fn(|cx| self.poll_packet(cx)).await
+    }
+
+    /// Receive the next packet, returning the first byte of its fixed header. The body is
+    /// `rx[..rx_len]`.
+    fn poll_packet(&mut self, cx: &mut Context<'_>) -> Poll<Result<u8>> {
+        loop {
+            let buf = match Pin::new(&mut self.socket).poll_fill_buf(cx) {
+                Poll::Ready(Ok([])) => {
+                    return Poll::Ready(Err(Error::Io(io::Error::UnexpectedEof)))
+                }
+                Poll::Ready(Ok(buf)) => buf,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let mut n = 0;
+            let mut res = None;
+            while n < buf.len() && res.is_none() {
+                match self.rx_state {
+                    RxState::Header => {
+                        let b = buf[n];
+                        n += 1;
+                        self.rx_header[self.rx_header_len] = b;
+                        self.rx_header_len += 1;
+                        if self.rx_header_len >= 2 && b & 0x80 == 0 {
+                            let mut r = Reader::new(&self.rx_header[1..self.rx_header_len]);
+                            let len = unwrap!(r.varint().ok());
+                            self.rx_len = 0;
+                            self.rx_state = if len > self.rx.len() {
+                                warn!("MQTT packet of {} bytes too large, dropping it", len);
+                                RxState::Discard(len)
+                            } else {
+                                RxState::Body(len)
+                            };
+                        } else if self.rx_header_len == MAX_HEADER_LEN {
+                            self.rx_header_len = 0;
+                            res = Some(Err(Error::Protocol));
+                            continue;
+                        }
+                    }
+                    RxState::Body(len) => {
+                        let m = (len - self.rx_len).min(buf.len() - n);
+                        self.rx[self.rx_len..self.rx_len + m].copy_from_slice(&buf[n..n + m]);
+                        self.rx_len += m;
+                        n += m;
+                    }
+                    RxState::Discard(left) => {
+                        let m = left.min(buf.len() - n);
+                        self.rx_state = RxState::Discard(left - m);
+                        n += m;
+                    }
+                }
+
+                match self.rx_state {
+                    RxState::Body(len) if self.rx_len == len => {
+                        res = Some(Ok(self.rx_header[0]));
+                    }
+                    RxState::Discard(0) => res = Some(Err(Error::PacketTooLarge)),
+                    _ => continue,
+                }
+                self.rx_state = RxState::Header;
+                self.rx_header_len = 0;
+            }
+            Pin::new(&mut self.socket).consume(n);
+
+            if let Some(res) = res {
+                return Poll::Ready(res);
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Encoding and decoding of MQTT packets.
+
+use super::{Error, QoS, Result};
+
+pub const CONNECT: u8 = 1;
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PUBACK: u8 = 4;
+pub const SUBSCRIBE: u8 = 8;
+pub const SUBACK: u8 = 9;
+pub const PINGREQ: u8 = 12;
+pub const PINGRESP: u8 = 13;
+pub const DISCONNECT: u8 = 14;
+
+/// Longest fixed header: the type byte and a 4-byte remaining length.
+pub const MAX_HEADER_LEN: usize = 5;
+
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<()> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<()> {
+        self.bytes(&[val])
+    }
+
+    pub fn u16(&mut self, val: u16) -> Result<()> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Binary data or UTF-8 string, prefixed with its length.
+    pub fn prefixed(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > u16::MAX as usize {
+            return Err(Error::BufferTooSmall);
+        }
+        self.u16(data.len() as u16)?;
+        self.bytes(data)
+    }
+
+    pub fn varint(&mut self, val: usize) -> Result<()> {
+        let mut buf = [0; 4];
+        let n = encode_varint(val, &mut buf);
+        self.bytes(&buf[..n])
+    }
+}
+
+/// Encode a variable byte integer, returning its length.
+fn encode_varint(mut val: usize, buf: &mut [u8; 4]) -> usize {
+    let mut n = 0;
+    loop {
+        let mut b = (val % 128) as u8;
+        val /= 128;
+        if val > 0 {
+            b |= 0x80;
+        }
+        buf[n] = b;
+        n += 1;
+        if val == 0 || n == buf.len() {
+            return n;
+        }
+    }
+}
+
+/// Write the fixed header of a packet whose body is at `buf[MAX_HEADER_LEN..][..len]`,
+/// right before the body. Returns where the packet starts in `buf`.
+pub fn finish(buf: &mut [u8], header: u8, len: usize) -> usize {
+    let mut varint = [0; 4];
+    let n = encode_varint(len, &mut varint);
+    let start = MAX_HEADER_LEN - 1 - n;
+    buf[start] = header;
+    buf[start + 1..MAX_HEADER_LEN].copy_from_slice(&varint[..n]);
+    start
+}
+
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    /// The data left to read.
+    pub fn rest(self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.0.len() {
+            return Err(Error::Protocol);
+        }
+        let (data, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn prefixed(&mut self) -> Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn str(&mut self) -> Result<&'a str> {
+        core::str::from_utf8(self.prefixed()?).map_err(|_| Error::Protocol)
+    }
+
+    pub fn varint(&mut self) -> Result<usize> {
+        let mut val = 0;
+        for i in 0..4 {
+            let b = self.u8()?;
+            val |= ((b & 0x7F) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        Err(Error::Protocol)
+    }
+
+    /// Skip MQTT 5 properties.
+    pub fn skip_properties(&mut self) -> Result<()> {
+        let len = self.varint()?;
+        self.bytes(len)?;
+        Ok(())
+    }
+}
+
+/// Flags of a PUBLISH fixed header.
+pub fn publish_flags(qos: QoS, retain: bool) -> u8 {
+    (qos as u8) << 1 | retain as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        let cases: [(usize, &[u8]); 6] = [
+            (0, &[0x00]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xFF, 0x7F]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (268_435_455, &[0xFF, 0xFF, 0xFF, 0x7F]),
+        ];
+        for &(val, encoded) in cases.iter() {
+            let mut buf = [0; 4];
+            let n = encode_varint(val, &mut buf);
+            assert_eq!(&buf[..n], encoded);
+            assert_eq!(Reader::new(encoded).varint(), Ok(val));
+        }
+
+        // At most 4 bytes.
+        assert_eq!(
+            Reader::new(&[0x80, 0x80, 0x80, 0x80, 0x01]).varint(),
+            Err(Error::Protocol)
+        );
+        assert_eq!(Reader::new(&[0x80]).varint(), Err(Error::Protocol));
+    }
+
+    #[test]
+    fn finish() {
+        let mut buf = [0; MAX_HEADER_LEN + 200];
+        let start = super::finish(&mut buf, PINGREQ << 4, 0);
+        assert_eq!(&buf[start..MAX_HEADER_LEN], &[0xC0, 0x00]);
+
+        let start = super::finish(&mut buf, PUBLISH << 4, 200);
+        assert_eq!(&buf[start..MAX_HEADER_LEN], &[0x30, 0xC8, 0x01]);
+    }
+
+    #[test]
+    fn writer() {
+        let mut buf = [0; 16];
+        let mut w = Writer::new(&mut buf);
+        w.prefixed(b"MQTT").unwrap();
+        w.u8(4).unwrap();
+        w.u16(0x1234).unwrap();
+        w.varint(300).unwrap();
+        let len = w.len();
+        assert_eq!(
+            &buf[..len],
+            &[0, 4, b'M', b'Q', b'T', b'T', 4, 0x12, 0x34, 0xAC, 0x02]
+        );
+
+        let mut w = Writer::new(&mut buf);
+        assert_eq!(w.bytes(&[0; 17]), Err(Error::BufferTooSmall));
+        assert_eq!(w.prefixed(&[0; 15]), Err(Error::BufferTooSmall));
+        assert_eq!(w.len(), 2);
+    }
+
+    #[test]
+    fn reader() {
+        let data = [0, 3, b'a', b'/', b'b', 0x12, 0x34, 2, 0xAA, 0xBB, b'x'];
+        let mut r = Reader::new(&data);
+        assert_eq!(r.str(), Ok("a/b"));
+        assert_eq!(r.u16(), Ok(0x1234));
+        assert_eq!(r.skip_properties(), Ok(()));
+        assert_eq!(r.rest(), b"x");
+
+        assert_eq!(Reader::new(&[0, 2, 0xFF, 0xFE]).str(), Err(Error::Protocol));
+        assert_eq!(Reader::new(&[0, 3, b'a']).prefixed(), Err(Error::Protocol));
+        assert_eq!(Reader::new(&[5, 0]).skip_properties(), Err(Error::Protocol));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(publish_flags(QoS::AtMostOnce, false), 0x00);
+        assert_eq!(publish_flags(QoS::AtLeastOnce, false), 0x02);
+        assert_eq!(publish_flags(QoS::AtLeastOnce, true), 0x03);
+    }
+}
@@ -0,0 +1,238 @@
+//! Packet capture.
+//!
+//! [PcapDevice] wraps a [Device] and writes every packet it transmits or receives to a
+//! [PcapSink], in the classic pcap file format understood by Wireshark and tcpdump.
+//! Timestamps are taken from `embassy::time::Instant`, so they are relative to boot.
+//!
+//! With the `std` feature, a `std::fs::File` can be used as a sink directly. On embedded
+//! targets, use a [PcapBuffer] and run [PcapBuffer::run] in a separate task to stream
+//! the capture over any `AsyncWrite`, for example a UART.
+
+use core::cell::RefCell;
+use core::task::{Poll, Waker};
+use embassy::io::{self, AsyncWrite, AsyncWriteExt};
+use embassy::time::{Instant, TICKS_PER_SECOND};
+use embassy::util::{CriticalSectionMutex, RawMutex, WakerRegistration};
+use smoltcp::phy::{DeviceCapabilities, Medium};
+
+use crate::device::{Device, LinkState};
+use crate::fmt::*;
+use crate::Result;
+
+const MAGIC: u32 = 0xa1b2_c3d4;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const SNAPLEN: u32 = 65535;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+
+/// Destination of a packet capture.
+pub trait PcapSink {
+    /// Write a piece of the capture, made of the concatenation of `chunks`.
+    ///
+    /// This is called from within the network stack, so it must not block. A sink that
+    /// can't keep up should drop the whole write, never part of it, so the capture stays
+    /// readable.
+    fn write(&mut self, chunks: &[&[u8]]);
+}
+
+#[cfg(feature = "std")]
+impl PcapSink for std::fs::File {
+    fn write(&mut self, chunks: &[&[u8]]) {
+        use std::io::Write;
+
+        for chunk in chunks {
+            if self.write_all(chunk).is_err() {
+                warn!("pcap: writing to file failed");
+                return;
+            }
+        }
+    }
+}
+
+/// [Device] that captures all traffic of an inner device, see the [module documentation](self).
+pub struct PcapDevice<D: Device, S: PcapSink> {
+    device: D,
+    sink: S,
+}
+
+impl<D: Device, S: PcapSink> PcapDevice<D, S> {
+    /// Wrap `device`, writing the pcap file header to `sink` right away.
+    pub fn new(mut device: D, mut sink: S) -> Self {
+        let linktype = match device.capabilities().medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => LINKTYPE_ETHERNET,
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => LINKTYPE_RAW,
+        };
+
+        let mut header = [0; 24];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&VERSION_MAJOR.to_le_bytes());
+        header[6..8].copy_from_slice(&VERSION_MINOR.to_le_bytes());
+        // thiszone and sigfigs are left as 0.
+        header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+        header[20..24].copy_from_slice(&linktype.to_le_bytes());
+        sink.write(&[&header]);
+
+        Self { device, sink }
+    }
+
+    /// Unwrap the inner device and sink.
+    pub fn into_inner(self) -> (D, S) {
+        (self.device, self.sink)
+    }
+}
+
+/// Write a record for `pkt`, timestamped now.
+fn capture<S: PcapSink>(sink: &mut S, pkt: &[u8]) {
+    let ticks = Instant::now().as_ticks();
+    let secs = (ticks / TICKS_PER_SECOND) as u32;
+    let micros = ((ticks % TICKS_PER_SECOND) * 1_000_000 / TICKS_PER_SECOND) as u32;
+    let len = pkt.len() as u32;
+
+    let mut header = [0; 16];
+    header[0..4].copy_from_slice(&secs.to_le_bytes());
+    header[4..8].copy_from_slice(&micros.to_le_bytes());
+    header[8..12].copy_from_slice(&len.to_le_bytes()); // captured length
+    header[12..16].copy_from_slice(&len.to_le_bytes()); // original length
+    sink.write(&[&header, pkt]);
+}
+
+impl<D: Device, S: PcapSink> Device for PcapDevice<D, S> {
+    fn is_receive_ready(&mut self) -> bool {
+        self.device.is_receive_ready()
+    }
+
+    fn receive(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        let sink = &mut self.sink;
+        self.device.receive(&mut |buf| {
+            capture(sink, buf);
+            f(buf)
+        })
+    }
+
+    fn is_transmit_ready(&mut self) -> bool {
+        self.device.is_transmit_ready()
+    }
+
+    fn transmit(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8]) -> Result<()>) -> Result<()> {
+        let sink = &mut self.sink;
+        self.device.transmit(len, &mut |buf| {
+            f(buf)?;
+            capture(sink, buf);
+            Ok(())
+        })
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.device.register_waker(waker)
+    }
+
+    fn poll_at(&mut self) -> Option<Instant> {
+        self.device.poll_at()
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        self.device.capabilities()
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        self.device.link_state()
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        self.device.ethernet_address()
+    }
+}
+
+struct BufferState<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+    /// Number of writes dropped because the buffer was full.
+    dropped: u32,
+    waker: WakerRegistration,
+}
+
+/// Fixed-size buffer between a [PcapDevice] and an `AsyncWrite`.
+///
+/// Writes that don't fit in the `N` bytes of free space are dropped whole, so the buffer
+/// should hold at least a few maximum-size packets, plus 16 bytes of header for each.
+///
+/// The buffer is protected by critical sections, so the stack and [run](Self::run) may be
+/// on different executors, for example one in thread mode and one in an interrupt.
+pub struct PcapBuffer<const N: usize> {
+    state: CriticalSectionMutex<RefCell<BufferState<N>>>,
+}
+
+impl<const N: usize> PcapBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            state: CriticalSectionMutex::new(RefCell::new(BufferState {
+                buf: [0; N],
+                start: 0,
+                len: 0,
+                dropped: 0,
+                waker: WakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// Number of packets dropped from the capture so far because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.state.lock(|s| s.borrow().dropped)
+    }
+
+    /// Copy the capture to `w` as it is written.
+    ///
+    /// Only returns if writing fails.
+    pub async fn run<W: AsyncWrite + Unpin>(&self, w: &mut W) -> io::Error {
+        let mut chunk = [0; 64];
+        loop {
+            let n = futures::future::poll_fn(|cx| {
+                self.state.lock(|s| {
+                    let mut s = s.borrow_mut();
+                    if s.len == 0 {
+                        s.waker.register(cx.waker());
+                        return Poll::Pending;
+                    }
+
+                    let n = s.len.min(N - s.start).min(chunk.len());
+                    chunk[..n].copy_from_slice(&s.buf[s.start..s.start + n]);
+                    s.start = (s.start + n) % N;
+                    s.len -= n;
+                    Poll::Ready(n)
+                })
+            })
+            .await;
+
+            if let Err(e) = w.write_all(&chunk[..n]).await {
+                return e;
+            }
+        }
+    }
+}
+
+impl<'a, const N: usize> PcapSink for &'a PcapBuffer<N> {
+    fn write(&mut self, chunks: &[&[u8]]) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+
+            let total: usize = chunks.iter().map(|c| c.len()).sum();
+            if total > N - s.len {
+                s.dropped = s.dropped.wrapping_add(1);
+                return;
+            }
+
+            for chunk in chunks {
+                for &b in chunk.iter() {
+                    let i = (s.start + s.len) % N;
+                    s.buf[i] = b;
+                    s.len += 1;
+                }
+            }
+            s.waker.wake();
+        })
+    }
+}
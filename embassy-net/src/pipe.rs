@@ -0,0 +1,206 @@
+//! In-memory devices, for testing.
+//!
+//! [pipe] creates two devices connected to each other, [PipeDevice::loopback] creates a
+//! device connected to itself. Packets are copied into a queue on transmit, and delivered
+//! to the receiving stack after the configured latency, possibly dropped or reordered
+//! on the way. This allows testing stacks against each other in a single process, without
+//! a real network interface.
+
+use core::task::Waker;
+use embassy::time::{Duration, Instant};
+use smoltcp::phy::{DeviceCapabilities, Medium};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use crate::device::{Device, LinkState};
+use crate::fmt::*;
+use crate::stack::rand;
+use crate::Result;
+
+/// Maximum number of packets in flight in each direction. When full, the sender waits.
+const QUEUE_LEN: usize = 64;
+
+/// Behavior of an in-memory link.
+#[derive(Debug, Clone, Copy)]
+pub struct PipeConfig {
+    pub medium: Medium,
+    /// Maximum packet size, including the Ethernet header if any.
+    pub mtu: usize,
+    /// Time between a packet being transmitted and it being received.
+    pub latency: Duration,
+    /// Probability, between 0 and 1, that a packet is dropped.
+    pub loss: f32,
+    /// Probability, between 0 and 1, that a packet overtakes the one sent before it.
+    pub reorder: f32,
+}
+
+impl Default for PipeConfig {
+    /// A perfect link, using Ethernet if enabled.
+    fn default() -> Self {
+        Self {
+            medium: Medium::default(),
+            mtu: 1514,
+            latency: Duration::from_ticks(0),
+            loss: 0.0,
+            reorder: 0.0,
+        }
+    }
+}
+
+struct Queue {
+    packets: VecDeque<(Instant, Vec<u8>)>,
+    /// Waker of the receiving stack.
+    rx_waker: Option<Waker>,
+    /// Waker of the transmitting stack, woken when a full queue gets room.
+    tx_waker: Option<Waker>,
+}
+
+impl Queue {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            packets: VecDeque::new(),
+            rx_waker: None,
+            tx_waker: None,
+        }))
+    }
+}
+
+/// In-memory [Device], see the [module documentation](self).
+pub struct PipeDevice {
+    config: PipeConfig,
+    mac: [u8; 6],
+    tx: Arc<Mutex<Queue>>,
+    rx: Arc<Mutex<Queue>>,
+}
+
+/// Create two devices connected to each other.
+///
+/// Their Ethernet addresses are `02:00:00:00:00:01` and `02:00:00:00:00:02`.
+pub fn pipe(config: PipeConfig) -> (PipeDevice, PipeDevice) {
+    let a_to_b = Queue::new();
+    let b_to_a = Queue::new();
+    let a = PipeDevice {
+        config,
+        mac: [0x02, 0, 0, 0, 0, 0x01],
+        tx: a_to_b.clone(),
+        rx: b_to_a.clone(),
+    };
+    let b = PipeDevice {
+        config,
+        mac: [0x02, 0, 0, 0, 0, 0x02],
+        tx: b_to_a,
+        rx: a_to_b,
+    };
+    (a, b)
+}
+
+impl PipeDevice {
+    /// Create a device that receives the packets it transmits.
+    ///
+    /// Its Ethernet address is `02:00:00:00:00:00`.
+    pub fn loopback(config: PipeConfig) -> Self {
+        let queue = Queue::new();
+        Self {
+            config,
+            mac: [0x02, 0, 0, 0, 0, 0],
+            tx: queue.clone(),
+            rx: queue,
+        }
+    }
+}
+
+/// Returns true with probability `p`.
+fn chance(p: f32) -> bool {
+    if p <= 0.0 {
+        return false;
+    }
+    let mut buf = [0; 4];
+    rand(&mut buf);
+    (u32::from_le_bytes(buf) as f32 / u32::MAX as f32) < p
+}
+
+impl Device for PipeDevice {
+    fn is_receive_ready(&mut self) -> bool {
+        match self.rx.lock().unwrap().packets.front() {
+            Some((due, _)) => *due <= Instant::now(),
+            None => false,
+        }
+    }
+
+    fn receive(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        let mut q = self.rx.lock().unwrap();
+        let (_, mut data) = unwrap!(q.packets.pop_front());
+        if q.packets.len() == QUEUE_LEN - 1 {
+            if let Some(w) = q.tx_waker.take() {
+                w.wake();
+            }
+        }
+        drop(q);
+
+        f(&mut data)
+    }
+
+    fn is_transmit_ready(&mut self) -> bool {
+        self.tx.lock().unwrap().packets.len() < QUEUE_LEN
+    }
+
+    fn transmit(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8]) -> Result<()>) -> Result<()> {
+        let mut data = vec![0; len];
+        f(&mut data)?;
+
+        if chance(self.config.loss) {
+            trace!("pipe: dropping packet");
+            return Ok(());
+        }
+
+        let mut q = self.tx.lock().unwrap();
+        let mut entry = (Instant::now() + self.config.latency, data);
+        if !q.packets.is_empty() && chance(self.config.reorder) {
+            trace!("pipe: reordering packet");
+            // Swap places with the previous packet, keeping delivery times in order.
+            let i = q.packets.len() - 1;
+            core::mem::swap(&mut entry.0, &mut q.packets[i].0);
+            q.packets.insert(i, entry);
+        } else {
+            q.packets.push_back(entry);
+        }
+
+        if let Some(w) = q.rx_waker.take() {
+            w.wake();
+        }
+        Ok(())
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        fn register(slot: &mut Option<Waker>, waker: &Waker) {
+            match slot {
+                Some(w) if w.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        }
+
+        register(&mut self.rx.lock().unwrap().rx_waker, waker);
+        register(&mut self.tx.lock().unwrap().tx_waker, waker);
+    }
+
+    fn poll_at(&mut self) -> Option<Instant> {
+        // Delivery times are in order, even when packets are reordered.
+        self.rx.lock().unwrap().packets.front().map(|(due, _)| *due)
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = self.config.mtu;
+        caps.medium = self.config.medium;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        LinkState::Up
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        self.mac
+    }
+}
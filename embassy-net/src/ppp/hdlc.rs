@@ -0,0 +1,291 @@
+//! HDLC-like framing (RFC 1662).
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+const ADDRESS: u8 = 0xFF;
+const CONTROL: u8 = 0x03;
+
+const FCS_INIT: u16 = 0xFFFF;
+/// FCS over a frame including its own FCS field, if it's intact.
+const FCS_GOOD: u16 = 0xF0B8;
+
+fn fcs(mut fcs: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        fcs ^= b as u16;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 {
+                (fcs >> 1) ^ 0x8408
+            } else {
+                fcs >> 1
+            };
+        }
+    }
+    fcs
+}
+
+/// Bytes that must be escaped on the wire.
+///
+/// This includes all control characters, which is what the default async control
+/// character map asks for. We never negotiate a smaller map.
+fn needs_escape(b: u8) -> bool {
+    b < 0x20 || b == FLAG || b == ESCAPE
+}
+
+/// Size of the buffer needed to encode a frame with `len` bytes of protocol and
+/// information fields, in the worst case where every byte is escaped.
+pub const fn encoded_len(len: usize) -> usize {
+    2 + 2 * (2 + len + 2)
+}
+
+/// Encode a frame into `out`, returning the number of bytes written.
+///
+/// `frame` holds the protocol and information fields. `out` must be at least
+/// `encoded_len(frame.len())` bytes long.
+pub fn encode(frame: &[u8], out: &mut [u8]) -> usize {
+    let header = [ADDRESS, CONTROL];
+    let fcs = !fcs(fcs(FCS_INIT, &header), frame);
+
+    out[0] = FLAG;
+    let mut pos = 1;
+    for &b in header.iter().chain(frame).chain(&fcs.to_le_bytes()) {
+        if needs_escape(b) {
+            out[pos] = ESCAPE;
+            out[pos + 1] = b ^ ESCAPE_XOR;
+            pos += 2;
+        } else {
+            out[pos] = b;
+            pos += 1;
+        }
+    }
+    out[pos] = FLAG;
+    pos + 1
+}
+
+/// Reassembles frames from the received bytes.
+pub struct Decoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    escape: bool,
+    /// The current frame didn't fit in `buf`, it will be dropped.
+    overflow: bool,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            escape: false,
+            overflow: false,
+        }
+    }
+
+    /// Process a received byte.
+    ///
+    /// Returns the protocol number and information field when it completes a valid frame.
+    /// Frames with a bad FCS, aborted or too long frames are dropped silently.
+    pub fn push(&mut self, b: u8) -> Option<(u16, &[u8])> {
+        match b {
+            FLAG => {
+                // An escape right before the flag aborts the frame.
+                let valid = !self.overflow && !self.escape && self.len >= 4;
+                let len = self.len;
+                self.len = 0;
+                self.escape = false;
+                self.overflow = false;
+
+                if !valid || fcs(FCS_INIT, &self.buf[..len]) != FCS_GOOD {
+                    return None;
+                }
+                parse(&self.buf[..len - 2])
+            }
+            ESCAPE => {
+                self.escape = true;
+                None
+            }
+            // The peer escapes all control characters, unescaped ones were inserted by the
+            // link (for example XON/XOFF flow control) and must be ignored.
+            b if b < 0x20 => None,
+            b => {
+                let b = if self.escape {
+                    self.escape = false;
+                    b ^ ESCAPE_XOR
+                } else {
+                    b
+                };
+                if self.len < N {
+                    self.buf[self.len] = b;
+                    self.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Split a frame without its FCS into protocol number and information field.
+fn parse(frame: &[u8]) -> Option<(u16, &[u8])> {
+    // The address and control fields may be compressed away, and the protocol field
+    // shortened to one byte. We never ask for that, but it costs nothing to accept it.
+    let frame = match frame {
+        [ADDRESS, CONTROL, rest @ ..] => rest,
+        _ => frame,
+    };
+    match frame {
+        [p, rest @ ..] if p & 1 == 1 => Some((*p as u16, rest)),
+        [p0, p1, rest @ ..] => Some((u16::from_be_bytes([*p0, *p1]), rest)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LCP: u16 = 0xC021;
+
+    fn encode_frame(frame: &[u8]) -> heapless::Vec<u8, 64> {
+        let mut out = [0; 64];
+        let len = encode(frame, &mut out);
+        heapless::Vec::from_slice(&out[..len]).unwrap()
+    }
+
+    /// Push `bytes` into `decoder`, returning the last frame completed.
+    fn decode<const N: usize>(
+        decoder: &mut Decoder<N>,
+        bytes: &[u8],
+    ) -> Option<(u16, heapless::Vec<u8, 64>)> {
+        let mut frame = None;
+        for &b in bytes {
+            if let Some((protocol, info)) = decoder.push(b) {
+                frame = Some((protocol, heapless::Vec::from_slice(info).unwrap()));
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn fcs_check_value() {
+        // The FCS-16 check value from RFC 1662, appendix C.2.
+        assert_eq!(!fcs(FCS_INIT, b"123456789"), 0x906E);
+    }
+
+    #[test]
+    fn encode_escapes() {
+        let encoded = encode_frame(&[0xC0, 0x21, 0x7E, 0x7D, 0x11, 0x41]);
+        assert_eq!(
+            &encoded[..12],
+            &[
+                FLAG, ADDRESS, ESCAPE, 0x23, // address, control
+                0xC0, 0x21, // protocol
+                ESCAPE, 0x5E, ESCAPE, 0x5D, ESCAPE, 0x31, // 0x7E, 0x7D, 0x11
+            ]
+        );
+        assert_eq!(encoded[12], 0x41);
+        assert_eq!(encoded[encoded.len() - 1], FLAG);
+        // Only the flags delimiting the frame, and no control characters.
+        assert!(encoded[1..encoded.len() - 1]
+            .iter()
+            .all(|&b| b != FLAG && b >= 0x20));
+    }
+
+    #[test]
+    fn round_trip() {
+        let info = [0x01, 0x7E, 0x7D, 0x00, 0x1F, 0x20, 0xFF];
+        let mut frame = [0; 2 + 7];
+        frame[..2].copy_from_slice(&LCP.to_be_bytes());
+        frame[2..].copy_from_slice(&info);
+
+        let mut decoder = Decoder::<64>::new();
+        let (protocol, decoded) = decode(&mut decoder, &encode_frame(&frame)).unwrap();
+        assert_eq!(protocol, LCP);
+        assert_eq!(&decoded[..], &info);
+
+        // Frames can share the flag between them.
+        let encoded = encode_frame(&frame);
+        assert!(decode(&mut decoder, &encoded[1..]).is_some());
+    }
+
+    #[test]
+    fn unescaped_control_characters_are_ignored() {
+        let encoded = encode_frame(&[0xC0, 0x21, 0x41, 0x42]);
+        let mut with_xon = heapless::Vec::<u8, 64>::new();
+        with_xon.extend_from_slice(&encoded[..6]).unwrap();
+        with_xon.push(0x11).unwrap();
+        with_xon.extend_from_slice(&encoded[6..]).unwrap();
+
+        let mut decoder = Decoder::<64>::new();
+        let (protocol, info) = decode(&mut decoder, &with_xon).unwrap();
+        assert_eq!(protocol, LCP);
+        assert_eq!(&info[..], &[0x41, 0x42]);
+    }
+
+    #[test]
+    fn compressed_fields() {
+        // No address and control fields, one-byte protocol field.
+        let mut frame = [0x21, 0x45, 0x00, 0, 0];
+        let fcs = !fcs(FCS_INIT, &frame[..3]);
+        frame[3..].copy_from_slice(&fcs.to_le_bytes());
+
+        let mut bytes = heapless::Vec::<u8, 16>::new();
+        bytes.push(FLAG).unwrap();
+        for &b in frame.iter() {
+            if needs_escape(b) {
+                bytes.extend_from_slice(&[ESCAPE, b ^ ESCAPE_XOR]).unwrap();
+            } else {
+                bytes.push(b).unwrap();
+            }
+        }
+        bytes.push(FLAG).unwrap();
+
+        let mut decoder = Decoder::<64>::new();
+        let (protocol, info) = decode(&mut decoder, &bytes).unwrap();
+        assert_eq!(protocol, 0x0021);
+        assert_eq!(&info[..], &[0x45, 0x00]);
+    }
+
+    #[test]
+    fn bad_fcs() {
+        let mut encoded = encode_frame(&[0xC0, 0x21, 0x41, 0x42]);
+        encoded[6] = 0x43;
+        let mut decoder = Decoder::<64>::new();
+        assert!(decode(&mut decoder, &encoded).is_none());
+
+        // The next frame is fine.
+        let encoded = encode_frame(&[0xC0, 0x21, 0x41, 0x42]);
+        assert!(decode(&mut decoder, &encoded).is_some());
+    }
+
+    #[test]
+    fn abort() {
+        let encoded = encode_frame(&[0xC0, 0x21, 0x41, 0x42]);
+        let mut decoder = Decoder::<64>::new();
+        // Everything but the closing flag, then an escape right before it.
+        assert!(decode(&mut decoder, &encoded[..encoded.len() - 1]).is_none());
+        assert!(decode(&mut decoder, &[ESCAPE, FLAG]).is_none());
+
+        assert!(decode(&mut decoder, &encoded).is_some());
+    }
+
+    #[test]
+    fn overflow() {
+        let long = encode_frame(&[0xC0, 0x21, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let short = encode_frame(&[0xC0, 0x21, 0x41]);
+        // Room for the address, control and protocol fields, 1 byte and the FCS.
+        let mut decoder = Decoder::<7>::new();
+        assert!(decode(&mut decoder, &long).is_none());
+
+        let (protocol, info) = decode(&mut decoder, &short).unwrap();
+        assert_eq!(protocol, LCP);
+        assert_eq!(&info[..], &[0x41]);
+    }
+
+    #[test]
+    fn runt_frames() {
+        let mut decoder = Decoder::<64>::new();
+        assert!(decode(&mut decoder, &[FLAG, FLAG, 0x41, FLAG]).is_none());
+    }
+}
@@ -0,0 +1,1008 @@
+//! PPP over an asynchronous serial port, for example a cellular modem on a UART.
+//!
+//! [Ppp] handles the HDLC-like framing (RFC 1662), LCP link negotiation (RFC 1661), PAP
+//! authentication if the peer asks for it (RFC 1334), and IPCP negotiation of the address
+//! and DNS servers (RFC 1332, RFC 1877). Its [device](Ppp::device) carries the IPv4
+//! packets with `Medium::Ip`, and its [configurator](Ppp::configurator) configures the
+//! stack with the negotiated address once the link is up.
+//!
+//! ```ignore
+//! static PPP: Forever<Ppp> = Forever::new();
+//!
+//! let ppp = PPP.put(Ppp::new(PppConfig::default()).unwrap());
+//! let stack = Stack::new(
+//!     DEVICE.put(ppp.device()),
+//!     CONFIG.put(ppp.configurator()),
+//!     RESOURCES.put(StackResources::new()),
+//! )
+//! .unwrap();
+//!
+//! // In another task, with anything implementing `AsyncBufRead + AsyncWrite`:
+//! ppp.run(&mut uart).await;
+//! ```
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::time::{Duration, Instant, Timer};
+use embassy::util::{ThreadModeMutex, WakerRegistration};
+use futures::pin_mut;
+use heapless::Vec;
+use smoltcp::phy::{DeviceCapabilities, Medium};
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+mod hdlc;
+
+use crate::config::{Config, Configurator, Event};
+use crate::device::{Device, LinkState};
+use crate::fmt::*;
+use crate::{Error, Interface, Result, SocketSet};
+
+/// Maximum size of an IP packet, this is the MRU both sides use by default.
+pub const MTU: usize = 1500;
+
+/// Number of received IP packets that can wait for the stack.
+const RX_QUEUE_LEN: usize = 2;
+/// Number of control packets that can wait to be sent.
+const CTRL_QUEUE_LEN: usize = 4;
+/// Maximum size of a control packet, including the protocol field.
+const CTRL_LEN: usize = 128;
+/// Maximum size of the data of a control packet, after the protocol field and header.
+const CTRL_DATA_LEN: usize = CTRL_LEN - 2 - 4;
+/// Maximum size of the PAP user name and password together. They're sent in one control
+/// packet, each preceded by its length.
+pub const PAP_MAX_CREDENTIALS_LEN: usize = CTRL_DATA_LEN - 2;
+/// Protocol field, information field and FCS.
+const FRAME_LEN: usize = 2 + MTU + 2;
+/// The address and control fields may be present too.
+const DECODER_LEN: usize = 2 + FRAME_LEN;
+const TX_BUF_LEN: usize = hdlc::encoded_len(2 + MTU);
+
+const RESTART_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_CONFIGURE: u32 = 10;
+
+const PROTO_IPV4: u16 = 0x0021;
+const PROTO_LCP: u16 = 0xC021;
+const PROTO_PAP: u16 = 0xC023;
+const PROTO_IPCP: u16 = 0x8021;
+
+const CONFIGURE_REQUEST: u8 = 1;
+const CONFIGURE_ACK: u8 = 2;
+const CONFIGURE_NAK: u8 = 3;
+const CONFIGURE_REJECT: u8 = 4;
+const TERMINATE_REQUEST: u8 = 5;
+const TERMINATE_ACK: u8 = 6;
+const CODE_REJECT: u8 = 7;
+const PROTOCOL_REJECT: u8 = 8;
+const ECHO_REQUEST: u8 = 9;
+const ECHO_REPLY: u8 = 10;
+const DISCARD_REQUEST: u8 = 11;
+
+const LCP_MRU: u8 = 1;
+const LCP_ACCM: u8 = 2;
+const LCP_AUTH: u8 = 3;
+const LCP_MAGIC: u8 = 5;
+const LCP_PFC: u8 = 7;
+const LCP_ACFC: u8 = 8;
+
+const IPCP_ADDRESS: u8 = 3;
+const IPCP_DNS1: u8 = 129;
+const IPCP_DNS2: u8 = 131;
+
+const PAP_AUTH_REQUEST: u8 = 1;
+const PAP_AUTH_ACK: u8 = 2;
+const PAP_AUTH_NAK: u8 = 3;
+
+/// Settings of a [Ppp] link.
+#[derive(Debug, Clone, Copy)]
+pub struct PppConfig {
+    /// User name for PAP, if the peer asks for authentication.
+    pub username: &'static str,
+    /// Password for PAP, if the peer asks for authentication.
+    pub password: &'static str,
+}
+
+impl Default for PppConfig {
+    fn default() -> Self {
+        Self {
+            username: "",
+            password: "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    /// `run` is not running.
+    Dead,
+    /// Negotiating LCP.
+    Establish,
+    /// Authenticating with PAP.
+    Authenticate,
+    /// Negotiating IPCP.
+    Network,
+    /// IP packets can flow.
+    Open,
+}
+
+/// State of the Configure-Request exchanges of LCP or IPCP.
+struct Negotiation {
+    /// The peer acked our Configure-Request.
+    ours_acked: bool,
+    /// We acked the peer's Configure-Request.
+    theirs_acked: bool,
+    /// Identifier of our last Configure-Request.
+    id: u8,
+    /// When to send our Configure-Request (again), until it is acked.
+    retry_at: Instant,
+    attempts: u32,
+}
+
+impl Negotiation {
+    fn new(retry_at: Instant) -> Self {
+        Self {
+            ours_acked: false,
+            theirs_acked: false,
+            id: 0,
+            retry_at,
+            attempts: 0,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.ours_acked && self.theirs_acked
+    }
+}
+
+enum Verdict {
+    Ack,
+    /// Suggest this option instead.
+    Nak(&'static [u8]),
+    Reject,
+}
+
+struct PacketQueue<const N: usize, const LEN: usize> {
+    bufs: [[u8; LEN]; N],
+    lens: [usize; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize, const LEN: usize> PacketQueue<N, LEN> {
+    const fn new() -> Self {
+        Self {
+            bufs: [[0; LEN]; N],
+            lens: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns false if the queue is full or the packet too large.
+    fn push(&mut self, pkt: &[u8]) -> bool {
+        if self.is_full() || pkt.len() > LEN {
+            return false;
+        }
+        let i = (self.head + self.len) % N;
+        self.bufs[i][..pkt.len()].copy_from_slice(pkt);
+        self.lens[i] = pkt.len();
+        self.len += 1;
+        true
+    }
+
+    fn front(&mut self) -> Option<&mut [u8]> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(&mut self.bufs[self.head][..self.lens[self.head]])
+    }
+
+    fn pop(&mut self) {
+        if !self.is_empty() {
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+        }
+    }
+}
+
+struct Link {
+    config: PppConfig,
+    phase: Phase,
+    lcp: Negotiation,
+    ipcp: Negotiation,
+    /// The peer asked us to authenticate with PAP.
+    pap: bool,
+    pap_id: u8,
+    pap_retry_at: Instant,
+    pap_attempts: u32,
+    next_id: u8,
+
+    /// Our address, as requested in IPCP.
+    address: Ipv4Address,
+    /// DNS servers requested in IPCP, `None` if the peer rejected the option.
+    dns_servers: [Option<Ipv4Address>; 2],
+    peer_address: Option<Ipv4Address>,
+
+    rx_packets: PacketQueue<RX_QUEUE_LEN, MTU>,
+    ctrl_packets: PacketQueue<CTRL_QUEUE_LEN, CTRL_LEN>,
+    /// Protocol field and packet being transmitted by the stack.
+    tx_packet: [u8; 2 + MTU],
+    /// Encoded frame being written to the port.
+    tx_buf: [u8; TX_BUF_LEN],
+    tx_pos: usize,
+    tx_len: usize,
+
+    /// Waker of the stack.
+    device_waker: WakerRegistration,
+    /// Waker of `run`.
+    runner_waker: WakerRegistration,
+}
+
+impl Link {
+    fn set_phase(&mut self, phase: Phase) {
+        if (self.phase == Phase::Open) != (phase == Phase::Open) {
+            self.device_waker.wake();
+        }
+        self.phase = phase;
+    }
+
+    /// (Re)start negotiating from scratch, sending the first LCP request at `at`.
+    fn start(&mut self, at: Instant) {
+        self.set_phase(Phase::Establish);
+        self.lcp = Negotiation::new(at);
+        self.ipcp = Negotiation::new(at);
+        self.pap = false;
+        self.address = Ipv4Address::UNSPECIFIED;
+        self.dns_servers = [Some(Ipv4Address::UNSPECIFIED); 2];
+        self.peer_address = None;
+    }
+
+    fn stop(&mut self) {
+        self.set_phase(Phase::Dead);
+        self.ctrl_packets = PacketQueue::new();
+        self.tx_len = 0;
+        self.tx_pos = 0;
+        self.device_waker.wake();
+    }
+
+    fn enter_network(&mut self, now: Instant) {
+        self.set_phase(Phase::Network);
+        self.ipcp = Negotiation::new(now);
+    }
+
+    fn update_phase(&mut self, now: Instant) {
+        if self.phase == Phase::Establish && self.lcp.is_open() {
+            debug!("PPP: LCP opened");
+            if self.pap {
+                self.set_phase(Phase::Authenticate);
+                self.pap_retry_at = now;
+                self.pap_attempts = 0;
+            } else {
+                self.enter_network(now);
+            }
+        }
+        if self.phase == Phase::Network && self.ipcp.is_open() {
+            info!("PPP: link up, address {}", self.address);
+            self.set_phase(Phase::Open);
+        }
+    }
+
+    fn config(&self) -> Option<Config> {
+        if self.phase != Phase::Open {
+            return None;
+        }
+
+        let mut dns_servers = Vec::new();
+        for &addr in self.dns_servers.iter().flatten() {
+            if !addr.is_unspecified() {
+                // Can't fail, there are at most 2.
+                let _ = dns_servers.push(addr);
+            }
+        }
+
+        let mut config = Config::new(Ipv4Cidr::new(self.address, 32));
+        config.gateway = self.peer_address;
+        config.dns_servers = dns_servers;
+        Some(config)
+    }
+
+    fn new_id(&mut self) -> u8 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+
+    fn negotiation(&mut self, protocol: u16) -> &mut Negotiation {
+        match protocol {
+            PROTO_LCP => &mut self.lcp,
+            _ => &mut self.ipcp,
+        }
+    }
+
+    /// Queue a control packet for sending.
+    fn send_ctrl(&mut self, protocol: u16, code: u8, id: u8, data: &[u8]) {
+        if data.len() > CTRL_DATA_LEN {
+            warn!("PPP: control packet too large, dropping it");
+            return;
+        }
+
+        let len = 4 + data.len();
+        let mut buf = [0; CTRL_LEN];
+        buf[0..2].copy_from_slice(&protocol.to_be_bytes());
+        buf[2] = code;
+        buf[3] = id;
+        buf[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        buf[6..2 + len].copy_from_slice(data);
+        if !self.ctrl_packets.push(&buf[..2 + len]) {
+            debug!("PPP: control queue full, dropping packet");
+        }
+    }
+
+    /// Send anything that is due.
+    fn poll_timers(&mut self, now: Instant) {
+        match self.phase {
+            Phase::Establish if !self.lcp.ours_acked && self.lcp.retry_at <= now => {
+                self.send_configure_request(PROTO_LCP, now)
+            }
+            Phase::Authenticate if self.pap_retry_at <= now => self.send_pap(now),
+            Phase::Network if !self.ipcp.ours_acked && self.ipcp.retry_at <= now => {
+                self.send_configure_request(PROTO_IPCP, now)
+            }
+            _ => {}
+        }
+    }
+
+    /// When `poll_timers` has something to do next.
+    fn next_deadline(&self) -> Option<Instant> {
+        match self.phase {
+            Phase::Establish if !self.lcp.ours_acked => Some(self.lcp.retry_at),
+            Phase::Authenticate => Some(self.pap_retry_at),
+            Phase::Network if !self.ipcp.ours_acked => Some(self.ipcp.retry_at),
+            _ => None,
+        }
+    }
+
+    fn send_configure_request(&mut self, protocol: u16, now: Instant) {
+        if self.negotiation(protocol).attempts >= MAX_CONFIGURE {
+            warn!("PPP: negotiation timed out, restarting");
+            self.start(now);
+            return;
+        }
+
+        // We're happy with the default LCP options, we only ask for addresses in IPCP.
+        let mut opts = [0; 18];
+        let mut len = 0;
+        if protocol == PROTO_IPCP {
+            let addrs = [
+                (IPCP_ADDRESS, Some(self.address)),
+                (IPCP_DNS1, self.dns_servers[0]),
+                (IPCP_DNS2, self.dns_servers[1]),
+            ];
+            for &(kind, addr) in addrs.iter() {
+                if let Some(addr) = addr {
+                    opts[len] = kind;
+                    opts[len + 1] = 6;
+                    opts[len + 2..len + 6].copy_from_slice(addr.as_bytes());
+                    len += 6;
+                }
+            }
+        }
+
+        let id = self.new_id();
+        self.send_ctrl(protocol, CONFIGURE_REQUEST, id, &opts[..len]);
+        let neg = self.negotiation(protocol);
+        neg.id = id;
+        neg.retry_at = now + RESTART_TIMEOUT;
+        neg.attempts += 1;
+    }
+
+    fn send_pap(&mut self, now: Instant) {
+        if self.pap_attempts >= MAX_CONFIGURE {
+            warn!("PPP: authentication timed out, restarting");
+            self.start(now);
+            return;
+        }
+
+        // `Ppp::new` checked that they fit.
+        let username = self.config.username.as_bytes();
+        let password = self.config.password.as_bytes();
+        let len = 2 + username.len() + password.len();
+        let mut data = [0; CTRL_DATA_LEN];
+        data[0] = username.len() as u8;
+        data[1..1 + username.len()].copy_from_slice(username);
+        data[1 + username.len()] = password.len() as u8;
+        data[2 + username.len()..len].copy_from_slice(password);
+
+        self.pap_id = self.new_id();
+        self.send_ctrl(PROTO_PAP, PAP_AUTH_REQUEST, self.pap_id, &data[..len]);
+        self.pap_retry_at = now + RESTART_TIMEOUT;
+        self.pap_attempts += 1;
+    }
+
+    fn handle_frame(&mut self, protocol: u16, info: &[u8], now: Instant) {
+        match protocol {
+            PROTO_LCP => self.handle_control(PROTO_LCP, info, now),
+            PROTO_PAP if self.phase == Phase::Authenticate => self.handle_pap(info, now),
+            PROTO_IPCP if self.phase >= Phase::Network => {
+                self.handle_control(PROTO_IPCP, info, now)
+            }
+            PROTO_IPV4 if self.phase == Phase::Open => {
+                if self.rx_packets.push(info) {
+                    self.device_waker.wake();
+                } else {
+                    debug!("PPP: rx queue full, dropping packet");
+                }
+            }
+            // Not expected in the current phase, drop silently.
+            PROTO_PAP | PROTO_IPCP | PROTO_IPV4 => {}
+            _ if self.lcp.is_open() => {
+                debug!("PPP: rejecting protocol {}", protocol);
+                let mut data = [0; CTRL_DATA_LEN];
+                let len = (2 + info.len()).min(CTRL_DATA_LEN);
+                data[0..2].copy_from_slice(&protocol.to_be_bytes());
+                data[2..len].copy_from_slice(&info[..len - 2]);
+                let id = self.new_id();
+                self.send_ctrl(PROTO_LCP, PROTOCOL_REJECT, id, &data[..len]);
+            }
+            _ => {}
+        }
+
+        self.update_phase(now);
+    }
+
+    fn handle_pap(&mut self, pkt: &[u8], now: Instant) {
+        if pkt.len() < 4 || pkt[1] != self.pap_id {
+            return;
+        }
+        match pkt[0] {
+            PAP_AUTH_ACK => {
+                debug!("PPP: authenticated");
+                self.enter_network(now);
+            }
+            PAP_AUTH_NAK => {
+                warn!("PPP: authentication failed");
+                self.start(now + RESTART_TIMEOUT);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle an LCP or IPCP packet.
+    fn handle_control(&mut self, protocol: u16, pkt: &[u8], now: Instant) {
+        if pkt.len() < 4 {
+            return;
+        }
+        let code = pkt[0];
+        let id = pkt[1];
+        let len = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
+        if len < 4 || len > pkt.len() {
+            return;
+        }
+        let data = &pkt[4..len];
+        let our_id = self.negotiation(protocol).id;
+
+        match code {
+            CONFIGURE_REQUEST => {
+                // The peer is renegotiating an opened link, start over.
+                if self.negotiation(protocol).is_open() {
+                    if protocol == PROTO_LCP {
+                        self.start(now);
+                    } else {
+                        self.enter_network(now);
+                    }
+                }
+                let acked = self.respond_configure(protocol, id, data);
+                self.negotiation(protocol).theirs_acked = acked;
+            }
+            CONFIGURE_ACK if id == our_id => {
+                self.negotiation(protocol).ours_acked = true;
+            }
+            CONFIGURE_NAK | CONFIGURE_REJECT if id == our_id => {
+                if protocol == PROTO_IPCP {
+                    self.adjust_ipcp(code, data);
+                }
+                // Send the adjusted request right away.
+                self.negotiation(protocol).retry_at = now;
+            }
+            TERMINATE_REQUEST => {
+                self.send_ctrl(protocol, TERMINATE_ACK, id, &[]);
+                if protocol == PROTO_LCP {
+                    info!("PPP: link terminated by peer");
+                    self.start(now + RESTART_TIMEOUT);
+                } else {
+                    self.set_phase(Phase::Network);
+                    self.ipcp = Negotiation::new(now + RESTART_TIMEOUT);
+                }
+            }
+            ECHO_REQUEST if protocol == PROTO_LCP => {
+                if self.lcp.is_open() && data.len() >= 4 {
+                    // Reply with our magic number, which is 0 since we don't negotiate one.
+                    let mut reply = [0; CTRL_DATA_LEN];
+                    let len = data.len().min(CTRL_DATA_LEN);
+                    reply[4..len].copy_from_slice(&data[4..len]);
+                    self.send_ctrl(PROTO_LCP, ECHO_REPLY, id, &reply[..len]);
+                }
+            }
+            CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJECT | TERMINATE_ACK | CODE_REJECT => {}
+            PROTOCOL_REJECT | ECHO_REPLY | DISCARD_REQUEST if protocol == PROTO_LCP => {}
+            _ => {
+                let len = pkt.len().min(CTRL_DATA_LEN);
+                let id = self.new_id();
+                self.send_ctrl(protocol, CODE_REJECT, id, &pkt[..len]);
+            }
+        }
+    }
+
+    /// Answer the peer's Configure-Request, returning true if it was acked.
+    fn respond_configure(&mut self, protocol: u16, id: u8, data: &[u8]) -> bool {
+        let mut naks = [0; CTRL_DATA_LEN];
+        let mut naks_len = 0;
+        let mut rejects = [0; CTRL_DATA_LEN];
+        let mut rejects_len = 0;
+
+        for opt in Options(data) {
+            let (kind, value, raw) = match opt {
+                Some(opt) => opt,
+                // Malformed, ignore the whole request.
+                None => return false,
+            };
+
+            let verdict = match protocol {
+                PROTO_LCP => check_lcp_option(kind, value),
+                _ => check_ipcp_option(kind, value),
+            };
+            match verdict {
+                Verdict::Ack => {}
+                Verdict::Nak(opt) => {
+                    if naks_len + opt.len() <= naks.len() {
+                        naks[naks_len..naks_len + opt.len()].copy_from_slice(opt);
+                        naks_len += opt.len();
+                    }
+                }
+                Verdict::Reject => {
+                    if rejects_len + raw.len() <= rejects.len() {
+                        rejects[rejects_len..rejects_len + raw.len()].copy_from_slice(raw);
+                        rejects_len += raw.len();
+                    }
+                }
+            }
+        }
+
+        if rejects_len != 0 {
+            self.send_ctrl(protocol, CONFIGURE_REJECT, id, &rejects[..rejects_len]);
+            return false;
+        }
+        if naks_len != 0 {
+            self.send_ctrl(protocol, CONFIGURE_NAK, id, &naks[..naks_len]);
+            return false;
+        }
+
+        // Everything is acceptable, remember what was agreed on.
+        for (kind, value, _) in Options(data).flatten() {
+            match (protocol, kind) {
+                (PROTO_LCP, LCP_AUTH) => self.pap = true,
+                (PROTO_IPCP, IPCP_ADDRESS) => {
+                    self.peer_address = Some(Ipv4Address::from_bytes(value));
+                }
+                _ => {}
+            }
+        }
+        self.send_ctrl(protocol, CONFIGURE_ACK, id, data);
+        true
+    }
+
+    /// Apply a Configure-Nak or Configure-Reject of our IPCP request.
+    fn adjust_ipcp(&mut self, code: u8, data: &[u8]) {
+        for (kind, value, _) in Options(data).flatten() {
+            let addr = match value.len() {
+                4 => Some(Ipv4Address::from_bytes(value)),
+                _ => None,
+            };
+            let slot = match kind {
+                IPCP_ADDRESS => {
+                    if code == CONFIGURE_NAK {
+                        if let Some(addr) = addr {
+                            self.address = addr;
+                        }
+                    } else {
+                        warn!("PPP: peer refuses to assign an address");
+                    }
+                    continue;
+                }
+                IPCP_DNS1 => &mut self.dns_servers[0],
+                IPCP_DNS2 => &mut self.dns_servers[1],
+                _ => continue,
+            };
+            *slot = if code == CONFIGURE_NAK { addr } else { None };
+        }
+    }
+}
+
+fn check_lcp_option(kind: u8, value: &[u8]) -> Verdict {
+    match (kind, value.len()) {
+        // The stack sends packets of up to MTU bytes, the peer must be able to receive them.
+        (LCP_MRU, 2) if (u16::from_be_bytes([value[0], value[1]]) as usize) < MTU => {
+            const MRU: [u8; 4] = [LCP_MRU, 4, (MTU >> 8) as u8, MTU as u8];
+            Verdict::Nak(&MRU)
+        }
+        (LCP_MRU, 2) | (LCP_ACCM, 4) | (LCP_MAGIC, 4) | (LCP_PFC, 0) | (LCP_ACFC, 0) => {
+            Verdict::Ack
+        }
+        (LCP_AUTH, _) if value == PROTO_PAP.to_be_bytes() => Verdict::Ack,
+        // CHAP or anything else, we only support PAP.
+        (LCP_AUTH, _) => Verdict::Nak(&[LCP_AUTH, 4, 0xC0, 0x23]),
+        _ => Verdict::Reject,
+    }
+}
+
+fn check_ipcp_option(kind: u8, value: &[u8]) -> Verdict {
+    match (kind, value.len()) {
+        (IPCP_ADDRESS, 4) => Verdict::Ack,
+        _ => Verdict::Reject,
+    }
+}
+
+/// Iterator over the options of a Configure-* packet.
+///
+/// Yields the type, value and raw bytes of each option, or `None` if the options are
+/// malformed.
+struct Options<'a>(&'a [u8]);
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Option<(u8, &'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.0;
+        match data {
+            [] => None,
+            [kind, len, ..] if *len >= 2 && *len as usize <= data.len() => {
+                let (opt, rest) = data.split_at(*len as usize);
+                self.0 = rest;
+                Some(Some((*kind, &opt[2..], opt)))
+            }
+            _ => {
+                self.0 = &[];
+                Some(None)
+            }
+        }
+    }
+}
+
+struct State {
+    decoder: hdlc::Decoder<DECODER_LEN>,
+    link: Link,
+}
+
+impl State {
+    fn poll<P: AsyncBufRead + AsyncWrite + Unpin>(
+        &mut self,
+        cx: &mut Context<'_>,
+        port: &mut P,
+    ) -> Poll<io::Error> {
+        loop {
+            let now = Instant::now();
+            self.link.poll_timers(now);
+
+            // Control packets go first, the stack can only transmit when tx_buf is free.
+            if self.link.tx_len == 0 {
+                if let Some(pkt) = self.link.ctrl_packets.front() {
+                    self.link.tx_len = hdlc::encode(pkt, &mut self.link.tx_buf);
+                    self.link.tx_pos = 0;
+                    self.link.ctrl_packets.pop();
+                }
+            }
+
+            if self.link.tx_len != 0 {
+                let buf = &self.link.tx_buf[self.link.tx_pos..self.link.tx_len];
+                match Pin::new(&mut *port).poll_write(cx, buf) {
+                    Poll::Ready(Ok(0)) => return Poll::Ready(io::Error::WriteZero),
+                    Poll::Ready(Ok(n)) => {
+                        self.link.tx_pos += n;
+                        if self.link.tx_pos == self.link.tx_len {
+                            self.link.tx_pos = 0;
+                            self.link.tx_len = 0;
+                            self.link.device_waker.wake();
+                        }
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(e),
+                    Poll::Pending => {}
+                }
+            }
+
+            // Stop reading while the stack is behind, so the port applies backpressure
+            // instead of us dropping packets.
+            if !self.link.rx_packets.is_full() {
+                match Pin::new(&mut *port).poll_fill_buf(cx) {
+                    Poll::Ready(Ok([])) => return Poll::Ready(io::Error::UnexpectedEof),
+                    Poll::Ready(Ok(buf)) => {
+                        let mut n = 0;
+                        for &b in buf {
+                            n += 1;
+                            if let Some((protocol, info)) = self.decoder.push(b) {
+                                self.link.handle_frame(protocol, info, now);
+                                if self.link.rx_packets.is_full() {
+                                    break;
+                                }
+                            }
+                        }
+                        Pin::new(&mut *port).consume(n);
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(e),
+                    Poll::Pending => {}
+                }
+            }
+
+            if let Some(deadline) = self.link.next_deadline() {
+                let timer = Timer::at(deadline);
+                pin_mut!(timer);
+                if timer.poll(cx).is_ready() {
+                    continue;
+                }
+            }
+
+            // Woken by the device when the stack transmits or takes a received packet.
+            self.link.runner_waker.register(cx.waker());
+            return Poll::Pending;
+        }
+    }
+}
+
+/// PPP link, see the [module documentation](self).
+pub struct Ppp {
+    state: ThreadModeMutex<RefCell<State>>,
+}
+
+impl Ppp {
+    /// Create a link with the given settings.
+    ///
+    /// Fails with `Error::Exhausted` if the PAP user name and password are longer than
+    /// [PAP_MAX_CREDENTIALS_LEN] bytes together.
+    pub fn new(config: PppConfig) -> Result<Self> {
+        if config.username.len() + config.password.len() > PAP_MAX_CREDENTIALS_LEN {
+            return Err(Error::Exhausted);
+        }
+
+        let now = Instant::from_ticks(0);
+        Ok(Self {
+            state: ThreadModeMutex::new(RefCell::new(State {
+                decoder: hdlc::Decoder::new(),
+                link: Link {
+                    config,
+                    phase: Phase::Dead,
+                    lcp: Negotiation::new(now),
+                    ipcp: Negotiation::new(now),
+                    pap: false,
+                    pap_id: 0,
+                    pap_retry_at: now,
+                    pap_attempts: 0,
+                    next_id: 0,
+                    address: Ipv4Address::UNSPECIFIED,
+                    dns_servers: [Some(Ipv4Address::UNSPECIFIED); 2],
+                    peer_address: None,
+                    rx_packets: PacketQueue::new(),
+                    ctrl_packets: PacketQueue::new(),
+                    tx_packet: [0; 2 + MTU],
+                    tx_buf: [0; TX_BUF_LEN],
+                    tx_pos: 0,
+                    tx_len: 0,
+                    device_waker: WakerRegistration::new(),
+                    runner_waker: WakerRegistration::new(),
+                },
+            })),
+        })
+    }
+
+    /// The [Device] to give to the [Stack](crate::Stack).
+    pub fn device(&'static self) -> PppDevice {
+        PppDevice { ppp: self }
+    }
+
+    /// The [Configurator] to give to the [Stack](crate::Stack).
+    pub fn configurator(&'static self) -> PppConfigurator {
+        PppConfigurator {
+            ppp: self,
+            current: None,
+        }
+    }
+
+    /// Run the link over `port`. This must be running for the device to work.
+    ///
+    /// Only returns if reading or writing fails, for example because the modem hung up.
+    /// It can then be called again to reconnect.
+    pub async fn run<P: AsyncBufRead + AsyncWrite + Unpin>(&self, port: &mut P) -> io::Error {
+        self.with(|s| {
+            s.decoder = hdlc::Decoder::new();
+            s.link.start(Instant::now());
+        });
+        let err = futures::future::poll_fn(|cx| self.with(|s| s.poll(cx, port))).await;
+        self.with(|s| s.link.stop());
+        warn!("PPP: link lost: {:?}", err);
+        err
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        f(&mut *self.state.borrow().borrow_mut())
+    }
+}
+
+/// [Device] of a [Ppp] link.
+pub struct PppDevice {
+    ppp: &'static Ppp,
+}
+
+impl Device for PppDevice {
+    fn is_receive_ready(&mut self) -> bool {
+        self.ppp.with(|s| !s.link.rx_packets.is_empty())
+    }
+
+    fn receive(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        self.ppp.with(|s| {
+            if let Some(pkt) = s.link.rx_packets.front() {
+                f(pkt);
+                s.link.rx_packets.pop();
+                s.link.runner_waker.wake();
+            }
+        })
+    }
+
+    fn is_transmit_ready(&mut self) -> bool {
+        self.ppp.with(|s| s.link.tx_len == 0)
+    }
+
+    fn transmit(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8]) -> Result<()>) -> Result<()> {
+        if len > MTU {
+            return Err(Error::Truncated);
+        }
+        self.ppp.with(|s| {
+            let link = &mut s.link;
+            f(&mut link.tx_packet[2..2 + len])?;
+            if link.phase != Phase::Open {
+                // Link went down since the stack checked, drop the packet.
+                return Ok(());
+            }
+            link.tx_packet[0..2].copy_from_slice(&PROTO_IPV4.to_be_bytes());
+            link.tx_len = hdlc::encode(&link.tx_packet[..2 + len], &mut link.tx_buf);
+            link.tx_pos = 0;
+            link.runner_waker.wake();
+            Ok(())
+        })
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.ppp.with(|s| s.link.device_waker.register(waker))
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.medium = Medium::Ip;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        match self.ppp.with(|s| s.link.phase) {
+            Phase::Open => LinkState::Up,
+            _ => LinkState::Down,
+        }
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        [0; 6]
+    }
+}
+
+/// [Configurator] of a [Ppp] link, configures the address and DNS servers negotiated
+/// with IPCP.
+pub struct PppConfigurator {
+    ppp: &'static Ppp,
+    current: Option<Config>,
+}
+
+impl Configurator for PppConfigurator {
+    fn poll(
+        &mut self,
+        _iface: &mut Interface,
+        _sockets: &mut SocketSet,
+        _timestamp: SmolInstant,
+    ) -> Event {
+        let config = self.ppp.with(|s| s.link.config());
+        if config == self.current {
+            return Event::NoChange;
+        }
+
+        self.current = config.clone();
+        match config {
+            Some(config) => Event::Configured(config),
+            None => Event::Deconfigured,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mru() {
+        assert!(matches!(
+            check_lcp_option(LCP_MRU, &[0x05, 0xDC]),
+            Verdict::Ack
+        ));
+        assert!(matches!(
+            check_lcp_option(LCP_MRU, &[0x10, 0x00]),
+            Verdict::Ack
+        ));
+        match check_lcp_option(LCP_MRU, &[0x02, 0x40]) {
+            Verdict::Nak(opt) => assert_eq!(opt, &[LCP_MRU, 4, 0x05, 0xDC]),
+            _ => panic!("MRU below MTU not naked"),
+        }
+        assert!(matches!(
+            check_lcp_option(LCP_MRU, &[0x05]),
+            Verdict::Reject
+        ));
+    }
+
+    #[test]
+    fn auth() {
+        assert!(matches!(
+            check_lcp_option(LCP_AUTH, &[0xC0, 0x23]),
+            Verdict::Ack
+        ));
+        // CHAP with MD5.
+        match check_lcp_option(LCP_AUTH, &[0xC2, 0x23, 0x05]) {
+            Verdict::Nak(opt) => assert_eq!(opt, &[LCP_AUTH, 4, 0xC0, 0x23]),
+            _ => panic!("CHAP not naked"),
+        }
+    }
+
+    #[test]
+    fn options() {
+        let data = [LCP_MRU, 4, 0x05, 0xDC, LCP_PFC, 2, LCP_MAGIC, 6, 1, 2, 3, 4];
+        let mut options = Options(&data);
+        assert_eq!(
+            options.next(),
+            Some(Some((LCP_MRU, &[0x05, 0xDC][..], &data[..4])))
+        );
+        assert_eq!(options.next(), Some(Some((LCP_PFC, &[][..], &data[4..6]))));
+        assert_eq!(
+            options.next(),
+            Some(Some((LCP_MAGIC, &[1, 2, 3, 4][..], &data[6..])))
+        );
+        assert_eq!(options.next(), None);
+
+        // Length past the end, or too short to cover the header.
+        let mut options = Options(&[LCP_MRU, 4, 0x05]);
+        assert_eq!(options.next(), Some(None));
+        assert_eq!(options.next(), None);
+        let mut options = Options(&[LCP_PFC, 1, LCP_ACFC, 2]);
+        assert_eq!(options.next(), Some(None));
+        assert_eq!(options.next(), None);
+    }
+
+    #[test]
+    fn pap_credentials_too_long() {
+        const NAME: &str = "0123456789012345678901234567890123456789012345678901234567890123";
+        let config = PppConfig {
+            username: NAME,
+            password: &NAME[..PAP_MAX_CREDENTIALS_LEN - NAME.len()],
+        };
+        assert!(Ppp::new(config).is_ok());
+        let config = PppConfig {
+            username: NAME,
+            password: &NAME[..PAP_MAX_CREDENTIALS_LEN - NAME.len() + 1],
+        };
+        assert!(matches!(Ppp::new(config), Err(Error::Exhausted)));
+    }
+}
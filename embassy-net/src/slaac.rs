@@ -0,0 +1,210 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862).
+//!
+//! Listens for router advertisements on a raw ICMPv6 socket, and derives a global address
+//! from the advertised prefix and the interface's EUI-64 identifier. The advertising
+//! router is used as the default IPv6 gateway.
+
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer, SocketHandle};
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{
+    EthernetAddress, Icmpv6Packet, Icmpv6Repr, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr,
+    Ipv6Packet, Ipv6Repr, NdiscPrefixInfoFlags, NdiscRepr,
+};
+
+use crate::fmt::*;
+use crate::stack::{add_socket, eui64};
+use crate::{Result, SocketSet};
+
+const RX_LEN: usize = 512;
+const TX_LEN: usize = 64;
+
+/// Buffers for the SLAAC raw socket, part of [StackResources](crate::StackResources).
+pub(crate) struct SlaacResources {
+    rx_meta: [RawPacketMetadata; 2],
+    rx_buffer: [u8; RX_LEN],
+    tx_meta: [RawPacketMetadata; 1],
+    tx_buffer: [u8; TX_LEN],
+}
+
+impl SlaacResources {
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [RawPacketMetadata::EMPTY; 2],
+            rx_buffer: [0; RX_LEN],
+            tx_meta: [RawPacketMetadata::EMPTY; 1],
+            tx_buffer: [0; TX_LEN],
+        }
+    }
+}
+
+pub(crate) struct Slaac {
+    handle: SocketHandle,
+    mac: EthernetAddress,
+    /// A router solicitation should be sent as soon as possible.
+    solicit: bool,
+    address: Option<(Ipv6Cidr, SmolInstant)>,
+    router: Option<(Ipv6Address, SmolInstant)>,
+}
+
+impl Slaac {
+    pub fn new(
+        sockets: &mut SocketSet,
+        sockets_len: usize,
+        resources: &'static mut SlaacResources,
+        mac: EthernetAddress,
+    ) -> Result<Self> {
+        let socket = RawSocket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            RawSocketBuffer::new(&mut resources.rx_meta[..], &mut resources.rx_buffer[..]),
+            RawSocketBuffer::new(&mut resources.tx_meta[..], &mut resources.tx_buffer[..]),
+        );
+
+        Ok(Self {
+            handle: add_socket(sockets, sockets_len, socket)?,
+            mac,
+            solicit: true,
+            address: None,
+            router: None,
+        })
+    }
+
+    pub fn address(&self) -> Option<Ipv6Cidr> {
+        self.address.map(|(addr, _)| addr)
+    }
+
+    pub fn router(&self) -> Option<Ipv6Address> {
+        self.router.map(|(addr, _)| addr)
+    }
+
+    /// Forget everything learned, for example because the link went down.
+    pub fn reset(&mut self) {
+        self.solicit = true;
+        self.address = None;
+        self.router = None;
+    }
+
+    /// Process received router advertisements, and expire stale state.
+    ///
+    /// Returns true if the address or router changed.
+    pub fn poll(
+        &mut self,
+        sockets: &mut SocketSet,
+        link_local: Ipv6Address,
+        timestamp: SmolInstant,
+    ) -> bool {
+        let old = (self.address(), self.router());
+
+        if matches!(self.address, Some((_, t)) if t <= timestamp) {
+            self.address = None;
+        }
+        if matches!(self.router, Some((_, t)) if t <= timestamp) {
+            self.router = None;
+        }
+
+        let mut socket = sockets.get::<RawSocket>(self.handle);
+
+        if self.solicit {
+            match self.send_solicit(&mut socket, link_local) {
+                Ok(()) => self.solicit = false,
+                // tx buffer full, try again on next poll.
+                Err(_) => {}
+            }
+        }
+
+        while let Ok(pkt) = socket.recv() {
+            if let Err(e) = self.process(pkt, timestamp) {
+                debug!("SLAAC: ignoring malformed packet: {:?}", e);
+            }
+        }
+
+        old != (self.address(), self.router())
+    }
+
+    fn send_solicit(&self, socket: &mut RawSocket, src_addr: Ipv6Address) -> Result<()> {
+        let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit {
+            lladdr: Some(self.mac),
+        });
+        let ip_repr = Ipv6Repr {
+            src_addr,
+            dst_addr: Ipv6Address::LINK_LOCAL_ALL_ROUTERS,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: icmp_repr.buffer_len(),
+            hop_limit: 255,
+        };
+
+        let buf = socket.send(ip_repr.buffer_len() + icmp_repr.buffer_len())?;
+        let mut ip_packet = Ipv6Packet::new_unchecked(buf);
+        ip_repr.emit(&mut ip_packet);
+        let mut icmp_packet = Icmpv6Packet::new_unchecked(ip_packet.payload_mut());
+        icmp_repr.emit(
+            &src_addr.into(),
+            &ip_repr.dst_addr.into(),
+            &mut icmp_packet,
+            &ChecksumCapabilities::default(),
+        );
+        Ok(())
+    }
+
+    fn process(&mut self, pkt: &[u8], timestamp: SmolInstant) -> Result<()> {
+        let ip_packet = Ipv6Packet::new_checked(pkt)?;
+        let ip_repr = Ipv6Repr::parse(&ip_packet)?;
+
+        // RFC 4861 section 6.1.2: router advertisements must come from a link-local
+        // address, and must not have been forwarded.
+        if ip_repr.hop_limit != 255 || !ip_repr.src_addr.is_link_local() {
+            return Ok(());
+        }
+
+        let icmp_packet = Icmpv6Packet::new_checked(ip_packet.payload())?;
+        let icmp_repr = Icmpv6Repr::parse(
+            &ip_repr.src_addr.into(),
+            &ip_repr.dst_addr.into(),
+            &icmp_packet,
+            &ChecksumCapabilities::default(),
+        )?;
+
+        let (router_lifetime, prefix_info) = match icmp_repr {
+            Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+                router_lifetime,
+                prefix_info,
+                ..
+            }) => (router_lifetime, prefix_info),
+            _ => return Ok(()),
+        };
+
+        if router_lifetime.total_millis() == 0 {
+            if matches!(self.router, Some((addr, _)) if addr == ip_repr.src_addr) {
+                self.router = None;
+            }
+        } else {
+            self.router = Some((ip_repr.src_addr, timestamp + router_lifetime));
+        }
+
+        if let Some(info) = prefix_info {
+            // Only /64 prefixes can be combined with an EUI-64 interface identifier.
+            if !info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF) || info.prefix_len != 64 {
+                return Ok(());
+            }
+
+            let mut addr = [0; 16];
+            addr[..8].copy_from_slice(&info.prefix.as_bytes()[..8]);
+            addr[8..].copy_from_slice(&eui64(self.mac));
+            let addr = Ipv6Cidr::new(Ipv6Address::from_bytes(&addr), 64);
+
+            if info.valid_lifetime.total_millis() == 0 {
+                if matches!(self.address, Some((a, _)) if a == addr) {
+                    self.address = None;
+                }
+            } else {
+                if self.address.map(|(a, _)| a) != Some(addr) {
+                    debug!("SLAAC: acquired address {}", addr);
+                }
+                self.address = Some((addr, timestamp + info.valid_lifetime));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+//! SNTP client.
+//!
+//! Sets the [WallClock] from NTP servers, using SNTP (RFC 4330). [run] keeps it synced,
+//! usually in a dedicated task:
+//!
+//! ```ignore
+//! #[embassy::task]
+//! async fn sntp_task(stack: &'static Stack) {
+//!     sntp::run(stack, &SntpConfig { servers: &["pool.ntp.org"], ..Default::default() }).await
+//! }
+//! ```
+//!
+//! Server names are resolved with the [dns](crate::dns) module if enabled, otherwise only
+//! IPv4 address literals are accepted. Each sync also measures the drift of the local clock,
+//! so the wall clock stays accurate between syncs.
+
+use embassy::time::{with_timeout, Duration, Instant, Timer, WallClock, TICKS_PER_SECOND};
+use smoltcp::socket::UdpPacketMetadata;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use crate::fmt::*;
+use crate::stack::rand;
+use crate::{Stack, UdpSocket};
+
+const NTP_PORT: u16 = 123;
+const PACKET_LEN: usize = 48;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 2;
+
+/// Leap indicator 0, version 4, mode 3 (client).
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_TO_UNIX_SECS: u64 = 2_208_988_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A server name couldn't be resolved.
+    InvalidServer,
+    /// No server answered in time.
+    Timeout,
+    /// The server isn't synchronized, or asked us to stop querying it.
+    Unsynchronized,
+    /// The UDP socket returned an error.
+    Network(crate::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Parameters of [run].
+#[derive(Debug, Clone, Copy)]
+pub struct SntpConfig<'a> {
+    /// Names or addresses of the servers, tried in turn until one answers.
+    pub servers: &'a [&'a str],
+    /// Time between syncs.
+    pub interval: Duration,
+    /// Time before trying again after a failed sync.
+    pub retry_interval: Duration,
+}
+
+impl<'a> Default for SntpConfig<'a> {
+    fn default() -> Self {
+        Self {
+            servers: &[],
+            interval: Duration::from_secs(60 * 60),
+            retry_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Keep the [WallClock] synced with the servers. Never returns.
+///
+/// Waits for the stack to be configured before each sync.
+pub async fn run(stack: &Stack, config: &SntpConfig<'_>) {
+    loop {
+        while !stack.is_config_up() {
+            stack.wait_config_change().await;
+        }
+
+        match sync(stack, config.servers).await {
+            Ok(()) => Timer::after(config.interval).await,
+            Err(e) => {
+                warn!("SNTP: sync failed: {:?}", e);
+                Timer::after(config.retry_interval).await
+            }
+        }
+    }
+}
+
+/// Sync the [WallClock] once, with the first of the servers to answer.
+pub async fn sync(stack: &Stack, servers: &[&str]) -> Result<()> {
+    let mut res = Err(Error::Timeout);
+    for &server in servers {
+        res = match resolve(stack, server).await {
+            Ok(addr) => query(stack, addr).await,
+            Err(e) => Err(e),
+        };
+        if let Ok((unix, instant)) = res {
+            if let Some(old) = WallClock::at(instant) {
+                let offset = unix.as_millis() as i64 - old.as_millis() as i64;
+                debug!("SNTP: synced with {}, offset {} ms", server, offset);
+            } else {
+                debug!("SNTP: synced with {}", server);
+            }
+            WallClock::sync(unix, instant);
+            return Ok(());
+        }
+    }
+    res.map(|_| ())
+}
+
+#[cfg(feature = "dns")]
+async fn resolve(stack: &Stack, server: &str) -> Result<IpAddress> {
+    crate::dns::resolve(stack, server)
+        .await
+        .map_err(|_| Error::InvalidServer)
+}
+
+#[cfg(not(feature = "dns"))]
+async fn resolve(_stack: &Stack, server: &str) -> Result<IpAddress> {
+    server
+        .parse::<smoltcp::wire::Ipv4Address>()
+        .map(Into::into)
+        .map_err(|_| Error::InvalidServer)
+}
+
+/// Ask the time to a server, without setting the [WallClock].
+///
+/// Returns the time since the Unix epoch at the returned instant, compensated for the
+/// network delay. This needs a free slot in the socket set while the query is in progress.
+pub async fn query(stack: &Stack, server: IpAddress) -> Result<(Duration, Instant)> {
+    let mut rx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; PACKET_LEN];
+    let mut tx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; PACKET_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    )
+    .map_err(Error::Network)?;
+    socket.bind(0).map_err(Error::Network)?;
+
+    let server = IpEndpoint::new(server, NTP_PORT);
+    let mut buf = [0; PACKET_LEN];
+
+    for _ in 0..QUERY_ATTEMPTS {
+        // The server echoes the transmit timestamp, which doesn't have to be the time, so
+        // a random one identifies the answer to this request.
+        let mut transmit = [0; 8];
+        rand(&mut transmit);
+        buf.fill(0);
+        buf[0] = CLIENT_HEADER;
+        buf[40..48].copy_from_slice(&transmit);
+
+        let sent_at = Instant::now();
+        socket.send_to(&buf, server).await.map_err(Error::Network)?;
+
+        let deadline = sent_at + QUERY_TIMEOUT;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            let (len, from) = match with_timeout(
+                deadline.duration_since(now),
+                socket.recv_from(&mut buf),
+            )
+            .await
+            {
+                Ok(Ok(x)) => x,
+                Ok(Err(smoltcp::Error::Truncated)) => continue,
+                Ok(Err(e)) => return Err(Error::Network(e)),
+                Err(_) => break,
+            };
+            let received_at = Instant::now();
+
+            if from != server {
+                continue;
+            }
+            match parse_response(&buf[..len], &transmit) {
+                // Not an answer to our request, keep waiting.
+                None => continue,
+                Some(Err(e)) => return Err(e),
+                Some(Ok((server_rx, server_tx))) => {
+                    let unix = receive_time(server_rx, server_tx, received_at - sent_at);
+                    return Ok((unix, received_at));
+                }
+            }
+        }
+    }
+
+    Err(Error::Timeout)
+}
+
+/// Time since the Unix epoch when the response was received, given the server's receive
+/// and transmit timestamps and the round trip measured locally.
+fn receive_time(server_rx: u64, server_tx: u64, round_trip: Duration) -> Duration {
+    // The round trip, minus the time the server took to answer. The answer is assumed to
+    // have taken half of it.
+    let server_time = server_tx.saturating_sub(server_rx);
+    let delay = round_trip.as_ticks().saturating_sub(server_time);
+    Duration::from_ticks(server_tx + delay / 2)
+}
+
+/// Parse a response to the request with the given transmit timestamp.
+///
+/// Returns `None` if the packet is not a response to that request. Otherwise returns the
+/// receive and transmit timestamps of the server, in ticks since the Unix epoch.
+fn parse_response(pkt: &[u8], transmit: &[u8; 8]) -> Option<Result<(u64, u64)>> {
+    if pkt.len() < PACKET_LEN || pkt[0] & 0x07 != MODE_SERVER || &pkt[24..32] != transmit {
+        return None;
+    }
+    let stratum = pkt[1];
+    // Stratum 0 is a kiss-of-death, with a reason instead of a reference.
+    if pkt[0] >> 6 == LEAP_UNSYNCHRONIZED || stratum == 0 || stratum > 15 {
+        return Some(Err(Error::Unsynchronized));
+    }
+
+    let receive = timestamp(&pkt[32..40]);
+    let transmit = timestamp(&pkt[40..48]);
+    match (receive, transmit) {
+        (Some(receive), Some(transmit)) => Some(Ok((receive, transmit))),
+        _ => Some(Err(Error::Unsynchronized)),
+    }
+}
+
+/// Convert an NTP timestamp to ticks since the Unix epoch, `None` if it's zero.
+fn timestamp(b: &[u8]) -> Option<u64> {
+    let secs = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64;
+    let fraction = u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as u64;
+    if secs == 0 && fraction == 0 {
+        return None;
+    }
+    // Timestamps with the top bit clear are after the NTP era rollover in 2036.
+    let secs = match secs & 0x8000_0000 {
+        0 => secs + (1 << 32),
+        _ => secs,
+    };
+    let unix_secs = secs - NTP_TO_UNIX_SECS;
+    Some(unix_secs * TICKS_PER_SECOND + ((fraction * TICKS_PER_SECOND) >> 32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TRANSMIT: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// A response to the request with `TRANSMIT`, received at `rx` and sent at `tx`, as NTP
+    /// seconds.
+    fn response(rx: u32, tx: u32) -> [u8; PACKET_LEN] {
+        let mut pkt = [0; PACKET_LEN];
+        pkt[0] = 0x24; // Version 4, mode 4 (server).
+        pkt[1] = 2;
+        pkt[24..32].copy_from_slice(&TRANSMIT);
+        pkt[32..36].copy_from_slice(&rx.to_be_bytes());
+        pkt[40..44].copy_from_slice(&tx.to_be_bytes());
+        pkt
+    }
+
+    fn secs(secs: u64) -> u64 {
+        secs * TICKS_PER_SECOND
+    }
+
+    #[test]
+    fn timestamps() {
+        // 2021-05-07 00:00:00.5 UTC.
+        let t = timestamp(&[0xe4, 0x3f, 0x01, 0x80, 0x80, 0, 0, 0]);
+        assert_eq!(t, Some(secs(1_620_345_600) + TICKS_PER_SECOND / 2));
+        // The last second of era 0, 2036-02-07 06:28:15 UTC.
+        let t = timestamp(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert_eq!(t, Some(secs(2_085_978_495)));
+        // Era 1, with the top bit clear again.
+        assert_eq!(
+            timestamp(&[0, 0, 0, 0, 0, 0, 0, 1]),
+            Some(secs(2_085_978_496))
+        );
+        assert_eq!(
+            timestamp(&[0, 0, 0, 1, 0, 0, 0, 0]),
+            Some(secs(2_085_978_497))
+        );
+        let t = timestamp(&[0x7f, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert_eq!(t, Some(secs(2_085_978_496 + 0x7fff_ffff)));
+        // Zero is unset.
+        assert_eq!(timestamp(&[0; 8]), None);
+    }
+
+    #[test]
+    fn parse() {
+        let pkt = response(0xe43f_0180, 0xe43f_0181);
+        let rx = secs(1_620_345_600);
+        assert_eq!(
+            parse_response(&pkt, &TRANSMIT),
+            Some(Ok((rx, rx + secs(1))))
+        );
+
+        // Not an answer to our request.
+        assert_eq!(parse_response(&pkt[..47], &TRANSMIT), None);
+        assert_eq!(parse_response(&pkt, &[0; 8]), None);
+        let mut broadcast = pkt;
+        broadcast[0] = 0x25;
+        assert_eq!(parse_response(&broadcast, &TRANSMIT), None);
+
+        let mut unsynchronized = pkt;
+        unsynchronized[0] |= 0xc0;
+        assert_eq!(
+            parse_response(&unsynchronized, &TRANSMIT),
+            Some(Err(Error::Unsynchronized))
+        );
+        let mut kiss_of_death = pkt;
+        kiss_of_death[1] = 0;
+        kiss_of_death[12..16].copy_from_slice(b"RATE");
+        assert_eq!(
+            parse_response(&kiss_of_death, &TRANSMIT),
+            Some(Err(Error::Unsynchronized))
+        );
+        let mut unset = pkt;
+        unset[32..40].fill(0);
+        assert_eq!(
+            parse_response(&unset, &TRANSMIT),
+            Some(Err(Error::Unsynchronized))
+        );
+    }
+
+    #[test]
+    fn delay() {
+        let ms = |ms| Duration::from_millis(ms).as_ticks();
+        let rx = secs(1_620_345_600);
+
+        // 100 ms round trip, 20 of which in the server: the answer took 40 ms.
+        let unix = receive_time(rx, rx + ms(20), Duration::from_millis(100));
+        assert_eq!(unix.as_ticks(), rx + ms(20) + ms(40));
+        // An instant answer over a symmetric link.
+        let unix = receive_time(rx, rx, Duration::from_millis(30));
+        assert_eq!(unix.as_ticks(), rx + ms(30) / 2);
+        // The server claims to have taken longer than the round trip.
+        let unix = receive_time(rx, rx + ms(200), Duration::from_millis(100));
+        assert_eq!(unix.as_ticks(), rx + ms(200));
+        // Timestamps out of order.
+        let unix = receive_time(rx + ms(10), rx, Duration::from_millis(100));
+        assert_eq!(unix.as_ticks(), rx + ms(50));
+    }
+}
@@ -1,62 +1,146 @@
 use core::cell::RefCell;
 use core::future::Future;
+use core::pin::Pin;
 use core::task::Context;
 use core::task::Poll;
-use embassy::time::{Instant, Timer};
+use core::task::Waker;
+use embassy::time::{Duration, Instant, Timer};
 use embassy::util::ThreadModeMutex;
-use embassy::util::{Forever, WakerRegistration};
+use embassy::util::WakerRegistration;
 use futures::pin_mut;
+use heapless::Vec;
 use smoltcp::iface::InterfaceBuilder;
 #[cfg(feature = "medium-ethernet")]
 use smoltcp::iface::{Neighbor, NeighborCache, Route, Routes};
 use smoltcp::phy::Device as _;
 use smoltcp::phy::Medium;
-use smoltcp::socket::SocketSetItem;
+use smoltcp::socket::{Socket, SocketHandle, SocketSetItem};
 use smoltcp::time::Instant as SmolInstant;
 #[cfg(feature = "medium-ethernet")]
 use smoltcp::wire::EthernetAddress;
 use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 
-use crate::config::Configurator;
+#[cfg(feature = "dhcpv4")]
+use crate::config::DhcpResources;
 use crate::config::Event;
+use crate::config::{Config, Configurator, ConfiguratorResources};
 use crate::device::{Device, DeviceAdapter, LinkState};
 use crate::fmt::*;
-use crate::{Interface, SocketSet};
+#[cfg(feature = "slaac")]
+use crate::slaac::{Slaac, SlaacResources};
+use crate::{Error, Interface, Result, SocketSet};
 
+#[cfg(not(feature = "proto-ipv6"))]
 const ADDRESSES_LEN: usize = 1;
-const NEIGHBOR_CACHE_LEN: usize = 8;
-const SOCKETS_LEN: usize = 2;
+/// The IPv4 address, the IPv6 addresses from the `Config`, the SLAAC address and the
+/// link-local address.
+#[cfg(feature = "proto-ipv6")]
+const ADDRESSES_LEN: usize = 1 + 2 + 1 + 1;
+#[cfg(all(feature = "medium-ethernet", not(feature = "proto-ipv6")))]
+const ROUTES_LEN: usize = 1;
+#[cfg(all(feature = "medium-ethernet", feature = "proto-ipv6"))]
+const ROUTES_LEN: usize = 2;
 const LOCAL_PORT_MIN: u16 = 1025;
 const LOCAL_PORT_MAX: u16 = 65535;
-
-struct StackResources {
+const EVENT_WAKERS_LEN: usize = 4;
+/// How often tasks waiting for a link or config change check for it, when there are more
+/// of them than `EVENT_WAKERS_LEN`.
+const EVENT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Memory used by the network stack.
+///
+/// `SOCK` is the maximum number of sockets that can exist at the same time, including the
+/// ones used internally by [DhcpConfigurator](crate::DhcpConfigurator) and SLAAC, if enabled. `NEIGHBOR` is the number
+/// of entries in the neighbor (ARP) cache, which is only used with Ethernet devices. The
+/// buffers of the DHCP and SLAAC sockets are included too.
+///
+/// This must live forever, so it is usually placed in a `Forever`:
+///
+/// ```ignore
+/// static RESOURCES: Forever<StackResources<6, 8>> = Forever::new();
+///
+/// let stack = Stack::new(device, configurator, RESOURCES.put(StackResources::new())).unwrap();
+/// ```
+pub struct StackResources<const SOCK: usize, const NEIGHBOR: usize> {
     addresses: [IpCidr; ADDRESSES_LEN],
-    sockets: [Option<SocketSetItem<'static>>; SOCKETS_LEN],
+    sockets: [Option<SocketSetItem<'static>>; SOCK],
 
     #[cfg(feature = "medium-ethernet")]
-    routes: [Option<(IpCidr, Route)>; 1],
+    routes: [Option<(IpCidr, Route)>; ROUTES_LEN],
     #[cfg(feature = "medium-ethernet")]
-    neighbor_cache: [Option<(IpAddress, Neighbor)>; NEIGHBOR_CACHE_LEN],
+    neighbor_cache: [Option<(IpAddress, Neighbor)>; NEIGHBOR],
+
+    #[cfg(feature = "dhcpv4")]
+    dhcp: DhcpResources,
+    #[cfg(feature = "slaac")]
+    slaac: SlaacResources,
+}
+
+impl<const SOCK: usize, const NEIGHBOR: usize> StackResources<SOCK, NEIGHBOR> {
+    pub fn new() -> Self {
+        const NONE_SOCKET: Option<SocketSetItem<'static>> = None;
+
+        Self {
+            addresses: [unused_addr(); ADDRESSES_LEN],
+            sockets: [NONE_SOCKET; SOCK],
+
+            #[cfg(feature = "medium-ethernet")]
+            routes: [None; ROUTES_LEN],
+            #[cfg(feature = "medium-ethernet")]
+            neighbor_cache: [None; NEIGHBOR],
+
+            #[cfg(feature = "dhcpv4")]
+            dhcp: DhcpResources::new(),
+            #[cfg(feature = "slaac")]
+            slaac: SlaacResources::new(),
+        }
+    }
 }
 
-static STACK_RESOURCES: Forever<StackResources> = Forever::new();
-static STACK: ThreadModeMutex<RefCell<Option<Stack>>> = ThreadModeMutex::new(RefCell::new(None));
+/// A network stack, bound to a single [Device].
+///
+/// Create one per network interface. Sockets are created on a specific stack, and only
+/// send and receive through its device. The stack does nothing unless [Stack::run] is
+/// running, usually in a dedicated task.
+///
+/// A stack can only be used from thread mode.
+pub struct Stack {
+    inner: ThreadModeMutex<RefCell<StackInner>>,
+}
 
-pub(crate) struct Stack {
+pub(crate) struct StackInner {
     iface: Interface,
     pub sockets: SocketSet,
+    sockets_len: usize,
     link_up: bool,
-    config_up: bool,
+    link_generation: u32,
+    pub config: Option<Config>,
+    config_generation: u32,
+    event_wakers: EventWakers,
     next_local_port: u16,
+    #[cfg(feature = "dns")]
+    pub dns_cache: crate::dns::Cache,
+    #[cfg(feature = "proto-ipv6")]
+    ipv6_link_local: Option<Ipv6Cidr>,
+    #[cfg(feature = "proto-ipv6")]
+    ipv6_addresses: Vec<Ipv6Cidr, 2>,
+    #[cfg(feature = "proto-ipv6")]
+    ipv6_gateway: Option<Ipv6Address>,
+    #[cfg(feature = "slaac")]
+    slaac: Option<Slaac>,
     configurator: &'static mut dyn Configurator,
     waker: WakerRegistration,
 }
 
-impl Stack {
-    pub(crate) fn with<R>(f: impl FnOnce(&mut Stack) -> R) -> R {
-        let mut stack = STACK.borrow().borrow_mut();
-        let stack = stack.as_mut().unwrap();
-        f(stack)
+impl StackInner {
+    /// Add a socket to the socket set, failing with `Error::Exhausted` if it is full.
+    pub(crate) fn add_socket<T>(&mut self, socket: T) -> Result<SocketHandle>
+    where
+        T: Into<Socket<'static>>,
+    {
+        add_socket(&mut self.sockets, self.sockets_len, socket)
     }
 
     pub fn get_local_port(&mut self) -> u16 {
@@ -73,6 +157,11 @@ impl Stack {
         self.waker.wake()
     }
 
+    fn config_changed(&mut self) {
+        self.config_generation = self.config_generation.wrapping_add(1);
+        self.event_wakers.wake();
+    }
+
     fn poll_configurator(&mut self, timestamp: SmolInstant) {
         let medium = self.iface.device().capabilities().medium;
 
@@ -103,8 +192,26 @@ impl Stack {
                 for (i, s) in config.dns_servers.iter().enumerate() {
                     debug!("   DNS server {}:    {}", i, s);
                 }
+                if let Some(lease) = &config.dhcp {
+                    debug!("   DHCP server:     {}", lease.server);
+                    debug!("   Lease duration:  {}s", lease.duration.secs());
+                }
+
+                #[cfg(feature = "proto-ipv6")]
+                {
+                    for a in config.ipv6_addresses.iter() {
+                        debug!("   IPv6 address:    {}", a);
+                    }
+                    if let Some(gateway) = config.ipv6_gateway {
+                        debug!("   IPv6 gateway:    {}", gateway);
+                    }
+                    self.ipv6_addresses = config.ipv6_addresses.clone();
+                    self.ipv6_gateway = config.ipv6_gateway;
+                    self.update_ipv6();
+                }
 
-                self.config_up = true;
+                self.config = Some(config);
+                self.config_changed();
             }
             Event::Deconfigured => {
                 debug!("Lost IP configuration");
@@ -113,13 +220,99 @@ impl Stack {
                 if medium == Medium::Ethernet {
                     self.iface.routes_mut().remove_default_ipv4_route();
                 }
-                self.config_up = false;
+                #[cfg(feature = "proto-ipv6")]
+                {
+                    self.ipv6_addresses.clear();
+                    self.ipv6_gateway = None;
+                    self.update_ipv6();
+                }
+                self.config = None;
+                self.config_changed();
             }
         }
     }
 
+    /// Apply the current IPv6 addresses and default gateway to the interface.
+    #[cfg(feature = "proto-ipv6")]
+    fn update_ipv6(&mut self) {
+        // smoltcp uses the first IPv6 address as the source of outgoing packets, so
+        // global addresses must come before the link-local one.
+        let mut addrs = self
+            .ipv6_addresses
+            .iter()
+            .copied()
+            .chain(self.slaac_address())
+            .chain(self.ipv6_link_local);
+        self.iface.update_ip_addrs(|dests| {
+            // The first slot holds the IPv4 address.
+            for dest in dests[1..].iter_mut() {
+                *dest = addrs.next().map(IpCidr::Ipv6).unwrap_or_else(unused_addr);
+            }
+        });
+
+        #[cfg(feature = "medium-ethernet")]
+        if self.iface.device().capabilities().medium == Medium::Ethernet {
+            match self.ipv6_gateway.or(self.slaac_router()) {
+                Some(gateway) => {
+                    self.iface
+                        .routes_mut()
+                        .add_default_ipv6_route(gateway)
+                        .unwrap();
+                }
+                None => {
+                    self.iface.routes_mut().remove_default_ipv6_route();
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "slaac")]
+    fn slaac_address(&self) -> Option<Ipv6Cidr> {
+        self.slaac.as_ref().and_then(|s| s.address())
+    }
+
+    #[cfg(all(feature = "proto-ipv6", not(feature = "slaac")))]
+    fn slaac_address(&self) -> Option<Ipv6Cidr> {
+        None
+    }
+
+    #[cfg(feature = "slaac")]
+    fn slaac_router(&self) -> Option<Ipv6Address> {
+        self.slaac.as_ref().and_then(|s| s.router())
+    }
+
+    #[cfg(all(
+        feature = "proto-ipv6",
+        feature = "medium-ethernet",
+        not(feature = "slaac")
+    ))]
+    fn slaac_router(&self) -> Option<Ipv6Address> {
+        None
+    }
+
+    #[cfg(feature = "slaac")]
+    fn poll_slaac(&mut self, old_link_up: bool, timestamp: SmolInstant) {
+        let (slaac, link_local) = match (&mut self.slaac, self.ipv6_link_local) {
+            (Some(slaac), Some(link_local)) => (slaac, link_local),
+            _ => return,
+        };
+
+        let changed = if self.link_up {
+            slaac.poll(&mut self.sockets, link_local.address(), timestamp)
+        } else if old_link_up {
+            slaac.reset();
+            true
+        } else {
+            false
+        };
+
+        if changed {
+            self.update_ipv6();
+        }
+    }
+
     fn poll(&mut self, cx: &mut Context<'_>) {
-        self.iface.device_mut().device.register_waker(cx.waker());
+        self.iface.device_mut().device().register_waker(cx.waker());
         self.waker.register(cx.waker());
 
         let timestamp = instant_to_smoltcp(Instant::now());
@@ -131,7 +324,7 @@ impl Stack {
 
         // Update link up
         let old_link_up = self.link_up;
-        self.link_up = self.iface.device_mut().device.link_state() == LinkState::Up;
+        self.link_up = self.iface.device_mut().device().link_state() == LinkState::Up;
 
         // Print when changed
         if old_link_up != self.link_up {
@@ -140,13 +333,28 @@ impl Stack {
             } else {
                 info!("Link down!");
             }
+            self.link_generation = self.link_generation.wrapping_add(1);
+            self.event_wakers.wake();
         }
 
         if old_link_up || self.link_up {
             self.poll_configurator(timestamp)
         }
 
-        if let Some(poll_at) = self.iface.poll_at(&mut self.sockets, timestamp) {
+        #[cfg(feature = "slaac")]
+        self.poll_slaac(old_link_up, timestamp);
+
+        let mut poll_at = self.iface.poll_at(&mut self.sockets, timestamp);
+        if let Some(t) = self.iface.device_mut().device().poll_at() {
+            let t = instant_to_smoltcp(t);
+            poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
+        }
+        if self.link_up {
+            if let Some(t) = self.configurator.poll_at() {
+                poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
+            }
+        }
+        if let Some(poll_at) = poll_at {
             let t = Timer::at(instant_from_smoltcp(poll_at));
             pin_mut!(t);
             if t.poll(cx).is_ready() {
@@ -163,83 +371,324 @@ fn set_ipv4_addr(iface: &mut Interface, cidr: Ipv4Cidr) {
     });
 }
 
-/// Initialize embassy_net.
-/// This function must be called from thread mode.
-pub fn init(device: &'static mut dyn Device, configurator: &'static mut dyn Configurator) {
-    const NONE_SOCKET: Option<SocketSetItem<'static>> = None;
-    let res = STACK_RESOURCES.put(StackResources {
-        addresses: [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32)],
-        sockets: [NONE_SOCKET; SOCKETS_LEN],
+/// Add a socket to `sockets`, which has room for `len` of them, failing with
+/// `Error::Exhausted` if it is full.
+pub(crate) fn add_socket<T>(sockets: &mut SocketSet, len: usize, socket: T) -> Result<SocketHandle>
+where
+    T: Into<Socket<'static>>,
+{
+    // SocketSet::add panics when full, so check first.
+    if sockets.iter().count() >= len {
+        return Err(Error::Exhausted);
+    }
+    Ok(sockets.add(socket))
+}
+
+fn unused_addr() -> IpCidr {
+    IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32)
+}
+
+/// Modified EUI-64 interface identifier, derived from a MAC address (RFC 4291 appendix A).
+#[cfg(all(feature = "proto-ipv6", feature = "medium-ethernet"))]
+pub(crate) fn eui64(mac: EthernetAddress) -> [u8; 8] {
+    let m = mac.as_bytes();
+    [m[0] ^ 0x02, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]]
+}
+
+impl Stack {
+    /// Create a new stack for the given device.
+    ///
+    /// Fails with `Error::Exhausted` if `SOCK` is too small for the sockets the stack uses
+    /// internally, or with the error of [Configurator::init].
+    pub fn new<const SOCK: usize, const NEIGHBOR: usize>(
+        device: &'static mut dyn Device,
+        configurator: &'static mut dyn Configurator,
+        resources: &'static mut StackResources<SOCK, NEIGHBOR>,
+    ) -> Result<Self> {
+        let medium = device.capabilities().medium;
 
         #[cfg(feature = "medium-ethernet")]
-        routes: [None; 1],
+        let ethernet_addr = if medium == Medium::Ethernet {
+            device.ethernet_address()
+        } else {
+            [0, 0, 0, 0, 0, 0]
+        };
+
+        let mut b = InterfaceBuilder::new(DeviceAdapter::new(device));
+        b = b.ip_addrs(&mut resources.addresses[..]);
+
         #[cfg(feature = "medium-ethernet")]
-        neighbor_cache: [None; NEIGHBOR_CACHE_LEN],
-    });
+        if medium == Medium::Ethernet {
+            b = b.ethernet_addr(EthernetAddress(ethernet_addr));
+            b = b.neighbor_cache(NeighborCache::new(&mut resources.neighbor_cache[..]));
+            b = b.routes(Routes::new(&mut resources.routes[..]));
+        }
 
-    let medium = device.capabilities().medium;
+        let iface = b.finalize();
+
+        let mut sockets = SocketSet::new(&mut resources.sockets[..]);
+        configurator.init(&mut ConfiguratorResources {
+            sockets: &mut sockets,
+            sockets_len: SOCK,
+            #[cfg(feature = "dhcpv4")]
+            dhcp: Some(&mut resources.dhcp),
+        })?;
+
+        #[cfg(all(feature = "proto-ipv6", feature = "medium-ethernet"))]
+        let ipv6_link_local = if medium == Medium::Ethernet {
+            let mut addr = [0; 16];
+            addr[..2].copy_from_slice(&[0xfe, 0x80]);
+            addr[8..].copy_from_slice(&eui64(EthernetAddress(ethernet_addr)));
+            Some(Ipv6Cidr::new(Ipv6Address::from_bytes(&addr), 64))
+        } else {
+            None
+        };
+        #[cfg(all(feature = "proto-ipv6", not(feature = "medium-ethernet")))]
+        let ipv6_link_local = None;
 
-    #[cfg(feature = "medium-ethernet")]
-    let ethernet_addr = if medium == Medium::Ethernet {
-        device.ethernet_address()
-    } else {
-        [0, 0, 0, 0, 0, 0]
-    };
+        #[cfg(feature = "slaac")]
+        let slaac = if medium == Medium::Ethernet {
+            let mac = EthernetAddress(ethernet_addr);
+            Some(Slaac::new(&mut sockets, SOCK, &mut resources.slaac, mac)?)
+        } else {
+            None
+        };
 
-    let mut b = InterfaceBuilder::new(DeviceAdapter::new(device));
-    b = b.ip_addrs(&mut res.addresses[..]);
+        let local_port = loop {
+            let mut res = [0u8; 2];
+            rand(&mut res);
+            let port = u16::from_le_bytes(res);
+            if port >= LOCAL_PORT_MIN && port <= LOCAL_PORT_MAX {
+                break port;
+            }
+        };
 
-    #[cfg(feature = "medium-ethernet")]
-    if medium == Medium::Ethernet {
-        b = b.ethernet_addr(EthernetAddress(ethernet_addr));
-        b = b.neighbor_cache(NeighborCache::new(&mut res.neighbor_cache[..]));
-        b = b.routes(Routes::new(&mut res.routes[..]));
+        let inner = StackInner {
+            iface,
+            sockets,
+            sockets_len: SOCK,
+            link_up: false,
+            link_generation: 0,
+            config: None,
+            config_generation: 0,
+            event_wakers: EventWakers::new(),
+            configurator,
+            next_local_port: local_port,
+            #[cfg(feature = "dns")]
+            dns_cache: crate::dns::Cache::new(),
+            #[cfg(feature = "proto-ipv6")]
+            ipv6_link_local,
+            #[cfg(feature = "proto-ipv6")]
+            ipv6_addresses: Vec::new(),
+            #[cfg(feature = "proto-ipv6")]
+            ipv6_gateway: None,
+            #[cfg(feature = "slaac")]
+            slaac,
+            waker: WakerRegistration::new(),
+        };
+
+        let stack = Self {
+            inner: ThreadModeMutex::new(RefCell::new(inner)),
+        };
+
+        // Install the link-local address.
+        #[cfg(feature = "proto-ipv6")]
+        stack.with(|stack| stack.update_ipv6());
+
+        Ok(stack)
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut StackInner) -> R) -> R {
+        f(&mut *self.inner.borrow().borrow_mut())
+    }
+
+    pub fn is_link_up(&self) -> bool {
+        self.with(|stack| stack.link_up)
+    }
+
+    pub fn is_config_up(&self) -> bool {
+        self.with(|stack| stack.config.is_some())
+    }
+
+    /// Get the current IP configuration, if any.
+    pub fn config(&self) -> Option<Config> {
+        self.with(|stack| stack.config.clone())
+    }
+
+    /// Ask the configurator to refresh the IP configuration now, for example renew the
+    /// DHCP lease.
+    pub fn renew_config(&self) {
+        self.with(|stack| {
+            stack.configurator.renew();
+            stack.wake();
+        })
+    }
+
+    /// Ask the configurator to give up the IP configuration, for example release the DHCP
+    /// lease before going to deep sleep.
+    ///
+    /// Use [wait_config_change](Self::wait_config_change) to know when it's done. No new
+    /// configuration is acquired until [renew_config](Self::renew_config) is called.
+    pub fn release_config(&self) {
+        self.with(|stack| {
+            stack.configurator.release();
+            stack.wake();
+        })
     }
 
-    let iface = b.finalize();
+    /// Wait until the link goes up or down, and return the new state.
+    ///
+    /// Only changes that happen after this is first polled are reported.
+    pub async fn wait_link_change(&self) -> LinkState {
+        let mut generation = None;
+        self.wait_event(|stack| {
+            let generation = *generation.get_or_insert(stack.link_generation);
+            if stack.link_generation == generation {
+                return None;
+            }
+            Some(match stack.link_up {
+                true => LinkState::Up,
+                false => LinkState::Down,
+            })
+        })
+        .await
+    }
 
-    let sockets = SocketSet::new(&mut res.sockets[..]);
+    /// Wait until the IP configuration is acquired, modified or lost.
+    ///
+    /// Returns [ConfigEvent::Configured](crate::ConfigEvent::Configured) with the new
+    /// configuration, or [ConfigEvent::Deconfigured](crate::ConfigEvent::Deconfigured).
+    /// Only changes that happen after this is first polled are reported.
+    pub async fn wait_config_change(&self) -> Event {
+        let mut generation = None;
+        self.wait_event(|stack| {
+            let generation = *generation.get_or_insert(stack.config_generation);
+            if stack.config_generation == generation {
+                return None;
+            }
+            Some(match &stack.config {
+                Some(config) => Event::Configured(config.clone()),
+                None => Event::Deconfigured,
+            })
+        })
+        .await
+    }
 
-    let local_port = loop {
-        let mut res = [0u8; 2];
-        rand(&mut res);
-        let port = u16::from_le_bytes(res);
-        if port >= LOCAL_PORT_MIN && port <= LOCAL_PORT_MAX {
-            break port;
+    /// Wait until `f` returns `Some`, calling it again after each link or config change.
+    async fn wait_event<T>(&self, mut f: impl FnMut(&mut StackInner) -> Option<T>) -> T {
+        /// Unregisters the waiting future if it's dropped before the change happens.
+        struct Guard<'a>(&'a Stack, EventWaiter);
+
+        impl<'a> Drop for Guard<'a> {
+            fn drop(&mut self) {
+                let waiter = &mut self.1;
+                self.0.with(|stack| stack.event_wakers.unregister(waiter))
+            }
         }
-    };
-
-    let stack = Stack {
-        iface,
-        sockets,
-        link_up: false,
-        config_up: false,
-        configurator,
-        next_local_port: local_port,
-        waker: WakerRegistration::new(),
-    };
-
-    *STACK.borrow().borrow_mut() = Some(stack);
+
+        let mut guard = Guard(self, EventWaiter::new());
+        futures::future::poll_fn(|cx| {
+            self.with(|stack| match f(stack) {
+                Some(res) => Poll::Ready(res),
+                None => {
+                    stack.event_wakers.register(cx, &mut guard.1);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Run the stack. This must be running for any of the stack's sockets to work.
+    pub async fn run(&self) {
+        futures::future::poll_fn(|cx| {
+            self.with(|stack| stack.poll(cx));
+            Poll::<()>::Pending
+        })
+        .await
+    }
 }
 
-pub fn is_init() -> bool {
-    STACK.borrow().borrow().is_some()
+/// Wakers of the tasks waiting in `wait_link_change` or `wait_config_change`.
+///
+/// Unlike `WakerRegistration`, this can hold several wakers, so a few tasks can wait at
+/// the same time without waking each other. If more tasks than that are waiting, the
+/// extra ones aren't registered, they check again every `EVENT_RETRY_INTERVAL` instead.
+/// Evicting a registered waker would make it register again right away, evicting
+/// another one, and so on forever.
+struct EventWakers {
+    /// Registered wakers, with the number of waiting futures sharing each of them.
+    wakers: Vec<(Waker, usize), EVENT_WAKERS_LEN>,
+    /// Incremented each time the wakers are woken, which unregisters them all.
+    epoch: u32,
 }
 
-pub fn is_link_up() -> bool {
-    STACK.borrow().borrow().as_ref().unwrap().link_up
+/// The registration of a future waiting on [EventWakers].
+struct EventWaiter {
+    /// The registered waker, and the epoch it was registered in.
+    registered: Option<(Waker, u32)>,
+    retry: Option<Timer>,
 }
 
-pub fn is_config_up() -> bool {
-    STACK.borrow().borrow().as_ref().unwrap().config_up
+impl EventWaiter {
+    const fn new() -> Self {
+        Self {
+            registered: None,
+            retry: None,
+        }
+    }
 }
 
-pub async fn run() {
-    futures::future::poll_fn(|cx| {
-        Stack::with(|stack| stack.poll(cx));
-        Poll::<()>::Pending
-    })
-    .await
+impl EventWakers {
+    const fn new() -> Self {
+        Self {
+            wakers: Vec::new(),
+            epoch: 0,
+        }
+    }
+
+    /// Register the waker of `cx` for `waiter`, or if there's no room, arm its retry timer.
+    fn register(&mut self, cx: &mut Context<'_>, waiter: &mut EventWaiter) {
+        let waker = cx.waker();
+        if let Some((w, epoch)) = &waiter.registered {
+            if *epoch == self.epoch && w.will_wake(waker) {
+                return;
+            }
+            // The future was woken, or moved to another task.
+            self.unregister(waiter);
+        }
+
+        if let Some((_, count)) = self.wakers.iter_mut().find(|(w, _)| w.will_wake(waker)) {
+            *count += 1;
+        } else if self.wakers.push((waker.clone(), 1)).is_err() {
+            let timer = waiter.retry.insert(Timer::after(EVENT_RETRY_INTERVAL));
+            // A new timer can't be ready already.
+            let _ = Pin::new(timer).poll(cx);
+            return;
+        }
+        waiter.registered = Some((waker.clone(), self.epoch));
+    }
+
+    /// Remove the registration of `waiter`, if it's still registered.
+    fn unregister(&mut self, waiter: &mut EventWaiter) {
+        let waker = match waiter.registered.take() {
+            Some((waker, epoch)) if epoch == self.epoch => waker,
+            _ => return,
+        };
+        if let Some(i) = self.wakers.iter().position(|(w, _)| w.will_wake(&waker)) {
+            self.wakers[i].1 -= 1;
+            if self.wakers[i].1 == 0 {
+                self.wakers.swap_remove(i);
+            }
+        }
+    }
+
+    fn wake(&mut self) {
+        self.epoch = self.epoch.wrapping_add(1);
+        while let Some((w, _)) = self.wakers.pop() {
+            w.wake();
+        }
+    }
 }
 
 fn instant_to_smoltcp(instant: Instant) -> SmolInstant {
@@ -254,6 +703,82 @@ extern "Rust" {
     fn _embassy_rand(buf: &mut [u8]);
 }
 
-fn rand(buf: &mut [u8]) {
+pub(crate) fn rand(buf: &mut [u8]) {
     unsafe { _embassy_rand(buf) }
 }
+
+/// Applications provide the random numbers, unit tests have to do it themselves.
+#[cfg(test)]
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = i as u8 ^ 0x5A;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{RawWaker, RawWakerVTable};
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_COUNT: AtomicUsize = AtomicUsize::new(0);
+    static WAKES: [AtomicUsize; EVENT_WAKERS_LEN + 1] = [NEW_COUNT; EVENT_WAKERS_LEN + 1];
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake, drop_waker);
+
+    unsafe fn clone_waker(data: *const ()) -> RawWaker {
+        RawWaker::new(data, &VTABLE)
+    }
+
+    unsafe fn wake(data: *const ()) {
+        (*(data as *const AtomicUsize)).fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn drop_waker(_data: *const ()) {}
+
+    /// A waker counting its wakes in `WAKES[i]`.
+    fn waker(i: usize) -> Waker {
+        let data = &WAKES[i] as *const AtomicUsize as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) }
+    }
+
+    fn register(wakers: &mut EventWakers, waiter: &mut EventWaiter, i: usize) {
+        let waker = waker(i);
+        wakers.register(&mut Context::from_waker(&waker), waiter);
+    }
+
+    #[test]
+    fn event_wakers() {
+        let mut wakers = EventWakers::new();
+
+        // Waiters that go away leave their slot.
+        for i in 0..EVENT_WAKERS_LEN + 1 {
+            let mut waiter = EventWaiter::new();
+            register(&mut wakers, &mut waiter, i);
+            assert!(waiter.retry.is_none());
+            wakers.unregister(&mut waiter);
+        }
+        assert!(wakers.wakers.is_empty());
+
+        // Two waiters in the same task share a slot, which stays until both are gone.
+        let mut a = EventWaiter::new();
+        let mut b = EventWaiter::new();
+        register(&mut wakers, &mut a, 0);
+        register(&mut wakers, &mut a, 0);
+        register(&mut wakers, &mut b, 0);
+        wakers.unregister(&mut a);
+        assert_eq!(wakers.wakers.len(), 1);
+        wakers.wake();
+        assert_eq!(WAKES[0].load(Ordering::Relaxed), 1);
+
+        // Waking unregisters everyone, a later unregister doesn't touch the new waiters.
+        let mut c = EventWaiter::new();
+        register(&mut wakers, &mut c, 1);
+        wakers.unregister(&mut b);
+        assert_eq!(wakers.wakers.len(), 1);
+        wakers.wake();
+        assert_eq!(WAKES[1].load(Ordering::Relaxed), 1);
+    }
+}
@@ -10,11 +10,11 @@ use smoltcp::socket::{TcpSocketBuffer, TcpState};
 use smoltcp::time::Duration;
 use smoltcp::wire::IpEndpoint;
 
-use super::stack::Stack;
 use crate::fmt::*;
-use crate::{Error, Result};
+use crate::{Error, Result, Stack};
 
 pub struct TcpSocket<'a> {
+    stack: &'a Stack,
     handle: SocketHandle,
     ghost: PhantomData<&'a mut [u8]>,
 }
@@ -22,27 +22,31 @@ pub struct TcpSocket<'a> {
 impl<'a> Unpin for TcpSocket<'a> {}
 
 impl<'a> TcpSocket<'a> {
-    pub fn new(rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Self {
-        let handle = Stack::with(|stack| {
+    /// Create a new TCP socket.
+    ///
+    /// Fails with `Error::Exhausted` if the stack's socket set is full.
+    pub fn new(stack: &'a Stack, rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Result<Self> {
+        let handle = stack.with(|stack| {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            stack.sockets.add(SyncTcpSocket::new(
+            stack.add_socket(SyncTcpSocket::new(
                 TcpSocketBuffer::new(rx_buffer),
                 TcpSocketBuffer::new(tx_buffer),
             ))
-        });
+        })?;
 
-        Self {
+        Ok(Self {
+            stack,
             handle,
             ghost: PhantomData,
-        }
+        })
     }
 
     pub async fn connect<T>(&mut self, remote_endpoint: T) -> Result<()>
     where
         T: Into<IpEndpoint>,
     {
-        let local_port = Stack::with(|stack| stack.get_local_port());
+        let local_port = self.stack.with(|stack| stack.get_local_port());
         self.with(|s| s.connect(remote_endpoint, local_port))?;
 
         futures::future::poll_fn(|cx| {
@@ -59,6 +63,34 @@ impl<'a> TcpSocket<'a> {
         .await
     }
 
+    /// Listen on the given local endpoint, and wait for a remote host to connect.
+    ///
+    /// A socket accepts a single connection. To serve several connections at once, use
+    /// one socket per connection, all accepting on the same port, each with its own
+    /// buffers. An incoming connection is handed to one of the sockets currently waiting
+    /// in `accept`.
+    ///
+    /// Like [connect](Self::connect), this fails with `Unaddressable` if the socket is closed
+    /// or aborted, or the connection times out, before it's established.
+    pub async fn accept<T>(&mut self, local_endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        self.with(|s| s.listen(local_endpoint))?;
+
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.state() {
+                TcpState::Closed | TcpState::TimeWait => Poll::Ready(Err(Error::Unaddressable)),
+                TcpState::Listen | TcpState::SynSent | TcpState::SynReceived => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(Ok(())),
+            })
+        })
+        .await
+    }
+
     pub fn set_timeout(&mut self, duration: Option<Duration>) {
         self.with(|s| s.set_timeout(duration))
     }
@@ -100,7 +132,7 @@ impl<'a> TcpSocket<'a> {
     }
 
     fn with<R>(&self, f: impl FnOnce(&mut SyncTcpSocket) -> R) -> R {
-        Stack::with(|stack| {
+        self.stack.with(|stack| {
             let res = {
                 let mut s = stack.sockets.get::<SyncTcpSocket>(self.handle);
                 f(&mut *s)
@@ -118,7 +150,7 @@ fn to_ioerr(_err: Error) -> io::Error {
 
 impl<'a> Drop for TcpSocket<'a> {
     fn drop(&mut self) {
-        Stack::with(|stack| {
+        self.stack.with(|stack| {
             stack.sockets.remove(self.handle);
         })
     }
@@ -0,0 +1,175 @@
+//! Server authentication: extracting the key from the server's certificate, and checking
+//! its CertificateVerify signature.
+
+use p256::ecdsa::signature::Verifier as _;
+use p256::ecdsa::{Signature, VerifyingKey};
+
+use super::keys::HASH_LEN;
+use super::{Error, Result};
+
+const SEQUENCE: u8 = 0x30;
+const BIT_STRING: u8 = 0x03;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+/// The optional `[0] EXPLICIT Version` of a TBSCertificate.
+const VERSION: u8 = 0xA0;
+
+/// id-ecPublicKey, 1.2.840.10045.2.1
+const EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+/// prime256v1, 1.2.840.10045.3.1.7
+const PRIME256V1: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+
+/// Context string of the signature in the server's CertificateVerify.
+const SERVER_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify";
+
+/// Reader of DER-encoded elements.
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// Read the next element, returning its tag, contents and whole encoding.
+    fn next(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let data = self.0;
+        let (tag, first, mut rest) = match data {
+            [tag, first, rest @ ..] => (*tag, *first, rest),
+            _ => return Err(Error::BadCertificate),
+        };
+        let len = match first {
+            n if n < 0x80 => n as usize,
+            // Long form, certificates don't need more than 3 bytes.
+            0x81..=0x83 => {
+                let n = (first & 0x7F) as usize;
+                if rest.len() < n {
+                    return Err(Error::BadCertificate);
+                }
+                let len = rest[..n].iter().fold(0, |len, &b| len << 8 | b as usize);
+                rest = &rest[n..];
+                len
+            }
+            _ => return Err(Error::BadCertificate),
+        };
+        if rest.len() < len {
+            return Err(Error::BadCertificate);
+        }
+        let header_len = data.len() - rest.len();
+        self.0 = &rest[len..];
+        Ok((tag, &rest[..len], &data[..header_len + len]))
+    }
+
+    /// Read the next element, which must have the given tag, returning its contents.
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.next()? {
+            (t, contents, _) if t == tag => Ok(contents),
+            _ => Err(Error::BadCertificate),
+        }
+    }
+}
+
+/// The encoded SubjectPublicKeyInfo of a DER certificate.
+pub fn spki(cert: &[u8]) -> Result<&[u8]> {
+    let cert = Der(cert).expect(SEQUENCE)?;
+    let mut tbs = Der(Der(cert).expect(SEQUENCE)?);
+
+    let (tag, _, _) = tbs.next()?;
+    if tag == VERSION {
+        // The serial number.
+        tbs.next()?;
+    }
+    // Signature algorithm, issuer, validity and subject.
+    for _ in 0..4 {
+        tbs.next()?;
+    }
+    match tbs.next()? {
+        (SEQUENCE, _, spki) => Ok(spki),
+        _ => Err(Error::BadCertificate),
+    }
+}
+
+/// The ECDSA P-256 key in a SubjectPublicKeyInfo. Other kinds of keys aren't supported.
+pub fn p256_key(spki: &[u8]) -> Result<VerifyingKey> {
+    let mut spki = Der(Der(spki).expect(SEQUENCE)?);
+    let mut algorithm = Der(spki.expect(SEQUENCE)?);
+    if algorithm.expect(OBJECT_IDENTIFIER)? != EC_PUBLIC_KEY
+        || algorithm.expect(OBJECT_IDENTIFIER)? != PRIME256V1
+    {
+        return Err(Error::BadCertificate);
+    }
+    match spki.expect(BIT_STRING)? {
+        [0, point @ ..] => VerifyingKey::from_sec1_bytes(point).map_err(|_| Error::BadCertificate),
+        _ => Err(Error::BadCertificate),
+    }
+}
+
+/// Check the ecdsa_secp256r1_sha256 signature of the server's CertificateVerify.
+/// `transcript_hash` is the hash of the handshake up to its Certificate.
+pub fn verify_signature(
+    key: &VerifyingKey,
+    transcript_hash: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    const PREFIX_LEN: usize = 64;
+    const CONTEXT_END: usize = PREFIX_LEN + SERVER_CONTEXT.len();
+    let mut content = [0x20; CONTEXT_END + 1 + HASH_LEN];
+    content[PREFIX_LEN..CONTEXT_END].copy_from_slice(SERVER_CONTEXT);
+    content[CONTEXT_END] = 0;
+    content[CONTEXT_END + 1..].copy_from_slice(transcript_hash);
+
+    let signature = Signature::from_der(signature).map_err(|_| Error::Decode)?;
+    key.verify(&content, &signature)
+        .map_err(|_| Error::DecryptError)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// A self-signed certificate for example.com, with an ECDSA P-256 key.
+    pub const CERT: &[u8] = include_bytes!("testdata/server.der");
+    /// SHA-256 hash of the certificate's SubjectPublicKeyInfo.
+    pub const SPKI_HASH: [u8; 32] = [
+        0xfd, 0xed, 0x1f, 0x63, 0x65, 0x0b, 0x24, 0x64, 0xab, 0xed, 0x8a, 0x40, 0xf5, 0xbb, 0x14,
+        0x97, 0xc0, 0x0c, 0xd1, 0x1a, 0x0c, 0xe9, 0xe9, 0x3d, 0xc5, 0x80, 0x0c, 0x52, 0xf6, 0x78,
+        0xfe, 0x16,
+    ];
+
+    #[test]
+    fn certificate_key() {
+        use sha2::{Digest, Sha256};
+
+        let spki = spki(CERT).unwrap();
+        assert_eq!(Sha256::digest(spki)[..], SPKI_HASH[..]);
+        p256_key(spki).unwrap();
+    }
+
+    #[test]
+    fn malformed_certificate() {
+        assert_eq!(spki(&CERT[..100]), Err(Error::BadCertificate));
+        assert_eq!(spki(&[]), Err(Error::BadCertificate));
+        // Indefinite length.
+        assert_eq!(spki(&[SEQUENCE, 0x80, 0, 0]), Err(Error::BadCertificate));
+
+        let spki = spki(CERT).unwrap();
+        assert!(p256_key(&spki[..spki.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn signature() {
+        // Signed over the hash [0x42; 32] with the certificate's key.
+        let signature = [
+            0x30, 0x46, 0x02, 0x21, 0x00, 0x80, 0x9f, 0x31, 0x7e, 0x66, 0x02, 0x68, 0xd5, 0xb4,
+            0xcb, 0x3d, 0xb7, 0x4d, 0x84, 0x5b, 0x9e, 0x31, 0xd7, 0x5c, 0xd7, 0x2f, 0xf1, 0x28,
+            0x18, 0xc2, 0x72, 0x87, 0xb7, 0xb6, 0x5f, 0xc1, 0xad, 0x02, 0x21, 0x00, 0xe3, 0x32,
+            0x20, 0xdf, 0x02, 0x44, 0x00, 0x18, 0xfd, 0x79, 0xdc, 0x54, 0x58, 0xc8, 0xc1, 0x0d,
+            0x51, 0x20, 0x16, 0x54, 0x30, 0x8d, 0x33, 0x14, 0x4f, 0x3b, 0x35, 0x2f, 0xf4, 0x01,
+            0x28, 0xfd,
+        ];
+        let key = p256_key(spki(CERT).unwrap()).unwrap();
+        assert_eq!(verify_signature(&key, &[0x42; 32], &signature), Ok(()));
+        assert_eq!(
+            verify_signature(&key, &[0x43; 32], &signature),
+            Err(Error::DecryptError)
+        );
+        assert_eq!(
+            verify_signature(&key, &[0x42; 32], &signature[1..]),
+            Err(Error::Decode)
+        );
+    }
+}
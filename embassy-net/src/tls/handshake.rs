@@ -0,0 +1,657 @@
+//! Encoding and decoding of handshake messages.
+
+use heapless::Vec;
+use p256::ecdsa::VerifyingKey;
+use sha2::{Digest, Sha256};
+
+use super::cert;
+use super::keys::{self, Secret, HASH_LEN};
+use super::{Error, Result, Verifier};
+
+pub const CLIENT_HELLO: u8 = 1;
+pub const SERVER_HELLO: u8 = 2;
+pub const NEW_SESSION_TICKET: u8 = 4;
+pub const ENCRYPTED_EXTENSIONS: u8 = 8;
+pub const CERTIFICATE: u8 = 11;
+pub const CERTIFICATE_REQUEST: u8 = 13;
+pub const CERTIFICATE_VERIFY: u8 = 15;
+pub const FINISHED: u8 = 20;
+pub const KEY_UPDATE: u8 = 24;
+
+const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+const X25519: u16 = 0x001d;
+const TLS_1_2: u16 = 0x0303;
+const TLS_1_3: u16 = 0x0304;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_MAX_FRAGMENT_LENGTH: u16 = 1;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_KEY_SHARE: u16 = 51;
+
+/// ecdsa_secp256r1_sha256, the only signature algorithm we can check.
+const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+
+/// Random of a ServerHello that is actually a HelloRetryRequest.
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// Longest certificate_request_context we can echo back.
+const MAX_CONTEXT_LEN: usize = 32;
+/// Size of the largest server certificate we accept. Certificates with ECDSA keys are
+/// usually under 1 kB.
+pub const MAX_CERTIFICATE_LEN: usize = 2048;
+/// Start of the first certificate in a Certificate message with an empty context.
+const CERTIFICATE_START: usize = 1 + 3 + 3;
+
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<()> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<()> {
+        self.bytes(&[val])
+    }
+
+    pub fn u16(&mut self, val: u16) -> Result<()> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Write what `f` writes, prefixed with its length on `n` bytes.
+    pub fn prefixed(&mut self, n: usize, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let start = self.len;
+        self.bytes(&[0; 3][..n])?;
+        f(self)?;
+        let len = (self.len - start - n) as u32;
+        self.buf[start..start + n].copy_from_slice(&len.to_be_bytes()[4 - n..]);
+        Ok(())
+    }
+}
+
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The data left to read.
+    pub fn rest(self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.0.len() {
+            return Err(Error::Decode);
+        }
+        let (data, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Result<usize> {
+        let b = self.bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    /// Read data prefixed with its length on `n` bytes.
+    pub fn prefixed(&mut self, n: usize) -> Result<Reader<'a>> {
+        let len = match n {
+            1 => self.u8()? as usize,
+            2 => self.u16()? as usize,
+            _ => self.u24()?,
+        };
+        Ok(Reader(self.bytes(len)?))
+    }
+}
+
+/// Write a ClientHello to `buf`, returning its length.
+pub fn client_hello(
+    buf: &mut [u8],
+    random: &[u8],
+    session_id: &[u8],
+    public_key: &[u8],
+    server_name: Option<&str>,
+    max_fragment_length: Option<u8>,
+) -> Result<usize> {
+    let mut w = Writer::new(buf);
+    w.u8(CLIENT_HELLO)?;
+    w.prefixed(3, |w| {
+        w.u16(TLS_1_2)?;
+        w.bytes(random)?;
+        w.prefixed(1, |w| w.bytes(session_id))?;
+        w.prefixed(2, |w| w.u16(TLS_AES_128_GCM_SHA256))?;
+        // Only the null compression method.
+        w.prefixed(1, |w| w.u8(0))?;
+        w.prefixed(2, |w| {
+            if let Some(name) = server_name {
+                w.u16(EXT_SERVER_NAME)?;
+                w.prefixed(2, |w| {
+                    w.prefixed(2, |w| {
+                        w.u8(0)?; // host_name
+                        w.prefixed(2, |w| w.bytes(name.as_bytes()))
+                    })
+                })?;
+            }
+            if let Some(code) = max_fragment_length {
+                w.u16(EXT_MAX_FRAGMENT_LENGTH)?;
+                w.prefixed(2, |w| w.u8(code))?;
+            }
+            w.u16(EXT_SUPPORTED_VERSIONS)?;
+            w.prefixed(2, |w| w.prefixed(1, |w| w.u16(TLS_1_3)))?;
+            w.u16(EXT_SUPPORTED_GROUPS)?;
+            w.prefixed(2, |w| w.prefixed(2, |w| w.u16(X25519)))?;
+            w.u16(EXT_SIGNATURE_ALGORITHMS)?;
+            w.prefixed(2, |w| w.prefixed(2, |w| w.u16(ECDSA_SECP256R1_SHA256)))?;
+            w.u16(EXT_KEY_SHARE)?;
+            w.prefixed(2, |w| {
+                w.prefixed(2, |w| {
+                    w.u16(X25519)?;
+                    w.prefixed(2, |w| w.bytes(public_key))
+                })
+            })
+        })
+    })?;
+    Ok(w.len())
+}
+
+/// Parse a ServerHello, returning the server's X25519 public key.
+pub fn parse_server_hello(msg: &[u8], session_id: &[u8]) -> Result<[u8; 32]> {
+    let mut r = Reader::new(msg);
+    if r.u8()? != SERVER_HELLO {
+        return Err(Error::UnexpectedMessage);
+    }
+    let body = r.prefixed(3)?;
+    if !r.is_empty() {
+        // The key changes after the ServerHello, nothing may follow it in the same record.
+        return Err(Error::UnexpectedMessage);
+    }
+    let mut r = body;
+    r.u16()?;
+    if r.bytes(32)? == HELLO_RETRY_REQUEST {
+        // We only offer one group and no PSK, there's nothing to retry with.
+        return Err(Error::HandshakeFailure);
+    }
+    if r.prefixed(1)?.rest() != session_id {
+        return Err(Error::HandshakeFailure);
+    }
+    if r.u16()? != TLS_AES_128_GCM_SHA256 || r.u8()? != 0 {
+        return Err(Error::HandshakeFailure);
+    }
+
+    let mut version = TLS_1_2;
+    let mut key = None;
+    let mut exts = r.prefixed(2)?;
+    while !exts.is_empty() {
+        let ext = exts.u16()?;
+        let mut data = exts.prefixed(2)?;
+        match ext {
+            EXT_SUPPORTED_VERSIONS => version = data.u16()?,
+            EXT_KEY_SHARE => {
+                if data.u16()? != X25519 {
+                    return Err(Error::HandshakeFailure);
+                }
+                let mut k = [0; 32];
+                k.copy_from_slice(data.prefixed(2)?.bytes(32)?);
+                key = Some(k);
+            }
+            _ => {}
+        }
+    }
+
+    if version != TLS_1_3 {
+        return Err(Error::HandshakeFailure);
+    }
+    key.ok_or(Error::HandshakeFailure)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    EncryptedExtensions,
+    /// A CertificateRequest or the Certificate.
+    CertificateRequest,
+    Certificate,
+    CertificateVerify,
+    Finished,
+    Done,
+}
+
+/// Processes the encrypted part of the server's flight, from EncryptedExtensions to
+/// Finished, checking the server's certificate and signature.
+///
+/// Messages can be split across records, and are hashed as they arrive without being
+/// stored, except for the first bytes that we need to look into: the leaf certificate of a
+/// Certificate message, and the start of the others.
+pub struct ServerFlight<'v> {
+    verifier: Verifier<'v>,
+    expect: Expect,
+    header: [u8; 4],
+    header_len: usize,
+    /// Bytes of the current message's body not received yet.
+    remaining: usize,
+    body: [u8; CERTIFICATE_START + MAX_CERTIFICATE_LEN],
+    body_len: usize,
+    /// Transcript hash before the current message.
+    hash: [u8; HASH_LEN],
+    /// Key of the server's certificate.
+    key: Option<VerifyingKey>,
+    certificate_request: Option<Vec<u8, MAX_CONTEXT_LEN>>,
+}
+
+impl<'v> ServerFlight<'v> {
+    pub fn new(verifier: Verifier<'v>) -> Self {
+        Self {
+            verifier,
+            expect: Expect::EncryptedExtensions,
+            header: [0; 4],
+            header_len: 0,
+            remaining: 0,
+            body: [0; CERTIFICATE_START + MAX_CERTIFICATE_LEN],
+            body_len: 0,
+            hash: [0; HASH_LEN],
+            key: None,
+            certificate_request: None,
+        }
+    }
+
+    /// The certificate_request_context if the server asked for a client certificate.
+    pub fn certificate_request(&self) -> Option<&[u8]> {
+        self.certificate_request.as_deref()
+    }
+
+    /// Process the contents of a handshake record. Returns true once the server's Finished
+    /// was received and checked.
+    pub fn process(
+        &mut self,
+        mut data: &[u8],
+        transcript: &mut Sha256,
+        server_secret: &Secret,
+    ) -> Result<bool> {
+        while !data.is_empty() {
+            if self.expect == Expect::Done {
+                // Nothing may follow the Finished in the same record.
+                return Err(Error::UnexpectedMessage);
+            }
+
+            if self.header_len < 4 {
+                let n = (4 - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+                self.header_len += n;
+                data = &data[n..];
+                if self.header_len < 4 {
+                    break;
+                }
+
+                self.remaining =
+                    u32::from_be_bytes([0, self.header[1], self.header[2], self.header[3]])
+                        as usize;
+                self.body_len = 0;
+                if matches!(self.header[0], CERTIFICATE_VERIFY | FINISHED) {
+                    self.hash.copy_from_slice(&transcript.clone().finalize());
+                }
+                transcript.update(&self.header);
+            } else {
+                let n = self.remaining.min(data.len());
+                let chunk = &data[..n];
+                transcript.update(chunk);
+                let m = n.min(self.body.len() - self.body_len);
+                self.body[self.body_len..self.body_len + m].copy_from_slice(&chunk[..m]);
+                self.body_len += m;
+                self.remaining -= n;
+                data = &data[n..];
+            }
+
+            if self.remaining == 0 {
+                self.handle_message(server_secret)?;
+                self.header_len = 0;
+            }
+        }
+
+        Ok(self.expect == Expect::Done)
+    }
+
+    fn handle_message(&mut self, server_secret: &Secret) -> Result<()> {
+        let body = &self.body[..self.body_len];
+        self.expect = match (self.expect, self.header[0]) {
+            (Expect::EncryptedExtensions, ENCRYPTED_EXTENSIONS) => Expect::CertificateRequest,
+            (Expect::CertificateRequest, CERTIFICATE_REQUEST) => {
+                let context = Reader::new(body).prefixed(1)?.rest();
+                let context = Vec::from_slice(context).map_err(|_| Error::Decode)?;
+                self.certificate_request = Some(context);
+                Expect::Certificate
+            }
+            (Expect::CertificateRequest, CERTIFICATE) | (Expect::Certificate, CERTIFICATE) => {
+                let cert = leaf_certificate(body)?;
+                let spki = cert::spki(cert)?;
+                let accepted = match self.verifier {
+                    Verifier::PinnedSpki(hashes) => {
+                        let hash = Sha256::digest(spki);
+                        hashes.iter().any(|h| h[..] == hash[..])
+                    }
+                    Verifier::Custom(f) => f(cert),
+                };
+                if !accepted {
+                    return Err(Error::BadCertificate);
+                }
+                self.key = Some(cert::p256_key(spki)?);
+                Expect::CertificateVerify
+            }
+            (Expect::CertificateVerify, CERTIFICATE_VERIFY) => {
+                let mut r = Reader::new(body);
+                if r.u16()? != ECDSA_SECP256R1_SHA256 {
+                    return Err(Error::HandshakeFailure);
+                }
+                let signature = r.prefixed(2)?.rest();
+                let key = self.key.as_ref().ok_or(Error::UnexpectedMessage)?;
+                cert::verify_signature(key, &self.hash, signature)?;
+                Expect::Finished
+            }
+            (Expect::Finished, FINISHED) => {
+                if body.len() != HASH_LEN {
+                    return Err(Error::Decode);
+                }
+                keys::verify_finished(server_secret, &self.hash, body)?;
+                Expect::Done
+            }
+            _ => return Err(Error::UnexpectedMessage),
+        };
+        Ok(())
+    }
+}
+
+/// The server's own certificate, the first of the chain in a Certificate message. `body` may
+/// be truncated after it.
+fn leaf_certificate(body: &[u8]) -> Result<&[u8]> {
+    let mut r = Reader::new(body);
+    // The context is only set in client certificates.
+    if r.u8()? != 0 {
+        return Err(Error::Decode);
+    }
+    r.u24()?;
+    let len = r.u24()?;
+    if len > MAX_CERTIFICATE_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+    r.bytes(len)
+}
+
+/// Splits the handshake messages received after the handshake. Records can hold several
+/// messages, and messages can be split across records.
+///
+/// We only act on KeyUpdate, so only the first byte of each message's body is kept.
+pub struct PostHandshake {
+    header: [u8; 4],
+    header_len: usize,
+    /// Bytes of the current message's body not received yet.
+    remaining: usize,
+    first: Option<u8>,
+}
+
+impl PostHandshake {
+    pub fn new() -> Self {
+        Self {
+            header: [0; 4],
+            header_len: 0,
+            remaining: 0,
+            first: None,
+        }
+    }
+
+    /// Whether a message was started but not completed.
+    pub fn is_partial(&self) -> bool {
+        self.header_len != 0
+    }
+
+    /// Consume `data` up to the end of the next message. Returns its type, length and first
+    /// byte once complete, or `None` once `data` is empty.
+    pub fn next(&mut self, data: &mut &[u8]) -> Option<(u8, usize, Option<u8>)> {
+        while !data.is_empty() {
+            if self.header_len < 4 {
+                let n = (4 - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+                self.header_len += n;
+                *data = &data[n..];
+                if self.header_len < 4 {
+                    break;
+                }
+                self.remaining = self.len();
+                self.first = None;
+            } else {
+                let n = self.remaining.min(data.len());
+                if self.first.is_none() {
+                    self.first = Some(data[0]);
+                }
+                self.remaining -= n;
+                *data = &data[n..];
+            }
+
+            if self.remaining == 0 {
+                self.header_len = 0;
+                return Some((self.header[0], self.len(), self.first));
+            }
+        }
+        None
+    }
+
+    fn len(&self) -> usize {
+        u32::from_be_bytes([0, self.header[1], self.header[2], self.header[3]]) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::cert::test::{CERT, SPKI_HASH};
+    use super::*;
+
+    const SERVER_SECRET: Secret = [7; HASH_LEN];
+    /// Signature of the transcript up to the Certificate, with the certificate's key.
+    const SIGNATURE: [u8; 71] = [
+        0x30, 0x45, 0x02, 0x20, 0x7d, 0x50, 0x81, 0x63, 0x05, 0xec, 0xc3, 0xbd, 0x26, 0xde, 0x9c,
+        0x2f, 0x40, 0xee, 0x09, 0xf5, 0xd1, 0xae, 0xda, 0x99, 0x63, 0x5f, 0x65, 0xfe, 0x22, 0xf4,
+        0x95, 0xf7, 0x8e, 0xfa, 0x14, 0x3e, 0x02, 0x21, 0x00, 0xc4, 0xeb, 0xc8, 0x28, 0x0a, 0x50,
+        0x6f, 0x5c, 0x39, 0x24, 0x46, 0xff, 0x7e, 0xcb, 0x11, 0x8a, 0x07, 0x5b, 0xfa, 0x92, 0xef,
+        0xad, 0xf5, 0x22, 0x7b, 0x3e, 0x3a, 0xf3, 0x4c, 0x4d, 0x56, 0xbb,
+    ];
+
+    /// Write the server's flight, with `signature` in its CertificateVerify.
+    fn write_flight(buf: &mut [u8], algorithm: u16, signature: &[u8]) -> usize {
+        let mut transcript = Sha256::new();
+        let mut w = Writer::new(buf);
+        w.u8(ENCRYPTED_EXTENSIONS).unwrap();
+        w.prefixed(3, |w| w.prefixed(2, |_| Ok(()))).unwrap();
+        w.u8(CERTIFICATE).unwrap();
+        w.prefixed(3, |w| {
+            w.prefixed(1, |_| Ok(()))?;
+            w.prefixed(3, |w| {
+                w.prefixed(3, |w| w.bytes(CERT))?;
+                w.prefixed(2, |_| Ok(()))
+            })
+        })
+        .unwrap();
+        w.u8(CERTIFICATE_VERIFY).unwrap();
+        w.prefixed(3, |w| {
+            w.u16(algorithm)?;
+            w.prefixed(2, |w| w.bytes(signature))
+        })
+        .unwrap();
+        let len = w.len();
+
+        transcript.update(&buf[..len]);
+        let verify_data = keys::finished(&SERVER_SECRET, &transcript.finalize());
+        let mut w = Writer::new(&mut buf[len..]);
+        w.u8(FINISHED).unwrap();
+        w.prefixed(3, |w| w.bytes(&verify_data)).unwrap();
+        len + w.len()
+    }
+
+    /// Process `data` in records of `record_len` bytes.
+    fn process(verifier: Verifier, data: &[u8], record_len: usize) -> Result<bool> {
+        let mut flight = ServerFlight::new(verifier);
+        let mut transcript = Sha256::new();
+        let mut done = false;
+        for record in data.chunks(record_len) {
+            assert!(!done);
+            done = flight.process(record, &mut transcript, &SERVER_SECRET)?;
+        }
+        Ok(done)
+    }
+
+    #[test]
+    fn server_flight() {
+        let mut buf = [0; 512];
+        let len = write_flight(&mut buf, ECDSA_SECP256R1_SHA256, &SIGNATURE);
+        for &record_len in [1, 3, 100, len].iter() {
+            let verifier = Verifier::PinnedSpki(&[[0; 32], SPKI_HASH]);
+            assert_eq!(process(verifier, &buf[..len], record_len), Ok(true));
+        }
+    }
+
+    #[test]
+    fn certificate_rejected() {
+        let mut buf = [0; 512];
+        let len = write_flight(&mut buf, ECDSA_SECP256R1_SHA256, &SIGNATURE);
+
+        let verifier = Verifier::PinnedSpki(&[[0; 32]]);
+        assert_eq!(
+            process(verifier, &buf[..len], len),
+            Err(Error::BadCertificate)
+        );
+
+        let verifier = Verifier::Custom(&|cert| cert == CERT);
+        assert_eq!(process(verifier, &buf[..len], len), Ok(true));
+        let verifier = Verifier::Custom(&|_| false);
+        assert_eq!(
+            process(verifier, &buf[..len], len),
+            Err(Error::BadCertificate)
+        );
+    }
+
+    #[test]
+    fn bad_signature() {
+        let verifier = Verifier::PinnedSpki(&[SPKI_HASH]);
+        let mut buf = [0; 512];
+
+        let mut signature = SIGNATURE;
+        signature[SIGNATURE.len() - 1] ^= 1;
+        let len = write_flight(&mut buf, ECDSA_SECP256R1_SHA256, &signature);
+        assert_eq!(
+            process(verifier, &buf[..len], len),
+            Err(Error::DecryptError)
+        );
+
+        // The signature covers the transcript.
+        let len = write_flight(&mut buf, ECDSA_SECP256R1_SHA256, &SIGNATURE);
+        let mut flight = ServerFlight::new(verifier);
+        let mut transcript = Sha256::new();
+        transcript.update(b"hello");
+        assert_eq!(
+            flight.process(&buf[..len], &mut transcript, &SERVER_SECRET),
+            Err(Error::DecryptError)
+        );
+
+        // rsa_pss_rsae_sha256
+        let len = write_flight(&mut buf, 0x0804, &SIGNATURE);
+        assert_eq!(
+            process(verifier, &buf[..len], len),
+            Err(Error::HandshakeFailure)
+        );
+    }
+
+    #[test]
+    fn bad_finished() {
+        let mut buf = [0; 512];
+        let len = write_flight(&mut buf, ECDSA_SECP256R1_SHA256, &SIGNATURE);
+        buf[len - 1] ^= 1;
+        let verifier = Verifier::PinnedSpki(&[SPKI_HASH]);
+        assert_eq!(
+            process(verifier, &buf[..len], len),
+            Err(Error::DecryptError)
+        );
+    }
+
+    #[test]
+    fn certificate_too_large() {
+        let mut buf = [0; 32];
+        let mut w = Writer::new(&mut buf);
+        w.bytes(&[ENCRYPTED_EXTENSIONS, 0, 0, 2, 0, 0]).unwrap();
+        // Only the start of the Certificate, its length is all that's checked.
+        w.bytes(&[CERTIFICATE, 0, 0x10, 0, 0, 0, 0x0f, 0xf9, 0, 0x0f, 0xf4])
+            .unwrap();
+        let len = w.len();
+
+        let mut flight = ServerFlight::new(Verifier::PinnedSpki(&[SPKI_HASH]));
+        let mut transcript = Sha256::new();
+        assert_eq!(
+            flight.process(&buf[..len], &mut transcript, &SERVER_SECRET),
+            Ok(false)
+        );
+        let rest = [0; 0x1000 - 7];
+        assert_eq!(
+            flight.process(&rest, &mut transcript, &SERVER_SECRET),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn post_handshake() {
+        // A NewSessionTicket split across two records, then a KeyUpdate.
+        let first = [NEW_SESSION_TICKET, 0, 0, 6, 1, 2];
+        let second = [3, 4, 5, 6, KEY_UPDATE, 0, 0, 1, 1];
+
+        let mut messages = PostHandshake::new();
+        let mut data = &first[..];
+        assert_eq!(messages.next(&mut data), None);
+        assert!(data.is_empty());
+        assert!(messages.is_partial());
+
+        let mut data = &second[..];
+        assert_eq!(
+            messages.next(&mut data),
+            Some((NEW_SESSION_TICKET, 6, Some(1)))
+        );
+        assert_eq!(messages.next(&mut data), Some((KEY_UPDATE, 1, Some(1))));
+        assert_eq!(messages.next(&mut data), None);
+        assert!(!messages.is_partial());
+
+        // Split in the header, with an empty message.
+        let mut data = &[KEY_UPDATE, 0][..];
+        assert_eq!(messages.next(&mut data), None);
+        let mut data = &[0, 0][..];
+        assert_eq!(messages.next(&mut data), Some((KEY_UPDATE, 0, None)));
+    }
+}
@@ -0,0 +1,318 @@
+//! Key schedule (RFC 8446 section 7) and record protection for TLS_AES_128_GCM_SHA256.
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::Aes128Gcm;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+use super::{Error, Result};
+use crate::fmt::*;
+
+pub const HASH_LEN: usize = 32;
+pub const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 16;
+const IV_LEN: usize = 12;
+/// Longest label we use, "c hs traffic".
+const MAX_LABEL_LEN: usize = 12;
+
+pub type Secret = [u8; HASH_LEN];
+
+/// HKDF-Expand-Label.
+fn expand_label<const N: usize>(secret: &Secret, label: &[u8], context: &[u8]) -> [u8; N] {
+    let mut info = [0; 2 + 1 + 6 + MAX_LABEL_LEN + 1 + HASH_LEN];
+    info[0..2].copy_from_slice(&(N as u16).to_be_bytes());
+    info[2] = (6 + label.len()) as u8;
+    info[3..9].copy_from_slice(b"tls13 ");
+    info[9..9 + label.len()].copy_from_slice(label);
+    let pos = 9 + label.len();
+    info[pos] = context.len() as u8;
+    info[pos + 1..pos + 1 + context.len()].copy_from_slice(context);
+
+    let hkdf = unwrap!(Hkdf::<Sha256>::from_prk(secret).ok());
+    let mut out = [0; N];
+    unwrap!(hkdf.expand(&info[..pos + 1 + context.len()], &mut out).ok());
+    out
+}
+
+fn derive_secret(secret: &Secret, label: &[u8], transcript_hash: &[u8]) -> Secret {
+    expand_label(secret, label, transcript_hash)
+}
+
+fn extract(salt: &Secret, ikm: &[u8]) -> Secret {
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(&salt[..]), ikm);
+    let mut out = [0; HASH_LEN];
+    out.copy_from_slice(&prk);
+    out
+}
+
+/// Secrets derived once the key exchange is done.
+pub struct HandshakeSecrets {
+    pub client: Secret,
+    pub server: Secret,
+    master: Secret,
+}
+
+impl HandshakeSecrets {
+    /// `hello_hash` is the transcript hash up to the ServerHello.
+    pub fn new(shared_secret: &[u8], hello_hash: &[u8]) -> Self {
+        // No PSK, so the early secret is derived from zeros.
+        let zeros = [0; HASH_LEN];
+        let empty_hash = Sha256::digest(b"");
+        let early = extract(&zeros, &zeros);
+        let handshake = extract(
+            &derive_secret(&early, b"derived", &empty_hash),
+            shared_secret,
+        );
+        let master = extract(&derive_secret(&handshake, b"derived", &empty_hash), &zeros);
+
+        Self {
+            client: derive_secret(&handshake, b"c hs traffic", hello_hash),
+            server: derive_secret(&handshake, b"s hs traffic", hello_hash),
+            master,
+        }
+    }
+
+    /// Client and server application traffic secrets. `hash` is the transcript hash up to
+    /// the server's Finished.
+    pub fn application(&self, hash: &[u8]) -> (Secret, Secret) {
+        (
+            derive_secret(&self.master, b"c ap traffic", hash),
+            derive_secret(&self.master, b"s ap traffic", hash),
+        )
+    }
+}
+
+/// Contents of a Finished message sent with the traffic `secret`.
+pub fn finished(secret: &Secret, transcript_hash: &[u8]) -> [u8; HASH_LEN] {
+    let key: Secret = expand_label(secret, b"finished", &[]);
+    let mut mac = unwrap!(Hmac::<Sha256>::new_from_slice(&key).ok());
+    mac.update(transcript_hash);
+    let mut out = [0; HASH_LEN];
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+/// Check the contents of the peer's Finished message, in constant time.
+pub fn verify_finished(secret: &Secret, transcript_hash: &[u8], verify_data: &[u8]) -> Result<()> {
+    let key: Secret = expand_label(secret, b"finished", &[]);
+    let mut mac = unwrap!(Hmac::<Sha256>::new_from_slice(&key).ok());
+    mac.update(transcript_hash);
+    mac.verify(verify_data).map_err(|_| Error::DecryptError)
+}
+
+/// Next traffic secret after a KeyUpdate.
+pub fn update(secret: &Secret) -> Secret {
+    expand_label(secret, b"traffic upd", &[])
+}
+
+/// Key, IV and sequence number protecting the records of one direction.
+pub struct TrafficKeys {
+    aead: Aes128Gcm,
+    iv: [u8; IV_LEN],
+    seq: u64,
+}
+
+impl TrafficKeys {
+    pub fn new(secret: &Secret) -> Self {
+        let key: [u8; KEY_LEN] = expand_label(secret, b"key", &[]);
+        Self {
+            aead: Aes128Gcm::new(GenericArray::from_slice(&key)),
+            iv: expand_label(secret, b"iv", &[]),
+            seq: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> [u8; IV_LEN] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[IV_LEN - 8..].iter_mut().zip(&self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    /// Encrypt `data` in place, `header` is the record header.
+    pub fn encrypt(&mut self, header: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+        let nonce = self.next_nonce();
+        let tag = unwrap!(self
+            .aead
+            .encrypt_in_place_detached(GenericArray::from_slice(&nonce), header, data)
+            .ok());
+        let mut out = [0; TAG_LEN];
+        out.copy_from_slice(&tag);
+        out
+    }
+
+    /// Decrypt `data` in place, `header` is the record header.
+    pub fn decrypt(&mut self, header: &[u8], data: &mut [u8], tag: &[u8]) -> Result<()> {
+        let nonce = self.next_nonce();
+        self.aead
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(&nonce),
+                header,
+                data,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| Error::DecryptError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Values of the "Simple 1-RTT Handshake" trace of RFC 8448.
+
+    const SHARED_SECRET: [u8; 32] = [
+        0x8b, 0xd4, 0x05, 0x4f, 0xb5, 0x5b, 0x9d, 0x63, 0xfd, 0xfb, 0xac, 0xf9, 0xf0, 0x4b, 0x9f,
+        0x0d, 0x35, 0xe6, 0xd6, 0x3f, 0x53, 0x75, 0x63, 0xef, 0xd4, 0x62, 0x72, 0x90, 0x0f, 0x89,
+        0x49, 0x2d,
+    ];
+    /// Hash of the ClientHello and ServerHello.
+    const HELLO_HASH: [u8; 32] = [
+        0x86, 0x0c, 0x06, 0xed, 0xc0, 0x78, 0x58, 0xee, 0x8e, 0x78, 0xf0, 0xe7, 0x42, 0x8c, 0x58,
+        0xed, 0xd6, 0xb4, 0x3f, 0x2c, 0xa3, 0xe6, 0xe9, 0x5f, 0x02, 0xed, 0x06, 0x3c, 0xf0, 0xe1,
+        0xca, 0xd8,
+    ];
+
+    #[test]
+    fn handshake_secrets() {
+        let secrets = HandshakeSecrets::new(&SHARED_SECRET, &HELLO_HASH);
+        let client_secret = [
+            0xb3, 0xed, 0xdb, 0x12, 0x6e, 0x06, 0x7f, 0x35, 0xa7, 0x80, 0xb3, 0xab, 0xf4, 0x5e,
+            0x2d, 0x8f, 0x3b, 0x1a, 0x95, 0x07, 0x38, 0xf5, 0x2e, 0x96, 0x00, 0x74, 0x6a, 0x0e,
+            0x27, 0xa5, 0x5a, 0x21,
+        ];
+        let server_secret = [
+            0xb6, 0x7b, 0x7d, 0x69, 0x0c, 0xc1, 0x6c, 0x4e, 0x75, 0xe5, 0x42, 0x13, 0xcb, 0x2d,
+            0x37, 0xb4, 0xe9, 0xc9, 0x12, 0xbc, 0xde, 0xd9, 0x10, 0x5d, 0x42, 0xbe, 0xfd, 0x59,
+            0xd3, 0x91, 0xad, 0x38,
+        ];
+        let master_secret = [
+            0x18, 0xdf, 0x06, 0x84, 0x3d, 0x13, 0xa0, 0x8b, 0xf2, 0xa4, 0x49, 0x84, 0x4c, 0x5f,
+            0x8a, 0x47, 0x80, 0x01, 0xbc, 0x4d, 0x4c, 0x62, 0x79, 0x84, 0xd5, 0xa4, 0x1d, 0xa8,
+            0xd0, 0x40, 0x29, 0x19,
+        ];
+        assert_eq!(secrets.client, client_secret);
+        assert_eq!(secrets.server, server_secret);
+        assert_eq!(secrets.master, master_secret);
+    }
+
+    #[test]
+    fn traffic_keys() {
+        let secrets = HandshakeSecrets::new(&SHARED_SECRET, &HELLO_HASH);
+
+        let key: [u8; KEY_LEN] = expand_label(&secrets.server, b"key", &[]);
+        let keys = TrafficKeys::new(&secrets.server);
+        assert_eq!(
+            key,
+            [
+                0x3f, 0xce, 0x51, 0x60, 0x09, 0xc2, 0x17, 0x27, 0xd0, 0xf2, 0xe4, 0xe8, 0x6e, 0xe4,
+                0x03, 0xbc,
+            ]
+        );
+        assert_eq!(
+            keys.iv,
+            [0x5d, 0x31, 0x3e, 0xb2, 0x67, 0x12, 0x76, 0xee, 0x13, 0x00, 0x0b, 0x30,]
+        );
+
+        let key: [u8; KEY_LEN] = expand_label(&secrets.client, b"key", &[]);
+        let keys = TrafficKeys::new(&secrets.client);
+        assert_eq!(
+            key,
+            [
+                0xdb, 0xfa, 0xa6, 0x93, 0xd1, 0x76, 0x2c, 0x5b, 0x66, 0x6a, 0xf5, 0xd9, 0x50, 0x25,
+                0x8d, 0x01,
+            ]
+        );
+        assert_eq!(
+            keys.iv,
+            [0x5b, 0xd3, 0xc7, 0x1b, 0x83, 0x6e, 0x0b, 0x76, 0xbb, 0x73, 0x26, 0x5f,]
+        );
+    }
+
+    #[test]
+    fn finished_key() {
+        let secrets = HandshakeSecrets::new(&SHARED_SECRET, &HELLO_HASH);
+        let key: Secret = expand_label(&secrets.server, b"finished", &[]);
+        assert_eq!(
+            key,
+            [
+                0x00, 0x8d, 0x3b, 0x66, 0xf8, 0x16, 0xea, 0x55, 0x9f, 0x96, 0xb5, 0x37, 0xe8, 0x85,
+                0xc3, 0x1f, 0xc0, 0x68, 0xbf, 0x49, 0x2c, 0x65, 0x2f, 0x01, 0xf2, 0x88, 0xa1, 0xd8,
+                0xcd, 0xc1, 0x9f, 0xc8,
+            ]
+        );
+    }
+
+    #[test]
+    fn finished() {
+        let secret = [1; HASH_LEN];
+        let verify_data = super::finished(&secret, &HELLO_HASH);
+        assert_eq!(verify_finished(&secret, &HELLO_HASH, &verify_data), Ok(()));
+        assert_eq!(
+            verify_finished(&[2; HASH_LEN], &HELLO_HASH, &verify_data),
+            Err(Error::DecryptError)
+        );
+        assert_eq!(
+            verify_finished(&secret, &[0; HASH_LEN], &verify_data),
+            Err(Error::DecryptError)
+        );
+        assert_eq!(
+            verify_finished(&secret, &HELLO_HASH, &verify_data[1..]),
+            Err(Error::DecryptError)
+        );
+    }
+
+    #[test]
+    fn nonce() {
+        let mut keys = TrafficKeys::new(&[1; HASH_LEN]);
+        let iv = keys.iv;
+        assert_eq!(keys.next_nonce(), iv);
+        let mut nonce = iv;
+        nonce[IV_LEN - 1] ^= 1;
+        assert_eq!(keys.next_nonce(), nonce);
+
+        keys.seq = 0x0102;
+        let mut nonce = iv;
+        nonce[IV_LEN - 2] ^= 1;
+        nonce[IV_LEN - 1] ^= 2;
+        assert_eq!(keys.next_nonce(), nonce);
+        assert_eq!(keys.seq, 0x0103);
+    }
+
+    #[test]
+    fn records() {
+        let secret = [1; HASH_LEN];
+        let mut tx = TrafficKeys::new(&secret);
+        let mut rx = TrafficKeys::new(&secret);
+        let header = [23, 3, 3, 0, 21];
+
+        for _ in 0..3 {
+            let mut data = *b"hello";
+            let tag = tx.encrypt(&header, &mut data);
+            assert_ne!(&data, b"hello");
+            assert_eq!(rx.decrypt(&header, &mut data, &tag), Ok(()));
+            assert_eq!(&data, b"hello");
+        }
+
+        let mut data = *b"hello";
+        let tag = tx.encrypt(&header, &mut data);
+        data[0] ^= 1;
+        assert_eq!(
+            rx.decrypt(&header, &mut data, &tag),
+            Err(Error::DecryptError)
+        );
+
+        // Records are numbered, a skipped one makes the next ones fail.
+        let mut data = *b"hello";
+        tx.encrypt(&header, &mut data);
+        let tag = tx.encrypt(&header, &mut data);
+        assert_eq!(
+            rx.decrypt(&header, &mut data, &tag),
+            Err(Error::DecryptError)
+        );
+    }
+}
@@ -0,0 +1,741 @@
+//! TLS 1.3 client.
+//!
+//! [TlsConnection] runs TLS over any `AsyncBufRead + AsyncWrite` transport, typically a
+//! [TcpSocket](crate::TcpSocket), and is itself `AsyncBufRead + AsyncWrite`. Records are
+//! assembled in buffers supplied by the caller, nothing is allocated.
+//!
+//! Only what's needed to talk to common servers is supported: the TLS_AES_128_GCM_SHA256
+//! cipher suite with X25519 key exchange. There are no client certificates and no session
+//! resumption.
+//!
+//! The server is authenticated by a [Verifier] given in the [TlsConfig]: the certificate it
+//! sends must be accepted by the verifier, and it must sign the handshake with that
+//! certificate's key. There is no certificate chain validation against CAs, the expected
+//! key is pinned instead, and expiry dates are not checked since devices may not know the
+//! time. Only ECDSA P-256 server keys are supported.
+//!
+//! ```ignore
+//! // The SHA-256 hash of the server's public key, see `Verifier::PinnedSpki`.
+//! const SERVER_KEY: [u8; 32] = [...];
+//!
+//! let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+//! socket.connect(remote_endpoint).await?;
+//!
+//! let mut tls = TlsConnection::new(socket, &mut record_rx, &mut record_tx);
+//! let config = TlsConfig {
+//!     server_name: Some("example.com"),
+//!     verifier: Verifier::PinnedSpki(&[SERVER_KEY]),
+//! };
+//! tls.open(&config, &mut rng).await?;
+//! tls.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await?;
+//! tls.flush().await?;
+//! ```
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::traits::rng::Rng;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+
+mod cert;
+mod handshake;
+mod keys;
+
+pub use self::handshake::MAX_CERTIFICATE_LEN;
+use self::handshake::{PostHandshake, ServerFlight};
+use self::keys::{HandshakeSecrets, Secret, TrafficKeys, HASH_LEN, TAG_LEN};
+use crate::fmt::*;
+
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+const ALERT_WARNING: u8 = 1;
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+
+const RECORD_HEADER_LEN: usize = 5;
+const MAX_PLAINTEXT_LEN: usize = 1 << 14;
+/// Size of the largest record a server may send.
+///
+/// An rx buffer of this size works with any server.
+pub const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + MAX_PLAINTEXT_LEN + 256;
+/// Smallest rx buffer, it holds records of 512 bytes.
+pub const MIN_RX_BUFFER_LEN: usize = RECORD_HEADER_LEN + 512 + 256;
+/// Smallest tx buffer, it holds the ClientHello.
+pub const MIN_TX_BUFFER_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Reading from or writing to the transport failed.
+    Io(io::Error),
+    /// The random number generator failed.
+    Rng,
+    /// The server sent a record larger than the rx buffer, or the ClientHello doesn't fit in
+    /// the tx buffer.
+    BufferTooSmall,
+    /// The server chose parameters we don't support.
+    HandshakeFailure,
+    /// The server sent a malformed message.
+    Decode,
+    /// The server sent a message that isn't valid at this point.
+    UnexpectedMessage,
+    /// A record, the server's CertificateVerify signature or its Finished message failed
+    /// authentication.
+    DecryptError,
+    /// The server's certificate was rejected by the verifier, or its key isn't ECDSA P-256.
+    BadCertificate,
+    /// The server sent a fatal alert, with this description.
+    Alert(u8),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+fn to_ioerr(err: Error) -> io::Error {
+    match err {
+        Error::Io(e) => e,
+        Error::Alert(_) => io::Error::ConnectionAborted,
+        _ => io::Error::InvalidData,
+    }
+}
+
+/// Parameters of the connection.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConfig<'a> {
+    /// Host name sent to the server (SNI), which many servers need to pick a certificate.
+    pub server_name: Option<&'a str>,
+    /// Decides whether the server's certificate is accepted.
+    pub verifier: Verifier<'a>,
+}
+
+/// How the server's certificate is checked.
+///
+/// Whichever is used, the server must also prove it has the certificate's private key, so
+/// a certificate copied from the real server is of no use to an attacker.
+#[derive(Clone, Copy)]
+pub enum Verifier<'a> {
+    /// Accept certificates whose public key, the DER SubjectPublicKeyInfo, has one of these
+    /// SHA-256 hashes. Listing both the current and the next key allows rotating keys.
+    ///
+    /// The hash can be computed from the certificate with
+    /// `openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`.
+    PinnedSpki(&'a [[u8; 32]]),
+    /// Accept the DER certificate if the function returns true.
+    Custom(&'a dyn Fn(&[u8]) -> bool),
+}
+
+impl<'a> core::fmt::Debug for Verifier<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Verifier::PinnedSpki(hashes) => f.debug_tuple("PinnedSpki").field(hashes).finish(),
+            Verifier::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// `open` wasn't called, or failed.
+    Closed,
+    Open,
+    /// The server sent close_notify, we can still write.
+    ReadClosed,
+}
+
+/// A TLS connection over `S`, see the [module documentation](self).
+///
+/// Written data is sent in records of up to the size of the tx buffer. Each `poll_write`
+/// encrypts one record into the tx buffer and sends as much of it as the transport takes.
+/// The rest is sent by the next write or read, or by [flush](Self::flush), which should be
+/// called once done writing.
+pub struct TlsConnection<'a, S> {
+    socket: S,
+    state: State,
+
+    rx: &'a mut [u8],
+    /// Bytes of the record being received.
+    rx_len: usize,
+    /// Decrypted contents of the last record received.
+    rx_pos: usize,
+    rx_end: usize,
+    rx_secret: Secret,
+    rx_keys: Option<TrafficKeys>,
+
+    tx: &'a mut [u8],
+    /// Record being sent.
+    tx_pos: usize,
+    tx_len: usize,
+    /// The record being sent is our KeyUpdate, the tx keys change once it's sent.
+    tx_key_update: bool,
+    tx_secret: Secret,
+    tx_keys: Option<TrafficKeys>,
+    /// The server asked us to update our keys too.
+    key_update_requested: bool,
+    post_handshake: PostHandshake,
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> TlsConnection<'a, S> {
+    /// Wrap a connected transport. Call [open](Self::open) to perform the handshake.
+    ///
+    /// `rx` should be [MAX_RECORD_LEN] bytes to work with any server. Smaller buffers make
+    /// us ask the server to send smaller records with the max_fragment_length extension,
+    /// which not all servers support. Panics if `rx` is smaller than [MIN_RX_BUFFER_LEN]
+    /// or `tx` is smaller than [MIN_TX_BUFFER_LEN].
+    pub fn new(socket: S, rx: &'a mut [u8], tx: &'a mut [u8]) -> Self {
+        assert!(rx.len() >= MIN_RX_BUFFER_LEN, "TLS rx buffer too small");
+        assert!(tx.len() >= MIN_TX_BUFFER_LEN, "TLS tx buffer too small");
+
+        Self {
+            socket,
+            state: State::Closed,
+            rx,
+            rx_len: 0,
+            rx_pos: 0,
+            rx_end: 0,
+            rx_secret: [0; HASH_LEN],
+            rx_keys: None,
+            tx,
+            tx_pos: 0,
+            tx_len: 0,
+            tx_key_update: false,
+            tx_secret: [0; HASH_LEN],
+            tx_keys: None,
+            key_update_requested: false,
+            post_handshake: PostHandshake::new(),
+        }
+    }
+
+    /// Perform the handshake.
+    pub async fn open<R: Rng>(&mut self, config: &TlsConfig<'_>, rng: &mut R) -> Result<()> {
+        let res = self.handshake(config, rng).await;
+        match res {
+            Ok(()) => self.state = State::Open,
+            Err(e) => warn!("TLS handshake failed: {:?}", e),
+        }
+        res
+    }
+
+    /// Send close_notify to tell the server we won't write anymore.
+    ///
+    /// This doesn't close the transport, and data can still be read until the server closes
+    /// its side.
+    pub async fn close(&mut self) -> Result<()> {
+        if self.state == State::Closed {
+            return Ok(());
+        }
+        self.flush().await?;
+        self.tx[RECORD_HEADER_LEN..RECORD_HEADER_LEN + 2]
+            .copy_from_slice(&[ALERT_WARNING, ALERT_CLOSE_NOTIFY]);
+        self.seal(ALERT, 2);
+        self.flush().await
+    }
+
+    /// Send the data written so far.
+    pub async fn flush(&mut self) -> Result<()> {
+        futures::future::poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// Unwrap the transport.
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    async fn handshake<R: Rng>(&mut self, config: &TlsConfig<'_>, rng: &mut R) -> Result<()> {
+        self.state = State::Closed;
+        self.rx_len = 0;
+        self.rx_pos = 0;
+        self.rx_end = 0;
+        self.rx_keys = None;
+        self.tx_pos = 0;
+        self.tx_len = 0;
+        self.tx_key_update = false;
+        self.tx_keys = None;
+        self.key_update_requested = false;
+        self.post_handshake = PostHandshake::new();
+
+        let mut random = [0; 96];
+        rng.fill_bytes(&mut random).await.map_err(|_| Error::Rng)?;
+        let client_random = &random[0..32];
+        // A session id is only sent for middlebox compatibility (RFC 8446 appendix D.4).
+        let session_id = &random[32..64];
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&random[64..96]);
+        let public_key = x25519(secret, X25519_BASEPOINT_BYTES);
+
+        let mut transcript = Sha256::new();
+
+        // ClientHello
+        let max_fragment_length = if self.rx.len() >= MAX_RECORD_LEN {
+            None
+        } else {
+            (1..=4)
+                .rev()
+                .find(|code| RECORD_HEADER_LEN + (256 << code) + 256 <= self.rx.len())
+        };
+        let len = handshake::client_hello(
+            &mut self.tx[RECORD_HEADER_LEN..],
+            client_random,
+            session_id,
+            &public_key,
+            config.server_name,
+            max_fragment_length,
+        )?;
+        transcript.update(&self.tx[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len]);
+        // The legacy version of the first record is TLS 1.0 for compatibility.
+        self.tx[..RECORD_HEADER_LEN].copy_from_slice(&[HANDSHAKE, 3, 1, 0, 0]);
+        self.tx[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+        self.tx_len = RECORD_HEADER_LEN + len;
+        self.flush().await?;
+
+        // ServerHello
+        let server_key = match self.read_record().await? {
+            HANDSHAKE => {
+                let msg = &self.rx[self.rx_pos..self.rx_end];
+                let key = handshake::parse_server_hello(msg, session_id)?;
+                transcript.update(msg);
+                key
+            }
+            ALERT => return Err(self.alert()),
+            _ => return Err(Error::UnexpectedMessage),
+        };
+        let shared_secret = x25519(secret, server_key);
+        if shared_secret == [0; 32] {
+            return Err(Error::HandshakeFailure);
+        }
+        let secrets = HandshakeSecrets::new(&shared_secret, &transcript.clone().finalize());
+        self.rx_keys = Some(TrafficKeys::new(&secrets.server));
+
+        // EncryptedExtensions to Finished
+        let mut flight = ServerFlight::new(config.verifier);
+        loop {
+            match self.read_record().await? {
+                HANDSHAKE => {
+                    let data = &self.rx[self.rx_pos..self.rx_end];
+                    if flight.process(data, &mut transcript, &secrets.server)? {
+                        break;
+                    }
+                }
+                CHANGE_CIPHER_SPEC => {}
+                ALERT => return Err(self.alert()),
+                _ => return Err(Error::UnexpectedMessage),
+            }
+        }
+        let (client_secret, server_secret) = secrets.application(&transcript.clone().finalize());
+
+        // Our CCS, then Certificate if asked and Finished
+        self.tx[..6].copy_from_slice(&[CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1]);
+        self.tx_len = 6;
+        self.flush().await?;
+
+        self.tx_keys = Some(TrafficKeys::new(&secrets.client));
+        if let Some(context) = flight.certificate_request() {
+            // We have no certificate, send an empty list and let the server decide.
+            let msg = &mut self.tx[RECORD_HEADER_LEN..];
+            let len = 4 + 1 + context.len() + 3;
+            msg[..4].copy_from_slice(&(len as u32 - 4).to_be_bytes());
+            msg[0] = handshake::CERTIFICATE;
+            msg[4] = context.len() as u8;
+            msg[5..5 + context.len()].copy_from_slice(context);
+            msg[5 + context.len()..len].copy_from_slice(&[0; 3]);
+            transcript.update(&msg[..len]);
+            self.seal(HANDSHAKE, len);
+            self.flush().await?;
+        }
+
+        let verify_data = keys::finished(&secrets.client, &transcript.finalize());
+        let msg = &mut self.tx[RECORD_HEADER_LEN..];
+        msg[..4].copy_from_slice(&[handshake::FINISHED, 0, 0, HASH_LEN as u8]);
+        msg[4..4 + HASH_LEN].copy_from_slice(&verify_data);
+        self.seal(HANDSHAKE, 4 + HASH_LEN);
+        self.flush().await?;
+
+        self.rx_secret = server_secret;
+        self.rx_keys = Some(TrafficKeys::new(&server_secret));
+        self.tx_secret = client_secret;
+        self.tx_keys = Some(TrafficKeys::new(&client_secret));
+        self.rx_pos = self.rx_end;
+        Ok(())
+    }
+
+    /// The error for the alert in the last record received.
+    fn alert(&self) -> Error {
+        match self.rx[self.rx_pos..self.rx_end] {
+            [_, description] => Error::Alert(description),
+            _ => Error::Decode,
+        }
+    }
+
+    async fn read_record(&mut self) -> Result<u8> {
+        futures::future::poll_fn(|cx| self.poll_record(cx)).await
+    }
+
+    /// Receive the next record and decrypt it if needed, returning its content type. Its
+    /// contents are `rx[rx_pos..rx_end]`.
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Result<u8>> {
+        let record_len = loop {
+            let want = if self.rx_len >= RECORD_HEADER_LEN {
+                let len = RECORD_HEADER_LEN + u16::from_be_bytes([self.rx[3], self.rx[4]]) as usize;
+                if len > self.rx.len() {
+                    return Poll::Ready(Err(Error::BufferTooSmall));
+                }
+                if self.rx_len == len {
+                    break len;
+                }
+                len
+            } else {
+                RECORD_HEADER_LEN
+            };
+
+            let buf = match Pin::new(&mut self.socket).poll_fill_buf(cx) {
+                // The server must send close_notify before closing.
+                Poll::Ready(Ok([])) => {
+                    return Poll::Ready(Err(Error::Io(io::Error::UnexpectedEof)))
+                }
+                Poll::Ready(Ok(buf)) => buf,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+            let n = buf.len().min(want - self.rx_len);
+            self.rx[self.rx_len..self.rx_len + n].copy_from_slice(&buf[..n]);
+            self.rx_len += n;
+            Pin::new(&mut self.socket).consume(n);
+        };
+        self.rx_len = 0;
+
+        let content_type = self.rx[0];
+        self.rx_pos = RECORD_HEADER_LEN;
+        self.rx_end = record_len;
+
+        let keys = match &mut self.rx_keys {
+            // CCS is never encrypted, the caller decides whether it's allowed.
+            Some(keys) if content_type == APPLICATION_DATA => keys,
+            Some(_) if content_type == CHANGE_CIPHER_SPEC => return Poll::Ready(Ok(content_type)),
+            Some(_) => return Poll::Ready(Err(Error::UnexpectedMessage)),
+            None => return Poll::Ready(Ok(content_type)),
+        };
+
+        if record_len < RECORD_HEADER_LEN + 1 + TAG_LEN {
+            return Poll::Ready(Err(Error::Decode));
+        }
+        let (header, body) = self.rx[..record_len].split_at_mut(RECORD_HEADER_LEN);
+        let (data, tag) = body.split_at_mut(body.len() - TAG_LEN);
+        if let Err(e) = keys.decrypt(header, data, tag) {
+            return Poll::Ready(Err(e));
+        }
+
+        // The real content type is the last non-zero byte, followed by padding.
+        match data.iter().rposition(|&b| b != 0) {
+            Some(end) => {
+                self.rx_end = RECORD_HEADER_LEN + end;
+                Poll::Ready(Ok(data[end]))
+            }
+            None => Poll::Ready(Err(Error::UnexpectedMessage)),
+        }
+    }
+
+    /// Handle the handshake messages the server can send after the handshake.
+    ///
+    /// Messages can be split across records, so only complete ones are handled.
+    fn handle_post_handshake(&mut self) -> Result<()> {
+        let mut data = &self.rx[self.rx_pos..self.rx_end];
+        while let Some((msg_type, len, first)) = self.post_handshake.next(&mut data) {
+            match msg_type {
+                // We don't do resumption.
+                handshake::NEW_SESSION_TICKET => {}
+                handshake::KEY_UPDATE => {
+                    if len != 1 {
+                        return Err(Error::Decode);
+                    }
+                    // The key changes after the KeyUpdate, nothing may follow it in the same
+                    // record.
+                    if !data.is_empty() {
+                        return Err(Error::UnexpectedMessage);
+                    }
+                    match first {
+                        Some(0) => {}
+                        Some(1) => self.key_update_requested = true,
+                        _ => return Err(Error::Decode),
+                    }
+                    self.rx_secret = keys::update(&self.rx_secret);
+                    self.rx_keys = Some(TrafficKeys::new(&self.rx_secret));
+                }
+                _ => return Err(Error::UnexpectedMessage),
+            }
+        }
+        self.rx_pos = self.rx_end;
+        Ok(())
+    }
+
+    /// Encrypt `len` bytes at `tx[RECORD_HEADER_LEN..]` into a record.
+    fn seal(&mut self, content_type: u8, len: usize) {
+        let keys = unwrap!(self.tx_keys.as_mut());
+        let start = RECORD_HEADER_LEN;
+        self.tx[start + len] = content_type;
+        let body_len = len + 1 + TAG_LEN;
+
+        let mut header = [APPLICATION_DATA, 3, 3, 0, 0];
+        header[3..5].copy_from_slice(&(body_len as u16).to_be_bytes());
+        self.tx[..start].copy_from_slice(&header);
+        let tag = keys.encrypt(&header, &mut self.tx[start..start + len + 1]);
+        self.tx[start + len + 1..start + body_len].copy_from_slice(&tag);
+
+        self.tx_pos = 0;
+        self.tx_len = start + body_len;
+    }
+
+    /// Send the record in the tx buffer, if any.
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.tx_len == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        while self.tx_pos < self.tx_len {
+            let buf = &self.tx[self.tx_pos..self.tx_len];
+            match Pin::new(&mut self.socket).poll_write(cx, buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Error::Io(io::Error::WriteZero))),
+                Poll::Ready(Ok(n)) => self.tx_pos += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.tx_pos = 0;
+        self.tx_len = 0;
+        if self.tx_key_update {
+            self.tx_key_update = false;
+            self.tx_secret = keys::update(&self.tx_secret);
+            self.tx_keys = Some(TrafficKeys::new(&self.tx_secret));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> AsyncBufRead for TlsConnection<'a, S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match this.state {
+            State::Open => {}
+            State::ReadClosed => return Poll::Ready(Ok(&[])),
+            State::Closed => return Poll::Ready(Err(io::Error::NotConnected)),
+        }
+
+        // Send what was written before waiting for data, the server may be waiting for it.
+        // If the transport can't take it yet, it's sent by a later call.
+        if let Poll::Ready(Err(e)) = this.poll_flush(cx) {
+            warn!("TLS write failed: {:?}", e);
+            this.state = State::Closed;
+            return Poll::Ready(Err(to_ioerr(e)));
+        }
+
+        while this.rx_pos == this.rx_end {
+            let res = match this.poll_record(cx) {
+                // Handshake messages can't be interleaved with other records.
+                Poll::Ready(Ok(APPLICATION_DATA)) if this.post_handshake.is_partial() => {
+                    Err(Error::UnexpectedMessage)
+                }
+                Poll::Ready(Ok(APPLICATION_DATA)) => Ok(()),
+                Poll::Ready(Ok(HANDSHAKE)) => this.handle_post_handshake(),
+                Poll::Ready(Ok(ALERT)) => match this.alert() {
+                    Error::Alert(ALERT_CLOSE_NOTIFY) => {
+                        this.state = State::ReadClosed;
+                        return Poll::Ready(Ok(&[]));
+                    }
+                    e => Err(e),
+                },
+                Poll::Ready(Ok(_)) => Err(Error::UnexpectedMessage),
+                Poll::Ready(Err(e)) => Err(e),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Err(e) = res {
+                warn!("TLS read failed: {:?}", e);
+                this.state = State::Closed;
+                return Poll::Ready(Err(to_ioerr(e)));
+            }
+        }
+
+        Poll::Ready(Ok(&this.rx[this.rx_pos..this.rx_end]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.rx_pos = (this.rx_pos + amt).min(this.rx_end);
+    }
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> AsyncWrite for TlsConnection<'a, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.state == State::Closed {
+            return Poll::Ready(Err(io::Error::NotConnected));
+        }
+
+        // Only one record is buffered, the previous one must be sent before sealing another.
+        loop {
+            match this.poll_flush(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(to_ioerr(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+            if !this.key_update_requested {
+                break;
+            }
+            this.key_update_requested = false;
+            // update_not_requested, the server already updated its keys.
+            this.tx[RECORD_HEADER_LEN..RECORD_HEADER_LEN + 5].copy_from_slice(&[
+                handshake::KEY_UPDATE,
+                0,
+                0,
+                1,
+                0,
+            ]);
+            this.seal(HANDSHAKE, 5);
+            this.tx_key_update = true;
+        }
+
+        let max_len = (this.tx.len() - RECORD_HEADER_LEN - 1 - TAG_LEN).min(MAX_PLAINTEXT_LEN);
+        let n = buf.len().min(max_len);
+        if n == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        this.tx[RECORD_HEADER_LEN..RECORD_HEADER_LEN + n].copy_from_slice(&buf[..n]);
+        this.seal(APPLICATION_DATA, n);
+        // The data is ours now. Start sending it, whatever isn't sent yet stays buffered.
+        match this.poll_flush(cx) {
+            Poll::Ready(Err(e)) => Poll::Ready(Err(to_ioerr(e))),
+            _ => Poll::Ready(Ok(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::task::noop_waker_ref;
+    use heapless::Vec;
+
+    /// Transport taking up to `space` bytes. Nothing is ever received.
+    struct Socket {
+        sent: Vec<u8, 256>,
+        space: usize,
+    }
+
+    impl AsyncBufRead for Socket {
+        fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            Poll::Pending
+        }
+
+        fn consume(self: Pin<&mut Self>, _amt: usize) {}
+    }
+
+    impl AsyncWrite for Socket {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let n = buf.len().min(this.space);
+            if n == 0 {
+                return Poll::Pending;
+            }
+            this.sent.extend_from_slice(&buf[..n]).unwrap();
+            this.space -= n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    const SECRET: Secret = [1; HASH_LEN];
+    /// Length of a record holding 5 bytes.
+    const RECORD_LEN: usize = RECORD_HEADER_LEN + 5 + 1 + TAG_LEN;
+
+    fn open<'a>(space: usize, rx: &'a mut [u8], tx: &'a mut [u8]) -> TlsConnection<'a, Socket> {
+        let socket = Socket {
+            sent: Vec::new(),
+            space,
+        };
+        let mut tls = TlsConnection::new(socket, rx, tx);
+        tls.state = State::Open;
+        tls.tx_secret = SECRET;
+        tls.tx_keys = Some(TrafficKeys::new(&SECRET));
+        tls
+    }
+
+    fn write(tls: &mut TlsConnection<'_, Socket>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        Pin::new(tls).poll_write(&mut cx, data)
+    }
+
+    /// Decrypt a record, returning its content type and contents.
+    fn decrypt(keys: &mut TrafficKeys, record: &mut [u8]) -> (u8, [u8; 5]) {
+        let (header, body) = record.split_at_mut(RECORD_HEADER_LEN);
+        let (data, tag) = body.split_at_mut(body.len() - TAG_LEN);
+        keys.decrypt(header, data, tag).unwrap();
+        let mut contents = [0; 5];
+        contents.copy_from_slice(&data[..5]);
+        (data[5], contents)
+    }
+
+    #[test]
+    fn write_buffered() {
+        let mut rx = [0; MIN_RX_BUFFER_LEN];
+        let mut tx = [0; MIN_TX_BUFFER_LEN];
+        let mut tls = open(10, &mut rx, &mut tx);
+
+        // The data is taken even though the transport only takes part of the record.
+        assert_eq!(write(&mut tls, b"hello"), Poll::Ready(Ok(5)));
+        assert_eq!(tls.socket.sent.len(), 10);
+        // Another record can't be sealed until this one is sent.
+        assert_eq!(write(&mut tls, b"world"), Poll::Pending);
+        tls.socket.space = 100;
+        assert_eq!(write(&mut tls, b"world"), Poll::Ready(Ok(5)));
+        assert_eq!(tls.socket.sent.len(), 2 * RECORD_LEN);
+
+        let mut keys = TrafficKeys::new(&SECRET);
+        let sent = &mut tls.socket.sent;
+        let (first, second) = sent.split_at_mut(RECORD_LEN);
+        assert_eq!(decrypt(&mut keys, first), (APPLICATION_DATA, *b"hello"));
+        assert_eq!(decrypt(&mut keys, second), (APPLICATION_DATA, *b"world"));
+    }
+
+    #[test]
+    fn flush() {
+        let mut rx = [0; MIN_RX_BUFFER_LEN];
+        let mut tx = [0; MIN_TX_BUFFER_LEN];
+        let mut tls = open(10, &mut rx, &mut tx);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        assert_eq!(write(&mut tls, b"hello"), Poll::Ready(Ok(5)));
+        assert_eq!(tls.poll_flush(&mut cx), Poll::Pending);
+        tls.socket.space = 100;
+        assert_eq!(tls.poll_flush(&mut cx), Poll::Ready(Ok(())));
+        assert_eq!(tls.socket.sent.len(), RECORD_LEN);
+        assert_eq!(tls.poll_flush(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn key_update() {
+        let mut rx = [0; MIN_RX_BUFFER_LEN];
+        let mut tx = [0; MIN_TX_BUFFER_LEN];
+        let mut tls = open(100, &mut rx, &mut tx);
+        tls.key_update_requested = true;
+
+        // Our KeyUpdate goes first, then the data with the new keys.
+        assert_eq!(write(&mut tls, b"hello"), Poll::Ready(Ok(5)));
+        assert_eq!(tls.socket.sent.len(), 2 * RECORD_LEN);
+        assert!(!tls.key_update_requested);
+
+        let sent = &mut tls.socket.sent;
+        let (first, second) = sent.split_at_mut(RECORD_LEN);
+        let mut keys = TrafficKeys::new(&SECRET);
+        assert_eq!(
+            decrypt(&mut keys, first),
+            (HANDSHAKE, [handshake::KEY_UPDATE, 0, 0, 1, 0])
+        );
+        let mut keys = TrafficKeys::new(&keys::update(&SECRET));
+        assert_eq!(decrypt(&mut keys, second), (APPLICATION_DATA, *b"hello"));
+    }
+}
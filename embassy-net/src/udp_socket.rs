@@ -0,0 +1,149 @@
+use core::marker::PhantomData;
+use core::mem;
+use core::task::Poll;
+use smoltcp::socket::SocketHandle;
+use smoltcp::socket::UdpSocket as SyncUdpSocket;
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::wire::IpEndpoint;
+
+use crate::{Error, Result, Stack};
+
+pub struct UdpSocket<'a> {
+    stack: &'a Stack,
+    handle: SocketHandle,
+    ghost: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Unpin for UdpSocket<'a> {}
+
+impl<'a> UdpSocket<'a> {
+    /// Create a new UDP socket.
+    ///
+    /// The metadata buffers hold one entry per queued datagram, the data buffers hold their
+    /// payloads.
+    ///
+    /// Fails with `Error::Exhausted` if the stack's socket set is full.
+    pub fn new(
+        stack: &'a Stack,
+        rx_meta: &'a mut [UdpPacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [UdpPacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Result<Self> {
+        let handle = stack.with(|stack| {
+            let rx_meta: &'static mut [UdpPacketMetadata] = unsafe { mem::transmute(rx_meta) };
+            let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+            let tx_meta: &'static mut [UdpPacketMetadata] = unsafe { mem::transmute(tx_meta) };
+            let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+            stack.add_socket(SyncUdpSocket::new(
+                UdpSocketBuffer::new(rx_meta, rx_buffer),
+                UdpSocketBuffer::new(tx_meta, tx_buffer),
+            ))
+        })?;
+
+        Ok(Self {
+            stack,
+            handle,
+            ghost: PhantomData,
+        })
+    }
+
+    /// Bind the socket to a local endpoint.
+    ///
+    /// If the port is 0, a local port is picked automatically.
+    pub fn bind<T>(&mut self, endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        let mut endpoint = endpoint.into();
+        if endpoint.port == 0 {
+            endpoint.port = self.stack.with(|stack| stack.get_local_port());
+        }
+        self.with(|s| s.bind(endpoint))
+    }
+
+    /// Send a datagram to the given remote endpoint.
+    ///
+    /// Waits until there is room for it in the tx buffer.
+    pub async fn send_to<T>(&mut self, buf: &[u8], remote_endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        let remote_endpoint = remote_endpoint.into();
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.send_slice(buf, remote_endpoint) {
+                // No space in the tx buffer
+                Err(Error::Exhausted) => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                r => Poll::Ready(r),
+            })
+        })
+        .await
+    }
+
+    /// Receive a datagram into `buf`.
+    ///
+    /// Waits until a datagram is available. Returns the number of bytes received and
+    /// the endpoint it was sent from. If `buf` is too small, returns `Error::Truncated`
+    /// and the datagram is dropped.
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint)> {
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.recv() {
+                // recv_slice would silently cut the datagram short.
+                Ok((data, _)) if data.len() > buf.len() => Poll::Ready(Err(Error::Truncated)),
+                Ok((data, endpoint)) => {
+                    buf[..data.len()].copy_from_slice(data);
+                    Poll::Ready(Ok((data.len(), endpoint)))
+                }
+                // No datagram ready
+                Err(Error::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                Err(e) => Poll::Ready(Err(e)),
+            })
+        })
+        .await
+    }
+
+    pub fn endpoint(&self) -> IpEndpoint {
+        self.with(|s| s.endpoint())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.with(|s| s.is_open())
+    }
+
+    pub fn close(&mut self) {
+        self.with(|s| s.close())
+    }
+
+    pub fn may_send(&self) -> bool {
+        self.with(|s| s.can_send())
+    }
+
+    pub fn may_recv(&self) -> bool {
+        self.with(|s| s.can_recv())
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut SyncUdpSocket) -> R) -> R {
+        self.stack.with(|stack| {
+            let res = {
+                let mut s = stack.sockets.get::<SyncUdpSocket>(self.handle);
+                f(&mut *s)
+            };
+            stack.wake();
+            res
+        })
+    }
+}
+
+impl<'a> Drop for UdpSocket<'a> {
+    fn drop(&mut self) {
+        self.stack.with(|stack| {
+            stack.sockets.remove(self.handle);
+        })
+    }
+}
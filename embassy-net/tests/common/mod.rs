@@ -0,0 +1,135 @@
+//! In-process byte streams, to test protocols without a network stack.
+
+#![allow(dead_code)]
+
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Bytes going one way.
+struct Buffer {
+    data: VecDeque<u8>,
+    /// How many bytes it holds before writes wait.
+    capacity: usize,
+    closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Buffer {
+    fn new() -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            data: VecDeque::new(),
+            capacity: 4096,
+            closed: false,
+            reader: None,
+            writer: None,
+        }))
+    }
+}
+
+/// One end of a stream made with [stream].
+pub struct Stream {
+    rx: Rc<RefCell<Buffer>>,
+    tx: Rc<RefCell<Buffer>>,
+    /// Received bytes returned by `poll_fill_buf`.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+/// Two connected ends of a stream, like a TCP connection without losses.
+pub fn stream() -> (Stream, Stream) {
+    let (a, b) = (Buffer::new(), Buffer::new());
+    let end = |rx: &Rc<RefCell<Buffer>>, tx: &Rc<RefCell<Buffer>>| Stream {
+        rx: rx.clone(),
+        tx: tx.clone(),
+        read_buf: Vec::new(),
+        read_pos: 0,
+    };
+    (end(&a, &b), end(&b, &a))
+}
+
+impl Stream {
+    /// Set how many bytes the other end can have written without us reading them. Zero
+    /// stalls its writes.
+    pub fn set_rx_capacity(&mut self, capacity: usize) {
+        let mut rx = self.rx.borrow_mut();
+        rx.capacity = capacity;
+        if let Some(waker) = rx.writer.take() {
+            waker.wake();
+        }
+    }
+
+    /// Close the sending side, the other end reads EOF once it has read everything.
+    pub fn close(&mut self) {
+        let mut tx = self.tx.borrow_mut();
+        tx.closed = true;
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether nothing was received that wasn't read yet.
+    pub fn rx_is_empty(&self) -> bool {
+        self.read_pos == self.read_buf.len() && self.rx.borrow().data.is_empty()
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl AsyncBufRead for Stream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.read_pos == this.read_buf.len() {
+            let mut rx = this.rx.borrow_mut();
+            if rx.data.is_empty() {
+                if rx.closed {
+                    return Poll::Ready(Ok(&[]));
+                }
+                rx.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            this.read_buf = rx.data.drain(..).collect();
+            this.read_pos = 0;
+            if let Some(waker) = rx.writer.take() {
+                waker.wake();
+            }
+        }
+        Poll::Ready(Ok(&this.read_buf[this.read_pos..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.read_pos = (this.read_pos + amt).min(this.read_buf.len());
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut tx = self.tx.borrow_mut();
+        if tx.closed {
+            return Poll::Ready(Err(io::Error::BrokenPipe));
+        }
+        let n = buf.len().min(tx.capacity.saturating_sub(tx.data.len()));
+        if n == 0 && !buf.is_empty() {
+            tx.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        tx.data.extend(&buf[..n]);
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+}
@@ -0,0 +1,321 @@
+//! HTTP client and server talking over an in-process stream.
+
+mod common;
+
+use common::{stream, Stream};
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::time::{Duration, Timer};
+use embassy_net::http::client::{request, ClientRequest};
+use embassy_net::http::server::{read_request, write_chunked_response, write_response};
+use embassy_net::http::server::{Route, Router};
+use embassy_net::http::*;
+use embassy_std::{Executor, SimClock};
+use futures::future::join;
+use std::future::Future;
+
+/// Run `test` with simulated time, so the pauses that split writes don't slow it down.
+fn run<F: Future + 'static>(test: F) -> F::Output {
+    let clock = Box::leak(Box::new(SimClock::new()));
+    let executor = Box::leak(Box::new(Executor::new_simulated(clock)));
+    executor.block_on(test)
+}
+
+/// Write `data` one byte at a time, pausing in between so each byte is a separate read.
+async fn write_slowly(conn: &mut Stream, data: &[u8]) {
+    for b in data {
+        conn.write_all(&[*b]).await.unwrap();
+        Timer::after(Duration::from_millis(1)).await;
+    }
+}
+
+/// Send `req` and read the whole response, returning its status and body.
+async fn fetch(conn: &mut Stream, req: &ClientRequest<'_>) -> (u16, Vec<u8>) {
+    let mut buf = [0; 256];
+    let (resp, mut body) = request(conn, req, &mut buf).await.unwrap();
+    let mut data = [0; 64];
+    let n = body.read_to_end(&mut data).await.unwrap();
+    (resp.status, data[..n].to_vec())
+}
+
+fn get(path: &str) -> ClientRequest<'_> {
+    ClientRequest {
+        method: Method::Get,
+        host: "device",
+        path,
+        headers: &[],
+        body: &[],
+    }
+}
+
+#[test]
+fn chunked_response_split() {
+    run(async {
+        let (mut client, mut server) = stream();
+        let client_side = async {
+            let resp = fetch(&mut client, &get("/log")).await;
+            assert_eq!(resp, (200, b"hello, world".to_vec()));
+
+            // The body ended exactly at its last chunk.
+            let resp = fetch(&mut client, &get("/")).await;
+            assert_eq!(resp, (204, Vec::new()));
+        };
+        let server_side = async {
+            let mut buf = [0; 256];
+            {
+                let (req, _) = read_request(&mut server, &mut buf).await.unwrap();
+                assert_eq!(req.path, "/log");
+            }
+            write_slowly(
+                &mut server,
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\n",
+            )
+            .await;
+
+            {
+                let (req, _) = read_request(&mut server, &mut buf).await.unwrap();
+                assert_eq!(req.path, "/");
+            }
+            write_slowly(&mut server, b"HTTP/1.1 204 No Content\r\n\r\n").await;
+        };
+        join(client_side, server_side).await;
+    })
+}
+
+#[test]
+fn chunked_request_split() {
+    run(async {
+        let (mut client, mut server) = stream();
+        let client_side = write_slowly(
+            &mut client,
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\n\r\n\
+              GET /next HTTP/1.1\r\n\r\n",
+        );
+        let server_side = async {
+            let mut buf = [0; 256];
+            let mut data = [0; 64];
+            {
+                let (req, mut body) = read_request(&mut server, &mut buf).await.unwrap();
+                assert_eq!(req.method, Method::Post);
+                let n = body.read_to_end(&mut data).await.unwrap();
+                assert_eq!(&data[..n], b"abc0123456789abcdef");
+            }
+
+            let (req, mut body) = read_request(&mut server, &mut buf).await.unwrap();
+            assert_eq!(req.path, "/next");
+            assert_eq!(body.read_to_end(&mut data).await, Ok(0));
+        };
+        join(client_side, server_side).await;
+    })
+}
+
+#[test]
+fn bodyless_responses() {
+    run(async {
+        let (mut client, mut server) = stream();
+        // Headers describing the body they would have, which isn't sent.
+        server
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+                  HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\n\r\n\
+                  HTTP/1.1 204 No Content\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            )
+            .await
+            .unwrap();
+
+        let head = ClientRequest {
+            method: Method::Head,
+            ..get("/")
+        };
+        {
+            let mut buf = [0; 256];
+            let mut data = [0; 16];
+            let (resp, mut body) = request(&mut client, &head, &mut buf).await.unwrap();
+            assert_eq!(resp.status, 200);
+            assert_eq!(resp.headers.get("content-length"), Some("5"));
+            assert_eq!(body.read_to_end(&mut data).await, Ok(0));
+        }
+
+        assert_eq!(fetch(&mut client, &get("/")).await, (304, Vec::new()));
+        assert_eq!(fetch(&mut client, &get("/")).await, (204, Vec::new()));
+        assert_eq!(fetch(&mut client, &get("/")).await, (200, b"ok".to_vec()));
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Status,
+    Led,
+    Log,
+}
+
+static ROUTER: Router<'static, Endpoint> = Router::new(&[
+    Route {
+        method: Method::Get,
+        path: "/status",
+        endpoint: Endpoint::Status,
+    },
+    Route {
+        method: Method::Put,
+        path: "/led",
+        endpoint: Endpoint::Led,
+    },
+    Route {
+        method: Method::Get,
+        path: "/log/*",
+        endpoint: Endpoint::Log,
+    },
+]);
+
+/// Serve requests until the client closes the connection, returning the bodies put to
+/// `/led`.
+async fn serve(mut conn: Stream) -> Vec<Vec<u8>> {
+    let mut buf = [0; 256];
+    let mut led = Vec::new();
+    loop {
+        let (req, mut body) = match read_request(&mut conn, &mut buf).await {
+            Ok(r) => r,
+            Err(Error::Closed) => return led,
+            Err(e) => panic!("read_request failed: {:?}", e),
+        };
+        match ROUTER.route(&req) {
+            Ok(Endpoint::Status) => {
+                body.discard().await.unwrap();
+                write_response(&mut conn, 200, &[], b"ok").await.unwrap();
+            }
+            Ok(Endpoint::Led) => {
+                let mut data = [0; 16];
+                let n = body.read_to_end(&mut data).await.unwrap();
+                led.push(data[..n].to_vec());
+                write_response(&mut conn, 204, &[], b"").await.unwrap();
+            }
+            Ok(Endpoint::Log) => {
+                body.discard().await.unwrap();
+                let headers = [Header {
+                    name: "Content-Type",
+                    value: "text/plain",
+                }];
+                let mut body = write_chunked_response(&mut conn, 200, &headers)
+                    .await
+                    .unwrap();
+                body.write(b"line 1\n").await.unwrap();
+                body.write(b"").await.unwrap();
+                body.write(b"line 2\n").await.unwrap();
+                body.finish().await.unwrap();
+            }
+            Err(status) => {
+                body.discard().await.unwrap();
+                write_response(&mut conn, status, &[], b"").await.unwrap();
+            }
+        }
+        if !req.keep_alive {
+            return led;
+        }
+    }
+}
+
+#[test]
+fn routing() {
+    run(async {
+        let (mut client, server) = stream();
+        let client_side = async move {
+            {
+                let mut buf = [0; 256];
+                let (resp, _) = request(&mut client, &get("/missing"), &mut buf)
+                    .await
+                    .unwrap();
+                assert_eq!((resp.status, resp.reason), (404, "Not Found"));
+            }
+
+            let post = ClientRequest {
+                method: Method::Post,
+                body: b"x",
+                ..get("/status")
+            };
+            {
+                let mut buf = [0; 256];
+                let (resp, _) = request(&mut client, &post, &mut buf).await.unwrap();
+                assert_eq!((resp.status, resp.reason), (405, "Method Not Allowed"));
+            }
+
+            let resp = fetch(&mut client, &get("/status")).await;
+            assert_eq!(resp, (200, b"ok".to_vec()));
+
+            {
+                let mut buf = [0; 256];
+                let mut data = [0; 64];
+                let (resp, mut body) = request(&mut client, &get("/log/today"), &mut buf)
+                    .await
+                    .unwrap();
+                assert_eq!(resp.status, 200);
+                assert_eq!(resp.headers.get("content-type"), Some("text/plain"));
+                let n = body.read_to_end(&mut data).await.unwrap();
+                assert_eq!(&data[..n], b"line 1\nline 2\n");
+            }
+
+            // The server answers 100 Continue before reading the body, which is skipped.
+            let put = ClientRequest {
+                method: Method::Put,
+                headers: &[Header {
+                    name: "Expect",
+                    value: "100-continue",
+                }],
+                body: b"on",
+                ..get("/led")
+            };
+            assert_eq!(fetch(&mut client, &put).await, (204, Vec::new()));
+
+            let put = ClientRequest {
+                method: Method::Put,
+                headers: &[Header {
+                    name: "Connection",
+                    value: "close",
+                }],
+                body: b"off",
+                ..get("/led")
+            };
+            assert_eq!(fetch(&mut client, &put).await, (204, Vec::new()));
+        };
+        let (_, led) = join(client_side, serve(server)).await;
+        assert_eq!(led, vec![b"on".to_vec(), b"off".to_vec()]);
+    })
+}
+
+#[test]
+fn closed() {
+    run(async {
+        let (client, mut server) = stream();
+        drop(client);
+        let mut buf = [0; 256];
+        assert_eq!(
+            read_request(&mut server, &mut buf).await.err(),
+            Some(Error::Closed)
+        );
+
+        let (mut client, mut server) = stream();
+        client.write_all(b"GET / HTTP/1.1\r\nHost:").await.unwrap();
+        client.close();
+        assert!(matches!(
+            read_request(&mut server, &mut buf).await,
+            Err(Error::Io(_))
+        ));
+    })
+}
+
+#[test]
+fn head_too_large() {
+    run(async {
+        let (mut client, mut server) = stream();
+        client
+            .write_all(b"GET /a-rather-long-path HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(
+            read_request(&mut server, &mut buf).await.err(),
+            Some(Error::HeadTooLarge)
+        );
+    })
+}
@@ -0,0 +1,375 @@
+//! MQTT client against a stand-in broker, over an in-process stream.
+
+mod common;
+
+use common::{stream, Stream};
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::time::{with_timeout, Duration, Instant, Timer};
+use embassy_net::mqtt::*;
+use embassy_std::{Executor, SimClock};
+use futures::future::join;
+use std::future::Future;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+/// Run `test` with simulated time, so keep-alive intervals don't slow it down.
+fn run<F: Future + 'static>(test: F) -> F::Output {
+    let clock = Box::leak(Box::new(SimClock::new()));
+    let executor = Box::leak(Box::new(Executor::new_simulated(clock)));
+    executor.block_on(test)
+}
+
+/// The broker's end of the connection, which reads and writes raw packets.
+struct Broker(Stream);
+
+impl Broker {
+    async fn read(&mut self) -> (u8, Vec<u8>) {
+        let header = self.0.read_byte().await.unwrap();
+        let mut len = 0;
+        for i in 0..4 {
+            let b = self.0.read_byte().await.unwrap();
+            len |= ((b & 0x7F) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        self.0.read_exact(&mut body).await.unwrap();
+        (header, body)
+    }
+
+    async fn expect(&mut self, header: u8, body: &[u8]) {
+        assert_eq!(self.read().await, (header, body.to_vec()));
+    }
+
+    async fn write(&mut self, header: u8, body: &[u8]) {
+        let mut packet = vec![header];
+        let mut len = body.len();
+        loop {
+            let b = (len % 128) as u8;
+            len /= 128;
+            if len == 0 {
+                packet.push(b);
+                break;
+            }
+            packet.push(b | 0x80);
+        }
+        packet.extend_from_slice(body);
+        self.0.write_all(&packet).await.unwrap();
+    }
+}
+
+fn config(version: ProtocolVersion, keep_alive: u64) -> MqttConfig<'static> {
+    MqttConfig {
+        version,
+        client_id: "dev",
+        keep_alive: Duration::from_secs(keep_alive),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn connect() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+
+        // Sent ahead, the stream buffers it.
+        broker.write(CONNACK, &[0x01, 0x00]).await;
+        let config = MqttConfig {
+            username: Some("user"),
+            password: Some(b"pw"),
+            ..config(ProtocolVersion::V3_1_1, 30)
+        };
+        assert_eq!(client.connect(&config).await, Ok(true));
+        broker
+            .expect(
+                CONNECT,
+                &[
+                    0, 4, b'M', b'Q', b'T', b'T', // protocol name
+                    4,    // version
+                    0xC2, // username, password, clean session
+                    0, 30, // keep alive
+                    0, 3, b'd', b'e', b'v', // client id
+                    0, 4, b'u', b's', b'e', b'r', // username
+                    0, 2, b'p', b'w', // password
+                ],
+            )
+            .await;
+
+        broker.write(CONNACK, &[0x00, 0x05]).await;
+        assert_eq!(
+            client.connect(&config).await,
+            Err(Error::ConnectionRefused(5))
+        );
+    })
+}
+
+#[test]
+fn publish_subscribe() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        assert_eq!(
+            client.connect(&config(ProtocolVersion::V3_1_1, 0)).await,
+            Ok(false)
+        );
+        broker.read().await;
+
+        let id = client.subscribe(&[("cmd/#", QoS::AtLeastOnce)]).await;
+        assert_eq!(id, Ok(1));
+        broker
+            .expect(SUBSCRIBE, &[0, 1, 0, 5, b'c', b'm', b'd', b'/', b'#', 1])
+            .await;
+        broker.write(SUBACK, &[0, 1, 1]).await;
+        match client.next_event().await {
+            Ok(Event::Subscribed { packet_id, granted }) => {
+                assert_eq!(packet_id, 1);
+                assert_eq!(granted, &[1]);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // QoS 1, retained, with packet id 7.
+        let publish = [0, 5, b'c', b'm', b'd', b'/', b'x', 0, 7, b'o', b'n'];
+        broker.write(PUBLISH | 0x03, &publish).await;
+        match client.next_event().await {
+            Ok(Event::Message(msg)) => {
+                assert_eq!(msg.topic, "cmd/x");
+                assert_eq!(msg.payload, b"on");
+                assert_eq!(msg.qos, QoS::AtLeastOnce);
+                assert!(msg.retain);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // The message is acknowledged before the next packet is sent.
+        let id = client
+            .publish("status", b"ok", QoS::AtLeastOnce, false)
+            .await;
+        assert_eq!(id, Ok(Some(2)));
+        broker.expect(PUBACK, &[0, 7]).await;
+        broker
+            .expect(
+                PUBLISH | 0x02,
+                &[0, 6, b's', b't', b'a', b't', b'u', b's', 0, 2, b'o', b'k'],
+            )
+            .await;
+        broker.write(PUBACK, &[0, 2]).await;
+        assert!(matches!(client.next_event().await, Ok(Event::Published(2))));
+
+        let id = client.publish("status", b"ok", QoS::AtMostOnce, true).await;
+        assert_eq!(id, Ok(None));
+        broker
+            .expect(
+                PUBLISH | 0x01,
+                &[0, 6, b's', b't', b'a', b't', b'u', b's', b'o', b'k'],
+            )
+            .await;
+
+        assert_eq!(client.disconnect().await, Ok(()));
+        broker.expect(DISCONNECT, &[]).await;
+    })
+}
+
+#[test]
+fn mqtt5_properties() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+
+        // Receive Maximum property.
+        broker.write(CONNACK, &[0x00, 0x00, 3, 0x21, 0, 10]).await;
+        assert_eq!(
+            client.connect(&config(ProtocolVersion::V5, 0)).await,
+            Ok(false)
+        );
+        broker
+            .expect(
+                CONNECT,
+                &[
+                    0, 4, b'M', b'Q', b'T', b'T', 5, 0x02, 0, 0, // no keep alive
+                    0, // properties
+                    0, 3, b'd', b'e', b'v',
+                ],
+            )
+            .await;
+
+        assert_eq!(client.subscribe(&[("t", QoS::AtMostOnce)]).await, Ok(1));
+        broker.expect(SUBSCRIBE, &[0, 1, 0, 0, 1, b't', 0]).await;
+        // Reason String property, and a refused subscription.
+        broker.write(SUBACK, &[0, 1, 3, 0x1F, 0, 0, 0x80]).await;
+        match client.next_event().await {
+            Ok(Event::Subscribed { packet_id, granted }) => {
+                assert_eq!(packet_id, 1);
+                assert_eq!(granted, &[0x80]);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // Payload Format Indicator property.
+        broker
+            .write(PUBLISH, &[0, 1, b't', 2, 0x01, 0x01, b'h', b'i'])
+            .await;
+        match client.next_event().await {
+            Ok(Event::Message(msg)) => {
+                assert_eq!(msg.topic, "t");
+                assert_eq!(msg.payload, b"hi");
+                assert_eq!(msg.qos, QoS::AtMostOnce);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        assert_eq!(
+            client.publish("t", b"hi", QoS::AtMostOnce, false).await,
+            Ok(None)
+        );
+        broker.expect(PUBLISH, &[0, 1, b't', 0, b'h', b'i']).await;
+    })
+}
+
+#[test]
+fn keep_alive() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        let res = client.connect(&config(ProtocolVersion::V3_1_1, 10)).await;
+        assert_eq!(res, Ok(false));
+        broker.read().await;
+
+        // Pings at 10, 20 and 30 s are answered.
+        let client_side = async {
+            let res = with_timeout(Duration::from_secs(35), client.next_event()).await;
+            assert!(res.is_err());
+        };
+        let broker_side = async {
+            for _ in 0..3 {
+                broker.expect(PINGREQ, &[]).await;
+                broker.write(PINGRESP, &[]).await;
+            }
+        };
+        join(client_side, broker_side).await;
+
+        // The ping at 40 s isn't.
+        assert!(matches!(client.next_event().await, Err(Error::Timeout)));
+        assert_eq!(Instant::now().as_secs(), 50);
+        broker.expect(PINGREQ, &[]).await;
+    })
+}
+
+#[test]
+fn keep_alive_cancelled() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        let res = client.connect(&config(ProtocolVersion::V3_1_1, 10)).await;
+        assert_eq!(res, Ok(false));
+        broker.read().await;
+
+        // The ping at 10 s can't be sent, and the call is cancelled while sending it.
+        broker.0.set_rx_capacity(0);
+        let res = with_timeout(Duration::from_secs(15), client.next_event()).await;
+        assert!(res.is_err());
+        broker.0.set_rx_capacity(4096);
+
+        // The next call finishes sending it, and it's still awaiting an answer.
+        assert!(matches!(client.next_event().await, Err(Error::Timeout)));
+        assert_eq!(Instant::now().as_secs(), 20);
+        broker.expect(PINGREQ, &[]).await;
+        assert!(broker.0.rx_is_empty());
+    })
+}
+
+#[test]
+fn keep_alive_postponed() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 256];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        let res = client.connect(&config(ProtocolVersion::V3_1_1, 10)).await;
+        assert_eq!(res, Ok(false));
+        broker.read().await;
+
+        // Events aren't waited for a few intervals, then a message is sent at 35 s. The
+        // missed pings aren't made up for, the next one is due 10 s after the message.
+        Timer::after(Duration::from_secs(35)).await;
+        let res = client.publish("t", b"hi", QoS::AtMostOnce, false).await;
+        assert_eq!(res, Ok(None));
+        broker.read().await;
+
+        let client_side = async {
+            let res = with_timeout(Duration::from_secs(25), client.next_event()).await;
+            assert!(res.is_err());
+        };
+        let broker_side = async {
+            for &secs in &[45, 55] {
+                broker.expect(PINGREQ, &[]).await;
+                assert_eq!(Instant::now().as_secs(), secs);
+                broker.write(PINGRESP, &[]).await;
+            }
+        };
+        join(client_side, broker_side).await;
+    })
+}
+
+#[test]
+fn packet_too_large() {
+    run(async {
+        let (client, broker) = stream();
+        let mut broker = Broker(broker);
+        let mut rx = [0; 16];
+        let mut tx = [0; 256];
+        let mut client = MqttClient::new(client, &mut rx, &mut tx);
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        let res = client.connect(&config(ProtocolVersion::V3_1_1, 0)).await;
+        assert_eq!(res, Ok(false));
+
+        let mut publish = vec![0, 1, b't'];
+        publish.extend_from_slice(&[0xAA; 40]);
+        broker.write(PUBLISH, &publish).await;
+        broker.write(PUBLISH, &[0, 1, b't', b'h', b'i']).await;
+
+        assert!(matches!(
+            client.next_event().await,
+            Err(Error::PacketTooLarge)
+        ));
+        // The packet was skipped, the next one is received.
+        match client.next_event().await {
+            Ok(Event::Message(msg)) => assert_eq!(msg.payload, b"hi"),
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // Not a packet the broker may send.
+        broker.write(CONNACK, &[0x00, 0x00]).await;
+        assert!(matches!(client.next_event().await, Err(Error::Protocol)));
+    })
+}
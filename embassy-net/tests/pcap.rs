@@ -0,0 +1,131 @@
+//! Packet capture of the traffic of a pipe device.
+
+mod common;
+
+use common::stream;
+use embassy::io::AsyncBufReadExt;
+use embassy::time::{Duration, Timer};
+use embassy_net::pcap::{PcapBuffer, PcapDevice, PcapSink};
+use embassy_net::pipe::{pipe, PipeConfig};
+use embassy_net::*;
+use embassy_std::{Executor, SimClock};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use std::future::Future;
+
+/// Run `test` with simulated time, so capture timestamps are predictable.
+fn run<F: Future + 'static>(test: F) -> F::Output {
+    let clock = Box::leak(Box::new(SimClock::new()));
+    let executor = Box::leak(Box::new(Executor::new_simulated(clock)));
+    executor.block_on(test)
+}
+
+/// Sink keeping the whole capture in memory.
+struct Capture(Vec<u8>);
+
+impl PcapSink for Capture {
+    fn write(&mut self, chunks: &[&[u8]]) {
+        for chunk in chunks {
+            self.0.extend_from_slice(chunk);
+        }
+    }
+}
+
+fn send(device: &mut impl Device, packet: &[u8]) {
+    assert!(device.is_transmit_ready());
+    device
+        .transmit(packet.len(), &mut |buf| {
+            buf.copy_from_slice(packet);
+            Ok(())
+        })
+        .unwrap();
+}
+
+fn recv(device: &mut impl Device) -> Vec<u8> {
+    assert!(device.is_receive_ready());
+    let mut packet = Vec::new();
+    device.receive(&mut |p| packet.extend_from_slice(p));
+    packet
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[test]
+fn capture() {
+    run(async {
+        let (device, mut other) = pipe(PipeConfig::default());
+        let mut device = PcapDevice::new(device, Capture(Vec::new()));
+
+        Timer::after(Duration::from_millis(1500)).await;
+        send(&mut device, &[0xaa; 60]);
+        assert_eq!(recv(&mut other), [0xaa; 60]);
+
+        Timer::after(Duration::from_millis(250)).await;
+        send(&mut other, &[0xbb; 42]);
+        assert_eq!(recv(&mut device), [0xbb; 42]);
+
+        let (_, Capture(data)) = device.into_inner();
+        assert_eq!(data.len(), 24 + (16 + 60) + (16 + 42));
+
+        // Global header: magic, version 2.4, thiszone, sigfigs, snaplen, Ethernet.
+        assert_eq!(&data[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&data[4..8], &[2, 0, 4, 0]);
+        assert_eq!(&data[8..16], &[0; 8]);
+        assert_eq!(u32_at(&data, 16), 65535);
+        assert_eq!(u32_at(&data, 20), 1);
+
+        // Records: seconds, microseconds, captured and original length, then the packet.
+        let tx = &data[24..24 + 16 + 60];
+        assert_eq!(u32_at(tx, 0), 1);
+        assert_eq!(u32_at(tx, 4), 500_000);
+        assert_eq!((u32_at(tx, 8), u32_at(tx, 12)), (60, 60));
+        assert_eq!(&tx[16..], &[0xaa; 60][..]);
+
+        let rx = &data[24 + 16 + 60..];
+        assert_eq!(u32_at(rx, 0), 1);
+        assert_eq!(u32_at(rx, 4), 750_000);
+        assert_eq!((u32_at(rx, 8), u32_at(rx, 12)), (42, 42));
+        assert_eq!(&rx[16..], &[0xbb; 42][..]);
+    })
+}
+
+#[test]
+fn buffer_full() {
+    run(async {
+        let buffer = Box::leak(Box::new(PcapBuffer::<128>::new()));
+        let (device, mut other) = pipe(PipeConfig::default());
+        let mut device = PcapDevice::new(device, &*buffer);
+
+        // The header and one record fill 100 bytes, the second record doesn't fit and is
+        // dropped whole. The packet itself still goes through.
+        send(&mut device, &[0xaa; 60]);
+        send(&mut device, &[0xbb; 60]);
+        assert_eq!(recv(&mut other), [0xaa; 60]);
+        assert_eq!(recv(&mut other), [0xbb; 60]);
+        assert_eq!(buffer.dropped(), 1);
+
+        let (mut w, mut r) = stream();
+        let copy = buffer.run(&mut w);
+        let read = async {
+            let mut data = [0; 100];
+            r.read_exact(&mut data).await.unwrap();
+            assert_eq!(u32_at(&data, 24 + 8), 60);
+            assert_eq!(&data[24 + 16..], &[0xaa; 60][..]);
+
+            // Once read, there's room again.
+            send(&mut device, &[0xcc; 60]);
+            let mut data = [0; 76];
+            r.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data[16..], &[0xcc; 60][..]);
+            assert!(r.rx_is_empty());
+        };
+        pin_mut!(copy, read);
+        match select(copy, read).await {
+            Either::Left((e, _)) => panic!("writing the capture failed: {:?}", e),
+            Either::Right(_) => {}
+        }
+        assert_eq!(buffer.dropped(), 1);
+    })
+}
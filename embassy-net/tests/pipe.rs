@@ -0,0 +1,322 @@
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::time::{with_timeout, Duration, Timer};
+use embassy_net::pipe::{pipe, PipeConfig, PipeDevice};
+use embassy_net::*;
+use embassy_std::{Executor, SimClock};
+use futures::future::{join, select, Either};
+use futures::pin_mut;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Deterministic random numbers, so that lossy links drop the same packets on every run.
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(0x2545_f491_4f6c_dd1d);
+    }
+    STATE.with(|state| {
+        for b in buf {
+            let mut x = state.get();
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            state.set(x);
+            *b = x as u8;
+        }
+    })
+}
+
+fn new_stack(device: PipeDevice, host: u8) -> &'static Stack {
+    let config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, host), 24));
+    let stack = Stack::new(
+        Box::leak(Box::new(device)),
+        Box::leak(Box::new(StaticConfigurator::new(config))),
+        Box::leak(Box::new(StackResources::<4, 4>::new())),
+    )
+    .unwrap();
+    Box::leak(Box::new(stack))
+}
+
+/// Run `test` on two stacks connected through a pipe, 192.168.69.1 and 192.168.69.2, once
+/// they're configured. Time is simulated, so latency and retransmissions don't slow the
+/// test down.
+fn run<F, Fut>(config: PipeConfig, test: F) -> Fut::Output
+where
+    F: FnOnce(&'static Stack, &'static Stack) -> Fut + 'static,
+    Fut: Future,
+{
+    let clock = Box::leak(Box::new(SimClock::new()));
+    let executor = Box::leak(Box::new(Executor::new_simulated(clock)));
+    executor.block_on(async move {
+        // Stacks can only be used from the executor's thread, so create them here.
+        let (a, b) = pipe(config);
+        let (a, b) = (new_stack(a, 1), new_stack(b, 2));
+
+        let stacks = join(a.run(), b.run());
+        let test = async {
+            for stack in [a, b].iter() {
+                while !stack.is_config_up() {
+                    stack.wait_config_change().await;
+                }
+            }
+            with_timeout(Duration::from_secs(10 * 60), test(a, b))
+                .await
+                .unwrap_or_else(|_| panic!("test timed out"))
+        };
+        pin_mut!(stacks, test);
+        match select(test, stacks).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => unreachable!(),
+        }
+    })
+}
+
+/// Run `test` on a stack at 192.168.69.2, once it's configured, and the other end of its
+/// link, which the test drives by hand.
+fn run_raw<F, Fut>(test: F) -> Fut::Output
+where
+    F: FnOnce(&'static Stack, PipeDevice) -> Fut + 'static,
+    Fut: Future,
+{
+    let clock = Box::leak(Box::new(SimClock::new()));
+    let executor = Box::leak(Box::new(Executor::new_simulated(clock)));
+    executor.block_on(async move {
+        let (raw, device) = pipe(PipeConfig::default());
+        let stack = new_stack(device, 2);
+
+        let test = async {
+            while !stack.is_config_up() {
+                stack.wait_config_change().await;
+            }
+            with_timeout(Duration::from_secs(10), test(stack, raw))
+                .await
+                .unwrap_or_else(|_| panic!("test timed out"))
+        };
+        let run = stack.run();
+        pin_mut!(run, test);
+        match select(test, run).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => unreachable!(),
+        }
+    })
+}
+
+/// Wait for the next packet `device` receives.
+async fn recv_raw(device: &mut PipeDevice) -> Vec<u8> {
+    while !device.is_receive_ready() {
+        Timer::after(Duration::from_millis(1)).await;
+    }
+    let mut packet = Vec::new();
+    device.receive(&mut |p| packet.extend_from_slice(p));
+    packet
+}
+
+fn send_raw(device: &mut PipeDevice, packet: &[u8]) {
+    assert!(device.is_transmit_ready());
+    device
+        .transmit(packet.len(), &mut |buf| {
+            buf.copy_from_slice(packet);
+            Ok(())
+        })
+        .unwrap();
+}
+
+/// Internet checksum of `data`.
+fn checksum(data: &[u8]) -> [u8; 2] {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u32::from(c[0]) << 8 | u32::from(*c.get(1).unwrap_or(&0)))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    (!(sum as u16)).to_be_bytes()
+}
+
+const MAC_1: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const MAC_2: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+const IP_1: [u8; 4] = [192, 168, 69, 1];
+const IP_2: [u8; 4] = [192, 168, 69, 2];
+
+/// The stack replies to ARP and ICMP echo requests from within the processing of the
+/// received packet, while the device still holds it.
+#[test]
+fn arp_and_ping() {
+    run_raw(|_stack, mut raw| async move {
+        let mut arp = Vec::new();
+        arp.extend_from_slice(&[0xff; 6]);
+        arp.extend_from_slice(&MAC_1);
+        arp.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0, 6, 4, 0, 1]);
+        arp.extend_from_slice(&MAC_1);
+        arp.extend_from_slice(&IP_1);
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&IP_2);
+        send_raw(&mut raw, &arp);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&MAC_1);
+        expected.extend_from_slice(&MAC_2);
+        expected.extend_from_slice(&[0x08, 0x06, 0, 1, 0x08, 0, 6, 4, 0, 2]);
+        expected.extend_from_slice(&MAC_2);
+        expected.extend_from_slice(&IP_2);
+        expected.extend_from_slice(&MAC_1);
+        expected.extend_from_slice(&IP_1);
+        assert_eq!(recv_raw(&mut raw).await, expected);
+
+        let mut icmp = vec![8, 0, 0, 0, 0x12, 0x34, 0, 1];
+        icmp.extend_from_slice(b"ping");
+        let sum = checksum(&icmp);
+        icmp[2..4].copy_from_slice(&sum);
+        let mut ip = vec![0x45, 0, 0, 20 + icmp.len() as u8, 0, 0, 0, 0, 64, 1, 0, 0];
+        ip.extend_from_slice(&IP_1);
+        ip.extend_from_slice(&IP_2);
+        let sum = checksum(&ip);
+        ip[10..12].copy_from_slice(&sum);
+        let mut ping = Vec::new();
+        ping.extend_from_slice(&MAC_2);
+        ping.extend_from_slice(&MAC_1);
+        ping.extend_from_slice(&[0x08, 0]);
+        ping.extend_from_slice(&ip);
+        ping.extend_from_slice(&icmp);
+        send_raw(&mut raw, &ping);
+
+        let pong = recv_raw(&mut raw).await;
+        assert_eq!(&pong[..6], &MAC_1);
+        assert_eq!(&pong[12..14], &[0x08, 0]);
+        let (ip, icmp) = pong[14..].split_at(20);
+        assert_eq!(checksum(ip), [0, 0]);
+        assert_eq!(ip[9], 1);
+        assert_eq!((&ip[12..16], &ip[16..20]), (&IP_2[..], &IP_1[..]));
+        assert_eq!(checksum(icmp), [0, 0]);
+        assert_eq!(icmp[0], 0);
+        assert_eq!(&icmp[4..], b"\x12\x34\x00\x01ping");
+    })
+}
+
+/// Send `data` from `a` to `b` over TCP, and return what `b` received.
+async fn tcp_transfer(a: &'static Stack, b: &'static Stack, data: Vec<u8>) -> Vec<u8> {
+    let server = async move {
+        let mut rx_buffer = [0; 2048];
+        let mut tx_buffer = [0; 2048];
+        let mut socket = TcpSocket::new(b, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(1234).await.unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            let buf = socket.read_buf().await.unwrap();
+            if buf.is_empty() {
+                break;
+            }
+            let n = buf.len();
+            received.extend_from_slice(buf);
+            Pin::new(&mut socket).consume(n);
+        }
+        socket.close();
+        received
+    };
+
+    let client = async move {
+        let mut rx_buffer = [0; 2048];
+        let mut tx_buffer = [0; 2048];
+        let mut socket = TcpSocket::new(a, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket
+            .connect((Ipv4Address::new(192, 168, 69, 2), 1234))
+            .await
+            .unwrap();
+        socket.write_all(&data).await.unwrap();
+        socket.close();
+        // Keep the socket until the server has closed too, so the data gets through.
+        while let Ok(buf) = socket.read_buf().await {
+            if buf.is_empty() {
+                break;
+            }
+            let n = buf.len();
+            Pin::new(&mut socket).consume(n);
+        }
+    };
+
+    join(server, client).await.0
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn tcp() {
+    let config = PipeConfig {
+        latency: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let data = pattern(10_000);
+    let sent = data.clone();
+    let received = run(config, move |a, b| tcp_transfer(a, b, sent));
+    assert_eq!(received, data);
+}
+
+#[test]
+fn tcp_lossy_link() {
+    let config = PipeConfig {
+        latency: Duration::from_millis(5),
+        loss: 0.1,
+        reorder: 0.1,
+        ..Default::default()
+    };
+    let data = pattern(50_000);
+    let sent = data.clone();
+    let received = run(config, move |a, b| tcp_transfer(a, b, sent));
+    assert_eq!(received, data);
+}
+
+#[test]
+fn udp() {
+    let config = PipeConfig {
+        latency: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let echoed = run(config, |a, b| async move {
+        let mut rx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0; 256];
+        let mut tx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut tx_buffer = [0; 256];
+        let mut server = UdpSocket::new(
+            b,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        )
+        .unwrap();
+        server.bind(7).unwrap();
+
+        let mut rx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0; 256];
+        let mut tx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut tx_buffer = [0; 256];
+        let mut client = UdpSocket::new(
+            a,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        )
+        .unwrap();
+        client.bind(1000).unwrap();
+
+        let server_endpoint = IpEndpoint::new(Ipv4Address::new(192, 168, 69, 2).into(), 7);
+        client.send_to(&[0; 17], server_endpoint).await.unwrap();
+        client.send_to(b"ping", server_endpoint).await.unwrap();
+        let mut buf = [0; 16];
+        // Too large for the buffer, it's dropped.
+        assert_eq!(server.recv_from(&mut buf).await, Err(Error::Truncated));
+        let (len, from) = server.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        server.send_to(&buf[..len], from).await.unwrap();
+
+        let (len, from) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(from, server_endpoint);
+        buf[..len].to_vec()
+    });
+    assert_eq!(echoed, b"ping");
+}
@@ -2,7 +2,7 @@ use core::cell::Cell;
 use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
 use critical_section::CriticalSection;
 use embassy::interrupt::InterruptExt;
-use embassy::time::Clock;
+use embassy::time::{Clock, TICKS_PER_SECOND};
 use embassy::util::{CriticalSectionMutex as Mutex, Unborrow};
 
 use crate::interrupt::Interrupt;
@@ -71,6 +71,10 @@ impl AlarmState {
 
 const ALARM_COUNT: usize = 3;
 
+// The RTC counts the 32.768kHz LFCLK without prescaler, so RTC ticks are embassy ticks.
+// This fails to compile unless the `tick-hz-32768` tick rate is selected.
+const _: [(); 1] = [(); (TICKS_PER_SECOND == 32768) as usize];
+
 pub struct RTC<T: Instance> {
     rtc: T,
     irq: T::Interrupt,
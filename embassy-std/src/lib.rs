@@ -1,35 +1,75 @@
 use embassy::executor::{raw, Spawner};
 use embassy::time::TICKS_PER_SECOND;
 use embassy::time::{Alarm, Clock};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
 use std::marker::PhantomData;
-use std::mem::MaybeUninit;
+use std::pin::Pin;
 use std::ptr;
-use std::sync::{Condvar, Mutex};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, Once};
 use std::time::{Duration as StdDuration, Instant as StdInstant};
 
-static mut CLOCK_ZERO: MaybeUninit<StdInstant> = MaybeUninit::uninit();
+mod sim;
+#[cfg(feature = "trace")]
+pub mod trace;
+
+pub use sim::SimClock;
+
+lazy_static::lazy_static! {
+    static ref CLOCK_ZERO: StdInstant = StdInstant::now();
+}
+static CLOCK_INIT: Once = Once::new();
+
+thread_local! {
+    /// Clock of the simulated executor running on this thread, if any.
+    static SIM_CLOCK: Cell<Option<&'static SimClock>> = Cell::new(None);
+}
+
+/// Time since startup, in ticks.
+fn real_now() -> u64 {
+    // Get the zero first: if this is the first call, it's initialized now.
+    let zero = *CLOCK_ZERO;
+    let dur = StdInstant::now().duration_since(zero);
+    dur.as_secs() * (TICKS_PER_SECOND as u64)
+        + (dur.subsec_nanos() as u64) * (TICKS_PER_SECOND as u64) / 1_000_000_000
+}
+
+/// The global embassy clock. Each thread running a simulated executor sees the time of
+/// its [SimClock], other threads see the real time.
 struct StdClock;
 impl Clock for StdClock {
     fn now(&self) -> u64 {
-        let zero = unsafe { CLOCK_ZERO.as_ptr().read() };
-        let dur = StdInstant::now().duration_since(zero);
-        dur.as_secs() * (TICKS_PER_SECOND as u64)
-            + (dur.subsec_nanos() as u64) * (TICKS_PER_SECOND as u64) / 1_000_000_000
+        match SIM_CLOCK.with(|c| c.get()) {
+            Some(clock) => clock.now(),
+            None => real_now(),
+        }
     }
 }
 
-static mut ALARM_AT: u64 = u64::MAX;
+/// Alarm of a std [Executor]. Each executor has its own.
+pub struct StdAlarm {
+    alarm_at: AtomicU64,
+}
+
+impl StdAlarm {
+    fn new() -> Self {
+        Self {
+            alarm_at: AtomicU64::new(u64::MAX),
+        }
+    }
+}
 
-pub struct StdAlarm;
 impl Alarm for StdAlarm {
     fn set_callback(&self, _callback: fn(*mut ()), _ctx: *mut ()) {}
 
     fn set(&self, timestamp: u64) {
-        unsafe { ALARM_AT = timestamp }
+        self.alarm_at.store(timestamp, Ordering::Relaxed)
     }
 
     fn clear(&self) {
-        unsafe { ALARM_AT = u64::MAX }
+        self.alarm_at.store(u64::MAX, Ordering::Relaxed)
     }
 }
 
@@ -46,14 +86,14 @@ impl Signaler {
         }
     }
 
-    fn wait(&self) {
+    fn wait(&self, alarm: &StdAlarm) {
         let mut signaled = self.mutex.lock().unwrap();
         while !*signaled {
-            let alarm_at = unsafe { ALARM_AT };
+            let alarm_at = alarm.alarm_at.load(Ordering::Relaxed);
             if alarm_at == u64::MAX {
                 signaled = self.condvar.wait(signaled).unwrap();
             } else {
-                let now = StdClock.now();
+                let now = real_now();
                 if now >= alarm_at {
                     break;
                 }
@@ -74,6 +114,30 @@ impl Signaler {
         *signaled = false;
     }
 
+    /// Like `wait`, but instead of sleeping until the alarm, jump the simulated clock to it.
+    fn wait_simulated(&self, clock: &SimClock) {
+        loop {
+            {
+                let mut signaled = self.mutex.lock().unwrap();
+                if *signaled {
+                    *signaled = false;
+                    return;
+                }
+            }
+
+            // All tasks are idle.
+            if !clock.advance_to_next_alarm() {
+                // No alarm either, so only another thread can wake us.
+                let mut signaled = self.mutex.lock().unwrap();
+                while !*signaled {
+                    signaled = self.condvar.wait(signaled).unwrap();
+                }
+                *signaled = false;
+                return;
+            }
+        }
+    }
+
     fn signal(ctx: *mut ()) {
         let this = unsafe { &*(ctx as *mut Self) };
         let mut signaled = this.mutex.lock().unwrap();
@@ -82,23 +146,95 @@ impl Signaler {
     }
 }
 
+/// Future run by [Executor::block_on], boxed so that one task slot can run any future.
+type RootFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Executor running on a std thread.
+///
+/// Each executor has its own alarm, so any number of them can run concurrently, each on
+/// its own thread. To spawn tasks into an executor from another thread, get a
+/// [SendSpawner](embassy::executor::SendSpawner) from its [Spawner::make_send].
+///
+/// The thread running an executor is its thread mode: [ThreadModeMutex]es can be borrowed
+/// from its tasks, as long as the same mutex isn't used from another executor thread.
+///
+/// [ThreadModeMutex]: embassy::util::ThreadModeMutex
 pub struct Executor {
     inner: raw::Executor,
     not_send: PhantomData<*mut ()>,
     signaler: Signaler,
+    alarm: StdAlarm,
+    sim_clock: Option<&'static SimClock>,
+    root: raw::Task<RootFuture>,
 }
 
 impl Executor {
     pub fn new() -> Self {
-        unsafe {
-            CLOCK_ZERO.as_mut_ptr().write(StdInstant::now());
-            embassy::time::set_clock(&StdClock);
+        init_clock();
+
+        Self {
+            inner: raw::Executor::new(Signaler::signal, ptr::null_mut()),
+            not_send: PhantomData,
+            signaler: Signaler::new(),
+            alarm: StdAlarm::new(),
+            sim_clock: None,
+            root: raw::Task::new(),
         }
+    }
+
+    /// Create an executor that uses simulated time instead of the wall clock.
+    ///
+    /// Whenever all tasks are idle, time jumps straight to the next alarm, so timers
+    /// complete without actually waiting. See [SimClock].
+    ///
+    /// The simulated time is only seen on the thread running this executor: executors on
+    /// other threads keep using the real time. Each simulated executor needs its own
+    /// [SimClock], since the clock is also its alarm.
+    pub fn new_simulated(clock: &'static SimClock) -> Self {
+        init_clock();
 
         Self {
             inner: raw::Executor::new(Signaler::signal, ptr::null_mut()),
             not_send: PhantomData,
             signaler: Signaler::new(),
+            alarm: StdAlarm::new(),
+            sim_clock: Some(clock),
+            root: raw::Task::new(),
+        }
+    }
+
+    fn start(&'static mut self) -> &'static Self {
+        self.inner.set_signal_ctx(&self.signaler as *const _ as _);
+        match self.sim_clock {
+            Some(clock) => self.inner.set_alarm(clock),
+            None => self.inner.set_alarm(&self.alarm),
+        }
+        self
+    }
+
+    /// Makes the current thread the executor's: its simulated time, if any, and thread mode.
+    ///
+    /// The simulated time is seen until the returned guard is dropped.
+    fn enter(&self) -> SimClockGuard {
+        embassy::util::enter_thread_mode();
+        if let Some(clock) = self.sim_clock {
+            SIM_CLOCK.with(|c| {
+                assert!(
+                    c.get().is_none(),
+                    "a simulated executor is already running on this thread"
+                );
+                c.set(Some(clock));
+            });
+        }
+        SimClockGuard {
+            active: self.sim_clock.is_some(),
+        }
+    }
+
+    fn wait(&self) {
+        match self.sim_clock {
+            Some(clock) => self.signaler.wait_simulated(clock),
+            None => self.signaler.wait(&self.alarm),
         }
     }
 
@@ -106,14 +242,82 @@ impl Executor {
     ///
     /// This function never returns.
     pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
-        self.inner.set_signal_ctx(&self.signaler as *const _ as _);
-        self.inner.set_alarm(&StdAlarm);
+        let this = self.start();
+        let _guard = this.enter();
 
-        init(unsafe { self.inner.spawner() });
+        init(unsafe { this.inner.spawner() });
+
+        loop {
+            unsafe { this.inner.run_queued() };
+            this.wait();
+        }
+    }
+
+    /// Runs the executor until `fut` completes, and returns its output.
+    ///
+    /// `fut` runs as a task, so it can use timers and anything else that needs an embassy
+    /// executor. Other tasks spawned into this executor stop running when this returns.
+    pub fn block_on<F: Future + 'static>(&'static mut self, fut: F) -> F::Output {
+        self.start().run_until(fut)
+    }
+
+    fn run_until<F: Future + 'static>(&'static self, fut: F) -> F::Output {
+        let _guard = self.enter();
+
+        let output = Rc::new(RefCell::new(None));
+        let task_output = output.clone();
+        let fut: RootFuture = Box::pin(async move {
+            let res = fut.await;
+            *task_output.borrow_mut() = Some(res);
+        });
+        // The root task is only busy if this is called from one of the executor's tasks.
+        if unsafe { self.inner.spawner() }
+            .spawn(self.root.spawn(move || fut))
+            .is_err()
+        {
+            panic!("block_on can't be called from a task of the same executor");
+        }
 
         loop {
             unsafe { self.inner.run_queued() };
-            self.signaler.wait();
+            if let Some(res) = output.borrow_mut().take() {
+                // The root task may have been woken while completing. Run the queue once
+                // more to take it out, so it can run the next future.
+                unsafe { self.inner.run_queued() };
+                return res;
+            }
+            self.wait();
+        }
+    }
+}
+
+/// Stops the thread from seeing the simulated time of an executor when dropped.
+struct SimClockGuard {
+    active: bool,
+}
+
+impl Drop for SimClockGuard {
+    fn drop(&mut self) {
+        if self.active {
+            SIM_CLOCK.with(|c| c.set(None));
         }
     }
 }
+
+fn init_clock() {
+    CLOCK_INIT.call_once(|| unsafe { embassy::time::set_clock(&StdClock) });
+}
+
+thread_local! {
+    /// Executor used by [block_on] on this thread.
+    static THREAD_EXECUTOR: &'static Executor = Box::leak(Box::new(Executor::new())).start();
+}
+
+/// Runs `fut` to completion on the current thread's executor, and returns its output.
+///
+/// This is intended for tests. Each thread has one executor, created the first time this
+/// is called on it and reused afterwards. Tasks spawned from `fut` stay in the executor,
+/// and run again during the next calls on the same thread.
+pub fn block_on<F: Future + 'static>(fut: F) -> F::Output {
+    THREAD_EXECUTOR.with(|executor| *executor).run_until(fut)
+}
@@ -0,0 +1,117 @@
+use embassy::time::{Alarm, Clock, Duration, Instant};
+use std::sync::Mutex;
+
+type Callback = (fn(*mut ()), *mut ());
+
+struct State {
+    now: u64,
+    alarm_at: u64,
+    callback: Option<Callback>,
+}
+
+/// Simulated clock, for deterministic tests.
+///
+/// Time only moves forward when [advance](SimClock::advance) is called, or when an executor
+/// created with [Executor::new_simulated](crate::Executor::new_simulated) has no work to do,
+/// in which case it jumps straight to the next alarm. A `Timer::after` of several seconds
+/// therefore completes instantly.
+///
+/// The simulated time is only seen by the thread running the executor: [Instant::now]
+/// returns the real time on other threads.
+///
+/// This implements both [Clock] and [Alarm].
+pub struct SimClock {
+    state: Mutex<State>,
+}
+
+// The callback context is only passed back to the callback, which may be called from any thread.
+unsafe impl Send for SimClock {}
+unsafe impl Sync for SimClock {}
+
+impl SimClock {
+    /// Creates a new clock, starting at tick 0.
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                now: 0,
+                alarm_at: u64::MAX,
+                callback: None,
+            }),
+        }
+    }
+
+    /// Moves time forward by `duration`, triggering the alarm if it's reached.
+    pub fn advance(&self, duration: Duration) {
+        let state = self.state.lock().unwrap();
+        let now = state.now + duration.as_ticks();
+        self.set_now(state, now);
+    }
+
+    /// Moves time forward to `instant`, triggering the alarm if it's reached.
+    ///
+    /// This is a noop if `instant` is in the past.
+    pub fn advance_to(&self, instant: Instant) {
+        let state = self.state.lock().unwrap();
+        let now = state.now.max(instant.as_ticks());
+        self.set_now(state, now);
+    }
+
+    /// Moves time forward to the alarm and triggers it.
+    ///
+    /// Returns false, without changing time, if no alarm is set.
+    pub(crate) fn advance_to_next_alarm(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        if state.alarm_at == u64::MAX {
+            return false;
+        }
+        let now = state.now.max(state.alarm_at);
+        self.set_now(state, now);
+        true
+    }
+
+    fn set_now(&self, mut state: std::sync::MutexGuard<'_, State>, now: u64) {
+        state.now = now;
+        let callback = if state.alarm_at <= now {
+            state.alarm_at = u64::MAX;
+            state.callback
+        } else {
+            None
+        };
+
+        // Don't hold the lock while calling the callback, it may call back into us.
+        drop(state);
+        if let Some((f, ctx)) = callback {
+            f(ctx)
+        }
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> u64 {
+        self.state.lock().unwrap().now
+    }
+}
+
+impl Alarm for SimClock {
+    fn set_callback(&self, callback: fn(*mut ()), ctx: *mut ()) {
+        self.state.lock().unwrap().callback = Some((callback, ctx));
+    }
+
+    fn set(&self, timestamp: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.alarm_at = timestamp;
+        let now = state.now;
+        // If the timestamp is already in the past, this triggers the alarm right away.
+        self.set_now(state, now);
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().alarm_at = u64::MAX;
+    }
+}
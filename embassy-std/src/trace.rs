@@ -0,0 +1,82 @@
+use embassy::executor::trace::{Event, TaskId, Tracer};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracer that records every executor event into a Vec, along with the time it happened.
+///
+/// Set it with [embassy::executor::trace::set_tracer].
+pub struct RecordingTracer {
+    events: Mutex<Vec<(Instant, Event)>>,
+}
+
+impl RecordingTracer {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes the events recorded so far, leaving the recording empty.
+    pub fn take_events(&self) -> Vec<(Instant, Event)> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl Default for RecordingTracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer for RecordingTracer {
+    fn event(&self, event: Event) {
+        self.events.lock().unwrap().push((Instant::now(), event));
+    }
+}
+
+/// Computes the total time spent polling each task.
+pub fn cpu_time(events: &[(Instant, Event)]) -> HashMap<TaskId, Duration> {
+    let mut res = HashMap::new();
+    let mut poll_begin = HashMap::new();
+    for (at, event) in events {
+        match *event {
+            Event::PollBegin { task } => {
+                poll_begin.insert(task, *at);
+            }
+            Event::PollEnd { task } => {
+                if let Some(begin) = poll_begin.remove(&task) {
+                    *res.entry(task).or_insert(Duration::ZERO) += at.duration_since(begin);
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+/// Computes, for each task, the longest time it spent between being woken and being polled.
+///
+/// A task with a large value here is being starved by other tasks of the same or higher priority.
+pub fn max_wake_latency(events: &[(Instant, Event)]) -> HashMap<TaskId, Duration> {
+    let mut res = HashMap::new();
+    let mut woken = HashMap::new();
+    for (at, event) in events {
+        match *event {
+            Event::TaskWoken { task } => {
+                woken.entry(task).or_insert(*at);
+            }
+            Event::PollBegin { task } => {
+                if let Some(woken_at) = woken.remove(&task) {
+                    let latency = at.duration_since(woken_at);
+                    let max = res.entry(task).or_insert(Duration::ZERO);
+                    if latency > *max {
+                        *max = latency;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::block_on;
+use embassy::util::{Channel, TryRecvError, TrySendError, WithCriticalSections};
+use futures::{join, poll};
+use std::sync::Arc;
+use std::task::Poll;
+
+type TestChannel<T, const N: usize> = Channel<WithCriticalSections, T, N>;
+
+#[test]
+fn send_recv_in_order() {
+    let c = TestChannel::<u32, 3>::new();
+    assert!(c.is_empty());
+    assert_eq!(c.try_recv(), Err(TryRecvError::Empty));
+
+    // Go around the buffer a few times.
+    for i in 0..10 {
+        assert_eq!(c.try_send(i), Ok(()));
+        assert_eq!(c.try_send(i + 100), Ok(()));
+        assert_eq!(c.len(), 2);
+        assert_eq!(c.try_recv(), Ok(i));
+        assert_eq!(c.try_recv(), Ok(i + 100));
+    }
+    assert!(c.is_empty());
+}
+
+#[test]
+fn try_send_full() {
+    let c = TestChannel::<u32, 2>::new();
+    assert_eq!(c.try_send(1), Ok(()));
+    assert_eq!(c.try_send(2), Ok(()));
+    assert!(c.is_full());
+    assert_eq!(c.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(c.try_recv(), Ok(1));
+    assert_eq!(c.try_send(3), Ok(()));
+    assert_eq!(c.try_recv(), Ok(2));
+    assert_eq!(c.try_recv(), Ok(3));
+}
+
+#[test]
+fn send_waits_for_room() {
+    block_on(async {
+        let c = TestChannel::<u32, 1>::new();
+        c.try_send(1).unwrap();
+
+        let send = c.send(2);
+        futures::pin_mut!(send);
+        assert_eq!(poll!(send.as_mut()), Poll::Pending);
+        assert_eq!(c.try_recv(), Ok(1));
+        assert_eq!(poll!(send.as_mut()), Poll::Ready(()));
+        assert_eq!(c.try_recv(), Ok(2));
+    })
+}
+
+#[test]
+fn recv_waits_for_message() {
+    block_on(async {
+        let c = TestChannel::<u32, 1>::new();
+
+        let recv = c.recv();
+        futures::pin_mut!(recv);
+        assert_eq!(poll!(recv.as_mut()), Poll::Pending);
+        c.try_send(1).unwrap();
+        assert_eq!(poll!(recv.as_mut()), Poll::Ready(1));
+    })
+}
+
+#[test]
+fn backpressure() {
+    block_on(async {
+        let c = TestChannel::<u32, 2>::new();
+        let sender = async {
+            for i in 0..20 {
+                c.send(i).await;
+                // The receiver can't fall more than the capacity behind.
+                assert!(c.len() <= 2);
+            }
+        };
+        let receiver = async {
+            let mut received = Vec::new();
+            while received.len() < 20 {
+                received.push(c.recv().await);
+            }
+            received
+        };
+        let ((), received) = join!(sender, receiver);
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+    })
+}
+
+#[test]
+fn multiple_producers() {
+    block_on(async {
+        let c = TestChannel::<u32, 1>::new();
+        let send_all = |base: u32| {
+            let c = &c;
+            async move {
+                for i in 0..5 {
+                    c.send(base + i).await;
+                }
+            }
+        };
+        let receiver = async {
+            let mut received = Vec::new();
+            for _ in 0..15 {
+                received.push(c.recv().await);
+            }
+            received
+        };
+        let (_, _, _, mut received) = join!(send_all(0), send_all(10), send_all(20), receiver);
+        received.sort_unstable();
+        let expected: Vec<_> = (0..5).chain(10..15).chain(20..25).collect();
+        assert_eq!(received, expected);
+    })
+}
+
+#[test]
+fn drop_drops_queued_messages() {
+    let msg = Arc::new(());
+    let c = TestChannel::<Arc<()>, 4>::new();
+    c.try_send(msg.clone()).unwrap();
+    c.try_send(msg.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&msg), 3);
+
+    drop(c.try_recv());
+    assert_eq!(Arc::strong_count(&msg), 2);
+    drop(c);
+    assert_eq!(Arc::strong_count(&msg), 1);
+}
@@ -0,0 +1,40 @@
+//! Helpers shared by the integration tests.
+
+use embassy::executor::raw;
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Runs `fut` to completion on an executor driven by hand.
+///
+/// Tests using this don't use timers, so once no task is woken anymore, `fut` can't make
+/// progress and this panics instead of hanging.
+pub fn block_on<F: Future + 'static>(fut: F) -> F::Output {
+    let signaled: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+    let signal = |ctx| unsafe { &*(ctx as *const AtomicBool) }.store(true, Ordering::SeqCst);
+    let executor = Box::leak(Box::new(raw::Executor::new(
+        signal,
+        signaled as *const _ as _,
+    )));
+
+    let output = Rc::new(RefCell::new(None));
+    let task_output = output.clone();
+    let task = Box::leak(Box::new(raw::Task::new()));
+    let token = task.spawn(move || async move {
+        *task_output.borrow_mut() = Some(fut.await);
+    });
+    unsafe { executor.spawner() }.spawn(token).unwrap();
+
+    loop {
+        signaled.store(false, Ordering::SeqCst);
+        unsafe { executor.run_queued() };
+        if let Some(res) = output.borrow_mut().take() {
+            return res;
+        }
+        assert!(
+            signaled.load(Ordering::SeqCst),
+            "future can't make progress"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::executor::{raw, JoinError, JoinHandle, SpawnError, Spawner};
+use futures::future::{pending, poll_fn, FusedFuture};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+
+/// Executor run by hand, so tests can check the state in between.
+struct TestExecutor {
+    inner: &'static raw::Executor,
+    signaled: &'static AtomicBool,
+}
+
+impl TestExecutor {
+    fn new() -> Self {
+        let signaled: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+        let signal = |ctx| unsafe { &*(ctx as *const AtomicBool) }.store(true, Ordering::SeqCst);
+        let inner = Box::leak(Box::new(raw::Executor::new(
+            signal,
+            signaled as *const _ as _,
+        )));
+        Self { inner, signaled }
+    }
+
+    fn spawner(&self) -> Spawner {
+        unsafe { self.inner.spawner() }
+    }
+
+    /// Run tasks until they are all waiting.
+    fn run(&self) {
+        loop {
+            self.signaled.store(false, Ordering::SeqCst);
+            unsafe { self.inner.run_queued() };
+            if !self.signaled.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+}
+
+fn new_task<F: Future + 'static>() -> &'static raw::Task<F> {
+    Box::leak(Box::new(raw::Task::new()))
+}
+
+type JoinResult<T> = Rc<RefCell<Option<Result<T, JoinError>>>>;
+
+/// Await `handle` in another task, storing the result.
+fn join<T: 'static>(executor: &TestExecutor, handle: JoinHandle<T>) -> JoinResult<T> {
+    let res = Rc::new(RefCell::new(None));
+    let task_res = res.clone();
+    let token = new_task().spawn(move || async move {
+        *task_res.borrow_mut() = Some(handle.await);
+    });
+    executor.spawner().spawn(token).unwrap();
+    res
+}
+
+async fn double(x: u32) -> u32 {
+    x * 2
+}
+
+async fn identity<T>(x: T) -> T {
+    x
+}
+
+async fn forever(_guard: Arc<()>) {
+    pending::<()>().await
+}
+
+#[test]
+fn join_returns_output() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+
+    let handle = spawner
+        .spawn_with_handle(task.spawn(|| double(21)))
+        .unwrap();
+    assert!(handle.is_running());
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Ok(42)));
+}
+
+#[embassy::task]
+async fn answer() -> u32 {
+    42
+}
+
+#[test]
+fn join_task_fn() {
+    let executor = TestExecutor::new();
+    let handle = executor.spawner().spawn_with_handle(answer()).unwrap();
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Ok(42)));
+}
+
+#[test]
+fn slot_freed_when_output_taken() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+
+    let handle = spawner.spawn_with_handle(task.spawn(|| double(1))).unwrap();
+    executor.run();
+    assert!(!handle.is_running());
+
+    // The output is still stored in the slot.
+    assert!(matches!(
+        spawner.spawn(task.spawn(|| double(2))),
+        Err(SpawnError::Busy)
+    ));
+
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Ok(2)));
+
+    let handle = spawner.spawn_with_handle(task.spawn(|| double(3))).unwrap();
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Ok(6)));
+}
+
+#[test]
+fn slot_freed_when_handle_dropped() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+    let output = Arc::new(());
+
+    let out = output.clone();
+    let handle = spawner
+        .spawn_with_handle(task.spawn(move || identity(out)))
+        .unwrap();
+    executor.run();
+    assert_eq!(Arc::strong_count(&output), 2);
+
+    // Dropping the handle drops the output it didn't take.
+    drop(handle);
+    assert_eq!(Arc::strong_count(&output), 1);
+    assert!(spawner.spawn(task.spawn(|| identity(output))).is_ok());
+}
+
+#[test]
+fn detached_task_runs_and_frees_slot() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+
+    let handle = spawner.spawn_with_handle(task.spawn(|| double(1))).unwrap();
+    drop(handle);
+    assert!(matches!(
+        spawner.spawn(task.spawn(|| double(2))),
+        Err(SpawnError::Busy)
+    ));
+    executor.run();
+    assert!(spawner.spawn(task.spawn(|| double(3))).is_ok());
+}
+
+#[test]
+fn cancel_drops_future_and_frees_slot() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+    let guard = Arc::new(());
+
+    let g = guard.clone();
+    let handle = spawner
+        .spawn_with_handle(task.spawn(|| forever(g)))
+        .unwrap();
+    executor.run();
+    assert!(handle.is_running());
+    assert_eq!(Arc::strong_count(&guard), 2);
+
+    handle.cancel();
+    executor.run();
+    assert!(!handle.is_running());
+    assert_eq!(Arc::strong_count(&guard), 1);
+
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Err(JoinError::Cancelled)));
+
+    assert!(spawner.spawn(task.spawn(|| forever(guard))).is_ok());
+}
+
+#[test]
+fn cancel_after_completion_is_noop() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+
+    let handle = spawner
+        .spawn_with_handle(new_task().spawn(|| double(4)))
+        .unwrap();
+    executor.run();
+    handle.cancel();
+    let res = join(&executor, handle);
+    executor.run();
+    assert_eq!(*res.borrow(), Some(Ok(8)));
+}
+
+#[test]
+fn poll_after_completion_is_pending() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let done = Rc::new(Cell::new(false));
+
+    let mut handle = spawner
+        .spawn_with_handle(new_task().spawn(|| double(1)))
+        .unwrap();
+    let task_done = done.clone();
+    let token = new_task().spawn(move || async move {
+        assert!(!handle.is_terminated());
+        assert_eq!((&mut handle).await, Ok(2));
+        assert!(handle.is_terminated());
+        poll_fn(|cx| {
+            assert!(Pin::new(&mut handle).poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        task_done.set(true);
+    });
+    spawner.spawn(token).unwrap();
+    executor.run();
+    assert!(done.get());
+}
+
+#[test]
+fn reused_slot_does_not_wake_previous_joiner() {
+    let executor = TestExecutor::new();
+    let spawner = executor.spawner();
+    let task = new_task();
+    let polls = Rc::new(Cell::new(0));
+
+    // Join a task, then keep waiting, counting how often we're polled.
+    let handle = spawner.spawn_with_handle(task.spawn(|| double(1))).unwrap();
+    let task_polls = polls.clone();
+    let token = new_task().spawn(move || async move {
+        handle.await.unwrap();
+        poll_fn(|_| {
+            task_polls.set(task_polls.get() + 1);
+            Poll::<()>::Pending
+        })
+        .await
+    });
+    spawner.spawn(token).unwrap();
+    executor.run();
+    assert_eq!(polls.get(), 1);
+
+    // The next task in the slot finishes without anyone awaiting its handle.
+    let handle = spawner.spawn_with_handle(task.spawn(|| double(2))).unwrap();
+    executor.run();
+    assert!(!handle.is_running());
+    assert_eq!(polls.get(), 1);
+}
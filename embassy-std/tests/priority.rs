@@ -0,0 +1,87 @@
+use embassy::executor::raw::{self, PRIORITY_LEVELS};
+use embassy::util::{Channel, WithCriticalSections};
+use std::cell::RefCell;
+use std::future::Future;
+use std::ptr;
+use std::rc::Rc;
+
+type Log = Rc<RefCell<Vec<&'static str>>>;
+
+fn executor() -> &'static raw::Executor {
+    Box::leak(Box::new(raw::Executor::new(|_| {}, ptr::null_mut())))
+}
+
+fn spawn<F: Future + 'static>(
+    executor: &'static raw::Executor,
+    priority: u8,
+    future: impl FnOnce() -> F,
+) {
+    let task: &'static raw::Task<F> = Box::leak(Box::new(raw::Task::new_with_priority(priority)));
+    unsafe { executor.spawner() }
+        .spawn(task.spawn(future))
+        .unwrap();
+}
+
+fn log_task(log: &Log, name: &'static str) -> impl Future<Output = ()> {
+    let log = log.clone();
+    async move { log.borrow_mut().push(name) }
+}
+
+#[test]
+fn higher_priority_runs_first() {
+    let executor = executor();
+    let log = Log::default();
+
+    spawn(executor, 0, || log_task(&log, "low"));
+    spawn(executor, 1, || log_task(&log, "mid"));
+    spawn(executor, PRIORITY_LEVELS as u8 - 1, || {
+        log_task(&log, "high")
+    });
+    spawn(executor, 1, || log_task(&log, "mid"));
+    unsafe { executor.run_queued() };
+
+    assert_eq!(*log.borrow(), ["high", "mid", "mid", "low"]);
+}
+
+#[test]
+fn woken_higher_priority_preempts_batch() {
+    let executor = executor();
+    let log = Log::default();
+    let channel: &'static Channel<WithCriticalSections, (), 4> =
+        Box::leak(Box::new(Channel::<WithCriticalSections, _, 4>::new()));
+
+    let high_log = log.clone();
+    spawn(executor, 1, move || async move {
+        loop {
+            channel.recv().await;
+            high_log.borrow_mut().push("high");
+        }
+    });
+    unsafe { executor.run_queued() };
+    assert!(log.borrow().is_empty());
+
+    // Each low priority task wakes the high priority one, which runs before the next task
+    // of the batch.
+    for _ in 0..2 {
+        let low_log = log.clone();
+        spawn(executor, 0, move || async move {
+            low_log.borrow_mut().push("low");
+            channel.try_send(()).unwrap();
+        });
+    }
+    unsafe { executor.run_queued() };
+    assert_eq!(*log.borrow(), ["low", "high", "low"]);
+
+    // Woken by the last task of the batch, so it runs in the next one.
+    unsafe { executor.run_queued() };
+    assert_eq!(*log.borrow(), ["low", "high", "low", "high"]);
+}
+
+#[test]
+#[should_panic]
+fn priority_out_of_range() {
+    fn new_task(priority: u8) -> raw::Task<std::future::Ready<()>> {
+        raw::Task::new_with_priority(priority)
+    }
+    new_task(PRIORITY_LEVELS as u8);
+}
@@ -0,0 +1,126 @@
+use embassy::time::{with_timeout, Alarm, Clock, Duration, Instant, TimeoutError, Timer};
+use embassy_std::{Executor, SimClock};
+use futures::future::pending;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Poll;
+use std::thread;
+
+fn on_alarm(ctx: *mut ()) {
+    unsafe { &*(ctx as *const AtomicUsize) }.fetch_add(1, Ordering::SeqCst);
+}
+
+fn secs(secs: u64) -> u64 {
+    Duration::from_secs(secs).as_ticks()
+}
+
+#[test]
+fn alarm_fires_when_clock_advances() {
+    let clock = SimClock::new();
+    let fired = AtomicUsize::new(0);
+    clock.set_callback(on_alarm, &fired as *const _ as _);
+
+    clock.set(secs(5));
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(fired.load(Ordering::SeqCst), 1);
+    assert_eq!(clock.now(), secs(5));
+
+    // It only fires once.
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+    // An alarm in the past fires right away.
+    clock.set(secs(2));
+    assert_eq!(fired.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cleared_alarm_does_not_fire() {
+    let clock = SimClock::new();
+    let fired = AtomicUsize::new(0);
+    clock.set_callback(on_alarm, &fired as *const _ as _);
+
+    clock.set(secs(10));
+    clock.clear();
+    clock.advance_to(Instant::from_ticks(secs(20)));
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+    assert_eq!(clock.now(), secs(20));
+
+    // Time never goes back.
+    clock.advance_to(Instant::from_ticks(secs(15)));
+    assert_eq!(clock.now(), secs(20));
+}
+
+/// A simulated executor, with its clock.
+fn simulated() -> (&'static SimClock, &'static mut Executor) {
+    let clock = Box::leak(Box::new(SimClock::new()));
+    (clock, Box::leak(Box::new(Executor::new_simulated(clock))))
+}
+
+#[test]
+fn timeout_fires_when_clock_advances() {
+    let (clock, executor) = simulated();
+    executor.block_on(async move {
+        let mut fut = Box::pin(with_timeout(Duration::from_secs(5), pending::<()>()));
+        assert!(futures::poll!(fut.as_mut()).is_pending());
+
+        clock.advance(Duration::from_secs(4));
+        assert!(futures::poll!(fut.as_mut()).is_pending());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(
+            futures::poll!(fut.as_mut()),
+            Poll::Ready(Err(TimeoutError))
+        ));
+    });
+}
+
+#[test]
+fn idle_executor_jumps_to_next_timer() {
+    let (_, executor) = simulated();
+    let (res, elapsed) = executor.block_on(async {
+        let start = Instant::now();
+        let res = with_timeout(Duration::from_secs(60 * 60), pending::<()>()).await;
+        (res, Instant::now() - start)
+    });
+    assert!(matches!(res, Err(TimeoutError)));
+    assert_eq!(elapsed, Duration::from_secs(60 * 60));
+}
+
+#[test]
+fn timer_completes_before_timeout() {
+    let (clock, executor) = simulated();
+    let res = executor.block_on(async {
+        with_timeout(Duration::from_secs(10), async {
+            Timer::after(Duration::from_secs(3)).await;
+            42u32
+        })
+        .await
+    });
+    assert!(matches!(res, Ok(42)));
+    assert_eq!(clock.now(), Duration::from_secs(3).as_ticks());
+}
+
+#[test]
+fn simulated_time_is_per_thread() {
+    let sim = thread::spawn(|| {
+        let (_, executor) = simulated();
+        executor.block_on(async {
+            Timer::after(Duration::from_secs(24 * 60 * 60)).await;
+            Instant::now()
+        })
+    });
+    let sim_now = sim.join().unwrap();
+    assert_eq!(
+        sim_now,
+        Instant::from_ticks(0) + Duration::from_secs(24 * 60 * 60)
+    );
+
+    // The real clock is unaffected, on this thread and on real executors.
+    let real_now = embassy_std::block_on(async {
+        Timer::after(Duration::from_millis(1)).await;
+        Instant::now()
+    });
+    assert!(real_now < sim_now);
+}
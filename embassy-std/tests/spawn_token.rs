@@ -0,0 +1,61 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::executor::{raw, SpawnError, Spawner};
+use std::ptr;
+use std::sync::Arc;
+
+fn spawner() -> Spawner {
+    let executor = Box::leak(Box::new(raw::Executor::new(|_| {}, ptr::null_mut())));
+    unsafe { executor.spawner() }
+}
+
+async fn hold(_guard: Arc<()>) {}
+
+#[test]
+fn dropped_token_frees_pool_slot() {
+    let spawner = spawner();
+    let pool: &'static [raw::Task<_>; 1] = Box::leak(Box::new([raw::Task::new()]));
+    let guard = Arc::new(());
+
+    let g = guard.clone();
+    let token = raw::Task::spawn_pool(pool, move || hold(g));
+    assert_eq!(Arc::strong_count(&guard), 2);
+
+    // The future is dropped without ever being polled.
+    drop(token);
+    assert_eq!(Arc::strong_count(&guard), 1);
+
+    assert!(spawner
+        .spawn(raw::Task::spawn_pool(pool, move || hold(guard)))
+        .is_ok());
+}
+
+#[test]
+fn busy_token_drop_is_noop() {
+    let spawner = spawner();
+    let task: &'static raw::Task<_> = Box::leak(Box::new(raw::Task::new()));
+
+    let token = task.spawn(|| hold(Arc::new(())));
+    // The slot is taken, so this token has no task.
+    let busy = task.spawn(|| hold(Arc::new(())));
+    drop(busy);
+    assert!(spawner.spawn(token).is_ok());
+    assert!(matches!(
+        spawner.spawn(task.spawn(|| hold(Arc::new(())))),
+        Err(SpawnError::Busy)
+    ));
+}
+
+#[embassy::task]
+async fn pooled() {}
+
+#[test]
+fn dropped_task_fn_token_frees_slot() {
+    let spawner = spawner();
+    drop(pooled());
+    drop(pooled());
+    assert!(spawner.spawn(pooled()).is_ok());
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use common::block_on;
+use embassy::sync::{Mutex, RwLock};
+use futures::poll;
+use std::task::Poll;
+
+fn is_pending<T>(p: Poll<T>) -> bool {
+    matches!(p, Poll::Pending)
+}
+
+#[test]
+fn mutex_waiters_served_in_order() {
+    block_on(async {
+        let m = Mutex::new(Vec::new());
+        let guard = m.try_lock().unwrap();
+
+        let mut waiters: Vec<_> = (0..3)
+            .map(|i| {
+                let m = &m;
+                Box::pin(async move { m.lock().await.push(i) })
+            })
+            .collect();
+        for w in waiters.iter_mut() {
+            assert!(is_pending(poll!(w.as_mut())));
+        }
+        // Tasks that didn't queue can't jump ahead of the waiters.
+        drop(guard);
+        assert!(m.try_lock().is_none());
+
+        // Poll in reverse, so that the order isn't just the polling order.
+        let mut done = [false; 3];
+        while done.contains(&false) {
+            for (w, done) in waiters.iter_mut().zip(done.iter_mut()).rev() {
+                if !*done {
+                    *done = !is_pending(poll!(w.as_mut()));
+                }
+            }
+        }
+        drop(waiters);
+
+        assert_eq!(*m.try_lock().unwrap(), [0, 1, 2]);
+    })
+}
+
+#[test]
+fn mutex_next_waiter_waits_for_unlock() {
+    block_on(async {
+        let m = Mutex::new(0);
+        let guard = m.try_lock().unwrap();
+
+        let mut first = Box::pin(m.lock());
+        let mut second = Box::pin(m.lock());
+        assert!(is_pending(poll!(first.as_mut())));
+        assert!(is_pending(poll!(second.as_mut())));
+
+        drop(guard);
+        let first = match poll!(first.as_mut()) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("first waiter didn't get the lock"),
+        };
+        assert!(is_pending(poll!(second.as_mut())));
+
+        drop(first);
+        assert!(!is_pending(poll!(second.as_mut())));
+    })
+}
+
+#[test]
+fn mutex_cancel_while_queued() {
+    block_on(async {
+        let m = Mutex::new(0);
+        let guard = m.try_lock().unwrap();
+
+        let mut first = Box::pin(m.lock());
+        let mut second = Box::pin(m.lock());
+        let mut third = Box::pin(m.lock());
+        assert!(is_pending(poll!(first.as_mut())));
+        assert!(is_pending(poll!(second.as_mut())));
+        assert!(is_pending(poll!(third.as_mut())));
+
+        // Dropping a waiter from the middle unlinks it from the queue.
+        drop(second);
+        drop(guard);
+        let first = match poll!(first.as_mut()) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("first waiter didn't get the lock"),
+        };
+        drop(first);
+        assert!(!is_pending(poll!(third.as_mut())));
+    })
+}
+
+#[test]
+fn mutex_cancel_after_granted() {
+    block_on(async {
+        let m = Mutex::new(0);
+        let guard = m.try_lock().unwrap();
+
+        let mut first = Box::pin(m.lock());
+        let mut second = Box::pin(m.lock());
+        assert!(is_pending(poll!(first.as_mut())));
+        assert!(is_pending(poll!(second.as_mut())));
+
+        // The lock is handed to the first waiter on unlock, and passed on when it's dropped
+        // without being polled again.
+        drop(guard);
+        drop(first);
+        assert!(!is_pending(poll!(second.as_mut())));
+    });
+
+    block_on(async {
+        let m = Mutex::new(0);
+        let guard = m.try_lock().unwrap();
+        let mut waiter = Box::pin(m.lock());
+        assert!(is_pending(poll!(waiter.as_mut())));
+        drop(guard);
+        drop(waiter);
+        assert!(m.try_lock().is_some());
+    })
+}
+
+#[test]
+fn rwlock_readers_share() {
+    block_on(async {
+        let l = RwLock::new(1);
+        let r1 = l.read().await;
+        let r2 = l.read().await;
+        assert_eq!(*r1 + *r2, 2);
+        assert!(l.try_write().is_none());
+        drop(r1);
+        assert!(l.try_write().is_none());
+        drop(r2);
+        assert!(l.try_write().is_some());
+    })
+}
+
+#[test]
+fn rwlock_writer_not_starved_by_readers() {
+    block_on(async {
+        let l = RwLock::new(0);
+        let reader = l.try_read().unwrap();
+
+        let mut writer = Box::pin(l.write());
+        assert!(is_pending(poll!(writer.as_mut())));
+
+        // Readers arriving after the writer wait behind it, even though the lock is only
+        // held for reading.
+        assert!(l.try_read().is_none());
+        let mut late_readers: Vec<_> = (0..2).map(|_| Box::pin(l.read())).collect();
+        for r in late_readers.iter_mut() {
+            assert!(is_pending(poll!(r.as_mut())));
+        }
+
+        drop(reader);
+        let mut writer = match poll!(writer.as_mut()) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("writer didn't get the lock"),
+        };
+        *writer = 42;
+        for r in late_readers.iter_mut() {
+            assert!(is_pending(poll!(r.as_mut())));
+        }
+
+        // All the readers queued behind the writer get the lock together.
+        drop(writer);
+        let mut guards = Vec::new();
+        for r in late_readers.iter_mut() {
+            match poll!(r.as_mut()) {
+                Poll::Ready(guard) => guards.push(guard),
+                Poll::Pending => panic!("reader didn't get the lock"),
+            }
+        }
+        assert!(guards.iter().all(|g| **g == 42));
+    })
+}
+
+#[test]
+fn rwlock_writer_waits_for_writer() {
+    block_on(async {
+        let l = RwLock::new(0);
+        let first = l.try_write().unwrap();
+        let mut reader = Box::pin(l.read());
+        let mut second = Box::pin(l.write());
+        assert!(is_pending(poll!(reader.as_mut())));
+        assert!(is_pending(poll!(second.as_mut())));
+
+        // The reader is first in line, the second writer waits until it's done.
+        drop(first);
+        assert!(is_pending(poll!(second.as_mut())));
+        let reader = match poll!(reader.as_mut()) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("reader didn't get the lock"),
+        };
+        assert!(is_pending(poll!(second.as_mut())));
+        drop(reader);
+        assert!(!is_pending(poll!(second.as_mut())));
+    })
+}
@@ -0,0 +1,117 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::executor::SendSpawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::{Channel, ThreadModeMutex, WithThreadModeOnly};
+use embassy_std::{block_on, Executor};
+use futures::join;
+use std::sync::mpsc;
+use std::thread::{self, ThreadId};
+
+/// Start an executor on a new thread, returning the thread and a spawner for it.
+fn start_executor() -> (ThreadId, SendSpawner) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(Executor::new()));
+        executor.run(|spawner| {
+            tx.send((thread::current().id(), spawner.make_send()))
+                .unwrap()
+        })
+    });
+    rx.recv().unwrap()
+}
+
+/// Uses thread-mode-only state and timers, then reports the thread it ran on.
+#[embassy::task(pool_size = 2)]
+async fn worker(done: mpsc::Sender<ThreadId>) {
+    let c = Channel::<WithThreadModeOnly, u32, 1>::new();
+    let send = async {
+        for i in 0..3 {
+            c.send(i).await;
+            Timer::after(Duration::from_millis(1)).await;
+        }
+    };
+    let recv = async {
+        for i in 0..3 {
+            assert_eq!(c.recv().await, i);
+        }
+    };
+    join!(send, recv);
+    done.send(thread::current().id()).unwrap();
+}
+
+#[test]
+fn two_executors_on_two_threads() {
+    let (thread_a, spawner_a) = start_executor();
+    let (thread_b, spawner_b) = start_executor();
+    assert_ne!(thread_a, thread_b);
+
+    let (tx, rx) = mpsc::channel();
+    spawner_a.spawn(worker(tx.clone())).unwrap();
+    spawner_b.spawn(worker(tx)).unwrap();
+
+    let timeout = std::time::Duration::from_secs(5);
+    let mut ran_on = [
+        rx.recv_timeout(timeout).unwrap(),
+        rx.recv_timeout(timeout).unwrap(),
+    ];
+    ran_on.sort_by_key(|id| *id != thread_a);
+    assert_eq!(ran_on, [thread_a, thread_b]);
+}
+
+#[test]
+fn block_on_any_thread() {
+    let res = thread::spawn(|| {
+        // Calls on the same thread reuse its executor.
+        (0..3)
+            .map(|i| {
+                block_on(async move {
+                    let c = Channel::<WithThreadModeOnly, u32, 1>::new();
+                    c.send(i).await;
+                    Timer::after(Duration::from_millis(1)).await;
+                    c.recv().await
+                })
+            })
+            .sum::<u32>()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(res, 3);
+}
+
+#[test]
+fn thread_mode_mutex_needs_executor_thread() {
+    let res = thread::spawn(|| *ThreadModeMutex::new(1).borrow()).join();
+    assert!(res.is_err());
+}
+
+#[test]
+fn thread_mode_mutex_stays_on_one_executor_thread() {
+    let m: &'static ThreadModeMutex<u32> = Box::leak(Box::new(ThreadModeMutex::new(1)));
+    let first = thread::spawn(move || block_on(async move { *m.borrow() })).join();
+    assert_eq!(first.unwrap(), 1);
+    let second = thread::spawn(move || block_on(async move { *m.borrow() })).join();
+    assert!(second.is_err());
+}
+
+#[test]
+fn main_thread_is_thread_mode() {
+    let m: &'static ThreadModeMutex<u32> = Box::leak(Box::new(ThreadModeMutex::new(1)));
+    let main = || thread::Builder::new().name("main".into());
+
+    // No executor is needed on the main thread, and it claims the mutex.
+    let res = main().spawn(move || *m.borrow()).unwrap().join();
+    assert_eq!(res.unwrap(), 1);
+    let other = thread::spawn(move || block_on(async move { *m.borrow() })).join();
+    assert!(other.is_err());
+
+    // The main thread can still borrow a mutex claimed by another executor thread.
+    let m: &'static ThreadModeMutex<u32> = Box::leak(Box::new(ThreadModeMutex::new(2)));
+    let other = thread::spawn(move || block_on(async move { *m.borrow() })).join();
+    assert_eq!(other.unwrap(), 2);
+    let res = main().spawn(move || *m.borrow()).unwrap().join();
+    assert_eq!(res.unwrap(), 2);
+}
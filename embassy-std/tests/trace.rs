@@ -0,0 +1,147 @@
+#![cfg(feature = "trace")]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::executor::raw;
+use embassy::executor::trace::{set_tracer, Event, SetTracerError};
+use embassy_std::trace::{max_wake_latency, RecordingTracer};
+use futures::future::poll_fn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, Once};
+use std::task::Poll;
+
+lazy_static::lazy_static! {
+    static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The tracer is global, so all tests share it, one at a time.
+fn tracer() -> (&'static RecordingTracer, MutexGuard<'static, ()>) {
+    let guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    static INIT: Once = Once::new();
+    static mut TRACER: Option<&'static RecordingTracer> = None;
+    INIT.call_once(|| {
+        let tracer = Box::leak(Box::new(RecordingTracer::new()));
+        set_tracer(tracer).unwrap();
+        unsafe { TRACER = Some(tracer) };
+    });
+    let tracer = unsafe { TRACER.unwrap() };
+    // Drop the events of the previous test.
+    tracer.take_events();
+    (tracer, guard)
+}
+
+fn new_executor(signaled: &'static AtomicBool) -> &'static raw::Executor {
+    let signal = |ctx| unsafe { &*(ctx as *const AtomicBool) }.store(true, Ordering::SeqCst);
+    Box::leak(Box::new(raw::Executor::new(
+        signal,
+        signaled as *const _ as _,
+    )))
+}
+
+/// Wakes itself once before completing.
+async fn yield_once() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[test]
+fn reports_task_lifecycle() {
+    let (tracer, _guard) = tracer();
+    let signaled: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+    let executor = new_executor(signaled);
+    let executor_id = executor as *const raw::Executor as usize;
+
+    let task: &'static raw::Task<_> = Box::leak(Box::new(raw::Task::new()));
+    unsafe { executor.spawner() }
+        .spawn(task.spawn(yield_once))
+        .unwrap();
+    while signaled.swap(false, Ordering::SeqCst) {
+        unsafe { executor.run_queued() };
+    }
+
+    let events: Vec<Event> = tracer.take_events().into_iter().map(|(_, e)| e).collect();
+    let task_id = events
+        .iter()
+        .find_map(|e| match *e {
+            Event::TaskSpawned { executor, task } if executor == executor_id => Some(task),
+            _ => None,
+        })
+        .unwrap();
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|e| match *e {
+            Event::TaskSpawned { task, .. }
+            | Event::TaskWoken { task }
+            | Event::PollBegin { task }
+            | Event::PollEnd { task } => task == task_id,
+            Event::ExecutorIdle { executor } => executor == executor_id,
+            Event::TimerArmed { .. } => false,
+        })
+        .collect();
+
+    assert_eq!(
+        events,
+        [
+            Event::TaskSpawned {
+                executor: executor_id,
+                task: task_id
+            },
+            Event::TaskWoken { task: task_id },
+            Event::PollBegin { task: task_id },
+            Event::TaskWoken { task: task_id },
+            Event::PollEnd { task: task_id },
+            Event::ExecutorIdle {
+                executor: executor_id
+            },
+            Event::PollBegin { task: task_id },
+            Event::PollEnd { task: task_id },
+            Event::ExecutorIdle {
+                executor: executor_id
+            },
+        ]
+    );
+}
+
+#[test]
+fn spawn_counts_as_first_wake() {
+    let (tracer, _guard) = tracer();
+    let signaled: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+    let executor = new_executor(signaled);
+
+    let task: &'static raw::Task<_> = Box::leak(Box::new(raw::Task::new()));
+    unsafe { executor.spawner() }
+        .spawn(task.spawn(|| async {}))
+        .unwrap();
+    unsafe { executor.run_queued() };
+
+    let events = tracer.take_events();
+    let task_id = events
+        .iter()
+        .find_map(|(_, e)| match *e {
+            Event::TaskSpawned { executor: e, task } if e == executor as *const _ as usize => {
+                Some(task)
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert!(max_wake_latency(&events).contains_key(&task_id));
+}
+
+#[test]
+fn tracer_is_set_once() {
+    let _guard = tracer();
+    assert_eq!(
+        set_tracer(Box::leak(Box::new(RecordingTracer::new()))),
+        Err(SetTracerError)
+    );
+}
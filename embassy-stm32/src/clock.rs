@@ -8,6 +8,7 @@ use atomic_polyfill::AtomicU32;
 use embassy::interrupt::InterruptExt;
 use embassy::time::{Clock as EmbassyClock, TICKS_PER_SECOND};
 
+use crate::fmt::assert;
 use crate::interrupt::{CriticalSection, Interrupt, Mutex};
 use crate::pac::timer::TimGp16;
 use crate::peripherals;
@@ -15,6 +16,8 @@ use crate::time::Hertz;
 
 // Clock timekeeping works with something we call "periods", which are time intervals
 // of 2^15 ticks. The Clock counter value is 16 bits, so one "overflow cycle" is 2 periods.
+// How long a period lasts depends on the tick rate: 1s at 32768 ticks per second, about 32ms
+// at 1MHz, and about 33s at 1kHz.
 //
 // A `period` count is maintained in parallel to the Timer hardware `counter`, like this:
 // - `period` and `counter` start at 0
@@ -29,7 +32,8 @@ use crate::time::Hertz;
 // a new period start has raced us between reading `period` and `counter`, so we assume the `counter` value
 // corresponds to the next period.
 //
-// `period` is a 32bit integer, so It overflows on 2^32 * 2^15 / 32768 seconds of uptime, which is 136 years.
+// `period` is a 32bit integer, so It overflows on 2^32 * 2^15 / TICKS_PER_SECOND seconds of uptime,
+// which is 136 years at 32768 ticks per second, or 1628 days at 1MHz.
 fn calc_now(period: u32, counter: u16) -> u64 {
     ((period as u64) << 15) + ((counter as u32 ^ ((period & 1) << 15)) as u64)
 }
@@ -55,8 +59,12 @@ const ALARM_COUNT: usize = 3;
 ///
 /// It can work with Timers 2, 3, 4, 5. This timer works internally with a unit of 2^15 ticks, which
 /// means that if a call to [`embassy::time::Clock::now`] is blocked for that amount of ticks the
-/// returned value will be wrong (an old value). The current default tick rate is 32768 ticks per
-/// second.
+/// returned value will be wrong (an old value). The tick rate is selected with the `tick-hz-*`
+/// features of `embassy`, and defaults to 32768 ticks per second. The timer input frequency must be
+/// an integer multiple of the tick rate.
+///
+/// The faster the tick rate, the shorter the window: 2^15 ticks is 1s at 32768 ticks per second,
+/// but only about 32ms at 1MHz.
 pub struct Clock<T: Instance> {
     _inner: T,
     irq: T::Interrupt,
@@ -266,7 +274,11 @@ impl TimerInner {
     unsafe fn prepare(&self, timer_freq: Hertz) {
         self.stop_and_reset();
 
-        let psc = timer_freq.0 / TICKS_PER_SECOND as u32 - 1;
+        assert!(
+            timer_freq.0 as u64 % TICKS_PER_SECOND == 0,
+            "Timer frequency must be a multiple of TICKS_PER_SECOND"
+        );
+        let psc = (timer_freq.0 as u64 / TICKS_PER_SECOND - 1) as u32;
         let psc: u16 = psc.try_into().unwrap();
 
         self.set_psc_arr(psc, u16::MAX);
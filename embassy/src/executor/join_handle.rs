@@ -0,0 +1,156 @@
+use atomic_polyfill::Ordering;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
+
+use super::raw::{
+    TaskHeader, TaskOutput, STATE_CANCELLED, STATE_JOIN_HANDLE, STATE_OUTPUT, STATE_SPAWNED,
+};
+use futures::future::FusedFuture;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoinError {
+    /// The task was cancelled before it completed.
+    Cancelled,
+}
+
+/// Handle to a spawned task, obtained from [Spawner::spawn_with_handle](super::Spawner::spawn_with_handle).
+///
+/// Awaiting the handle yields the task's output once it completes. The task can also be
+/// cancelled through the handle, in which case its future is dropped in place the next time
+/// the executor runs it, and awaiting the handle yields [JoinError::Cancelled].
+///
+/// Dropping the handle detaches the task: it keeps running, and its output is discarded.
+///
+/// The output of a task is stored in the task's slot, so the slot can't be reused by another
+/// spawn until the output has been taken. While a handle exists, the slot is freed once the
+/// task has completed or been cancelled, *and* the handle has returned the result or has
+/// been dropped. Without a handle, the slot is freed as soon as the task completes.
+///
+/// The handle is fused: once it has returned the result, polling it again returns
+/// `Poll::Pending` forever.
+pub struct JoinHandle<T> {
+    /// The task, until the result has been returned.
+    task: Option<NonNull<TaskHeader>>,
+    phantom: PhantomData<*mut T>,
+}
+
+unsafe impl<T: Send> Send for JoinHandle<T> {}
+
+impl<T> JoinHandle<T> {
+    /// Safety: the task must have STATE_JOIN_HANDLE set, and its output must be of type `T`.
+    pub(crate) unsafe fn new(task: NonNull<TaskHeader>) -> Self {
+        Self {
+            task: Some(task),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The task's header, or `None` if the result has already been returned, in which case
+    /// the slot may have been reused by another task.
+    fn header(&self) -> Option<&TaskHeader> {
+        self.task.map(|task| unsafe { &*task.as_ptr() })
+    }
+
+    /// Returns true if the task has not completed or been cancelled yet.
+    pub fn is_running(&self) -> bool {
+        match self.header() {
+            Some(header) => header.state.load(Ordering::Acquire) & STATE_SPAWNED != 0,
+            None => false,
+        }
+    }
+
+    /// Requests cancellation of the task.
+    ///
+    /// The task's future is dropped the next time the executor runs the task. This is a
+    /// noop if the task has already completed.
+    pub fn cancel(&self) {
+        let header = match self.header() {
+            Some(header) => header,
+            None => return,
+        };
+        let mut current = header.state.load(Ordering::Acquire);
+        loop {
+            if current & STATE_SPAWNED == 0 {
+                return;
+            }
+
+            match header.state.compare_exchange_weak(
+                current,
+                current | STATE_CANCELLED,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(next_current) => current = next_current,
+            }
+        }
+
+        // Make sure the executor gets to run the task, so it can drop it.
+        unsafe { header.enqueue() }
+    }
+}
+
+impl<T> Unpin for JoinHandle<T> {}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let task = match self.task {
+            Some(task) => task,
+            None => return Poll::Pending,
+        };
+        let header = unsafe { task.as_ref() };
+
+        // Register first, so we don't miss a completion between checking and registering.
+        header.join_waker.register(cx.waker());
+
+        let state = header.state.load(Ordering::Acquire);
+        let res = if state & STATE_OUTPUT != 0 {
+            let task = task.cast::<TaskOutput<T>>();
+            Ok(unsafe { task.as_ref().output.take() })
+        } else if state & STATE_SPAWNED == 0 {
+            Err(JoinError::Cancelled)
+        } else {
+            return Poll::Pending;
+        };
+
+        // The output has been moved out, so the slot can be reused. Unregister first, so the
+        // next task spawned in the slot doesn't wake us when it finishes.
+        header.join_waker.clear();
+        header
+            .state
+            .fetch_and(!(STATE_JOIN_HANDLE | STATE_OUTPUT), Ordering::AcqRel);
+        self.task = None;
+        Poll::Ready(res)
+    }
+}
+
+impl<T> FusedFuture for JoinHandle<T> {
+    fn is_terminated(&self) -> bool {
+        self.task.is_none()
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        let task = match self.task {
+            Some(task) => task,
+            None => return,
+        };
+        let header = unsafe { task.as_ref() };
+        header.join_waker.clear();
+        let state = header
+            .state
+            .fetch_and(!(STATE_JOIN_HANDLE | STATE_OUTPUT), Ordering::AcqRel);
+
+        if state & STATE_OUTPUT != 0 {
+            let task = task.cast::<TaskOutput<T>>();
+            unsafe { task.as_ref().output.drop_in_place() }
+        }
+    }
+}
@@ -1,18 +1,24 @@
+use atomic_polyfill::Ordering;
+use core::future::Future;
 use core::marker::PhantomData;
 use core::ptr::NonNull;
 use core::{mem, ptr};
 
+mod join_handle;
 pub mod raw;
 mod run_queue;
 pub(crate) mod timer;
 mod timer_queue;
+#[cfg(feature = "executor-trace")]
+pub mod trace;
 mod util;
 mod waker;
 
-use crate::fmt::panic;
 use crate::interrupt::{Interrupt, InterruptExt};
 use crate::time::Alarm;
 
+pub use join_handle::{JoinError, JoinHandle};
+
 #[must_use = "Calling a task function does nothing on its own. You must pass the returned SpawnToken to Executor::spawn()"]
 pub struct SpawnToken<F> {
     raw_task: Option<NonNull<raw::TaskHeader>>,
@@ -21,8 +27,10 @@ pub struct SpawnToken<F> {
 
 impl<F> Drop for SpawnToken<F> {
     fn drop(&mut self) {
-        // TODO deallocate the task instead.
-        panic!("SpawnToken instances may not be dropped. You must pass them to Executor::spawn()")
+        // The task was allocated but never spawned, so give its slot back.
+        if let Some(task) = self.raw_task {
+            unsafe { raw::TaskHeader::deallocate(task) }
+        }
     }
 }
 
@@ -58,6 +66,15 @@ impl Spawner {
         }
     }
 
+    /// Spawn a task, returning a [JoinHandle] that can be used to await its output
+    /// or to cancel it.
+    pub fn spawn_with_handle<F: Future>(
+        &self,
+        token: SpawnToken<F>,
+    ) -> Result<JoinHandle<F::Output>, SpawnError> {
+        spawn_with_handle(self.executor, token)
+    }
+
     /// Convert this Spawner to a SendSpawner. This allows you to send the
     /// spawner to other threads, but the spawner loses the ability to spawn
     /// non-Send tasks.
@@ -71,10 +88,11 @@ impl Spawner {
 
 /// Handle to spawn tasks into an executor from any thread.
 ///
-/// This Spawner can be used from any thread (it implements Send and Sync, so after  any task (Send and non-Send ones), but it can
-/// only be used in the executor thread (it is not Send itself).
+/// This Spawner can be used from any thread (it implements Send and Sync, so after sending
+/// it to another thread it can still be used), but it can only spawn Send tasks. The
+/// executor is woken to run the task, wherever it's spawned from.
 ///
-/// If you want to spawn tasks from another thread, use [SendSpawner].
+/// Get one from a [Spawner] with [Spawner::make_send].
 #[derive(Copy, Clone)]
 pub struct SendSpawner {
     executor: &'static raw::Executor,
@@ -84,12 +102,6 @@ pub struct SendSpawner {
 unsafe impl Send for SendSpawner {}
 unsafe impl Sync for SendSpawner {}
 
-/// Handle to spawn tasks to an executor.
-///
-/// This Spawner can spawn any task (Send and non-Send ones), but it can
-/// only be used in the executor thread (it is not Send itself).
-///
-/// If you want to spawn tasks from another thread, use [SendSpawner].
 impl SendSpawner {
     pub fn spawn<F: Send>(&self, token: SpawnToken<F>) -> Result<(), SpawnError> {
         let header = token.raw_task;
@@ -103,6 +115,34 @@ impl SendSpawner {
             None => Err(SpawnError::Busy),
         }
     }
+
+    /// Spawn a task, returning a [JoinHandle] that can be used to await its output
+    /// or to cancel it.
+    pub fn spawn_with_handle<F: Future + Send>(
+        &self,
+        token: SpawnToken<F>,
+    ) -> Result<JoinHandle<F::Output>, SpawnError> {
+        spawn_with_handle(self.executor, token)
+    }
+}
+
+fn spawn_with_handle<F: Future>(
+    executor: &'static raw::Executor,
+    token: SpawnToken<F>,
+) -> Result<JoinHandle<F::Output>, SpawnError> {
+    let task = token.raw_task;
+    mem::forget(token);
+
+    match task {
+        Some(task) => unsafe {
+            task.as_ref()
+                .state
+                .fetch_or(raw::STATE_JOIN_HANDLE, Ordering::AcqRel);
+            executor.spawn(task);
+            Ok(JoinHandle::new(task))
+        },
+        None => Err(SpawnError::Busy),
+    }
 }
 
 pub struct Executor {
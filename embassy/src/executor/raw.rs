@@ -8,12 +8,15 @@ use core::ptr::NonNull;
 use core::task::{Context, Poll, Waker};
 use core::{mem, ptr};
 
-use super::run_queue::{RunQueue, RunQueueItem};
+use super::run_queue::{Batch, RunQueue, RunQueueItem};
 use super::timer_queue::{TimerQueue, TimerQueueItem};
+#[cfg(feature = "executor-trace")]
+use super::trace;
 use super::util::UninitCell;
 use super::waker;
 use super::SpawnToken;
 use crate::time::{Alarm, Instant};
+use crate::util::AtomicWaker;
 
 /// Task is spawned (has a future)
 pub(crate) const STATE_SPAWNED: u32 = 1 << 0;
@@ -21,6 +24,51 @@ pub(crate) const STATE_SPAWNED: u32 = 1 << 0;
 pub(crate) const STATE_RUN_QUEUED: u32 = 1 << 1;
 /// Task is in the executor timer queue
 pub(crate) const STATE_TIMER_QUEUED: u32 = 1 << 2;
+/// A JoinHandle exists for the task
+pub(crate) const STATE_JOIN_HANDLE: u32 = 1 << 3;
+/// Task cancellation has been requested
+pub(crate) const STATE_CANCELLED: u32 = 1 << 4;
+/// Task has completed, and its output is waiting to be taken by the JoinHandle
+pub(crate) const STATE_OUTPUT: u32 = 1 << 5;
+
+#[cfg(any(
+    all(feature = "executor-priorities-1", feature = "executor-priorities-2"),
+    all(feature = "executor-priorities-1", feature = "executor-priorities-4"),
+    all(feature = "executor-priorities-1", feature = "executor-priorities-8"),
+    all(feature = "executor-priorities-2", feature = "executor-priorities-4"),
+    all(feature = "executor-priorities-2", feature = "executor-priorities-8"),
+    all(feature = "executor-priorities-4", feature = "executor-priorities-8"),
+))]
+compile_error!("Only one `executor-priorities-*` feature may be enabled.");
+
+/// Number of task priority levels supported by the executor, selected with the
+/// `executor-priorities-*` Cargo features.
+///
+/// Priorities go from 0 (lowest, the default) to `PRIORITY_LEVELS - 1` (highest).
+#[cfg(feature = "executor-priorities-1")]
+pub const PRIORITY_LEVELS: usize = 1;
+/// Number of task priority levels supported by the executor, selected with the
+/// `executor-priorities-*` Cargo features.
+///
+/// Priorities go from 0 (lowest, the default) to `PRIORITY_LEVELS - 1` (highest).
+#[cfg(feature = "executor-priorities-2")]
+pub const PRIORITY_LEVELS: usize = 2;
+/// Number of task priority levels supported by the executor, selected with the
+/// `executor-priorities-*` Cargo features.
+///
+/// Priorities go from 0 (lowest, the default) to `PRIORITY_LEVELS - 1` (highest).
+#[cfg(feature = "executor-priorities-8")]
+pub const PRIORITY_LEVELS: usize = 8;
+/// Number of task priority levels supported by the executor, selected with the
+/// `executor-priorities-*` Cargo features.
+///
+/// Priorities go from 0 (lowest, the default) to `PRIORITY_LEVELS - 1` (highest).
+#[cfg(not(any(
+    feature = "executor-priorities-1",
+    feature = "executor-priorities-2",
+    feature = "executor-priorities-8"
+)))]
+pub const PRIORITY_LEVELS: usize = 4;
 
 pub struct TaskHeader {
     pub(crate) state: AtomicU32,
@@ -29,10 +77,13 @@ pub struct TaskHeader {
     pub(crate) timer_queue_item: TimerQueueItem,
     pub(crate) executor: Cell<*const Executor>, // Valid if state != 0
     pub(crate) poll_fn: UninitCell<unsafe fn(NonNull<TaskHeader>)>, // Valid if STATE_SPAWNED
+    pub(crate) drop_fn: UninitCell<unsafe fn(NonNull<TaskHeader>)>, // Valid if STATE_SPAWNED
+    pub(crate) join_waker: AtomicWaker,         // Valid if STATE_JOIN_HANDLE
+    pub(crate) priority: u8,
 }
 
 impl TaskHeader {
-    pub(crate) const fn new() -> Self {
+    pub(crate) const fn new(priority: u8) -> Self {
         Self {
             state: AtomicU32::new(0),
             expires_at: Cell::new(Instant::from_ticks(0)),
@@ -40,6 +91,9 @@ impl TaskHeader {
             timer_queue_item: TimerQueueItem::new(),
             executor: Cell::new(ptr::null()),
             poll_fn: UninitCell::uninit(),
+            drop_fn: UninitCell::uninit(),
+            join_waker: AtomicWaker::new(),
+            priority,
         }
     }
 
@@ -69,6 +123,53 @@ impl TaskHeader {
         let executor = &*self.executor.get();
         executor.enqueue(self as *const TaskHeader as *mut TaskHeader);
     }
+
+    /// Drops the future of a task that was allocated but never spawned, and frees its slot.
+    ///
+    /// Safety: the task must not have been passed to an executor.
+    pub(crate) unsafe fn deallocate(p: NonNull<TaskHeader>) {
+        let this = p.as_ref();
+        this.drop_fn.read()(p);
+        this.state.store(0, Ordering::Release);
+    }
+
+    /// Marks the task as no longer spawned, and notifies the JoinHandle if any.
+    ///
+    /// Returns true if the output was handed over to the JoinHandle. If it returns false,
+    /// the caller is responsible for dropping the output.
+    pub(crate) fn finish(&self, has_output: bool) -> bool {
+        let mut current = self.state.load(Ordering::Acquire);
+        loop {
+            let has_handle = current & STATE_JOIN_HANDLE != 0;
+            let mut new = current & !(STATE_SPAWNED | STATE_CANCELLED);
+            if has_output && has_handle {
+                new |= STATE_OUTPUT;
+            }
+
+            match self.state.compare_exchange_weak(
+                current,
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    if has_handle {
+                        self.join_waker.wake();
+                    }
+                    return has_output && has_handle;
+                }
+                Err(next_current) => current = next_current,
+            }
+        }
+    }
+}
+
+// repr(C) is needed to guarantee that this is a prefix of `Task<F>` for every
+// `F: Future<Output = T>`, so the output can be accessed without knowing `F`.
+#[repr(C)]
+pub(crate) struct TaskOutput<T> {
+    pub(crate) raw: TaskHeader,
+    pub(crate) output: UninitCell<T>,
 }
 
 // repr(C) is needed to guarantee that the Task is located at offset 0
@@ -76,13 +177,29 @@ impl TaskHeader {
 #[repr(C)]
 pub struct Task<F: Future + 'static> {
     raw: TaskHeader,
-    future: UninitCell<F>, // Valid if STATE_SPAWNED
+    output: UninitCell<F::Output>, // Valid if STATE_OUTPUT
+    future: UninitCell<F>,         // Valid if STATE_SPAWNED
 }
 
 impl<F: Future + 'static> Task<F> {
     pub const fn new() -> Self {
+        Self::new_with_priority(0)
+    }
+
+    /// Create a task that runs at the given priority level.
+    ///
+    /// When several tasks are ready to run, the executor runs higher priority ones first.
+    ///
+    /// `priority` must be lower than [PRIORITY_LEVELS]. This fails to compile otherwise when
+    /// the task is created in a `static` or `const`, and panics at runtime.
+    #[allow(clippy::no_effect)]
+    pub const fn new_with_priority(priority: u8) -> Self {
+        // Out of bounds if the priority is out of range.
+        [(); PRIORITY_LEVELS][priority as usize];
+
         Self {
-            raw: TaskHeader::new(),
+            raw: TaskHeader::new(priority),
+            output: UninitCell::uninit(),
             future: UninitCell::uninit(),
         }
     }
@@ -122,6 +239,7 @@ impl<F: Future + 'static> Task<F> {
     unsafe fn spawn_initialize(&'static self, future: impl FnOnce() -> F) -> SpawnToken<F> {
         // Initialize the task
         self.raw.poll_fn.write(Self::poll);
+        self.raw.drop_fn.write(Self::drop_future);
         self.future.write(future());
 
         return SpawnToken {
@@ -130,16 +248,32 @@ impl<F: Future + 'static> Task<F> {
         };
     }
 
+    /// Drops the future of a task that never ran.
+    unsafe fn drop_future(p: NonNull<TaskHeader>) {
+        let this = &*(p.as_ptr() as *const Task<F>);
+        this.future.drop_in_place();
+    }
+
     unsafe fn poll(p: NonNull<TaskHeader>) {
         let this = &*(p.as_ptr() as *const Task<F>);
 
+        if this.raw.state.load(Ordering::Acquire) & STATE_CANCELLED != 0 {
+            this.future.drop_in_place();
+            this.raw.finish(false);
+            return;
+        }
+
         let future = Pin::new_unchecked(this.future.as_mut());
         let waker = waker::from_task(p);
         let mut cx = Context::from_waker(&waker);
         match future.poll(&mut cx) {
-            Poll::Ready(_) => {
+            Poll::Ready(output) => {
                 this.future.drop_in_place();
-                this.raw.state.fetch_and(!STATE_SPAWNED, Ordering::AcqRel);
+                this.output.write(output);
+                if !this.raw.finish(true) {
+                    // Nobody is waiting for the output.
+                    this.output.drop_in_place();
+                }
             }
             Poll::Pending => {}
         }
@@ -152,8 +286,10 @@ impl<F: Future + 'static> Task<F> {
 
 unsafe impl<F: Future + 'static> Sync for Task<F> {}
 
+const NEW_RUN_QUEUE: RunQueue = RunQueue::new();
+
 pub struct Executor {
-    run_queue: RunQueue,
+    run_queues: [RunQueue; PRIORITY_LEVELS],
     timer_queue: TimerQueue,
     signal_fn: fn(*mut ()),
     signal_ctx: *mut (),
@@ -163,7 +299,7 @@ pub struct Executor {
 impl Executor {
     pub const fn new(signal_fn: fn(*mut ()), signal_ctx: *mut ()) -> Self {
         Self {
-            run_queue: RunQueue::new(),
+            run_queues: [NEW_RUN_QUEUE; PRIORITY_LEVELS],
             timer_queue: TimerQueue::new(),
             signal_fn,
             signal_ctx,
@@ -180,12 +316,24 @@ impl Executor {
     }
 
     unsafe fn enqueue(&self, item: *mut TaskHeader) {
-        if self.run_queue.enqueue(item) {
+        #[cfg(feature = "executor-trace")]
+        trace::event(trace::Event::TaskWoken {
+            task: item as trace::TaskId,
+        });
+
+        let priority = (*item).priority as usize;
+        if self.run_queues[priority].enqueue(item) {
             (self.signal_fn)(self.signal_ctx)
         }
     }
 
     pub unsafe fn spawn(&'static self, task: NonNull<TaskHeader>) {
+        #[cfg(feature = "executor-trace")]
+        trace::event(trace::Event::TaskSpawned {
+            executor: trace::executor_id(self),
+            task: trace::task_id(task),
+        });
+
         let task = task.as_ref();
         task.executor.set(self);
         self.enqueue(task as *const _ as _);
@@ -198,25 +346,13 @@ impl Executor {
             });
         }
 
-        self.run_queue.dequeue_all(|p| {
-            let task = p.as_ref();
-            task.expires_at.set(Instant::MAX);
-
-            let state = task.state.fetch_and(!STATE_RUN_QUEUED, Ordering::AcqRel);
-            if state & STATE_SPAWNED == 0 {
-                // If task is not running, ignore it. This can happen in the following scenario:
-                //   - Task gets dequeued, poll starts
-                //   - While task is being polled, it gets woken. It gets placed in the queue.
-                //   - Task poll finishes, returning done=true
-                //   - RUNNING bit is cleared, but the task is already in the queue.
-                return;
-            }
-
-            // Run the task
-            task.poll_fn.read()(p as _);
+        for priority in (0..PRIORITY_LEVELS).rev() {
+            self.run_priority(priority);
+        }
 
-            // Enqueue or update into timer_queue
-            self.timer_queue.update(p);
+        #[cfg(feature = "executor-trace")]
+        trace::event(trace::Event::ExecutorIdle {
+            executor: trace::executor_id(self),
         });
 
         // If this is in the past, set_alarm will immediately trigger the alarm,
@@ -228,6 +364,85 @@ impl Executor {
         }
     }
 
+    /// Run one batch of tasks of the given priority.
+    ///
+    /// Before running each task, a batch of each higher priority level is run, highest first,
+    /// so that tasks of higher priority that have been woken in the meantime run first.
+    unsafe fn run_priority(&'static self, priority: usize) {
+        // This is a stack of batches, one per level from `priority` up to the current one.
+        // `batches` holds the tasks left in the batch of each level, and `next_higher` the
+        // higher level to run a batch of next, before running the next task of the level.
+        let mut batches = [Batch::EMPTY; PRIORITY_LEVELS];
+        let mut next_higher = [PRIORITY_LEVELS - 1; PRIORITY_LEVELS];
+        batches[priority] = self.run_queues[priority].take_all();
+        let mut level = priority;
+
+        loop {
+            if batches[level].is_empty() {
+                // Go back to the level whose batch this one was run for.
+                match (priority..level).rev().find(|&l| !batches[l].is_empty()) {
+                    Some(lower) => level = lower,
+                    None => return,
+                }
+                continue;
+            }
+
+            if next_higher[level] > level {
+                let higher = next_higher[level];
+                next_higher[level] -= 1;
+                batches[higher] = self.run_queues[higher].take_all();
+                next_higher[higher] = PRIORITY_LEVELS - 1;
+                level = higher;
+                continue;
+            }
+
+            next_higher[level] = PRIORITY_LEVELS - 1;
+            if let Some(p) = batches[level].pop() {
+                self.run_task(p);
+            }
+        }
+    }
+
+    unsafe fn run_task(&'static self, p: NonNull<TaskHeader>) {
+        let task = p.as_ref();
+        task.expires_at.set(Instant::MAX);
+
+        let state = task.state.fetch_and(!STATE_RUN_QUEUED, Ordering::AcqRel);
+        if state & STATE_SPAWNED == 0 {
+            // If task is not running, ignore it. This can happen in the following scenario:
+            //   - Task gets dequeued, poll starts
+            //   - While task is being polled, it gets woken. It gets placed in the queue.
+            //   - Task poll finishes, returning done=true
+            //   - RUNNING bit is cleared, but the task is already in the queue.
+            return;
+        }
+
+        #[cfg(feature = "executor-trace")]
+        trace::event(trace::Event::PollBegin {
+            task: trace::task_id(p),
+        });
+
+        // Run the task
+        task.poll_fn.read()(p as _);
+
+        #[cfg(feature = "executor-trace")]
+        {
+            trace::event(trace::Event::PollEnd {
+                task: trace::task_id(p),
+            });
+            let expires_at = task.expires_at.get();
+            if expires_at != Instant::MAX {
+                trace::event(trace::Event::TimerArmed {
+                    task: trace::task_id(p),
+                    expires_at: expires_at.as_ticks(),
+                });
+            }
+        }
+
+        // Enqueue or update into timer_queue
+        self.timer_queue.update(p);
+    }
+
     pub unsafe fn spawner(&'static self) -> super::Spawner {
         super::Spawner {
             executor: self,
@@ -55,17 +55,35 @@ impl RunQueue {
         prev.is_null()
     }
 
-    pub(crate) unsafe fn dequeue_all(&self, on_task: impl Fn(NonNull<TaskHeader>)) {
-        let mut task = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+    /// Takes all the tasks out of the queue, to be run as a batch.
+    pub(crate) fn take_all(&self) -> Batch {
+        Batch {
+            head: self.head.swap(ptr::null_mut(), Ordering::AcqRel),
+        }
+    }
+}
 
-        while !task.is_null() {
-            // If the task re-enqueues itself, the `next` pointer will get overwritten.
-            // Therefore, first read the next pointer, and only then process the task.
-            let next = (*task).run_queue_item.next.load(Ordering::Relaxed);
+/// Tasks taken out of a [RunQueue], in reverse order of enqueuing.
+#[derive(Clone, Copy)]
+pub(crate) struct Batch {
+    head: *mut TaskHeader,
+}
 
-            on_task(NonNull::new_unchecked(task));
+impl Batch {
+    pub const EMPTY: Self = Self {
+        head: ptr::null_mut(),
+    };
 
-            task = next
-        }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Removes the next task from the batch.
+    pub(crate) unsafe fn pop(&mut self) -> Option<NonNull<TaskHeader>> {
+        let task = NonNull::new(self.head)?;
+        // If the task re-enqueues itself, the `next` pointer will get overwritten.
+        // Therefore, read the next pointer before the task is processed.
+        self.head = task.as_ref().run_queue_item.next.load(Ordering::Relaxed);
+        Some(task)
     }
 }
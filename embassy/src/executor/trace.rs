@@ -0,0 +1,108 @@
+//! Executor instrumentation.
+//!
+//! When the `executor-trace` feature is enabled, the executor reports what it's doing
+//! to the [Tracer] set with [set_tracer]. This can be used to log task activity, compute
+//! per-task CPU time, or find tasks that are starved.
+
+use atomic_polyfill::{AtomicU8, Ordering};
+use core::cell::UnsafeCell;
+use core::ptr::NonNull;
+
+use super::raw::{Executor, TaskHeader};
+
+/// Identifies a task. This is the address of its [TaskHeader].
+pub type TaskId = usize;
+
+/// Identifies an executor. This is the address of its [raw::Executor](super::raw::Executor).
+pub type ExecutorId = usize;
+
+/// Event reported by the executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// A task has been spawned into an executor.
+    TaskSpawned { executor: ExecutorId, task: TaskId },
+    /// A task has been placed in the run queue of its executor.
+    TaskWoken { task: TaskId },
+    /// The executor is about to poll a task.
+    PollBegin { task: TaskId },
+    /// The executor has finished polling a task.
+    PollEnd { task: TaskId },
+    /// A task is waiting on a timer, and will be woken at the given tick.
+    TimerArmed { task: TaskId, expires_at: u64 },
+    /// The executor has run all tasks in its run queue.
+    ExecutorIdle { executor: ExecutorId },
+}
+
+/// Receiver of executor trace events.
+pub trait Tracer {
+    /// Called for every event.
+    ///
+    /// This may be called from any context (interrupt or thread mode), and from within
+    /// the executor, so it should be quick and must not wake tasks.
+    fn event(&self, event: Event);
+}
+
+/// Tracer that logs every event with defmt, at trace level.
+#[cfg(feature = "defmt")]
+pub struct DefmtTracer;
+
+#[cfg(feature = "defmt")]
+impl Tracer for DefmtTracer {
+    fn event(&self, event: Event) {
+        defmt::trace!("{:?}", event);
+    }
+}
+
+const TRACER_UNSET: u8 = 0;
+const TRACER_SETTING: u8 = 1;
+const TRACER_SET: u8 = 2;
+
+/// The tracer is written once, while `TRACER_STATE` is `TRACER_SETTING`, and only read
+/// once it's `TRACER_SET`.
+struct TracerCell(UnsafeCell<Option<&'static dyn Tracer>>);
+
+unsafe impl Sync for TracerCell {}
+
+static TRACER: TracerCell = TracerCell(UnsafeCell::new(None));
+static TRACER_STATE: AtomicU8 = AtomicU8::new(TRACER_UNSET);
+
+/// Error returned by [set_tracer] when a tracer is already set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SetTracerError;
+
+/// Sets the tracer that receives executor events.
+///
+/// The tracer can only be set once, usually before starting the executors. Events that
+/// happen before it's set are not reported.
+pub fn set_tracer(tracer: &'static dyn Tracer) -> Result<(), SetTracerError> {
+    TRACER_STATE
+        .compare_exchange(
+            TRACER_UNSET,
+            TRACER_SETTING,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .map_err(|_| SetTracerError)?;
+    unsafe { *TRACER.0.get() = Some(tracer) };
+    TRACER_STATE.store(TRACER_SET, Ordering::Release);
+    Ok(())
+}
+
+pub(crate) fn event(event: Event) {
+    if TRACER_STATE.load(Ordering::Acquire) != TRACER_SET {
+        return;
+    }
+    if let Some(tracer) = unsafe { *TRACER.0.get() } {
+        tracer.event(event)
+    }
+}
+
+pub(crate) fn task_id(task: NonNull<TaskHeader>) -> TaskId {
+    task.as_ptr() as TaskId
+}
+
+pub(crate) fn executor_id(executor: &Executor) -> ExecutorId {
+    executor as *const Executor as ExecutorId
+}
@@ -0,0 +1,235 @@
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr;
+use core::task::{Context, Poll};
+
+use crate::fmt::panic;
+use crate::util::WakerRegistration;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WaiterState {
+    /// Not polled yet.
+    Idle,
+    /// In the wait queue.
+    Queued,
+    /// Removed from the wait queue, and the lock has been acquired on its behalf.
+    Granted,
+    /// The lock has been handed over to the caller.
+    Done,
+}
+
+struct Waiter {
+    prev: *mut Waiter,
+    next: *mut Waiter,
+    exclusive: bool,
+    state: WaiterState,
+    waker: WakerRegistration,
+}
+
+/// Intrusive FIFO queue of waiters. The waiters live inside the (pinned) `Acquire` futures.
+struct WaitQueue {
+    head: *mut Waiter,
+    tail: *mut Waiter,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    unsafe fn push_back(&mut self, w: *mut Waiter) {
+        (*w).prev = self.tail;
+        (*w).next = ptr::null_mut();
+        if self.tail.is_null() {
+            self.head = w;
+        } else {
+            (*self.tail).next = w;
+        }
+        self.tail = w;
+        (*w).state = WaiterState::Queued;
+    }
+
+    unsafe fn remove(&mut self, w: *mut Waiter) {
+        let prev = (*w).prev;
+        let next = (*w).next;
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            (*next).prev = prev;
+        }
+        (*w).prev = ptr::null_mut();
+        (*w).next = ptr::null_mut();
+    }
+}
+
+struct LockState {
+    readers: usize,
+    writer: bool,
+    queue: WaitQueue,
+}
+
+impl LockState {
+    fn can_acquire(&self, exclusive: bool) -> bool {
+        if exclusive {
+            !self.writer && self.readers == 0
+        } else {
+            !self.writer
+        }
+    }
+
+    fn acquire(&mut self, exclusive: bool) {
+        if exclusive {
+            self.writer = true;
+        } else {
+            self.readers += 1;
+        }
+    }
+
+    fn try_acquire(&mut self, exclusive: bool) -> bool {
+        // Don't allow jumping the queue, so waiters are served in order.
+        if !self.queue.is_empty() || !self.can_acquire(exclusive) {
+            return false;
+        }
+        self.acquire(exclusive);
+        true
+    }
+
+    fn release(&mut self, exclusive: bool) {
+        if exclusive {
+            self.writer = false;
+        } else {
+            self.readers -= 1;
+        }
+
+        // Hand the lock over to as many waiters as possible, in order.
+        unsafe {
+            while let Some(w) = self.queue.head.as_mut() {
+                if !self.can_acquire(w.exclusive) {
+                    break;
+                }
+                self.acquire(w.exclusive);
+                self.queue.remove(w);
+                w.state = WaiterState::Granted;
+                w.waker.wake();
+            }
+        }
+    }
+}
+
+/// Fair lock state shared by [Mutex](super::Mutex) and [RwLock](super::RwLock).
+///
+/// Either one exclusive or any number of shared holders are allowed at a time. Tasks that
+/// can't acquire the lock immediately are queued, and served in FIFO order.
+pub(crate) struct RawLock {
+    state: UnsafeCell<LockState>,
+}
+
+unsafe impl Send for RawLock {}
+unsafe impl Sync for RawLock {}
+
+impl RawLock {
+    pub const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(LockState {
+                readers: 0,
+                writer: false,
+                queue: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn try_acquire(&self, exclusive: bool) -> bool {
+        critical_section::with(|_| unsafe { (*self.state.get()).try_acquire(exclusive) })
+    }
+
+    pub fn acquire(&self, exclusive: bool) -> Acquire<'_> {
+        Acquire {
+            lock: self,
+            waiter: UnsafeCell::new(Waiter {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                exclusive,
+                state: WaiterState::Idle,
+                waker: WakerRegistration::new(),
+            }),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// Release a previously acquired lock.
+    ///
+    /// Safety: the caller must currently hold the lock, in the given mode.
+    pub unsafe fn release(&self, exclusive: bool) {
+        critical_section::with(|_| (*self.state.get()).release(exclusive))
+    }
+}
+
+/// Future that completes when the lock has been acquired.
+pub(crate) struct Acquire<'a> {
+    lock: &'a RawLock,
+    waiter: UnsafeCell<Waiter>,
+    _pinned: PhantomPinned,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: the waiter is only linked into the queue while this future is pinned, and
+        // `Drop` unlinks it before the memory goes away.
+        critical_section::with(|_| unsafe {
+            let state = &mut *self.lock.state.get();
+            let w = self.waiter.get();
+            match (*w).state {
+                WaiterState::Idle => {
+                    if state.try_acquire((*w).exclusive) {
+                        (*w).state = WaiterState::Done;
+                        Poll::Ready(())
+                    } else {
+                        (*w).waker.register(cx.waker());
+                        state.queue.push_back(w);
+                        Poll::Pending
+                    }
+                }
+                WaiterState::Queued => {
+                    (*w).waker.register(cx.waker());
+                    Poll::Pending
+                }
+                WaiterState::Granted => {
+                    (*w).state = WaiterState::Done;
+                    Poll::Ready(())
+                }
+                WaiterState::Done => panic!("Acquire polled after completion"),
+            }
+        })
+    }
+}
+
+impl<'a> Drop for Acquire<'a> {
+    fn drop(&mut self) {
+        critical_section::with(|_| unsafe {
+            let state = &mut *self.lock.state.get();
+            let w = self.waiter.get();
+            match (*w).state {
+                WaiterState::Queued => state.queue.remove(w),
+                // The lock was handed to us, but we're no longer interested. Pass it on.
+                WaiterState::Granted => state.release((*w).exclusive),
+                WaiterState::Idle | WaiterState::Done => {}
+            }
+        })
+    }
+}
@@ -0,0 +1,7 @@
+//! Async synchronization primitives
+mod lock;
+mod mutex;
+mod rwlock;
+
+pub use mutex::*;
+pub use rwlock::*;
@@ -0,0 +1,105 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use super::lock::RawLock;
+
+/// Async mutex.
+///
+/// Unlike [ThreadModeMutex](crate::util::ThreadModeMutex) or
+/// [CriticalSectionMutex](crate::util::CriticalSectionMutex), the returned [MutexGuard]
+/// may be held across `.await` points. Tasks waiting for the lock are queued and woken
+/// in the order they started waiting.
+///
+/// Example:
+/// ``` no_run
+/// # #![feature(min_type_alias_impl_trait)]
+/// # #![feature(impl_trait_in_bindings)]
+/// # #![feature(type_alias_impl_trait)]
+/// #
+/// use embassy::sync::Mutex;
+/// use embassy::time::{Duration, Timer};
+///
+/// static COUNTER: Mutex<u32> = Mutex::new(0);
+///
+/// #[embassy::task(pool_size = 2)]
+/// async fn increment() {
+///     let mut counter = COUNTER.lock().await;
+///     Timer::after(Duration::from_millis(10)).await;
+///     *counter += 1;
+/// }
+/// ```
+pub struct Mutex<T: ?Sized> {
+    raw: RawLock,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new mutex in an unlocked state.
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawLock::new(),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the mutex, returning the protected value.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Locks the mutex, waiting until it is available.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.acquire(true).await;
+        MutexGuard { mutex: self }
+    }
+
+    /// Attempts to lock the mutex without waiting.
+    ///
+    /// Returns `None` if the mutex is locked, or if other tasks are already waiting for it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.raw.try_acquire(true) {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the protected value.
+    ///
+    /// No locking is needed, since the `&mut` borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+/// Exclusive access to the value protected by a [Mutex].
+///
+/// The mutex is unlocked when the guard is dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.raw.release(true) }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.inner.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.inner.get() }
+    }
+}
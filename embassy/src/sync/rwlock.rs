@@ -0,0 +1,115 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use super::lock::RawLock;
+
+/// Async reader-writer lock.
+///
+/// Allows any number of readers or a single writer at a time. The guards may be held
+/// across `.await` points. Waiting tasks are served in the order they started waiting, so
+/// a steady stream of readers can't starve a writer.
+pub struct RwLock<T: ?Sized> {
+    raw: RawLock,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new lock in an unlocked state.
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawLock::new(),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the lock, returning the protected value.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks for shared read access, waiting until there is no writer.
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.raw.acquire(false).await;
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Locks for exclusive write access, waiting until there are no readers or writer.
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.raw.acquire(true).await;
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Attempts to lock for shared read access without waiting.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        if self.raw.try_acquire(false) {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to lock for exclusive write access without waiting.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        if self.raw.try_acquire(true) {
+            Some(RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the protected value.
+    ///
+    /// No locking is needed, since the `&mut` borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+/// Shared read access to the value protected by a [RwLock].
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.raw.release(false) }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+/// Exclusive write access to the value protected by a [RwLock].
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.raw.release(true) }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
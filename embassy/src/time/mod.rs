@@ -4,16 +4,56 @@
 mod duration;
 mod instant;
 mod traits;
+mod wall_clock;
 
 pub use crate::executor::timer::{with_timeout, Delay, Ticker, TimeoutError, Timer};
 pub use duration::Duration;
 pub use instant::Instant;
 pub use traits::*;
+pub use wall_clock::WallClock;
 
 use crate::fmt::*;
 
-// TODO allow customizing, probably via Cargo features `tick-hz-32768` or something.
-pub const TICKS_PER_SECOND: u64 = 32768;
+#[cfg(any(
+    all(feature = "tick-hz-1000", feature = "tick-hz-32768"),
+    all(feature = "tick-hz-1000", feature = "tick-hz-1000000"),
+    all(feature = "tick-hz-1000", feature = "tick-hz-1000000000"),
+    all(feature = "tick-hz-32768", feature = "tick-hz-1000000"),
+    all(feature = "tick-hz-32768", feature = "tick-hz-1000000000"),
+    all(feature = "tick-hz-1000000", feature = "tick-hz-1000000000"),
+))]
+compile_error!("Only one `tick-hz-*` feature may be enabled.");
+
+/// Number of clock ticks per second, selected with the `tick-hz-*` Cargo features.
+#[cfg(feature = "tick-hz-1000")]
+pub const TICKS_PER_SECOND: u64 = 1_000;
+/// Number of clock ticks per second, selected with the `tick-hz-*` Cargo features.
+#[cfg(feature = "tick-hz-1000000")]
+pub const TICKS_PER_SECOND: u64 = 1_000_000;
+/// Number of clock ticks per second, selected with the `tick-hz-*` Cargo features.
+#[cfg(feature = "tick-hz-1000000000")]
+pub const TICKS_PER_SECOND: u64 = 1_000_000_000;
+/// Number of clock ticks per second, selected with the `tick-hz-*` Cargo features.
+#[cfg(not(any(
+    feature = "tick-hz-1000",
+    feature = "tick-hz-1000000",
+    feature = "tick-hz-1000000000"
+)))]
+pub const TICKS_PER_SECOND: u64 = 32_768;
+
+const fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Conversions between ticks and other units multiply by `TICKS_PER_SECOND / GCD` and divide by
+// `UNIT / GCD` instead of `TICKS_PER_SECOND` and `UNIT`, to keep intermediate values
+// as small as possible and avoid overflows.
+pub(crate) const GCD_1K: u64 = gcd(TICKS_PER_SECOND, 1_000);
+pub(crate) const GCD_1M: u64 = gcd(TICKS_PER_SECOND, 1_000_000);
 
 static mut CLOCK: Option<&'static dyn Clock> = None;
 
@@ -30,3 +70,99 @@ pub unsafe fn set_clock(clock: &'static dyn Clock) {
 pub(crate) fn now() -> u64 {
     unsafe { unwrap!(CLOCK, "No clock set").now() }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `value * to / from`, computed without overflow.
+    fn convert(value: u64, from: u64, to: u64) -> u64 {
+        (value as u128 * to as u128 / from as u128) as u64
+    }
+
+    /// Values to convert, up to the largest one that can't overflow when multiplying by
+    /// `mul / gcd`.
+    fn values(mul: u64, gcd: u64) -> impl Iterator<Item = u64> {
+        let max = u64::MAX / (mul / gcd);
+        (0..100).chain((max - 100)..=max).chain([max / 3, max / 7])
+    }
+
+    #[test]
+    fn duration_millis() {
+        for ms in values(TICKS_PER_SECOND, GCD_1K) {
+            let ticks = convert(ms, 1000, TICKS_PER_SECOND);
+            assert_eq!(Duration::from_millis(ms).as_ticks(), ticks);
+        }
+        for ticks in values(1000, GCD_1K) {
+            let ms = convert(ticks, TICKS_PER_SECOND, 1000);
+            assert_eq!(Duration::from_ticks(ticks).as_millis(), ms);
+        }
+    }
+
+    #[test]
+    fn duration_micros() {
+        for us in values(TICKS_PER_SECOND, GCD_1M) {
+            let ticks = convert(us, 1_000_000, TICKS_PER_SECOND);
+            assert_eq!(Duration::from_micros(us).as_ticks(), ticks);
+        }
+        for ticks in values(1_000_000, GCD_1M) {
+            let us = convert(ticks, TICKS_PER_SECOND, 1_000_000);
+            assert_eq!(Duration::from_ticks(ticks).as_micros(), us);
+        }
+    }
+
+    #[test]
+    fn instant_millis() {
+        for ms in values(TICKS_PER_SECOND, GCD_1K) {
+            let ticks = convert(ms, 1000, TICKS_PER_SECOND);
+            assert_eq!(Instant::from_millis(ms).as_ticks(), ticks);
+        }
+        for ticks in values(1000, GCD_1K) {
+            let ms = convert(ticks, TICKS_PER_SECOND, 1000);
+            assert_eq!(Instant::from_ticks(ticks).as_millis(), ms);
+        }
+    }
+
+    #[test]
+    fn whole_seconds_are_exact() {
+        let d = Duration::from_secs(3);
+        assert_eq!(d.as_ticks(), 3 * TICKS_PER_SECOND);
+        assert_eq!(d.as_secs(), 3);
+        assert_eq!(d.as_millis(), 3_000);
+        assert_eq!(d.as_micros(), 3_000_000);
+        assert_eq!(Duration::from_millis(3_000), d);
+        assert_eq!(Duration::from_micros(3_000_000), d);
+        assert_eq!(Instant::from_secs(3).as_millis(), 3_000);
+    }
+
+    #[test]
+    fn sub_tick_durations_round_down() {
+        // 1 ms is 32.768 ticks.
+        #[cfg(not(any(
+            feature = "tick-hz-1000",
+            feature = "tick-hz-1000000",
+            feature = "tick-hz-1000000000"
+        )))]
+        {
+            assert_eq!(Duration::from_millis(1).as_ticks(), 32);
+            assert_eq!(Duration::from_micros(31).as_ticks(), 1);
+            assert_eq!(Duration::from_micros(30).as_ticks(), 0);
+            assert_eq!(Duration::from_ticks(1).as_micros(), 30);
+        }
+        #[cfg(feature = "tick-hz-1000")]
+        {
+            assert_eq!(Duration::from_micros(999).as_ticks(), 0);
+            assert_eq!(Duration::from_ticks(1).as_micros(), 1000);
+        }
+        #[cfg(feature = "tick-hz-1000000")]
+        {
+            assert_eq!(Duration::from_micros(1).as_ticks(), 1);
+            assert_eq!(Duration::from_ticks(999).as_millis(), 0);
+        }
+        #[cfg(feature = "tick-hz-1000000000")]
+        {
+            assert_eq!(Duration::from_micros(1).as_ticks(), 1000);
+            assert_eq!(Duration::from_ticks(999).as_micros(), 0);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use core::cell::Cell;
+
+use super::{Duration, Instant};
+use crate::util::{CriticalSectionMutex, RawMutex};
+
+/// Syncs closer together than this don't update the drift estimate, the error of each
+/// sync would be too large compared to the drift over the interval.
+const MIN_DRIFT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Larger drifts are not the local clock being off, but the time source stepping.
+const MAX_DRIFT_PPB: i128 = 500_000;
+
+#[derive(Clone, Copy)]
+struct State {
+    /// Time of the last sync, in ticks since the Unix epoch.
+    unix: u64,
+    /// Instant of the last sync.
+    instant: Instant,
+    /// Sync the drift is measured from, as `(unix, instant)`.
+    drift_base: (u64, Instant),
+    /// How much faster the wall clock runs than `Instant`, in parts per billion, once
+    /// measured.
+    drift_ppb: Option<i32>,
+}
+
+static STATE: CriticalSectionMutex<Cell<Option<State>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// Wall-clock time, as opposed to [Instant] which counts from startup.
+///
+/// The wall clock is unset until [sync](WallClock::sync) is called with the time from an
+/// external source, such as SNTP, GPS or an RTC. From then on, the time is extrapolated
+/// from `Instant`, corrected for the drift of the MCU's clock measured between syncs.
+///
+/// The time jumps, possibly backwards, when it's synced.
+pub struct WallClock;
+
+impl WallClock {
+    /// Current time since the Unix epoch, or `None` if the wall clock is unset.
+    pub fn now() -> Option<Duration> {
+        Self::at(Instant::now())
+    }
+
+    /// Time since the Unix epoch at `instant`, which can be before the last sync, or `None`
+    /// if the wall clock is unset.
+    pub fn at(instant: Instant) -> Option<Duration> {
+        let state = STATE.lock(|s| s.get())?;
+        let elapsed = instant.as_ticks() as i128 - state.instant.as_ticks() as i128;
+        let drift = elapsed * state.drift_ppb.unwrap_or(0) as i128 / 1_000_000_000;
+        let unix = state.unix as i128 + elapsed + drift;
+        if unix < 0 || unix > u64::MAX as i128 {
+            return None;
+        }
+        Some(Duration::from_ticks(unix as u64))
+    }
+
+    /// Set the wall clock: `unix` is the time since the Unix epoch at `instant`.
+    ///
+    /// Syncs at least 10 minutes apart also measure the drift of the MCU's clock, which is
+    /// then corrected for until the next sync.
+    pub fn sync(unix: Duration, instant: Instant) {
+        let unix = unix.as_ticks();
+        STATE.lock(|s| {
+            let mut state = match s.get() {
+                Some(state) => state,
+                None => State {
+                    unix,
+                    instant,
+                    drift_base: (unix, instant),
+                    drift_ppb: None,
+                },
+            };
+
+            let (base_unix, base_instant) = state.drift_base;
+            let elapsed = instant.as_ticks() as i128 - base_instant.as_ticks() as i128;
+            if elapsed >= MIN_DRIFT_INTERVAL.as_ticks() as i128 {
+                let error = unix as i128 - base_unix as i128 - elapsed;
+                let drift = error * 1_000_000_000 / elapsed;
+                if drift.abs() <= MAX_DRIFT_PPB {
+                    let drift = drift as i32;
+                    // Smooth out the error of individual syncs.
+                    state.drift_ppb = Some(match state.drift_ppb {
+                        Some(old) => old + (drift - old) / 4,
+                        None => drift,
+                    });
+                }
+                state.drift_base = (unix, instant);
+            } else if elapsed < 0 {
+                state.drift_base = (unix, instant);
+            }
+
+            state.unix = unix;
+            state.instant = instant;
+            s.set(Some(state));
+        })
+    }
+
+    /// How much faster the wall clock runs than [Instant], in parts per billion, or `None`
+    /// if it wasn't measured yet. Positive when the MCU's clock is slow.
+    pub fn drift_ppb() -> Option<i32> {
+        STATE.lock(|s| s.get()).and_then(|s| s.drift_ppb)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// `d` as measured by a clock running faster than `Instant` by `ppb`.
+    fn ahead(d: Duration, ppb: i64) -> Duration {
+        let ticks = d.as_ticks() as i128 * (1_000_000_000 + ppb as i128) / 1_000_000_000;
+        Duration::from_ticks(ticks as u64)
+    }
+
+    fn assert_near(ppb: Option<i32>, expected: i32) {
+        let ppb = ppb.unwrap();
+        assert!(
+            (ppb - expected).abs() <= 100,
+            "{} ppb, expected {}",
+            ppb,
+            expected
+        );
+    }
+
+    // A single test, as the wall clock is global.
+    #[test]
+    fn sync() {
+        STATE.lock(|s| s.set(None));
+        assert_eq!(WallClock::at(Instant::from_secs(1)), None);
+
+        let mut unix = Duration::from_secs(1_600_000_000);
+        let mut instant = Instant::from_secs(100);
+        let minute = Duration::from_secs(60);
+        WallClock::sync(unix, instant);
+        assert_eq!(WallClock::at(instant + minute), Some(unix + minute));
+        assert_eq!(WallClock::at(instant - minute), Some(unix - minute));
+        assert_eq!(WallClock::drift_ppb(), None);
+
+        // Too close to the first sync to measure the drift.
+        let step = 5 * minute;
+        unix += ahead(step, 100_000);
+        instant += step;
+        WallClock::sync(unix, instant);
+        assert_eq!(WallClock::at(instant), Some(unix));
+        assert_eq!(WallClock::drift_ppb(), None);
+
+        // Measured from the first sync, then corrected for.
+        unix += ahead(step, 100_000);
+        instant += step;
+        WallClock::sync(unix, instant);
+        assert_near(WallClock::drift_ppb(), 100_000);
+        let predicted = WallClock::at(instant + HOUR).unwrap().as_ticks() as i64;
+        let expected = (unix + ahead(HOUR, 100_000)).as_ticks() as i64;
+        assert!((predicted - expected).abs() <= Duration::from_millis(1).as_ticks() as i64);
+
+        // A new drift is converged to a quarter of the way at each sync.
+        let mut expected = 100_000;
+        for _ in 0..40 {
+            unix += ahead(HOUR, 20_000);
+            instant += HOUR;
+            WallClock::sync(unix, instant);
+            expected += (20_000 - expected) / 4;
+            assert_near(WallClock::drift_ppb(), expected);
+        }
+        assert_near(WallClock::drift_ppb(), 20_000);
+
+        // A step of the source isn't taken for drift.
+        unix += ahead(HOUR, 20_000) + Duration::from_secs(10);
+        instant += HOUR;
+        WallClock::sync(unix, instant);
+        assert_eq!(WallClock::at(instant), Some(unix));
+        assert_near(WallClock::drift_ppb(), 20_000);
+    }
+}
@@ -0,0 +1,193 @@
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::task::{Context, Poll};
+
+use super::{
+    CriticalSectionMutex, MutexKind, RawMutex, ThreadModeMutex, WakerRegistration,
+    WithCriticalSections, WithThreadModeOnly,
+};
+
+/// Error returned by [Channel::try_send].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full. The message is handed back to the caller.
+    Full(T),
+}
+
+/// Error returned by [Channel::try_recv].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TryRecvError {
+    /// The channel is empty.
+    Empty,
+}
+
+struct ChannelState<T, const N: usize> {
+    buf: MaybeUninit<[T; N]>,
+    read_pos: usize,
+    len: usize,
+    senders_waker: WakerRegistration,
+    receivers_waker: WakerRegistration,
+}
+
+// NOTE: the wakers only ever enqueue tasks into their executor's run queue, which
+// is safe to do from any execution context. The messages themselves are moved
+// between contexts, so they must be Send.
+unsafe impl<T: Send, const N: usize> Send for ChannelState<T, N> {}
+
+impl<T, const N: usize> ChannelState<T, N> {
+    const fn new() -> Self {
+        Self {
+            buf: MaybeUninit::uninit(),
+            read_pos: 0,
+            len: 0,
+            senders_waker: WakerRegistration::new(),
+            receivers_waker: WakerRegistration::new(),
+        }
+    }
+
+    fn slot(&mut self, i: usize) -> *mut T {
+        unsafe { (self.buf.as_mut_ptr() as *mut T).add(i) }
+    }
+
+    fn try_send(&mut self, msg: T, cx: Option<&mut Context<'_>>) -> Result<(), TrySendError<T>> {
+        if self.len == N {
+            if let Some(cx) = cx {
+                self.senders_waker.register(cx.waker());
+            }
+            return Err(TrySendError::Full(msg));
+        }
+
+        let write_pos = (self.read_pos + self.len) % N;
+        unsafe { ptr::write(self.slot(write_pos), msg) };
+        self.len += 1;
+        self.receivers_waker.wake();
+        Ok(())
+    }
+
+    fn try_recv(&mut self, cx: Option<&mut Context<'_>>) -> Result<T, TryRecvError> {
+        if self.len == 0 {
+            if let Some(cx) = cx {
+                self.receivers_waker.register(cx.waker());
+            }
+            return Err(TryRecvError::Empty);
+        }
+
+        let msg = unsafe { ptr::read(self.slot(self.read_pos)) };
+        self.read_pos = (self.read_pos + 1) % N;
+        self.len -= 1;
+        self.senders_waker.wake();
+        Ok(msg)
+    }
+}
+
+impl<T, const N: usize> Drop for ChannelState<T, N> {
+    fn drop(&mut self) {
+        while self.try_recv(None).is_ok() {}
+    }
+}
+
+/// A bounded multi-producer, multi-consumer channel.
+///
+/// Messages are stored in a fixed-size buffer of `N` entries inside the channel itself, so
+/// it can be placed in a `static` and shared by any number of tasks. Sending to a full
+/// channel waits until a receiver makes room, receiving from an empty one waits until a
+/// message is sent.
+///
+/// The `M` parameter selects how the internal state is locked, see [MutexKind].
+///
+/// ```
+/// use embassy::util::{Channel, WithThreadModeOnly};
+///
+/// static CHANNEL: Channel<WithThreadModeOnly, u32, 4> = Channel::<WithThreadModeOnly, _, 4>::new();
+///
+/// CHANNEL.try_send(42).unwrap();
+/// assert_eq!(CHANNEL.try_recv(), Ok(42));
+/// ```
+///
+/// If two tasks wait on the same side of the channel concurrently, they may wake each other
+/// repeatedly (see [WakerRegistration]). This wastes CPU but things will still work.
+pub struct Channel<M: MutexKind, T, const N: usize> {
+    inner: M::Mutex<RefCell<ChannelState<T, N>>>,
+}
+
+impl<T, const N: usize> Channel<WithCriticalSections, T, N> {
+    /// Creates a new channel that can be shared between thread mode and interrupts.
+    pub const fn new() -> Self {
+        Self {
+            inner: CriticalSectionMutex::new(RefCell::new(ChannelState::new())),
+        }
+    }
+}
+
+impl<T, const N: usize> Channel<WithThreadModeOnly, T, N> {
+    /// Creates a new channel that can only be used from thread mode.
+    pub const fn new() -> Self {
+        Self {
+            inner: ThreadModeMutex::new(RefCell::new(ChannelState::new())),
+        }
+    }
+}
+
+impl<M: MutexKind, T, const N: usize> Channel<M, T, N> {
+    fn lock<R>(&self, f: impl FnOnce(&mut ChannelState<T, N>) -> R) -> R {
+        self.inner.lock(|state| f(&mut *state.borrow_mut()))
+    }
+
+    /// Attempts to send a message without waiting.
+    ///
+    /// Returns the message back in [TrySendError::Full] if the channel has no room for it.
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.lock(|c| c.try_send(msg, None))
+    }
+
+    /// Sends a message, waiting until there is room in the channel.
+    pub async fn send(&self, msg: T) {
+        let mut msg = Some(msg);
+        futures::future::poll_fn(|cx| {
+            let m = msg.take().unwrap();
+            match self.lock(|c| c.try_send(m, Some(cx))) {
+                Ok(()) => Poll::Ready(()),
+                Err(TrySendError::Full(m)) => {
+                    msg = Some(m);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Attempts to receive a message without waiting.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.lock(|c| c.try_recv(None))
+    }
+
+    /// Polls for a message, registering the current task to be woken when one is sent.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<T> {
+        match self.lock(|c| c.try_recv(Some(cx))) {
+            Ok(msg) => Poll::Ready(msg),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Receives a message, waiting until one is available.
+    pub async fn recv(&self) -> T {
+        futures::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Returns the number of messages currently in the channel.
+    pub fn len(&self) -> usize {
+        self.lock(|c| c.len)
+    }
+
+    /// Returns true if the channel holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the channel has no room for more messages.
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+}
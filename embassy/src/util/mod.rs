@@ -1,4 +1,5 @@
 //! Async utilities
+mod channel;
 mod drop_bomb;
 mod forever;
 mod mutex;
@@ -9,6 +10,7 @@ mod signal;
 #[cfg_attr(feature = "executor-agnostic", path = "waker_agnostic.rs")]
 mod waker;
 
+pub use channel::*;
 pub use drop_bomb::*;
 pub use forever::*;
 pub use mutex::*;
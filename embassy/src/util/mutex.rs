@@ -3,6 +3,39 @@ use critical_section::CriticalSection;
 
 use crate::fmt::assert;
 
+/// Any object implementing this trait guarantees exclusive access to the data contained
+/// within the mutex for the duration of the lock.
+pub trait RawMutex {
+    /// Data protected by the mutex.
+    type Data;
+
+    /// Locks the mutex for the duration of the closure, giving it access to the data.
+    fn lock<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R;
+}
+
+/// Selects the kind of [RawMutex] a synchronization primitive uses internally.
+///
+/// This allows primitives such as [Channel](super::Channel) to be shared with interrupts
+/// ([WithCriticalSections]) or to avoid the cost of critical sections when only used
+/// from thread mode ([WithThreadModeOnly]).
+pub trait MutexKind {
+    type Mutex<T>: RawMutex<Data = T>;
+}
+
+/// Mutex kind using critical sections. Data can be shared between thread mode and interrupts.
+pub enum WithCriticalSections {}
+
+impl MutexKind for WithCriticalSections {
+    type Mutex<T> = CriticalSectionMutex<T>;
+}
+
+/// Mutex kind that only allows access from thread mode.
+pub enum WithThreadModeOnly {}
+
+impl MutexKind for WithThreadModeOnly {
+    type Mutex<T> = ThreadModeMutex<T>;
+}
+
 /// A "mutex" based on critical sections
 ///
 /// # Safety
@@ -35,6 +68,14 @@ impl<T> CriticalSectionMutex<T> {
     }
 }
 
+impl<T> RawMutex for CriticalSectionMutex<T> {
+    type Data = T;
+
+    fn lock<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        critical_section::with(|cs| f(self.borrow(cs)))
+    }
+}
+
 /// A "mutex" that only allows borrowing from thread mode.
 ///
 /// # Safety
@@ -44,6 +85,9 @@ impl<T> CriticalSectionMutex<T> {
 /// On multi-core systems, a `ThreadModeMutex` **is not sufficient** to ensure exclusive access.
 pub struct ThreadModeMutex<T> {
     inner: UnsafeCell<T>,
+    /// Id of the thread that first borrowed the mutex, 0 if none did yet.
+    #[cfg(feature = "std")]
+    owner: std::sync::atomic::AtomicUsize,
 }
 
 // NOTE: ThreadModeMutex only allows borrowing from one execution context ever: thread mode.
@@ -57,6 +101,8 @@ impl<T> ThreadModeMutex<T> {
     pub const fn new(value: T) -> Self {
         ThreadModeMutex {
             inner: UnsafeCell::new(value),
+            #[cfg(feature = "std")]
+            owner: std::sync::atomic::AtomicUsize::new(0),
         }
     }
 }
@@ -68,13 +114,71 @@ impl<T> ThreadModeMutex<T> {
             in_thread_mode(),
             "ThreadModeMutex can only be borrowed from thread mode."
         );
+        // Each executor thread is a separate thread mode, so only one of them may use
+        // the mutex. The main thread is always thread mode, as in the single-executor case,
+        // so it isn't checked.
+        #[cfg(feature = "std")]
+        {
+            use std::sync::atomic::Ordering;
+            let id = thread_id();
+            let owner =
+                match self
+                    .owner
+                    .compare_exchange(0, id, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => id,
+                    Err(owner) => owner,
+                };
+            assert!(
+                owner == id || is_main_thread(),
+                "ThreadModeMutex can only be borrowed from one executor thread."
+            );
+        }
         unsafe { &*self.inner.get() }
     }
 }
 
+impl<T> RawMutex for ThreadModeMutex<T> {
+    type Data = T;
+
+    fn lock<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        f(self.borrow())
+    }
+}
+
+#[cfg(feature = "std")]
+static NEXT_THREAD_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static THREAD_MODE: core::cell::Cell<bool> = core::cell::Cell::new(false);
+    static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Marks the current thread as running an executor. This is the std equivalent of thread
+/// mode: [ThreadModeMutex]es can be borrowed from the thread afterwards. The main thread is
+/// always in thread mode.
+///
+/// The std executors call this when they start.
+#[cfg(feature = "std")]
+pub fn enter_thread_mode() {
+    THREAD_MODE.with(|t| t.set(true))
+}
+
+/// Identifies the current thread. Unlike `ThreadId`, this fits in an atomic.
+#[cfg(feature = "std")]
+fn thread_id() -> usize {
+    THREAD_ID.with(|id| *id)
+}
+
+#[cfg(feature = "std")]
+fn is_main_thread() -> bool {
+    Some("main") == std::thread::current().name()
+}
+
 pub fn in_thread_mode() -> bool {
     #[cfg(feature = "std")]
-    return Some("main") == std::thread::current().name();
+    return is_main_thread() || THREAD_MODE.with(|t| t.get());
 
     #[cfg(not(feature = "std"))]
     return cortex_m::peripheral::SCB::vect_active()
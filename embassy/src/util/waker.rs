@@ -73,4 +73,10 @@ impl AtomicWaker {
             unsafe { wake_task(w2) };
         }
     }
+
+    /// Unregister the waker, if any.
+    pub fn clear(&self) {
+        self.waker.store(ptr::null_mut(), Ordering::Relaxed);
+        compiler_fence(Ordering::SeqCst);
+    }
 }
@@ -81,4 +81,9 @@ impl AtomicWaker {
             }
         })
     }
+
+    /// Unregister the waker, if any.
+    pub fn clear(&self) {
+        critical_section::with(|cs| drop(self.waker.borrow(cs).replace(None)))
+    }
 }
@@ -9,7 +9,6 @@ use embassy::io::AsyncWriteExt;
 use embassy::util::Forever;
 use embassy_net::*;
 use embassy_std::Executor;
-use heapless::Vec;
 use log::*;
 
 #[path = "../tuntap.rs"]
@@ -18,6 +17,8 @@ mod tuntap;
 use crate::tuntap::TunTapDevice;
 
 static DEVICE: Forever<TunTapDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<2, 8>> = Forever::new();
 static CONFIG: Forever<DhcpConfigurator> = Forever::new();
 
 #[derive(Clap)]
@@ -30,8 +31,8 @@ struct Opts {
 }
 
 #[embassy::task]
-async fn net_task() {
-    embassy_net::run().await
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
 }
 
 #[embassy::task]
@@ -42,25 +43,31 @@ async fn main_task(spawner: Spawner) {
     let device = TunTapDevice::new(&opts.tap).unwrap();
 
     // Static IP configuration
-    let config = StaticConfigurator::new(Config {
-        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
-        dns_servers: Vec::new(),
-        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
-    });
+    let mut config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24));
+    config.gateway = Some(Ipv4Address::new(192, 168, 69, 1));
+    let config = StaticConfigurator::new(config);
 
     // DHCP configruation
-    let config = DhcpConfigurator::new();
+    let mut config = DhcpConfigurator::new();
+    config.set_hostname("embassy-std").unwrap();
 
     // Init network stack
-    embassy_net::init(DEVICE.put(device), CONFIG.put(config));
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(device),
+            CONFIG.put(config),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
 
     // Launch network task
-    spawner.spawn(net_task()).unwrap();
+    spawner.spawn(net_task(stack)).unwrap();
 
     // Then we can use it!
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
-    let mut socket = TcpSocket::new(&mut rx_buffer, &mut tx_buffer);
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
 
     socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
 
@@ -0,0 +1,198 @@
+//! HTTP server on port 8080, try it with
+//! `curl http://192.168.69.2:8080/`, `curl http://192.168.69.2:8080/count?5` or
+//! `curl -d hello http://192.168.69.2:8080/echo`.
+
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+use clap::{AppSettings, Clap};
+use core::fmt::Write;
+use embassy::executor::Spawner;
+use embassy::io::{self, AsyncBufReadExt};
+use embassy::util::Forever;
+use embassy_net::http::server::{
+    read_request, write_chunked_response, write_response, Route, Router,
+};
+use embassy_net::http::{Error as HttpError, Header, Method};
+use embassy_net::*;
+use embassy_std::Executor;
+use log::*;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<2, 8>> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Hello,
+    Count,
+    Echo,
+}
+
+static ROUTER: Router<'static, Endpoint> = Router::new(&[
+    Route {
+        method: Method::Get,
+        path: "/",
+        endpoint: Endpoint::Hello,
+    },
+    Route {
+        method: Method::Get,
+        path: "/count",
+        endpoint: Endpoint::Count,
+    },
+    Route {
+        method: Method::Post,
+        path: "/echo",
+        endpoint: Endpoint::Echo,
+    },
+]);
+
+const TEXT: Header<'static> = Header {
+    name: "Content-Type",
+    value: "text/plain",
+};
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+async fn serve(socket: &mut TcpSocket<'_>) -> Result<(), HttpError> {
+    let mut head = [0; 1024];
+    let mut body_buf = [0; 1024];
+
+    loop {
+        let (req, mut body) = read_request(socket, &mut head).await?;
+        info!("{:?} {}", req.method, req.path);
+
+        match ROUTER.route(&req) {
+            Ok(Endpoint::Hello) => {
+                body.discard().await?;
+                write_response(socket, 200, &[TEXT], b"Hello from embassy!\n").await?;
+            }
+            Ok(Endpoint::Count) => {
+                body.discard().await?;
+                let n: u32 = req.query.and_then(|q| q.parse().ok()).unwrap_or(10);
+                let mut body = write_chunked_response(socket, 200, &[TEXT]).await?;
+                for i in 0..n {
+                    let mut line = heapless::String::<16>::new();
+                    writeln!(line, "{}", i).unwrap();
+                    body.write(line.as_bytes()).await?;
+                }
+                body.finish().await?;
+            }
+            Ok(Endpoint::Echo) => match body.read_to_end(&mut body_buf).await {
+                Ok(n) => write_response(socket, 200, &[TEXT], &body_buf[..n]).await?,
+                Err(io::Error::Truncated) => {
+                    // The rest of the body wasn't read, so close the connection.
+                    write_response(socket, 413, &[], b"").await?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            },
+            Err(status) => {
+                body.discard().await?;
+                write_response(socket, status, &[], b"").await?;
+            }
+        }
+
+        if !req.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+#[embassy::task(pool_size = 2)]
+async fn http_task(stack: &'static Stack, id: usize) {
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+        if let Err(e) = socket.accept(8080).await {
+            warn!("[{}] accept error: {:?}", id, e);
+            continue;
+        }
+        info!(
+            "[{}] accepted connection from {:?}",
+            id,
+            socket.remote_endpoint()
+        );
+
+        match serve(&mut socket).await {
+            Ok(()) | Err(HttpError::Closed) => info!("[{}] connection closed", id),
+            Err(e) => warn!("[{}] error: {:?}", id, e),
+        }
+        socket.close();
+    }
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Static IP configuration
+    let mut config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24));
+    config.gateway = Some(Ipv4Address::new(192, 168, 69, 1));
+    let config = StaticConfigurator::new(config);
+
+    // Init network stack
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(device),
+            CONFIG.put(config),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    for id in 0..2 {
+        spawner.spawn(http_task(stack, id)).unwrap();
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
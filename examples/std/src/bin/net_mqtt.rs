@@ -0,0 +1,169 @@
+//! Publish a counter and print the messages received on `embassy/cmd`.
+//!
+//! Run a broker on the host side of the TAP interface, for example
+//! `mosquitto -v -c <(printf 'listener 1883 192.168.69.100\nallow_anonymous true\n')`,
+//! then watch with `mosquitto_sub -h 192.168.69.100 -t 'embassy/#' -v`.
+
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+use clap::{AppSettings, Clap};
+use core::fmt::Write;
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Ticker};
+use embassy::util::Forever;
+use embassy_net::mqtt::{Event, MqttClient, MqttConfig, QoS};
+use embassy_net::*;
+use embassy_std::Executor;
+use futures::future::{select, Either};
+use futures::pin_mut;
+use futures::StreamExt;
+use log::*;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<1, 8>> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Static IP configuration
+    let mut config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24));
+    config.gateway = Some(Ipv4Address::new(192, 168, 69, 1));
+    let config = StaticConfigurator::new(config);
+
+    // Init network stack
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(device),
+            CONFIG.put(config),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(90)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 100), 1883);
+    info!("connecting to {:?}...", remote_endpoint);
+    if let Err(e) = socket.connect(remote_endpoint).await {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+
+    let mut mqtt_rx = [0; 1024];
+    let mut mqtt_tx = [0; 1024];
+    let mut client = MqttClient::new(socket, &mut mqtt_rx, &mut mqtt_tx);
+    let config = MqttConfig {
+        client_id: "embassy-std",
+        keep_alive: Duration::from_secs(10),
+        ..Default::default()
+    };
+    if let Err(e) = client.connect(&config).await {
+        warn!("MQTT connect error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+    client
+        .subscribe(&[("embassy/cmd", QoS::AtLeastOnce)])
+        .await
+        .unwrap();
+
+    let mut ticker = Ticker::every(Duration::from_secs(5));
+    let mut counter = 0u32;
+    loop {
+        let event = {
+            let next = client.next_event();
+            pin_mut!(next);
+            match select(next, ticker.next()).await {
+                Either::Left((event, _)) => Some(event),
+                Either::Right(_) => None,
+            }
+        };
+
+        let event = match event {
+            Some(event) => event,
+            None => {
+                counter += 1;
+                let mut payload = heapless::String::<16>::new();
+                write!(payload, "{}", counter).unwrap();
+                let r = client
+                    .publish(
+                        "embassy/counter",
+                        payload.as_bytes(),
+                        QoS::AtLeastOnce,
+                        false,
+                    )
+                    .await;
+                if let Err(e) = r {
+                    warn!("publish error: {:?}", e);
+                    return;
+                }
+                continue;
+            }
+        };
+
+        match event {
+            Ok(Event::Message(msg)) => info!("{}: {:?}", msg.topic, msg.payload),
+            Ok(Event::Published(id)) => info!("publish {} acknowledged", id),
+            Ok(Event::Subscribed { granted, .. }) => info!("subscribed: {:?}", granted),
+            Err(e) => {
+                warn!("MQTT error: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
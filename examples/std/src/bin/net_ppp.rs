@@ -0,0 +1,143 @@
+//! Connect to a PPP peer over a serial port, then to a TCP server behind it.
+//!
+//! To try it without a modem, create a pty pair and run pppd on one end:
+//!
+//! ```text
+//! socat pty,raw,echo=0,link=/tmp/ttyA pty,raw,echo=0,link=/tmp/ttyB &
+//! sudo pppd /tmp/ttyB 115200 nodetach noauth local debug 192.168.7.1:192.168.7.10 ms-dns 1.1.1.1
+//! cargo run --bin net_ppp -- --device /tmp/ttyA
+//! ```
+//!
+//! and listen with `nc -l 192.168.7.1 8000` once the link is up.
+
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+#[path = "../serial_port.rs"]
+mod serial_port;
+
+use async_io::Async;
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::io::AsyncWriteExt;
+use embassy::util::Forever;
+use embassy_net::ppp::{Ppp, PppConfig, PppConfigurator, PppDevice};
+use embassy_net::*;
+use embassy_std::Executor;
+use log::*;
+use nix::sys::termios;
+
+use self::serial_port::SerialPort;
+
+static PPP: Forever<Ppp> = Forever::new();
+static DEVICE: Forever<PppDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<1, 1>> = Forever::new();
+static CONFIG: Forever<PppConfigurator> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// Serial port of the PPP peer
+    #[clap(long)]
+    device: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn ppp_task(ppp: &'static Ppp, device: String) {
+    let baudrate = termios::BaudRate::B115200;
+    let port = SerialPort::new(device.as_str(), baudrate).unwrap();
+    let port = Async::new(port).unwrap();
+    let port = futures::io::BufReader::new(port);
+    let mut port = embassy::io::FromStdIo::new(port);
+
+    let err = ppp.run(&mut port).await;
+    warn!("PPP link stopped: {:?}", err);
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    let ppp = PPP.put(Ppp::new(PppConfig::default()).unwrap());
+
+    // Init network stack
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(ppp.device()),
+            CONFIG.put(ppp.configurator()),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
+
+    // Launch network and PPP tasks
+    spawner.spawn(net_task(stack)).unwrap();
+    spawner.spawn(ppp_task(ppp, opts.device)).unwrap();
+
+    // Wait for IPCP to hand out an address.
+    let config = loop {
+        if let ConfigEvent::Configured(config) = stack.wait_config_change().await {
+            break config;
+        }
+    };
+    let peer = match config.gateway {
+        Some(peer) => peer,
+        None => {
+            warn!("peer didn't tell its address");
+            return;
+        }
+    };
+
+    // Then we can use it!
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+    let remote_endpoint = (peer, 8000);
+    info!("connecting to {:?}...", remote_endpoint);
+    let r = socket.connect(remote_endpoint).await;
+    if let Err(e) = r {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+    loop {
+        let r = socket.write_all(b"Hello!\n").await;
+        if let Err(e) = r {
+            warn!("write error: {:?}", e);
+            return;
+        }
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,129 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::util::Forever;
+use embassy_net::*;
+use embassy_std::Executor;
+use log::*;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<2, 8>> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task(pool_size = 2)]
+async fn echo_task(stack: &'static Stack, id: usize) {
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut buf = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+        info!("[{}] listening on port 1234...", id);
+        if let Err(e) = socket.accept(1234).await {
+            warn!("[{}] accept error: {:?}", id, e);
+            continue;
+        }
+        info!(
+            "[{}] accepted connection from {:?}",
+            id,
+            socket.remote_endpoint()
+        );
+
+        loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) => {
+                    info!("[{}] connection closed", id);
+                    break;
+                }
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("[{}] read error: {:?}", id, e);
+                    break;
+                }
+            };
+            if let Err(e) = socket.write_all(&buf[..n]).await {
+                warn!("[{}] write error: {:?}", id, e);
+                break;
+            }
+        }
+    }
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Static IP configuration
+    let mut config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24));
+    config.gateway = Some(Ipv4Address::new(192, 168, 69, 1));
+    let config = StaticConfigurator::new(config);
+
+    // Init network stack
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(device),
+            CONFIG.put(config),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Each echo task serves one connection at a time, with its own buffers.
+    for id in 0..2 {
+        spawner.spawn(echo_task(stack, id)).unwrap();
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
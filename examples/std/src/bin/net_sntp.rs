@@ -0,0 +1,123 @@
+//! Sync the wall clock with an NTP server and print it.
+//!
+//! Run an NTP server on the host side of the TAP interface, for example chrony with
+//! `allow 192.168.69.0/24` in its configuration, or pass the address of another one
+//! reachable through the gateway with `--server`.
+
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Ticker, WallClock};
+use embassy::util::Forever;
+use embassy_net::sntp::{self, SntpConfig};
+use embassy_net::*;
+use embassy_std::Executor;
+use futures::StreamExt;
+use log::*;
+
+#[path = "../tuntap.rs"]
+mod tuntap;
+
+use crate::tuntap::TunTapDevice;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static RESOURCES: Forever<StackResources<1, 8>> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static SERVERS: Forever<[&'static str; 1]> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// NTP server address
+    #[clap(long, default_value = "192.168.69.100")]
+    server: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn sntp_task(stack: &'static Stack, servers: &'static [&'static str]) {
+    let config = SntpConfig {
+        servers,
+        // Short, to see the drift being measured.
+        interval: Duration::from_secs(15 * 60),
+        ..Default::default()
+    };
+    sntp::run(stack, &config).await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Static IP configuration
+    let mut config = Config::new(Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24));
+    config.gateway = Some(Ipv4Address::new(192, 168, 69, 1));
+    let config = StaticConfigurator::new(config);
+
+    // Init network stack
+    let stack = STACK.put(
+        Stack::new(
+            DEVICE.put(device),
+            CONFIG.put(config),
+            RESOURCES.put(StackResources::new()),
+        )
+        .unwrap(),
+    );
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    let servers = SERVERS.put([Box::leak(opts.server.into_boxed_str())]);
+    spawner.spawn(sntp_task(stack, servers)).unwrap();
+
+    let mut ticker = Ticker::every(Duration::from_secs(5));
+    loop {
+        ticker.next().await;
+        match WallClock::now() {
+            Some(now) => info!(
+                "unix time: {}.{:03}, drift: {:?} ppb",
+                now.as_secs(),
+                now.as_millis() % 1000,
+                WallClock::drift_ppb()
+            ),
+            None => info!("wall clock not synced yet"),
+        }
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
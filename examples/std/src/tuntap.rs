@@ -130,6 +130,8 @@ impl io::Write for TunTap {
 pub struct TunTapDevice {
     device: Async<TunTap>,
     waker: Option<Waker>,
+    /// Packet read from the device but not yet passed to the stack.
+    rx: Option<Vec<u8>>,
 }
 
 impl TunTapDevice {
@@ -137,36 +139,28 @@ impl TunTapDevice {
         Ok(Self {
             device: Async::new(TunTap::new(name)?)?,
             waker: None,
+            rx: None,
         })
     }
 }
 
 use core::task::Waker;
-use embassy_net::{DeviceCapabilities, LinkState, Packet, PacketBox, PacketBoxExt, PacketBuf};
+use embassy_net::{DeviceCapabilities, LinkState};
 use std::task::Context;
 
 impl crate::Device for TunTapDevice {
-    fn is_transmit_ready(&mut self) -> bool {
-        true
-    }
-
-    fn transmit(&mut self, pkt: PacketBuf) {
-        // todo handle WouldBlock
-        match self.device.get_mut().write(&pkt) {
-            Ok(_) => {}
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                info!("transmit WouldBlock");
-            }
-            Err(e) => panic!("transmit error: {:?}", e),
+    fn is_receive_ready(&mut self) -> bool {
+        if self.rx.is_some() {
+            return true;
         }
-    }
 
-    fn receive(&mut self) -> Option<PacketBuf> {
-        let mut pkt = PacketBox::new(Packet::new()).unwrap();
+        let mut buf = vec![0; self.device.get_ref().mtu];
         loop {
-            match self.device.get_mut().read(&mut pkt[..]) {
+            match self.device.get_mut().read(&mut buf) {
                 Ok(n) => {
-                    return Some(pkt.slice(0..n));
+                    buf.truncate(n);
+                    self.rx = Some(buf);
+                    return true;
                 }
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
                     let ready = if let Some(w) = self.waker.as_ref() {
@@ -177,7 +171,7 @@ impl crate::Device for TunTapDevice {
                         false
                     };
                     if !ready {
-                        return None;
+                        return false;
                     }
                 }
                 Err(e) => panic!("read error: {:?}", e),
@@ -185,6 +179,34 @@ impl crate::Device for TunTapDevice {
         }
     }
 
+    fn receive(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        let mut buf = self.rx.take().unwrap();
+        f(&mut buf)
+    }
+
+    fn is_transmit_ready(&mut self) -> bool {
+        true
+    }
+
+    fn transmit(
+        &mut self,
+        len: usize,
+        f: &mut dyn FnMut(&mut [u8]) -> embassy_net::Result<()>,
+    ) -> embassy_net::Result<()> {
+        let mut buf = vec![0; len];
+        f(&mut buf)?;
+
+        // todo handle WouldBlock
+        match self.device.get_mut().write(&buf) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                info!("transmit WouldBlock");
+            }
+            Err(e) => panic!("transmit error: {:?}", e),
+        }
+        Ok(())
+    }
+
     fn register_waker(&mut self, w: &Waker) {
         match self.waker {
             // Optimization: If both the old and new Wakers wake the same task, we can simply